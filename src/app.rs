@@ -1,17 +1,34 @@
-use std::io;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
 
 use anyhow::Result;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind, MouseEventKind, MouseButton, EnableMouseCapture, DisableMouseCapture},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind, MouseButton, EnableMouseCapture, DisableMouseCapture},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use ratatui::prelude::*;
 
-use crate::diff::{compute_diff, DiffKind, DiffResult};
-use crate::loader::Trace;
+use crate::config::{Config, Formatters};
+use crate::diff::{compute_diff, compute_removals, hide_unchanged, DiffKind, DiffResult, HideUnchanged, RemovedItem};
+use crate::keymap::{vim_key, Keymap, VimKey};
+use crate::loader::{load_trace, State, Trace, VarOrder};
+use crate::panel::{panel_area, render_panel, visible_rows, ListPanel, PanelAction, PanelItem};
+use crate::prefs::{save_preferences, Preferences};
+use crate::query::{parse_expr, parse_path, Expr, PathQuery};
+use crate::quint::{eval_in_repl, find_definition, shell_command, shell_quote, start_run, start_verify, Job, DEFAULT_REPL_COMMAND, DEFAULT_RUN_COMMAND, DEFAULT_VERIFY_COMMAND};
+use crate::search::{filter_lines, find_matches, find_matches_in_trace, find_occurrences, line_for_path, matched_lines, path_history};
+use crate::session::{load_preset, load_session, load_trace_notes, save_preset, save_session, save_trace_notes, Session, TraceNotes};
+use crate::snapshot::screen_text;
+use crate::sparkline::{format_number, numeric_value, sparkline, value_range};
+use crate::stats::{change_counts, constant_paths, counters, heat_row, var_stats};
 use crate::theme::Theme;
-use crate::tree::{ExpansionState, TreeLine, render_value};
+use crate::tree::{add_guides, add_modified_markers, add_previous_values, align_lines, as_bytes, display_path, format_value_preview, in_module, indent_json, module_of, render_state, render_value, source_at, state_sections, value_at, ExpansionHistory, ExpansionState, NodePath, PrefixDisplay, SpanStyle, StyledSpan, TreeLine, TypeDisplay, CHOICES_NODE};
+use crate::watch::Watcher;
 
 /// Which panel is focused in diff mode
 #[derive(Clone, Copy, PartialEq)]
@@ -24,42 +41,368 @@ pub enum DiffFocus {
 #[derive(Clone, Copy, PartialEq)]
 pub enum ViewMode {
     Single,
-    Diff { left: usize, right: usize, focus: DiffFocus },
+    /// `other_cursor` is the cursor line of the unfocused panel, `App::cursor` that of the focused one
+    Diff { left: usize, right: usize, focus: DiffFocus, other_cursor: usize },
 }
 
+/// What is being typed in the status line
+#[derive(Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    Search,
+    SearchAll,
+    Command,
+    /// Number of a state to go to
+    State,
+}
+
+/// Text input shown in the status line
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub text: String,
+}
+
+/// Search results in the current state
+pub struct Search {
+    pub query: String,
+    pub matches: Vec<NodePath>,
+}
+
+/// What a background job was started for, to tell what its trace is once it finishes
+pub enum JobPurpose {
+    /// `:run` with these arguments
+    Run(String),
+    /// `:verify` of this invariant of this spec
+    Verify(PathBuf, String),
+}
+
+/// Nodes to keep expanded in every state shown, from the command line
+#[derive(Default)]
+pub struct PinnedExpansion {
+    /// `--expand-all`: expand everything
+    pub all: bool,
+    /// `--expand PATH`: expand these nodes and their ancestors
+    pub paths: Vec<PathQuery>,
+}
+
+/// Lines scrolled by a mouse wheel step, unless set in the config
+const DEFAULT_SCROLL_STEP: usize = 3;
+
+/// Lines kept visible above and below the cursor, unless set in the config
+const DEFAULT_SCROLL_PADDING: usize = 2;
+
+/// Time between two scans of the watched directory for new traces, or checks on the background job
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
 /// Application state
 pub struct App {
     pub trace: Trace,
     pub current_state: usize,
     pub should_quit: bool,
     pub expansion: ExpansionState,
+    pub history: ExpansionHistory,  // Expansion changes to undo with `u` and redo with `Ctrl-r`
     pub cursor: usize,  // Which line is selected
     pub scroll_offset: usize,  // First visible line
     pub auto_expand: bool,  // Auto-expand changed variables on state navigation
+    pub saved_auto_expand: Option<bool>,  // Auto-expansion as last toggled with `a`, saved for the next run
     pub view_mode: ViewMode,
     pub collapse_threshold: usize,  // Dynamic threshold for collapsing unchanged items
+    pub var_order: VarOrder,  // Order of top-level variables
+    pub type_display: TypeDisplay,  // How variable types from #meta are shown
+    pub group_modules: bool,  // Show qualified variables like `propeller::choreo::s` in a tree of module groups
+    pub prefixes: PrefixDisplay,  // How the module prefixes of qualified variable names are shown
+    pub hide_unchanged: HideUnchanged,  // Parts of the state left out because they did not change in the transition
+    pub guides: bool,  // Draw indentation guide lines
+    pub diff_markers: bool,  // Mark modified lines with `~`, when there are no colors to show changes
+    pub wrap: bool,  // Wrap long lines instead of cutting them off
+    pub formatters: Formatters,  // Custom value formatting from the config file
+    pub pending_key: Option<char>,  // First key of a two-key sequence like `]c`
+    pub keymap: Keymap,  // Key bindings on top of the default ones
+    pub scroll_step: usize,  // Lines scrolled by a mouse wheel step
+    pub scroll_padding: usize,  // Lines kept visible above and below the cursor
+    pub cursor_target: Option<NodePath>,  // Path to place the cursor on once lines are rebuilt
+    pub status: Option<String>,  // Message shown below the header until the next key press
+    pub prompt: Option<Prompt>,  // Text input in progress
+    pub search: Option<Search>,  // Highlighted search matches
+    pub filter: bool,  // Only show branches containing search matches
+    pub panel: Option<ListPanel>,  // Popup list (e.g. search results across states)
+    pub marked_state: Option<usize>,  // State marked with `m` to compare with `D`
+    pub tags: BTreeMap<usize, Vec<String>>,  // Labels given to states with `:tag`
+    pub hidden_vars: BTreeSet<String>,  // Variables left out of the tree with `V` or the `v` menu
+    pub snapshot: Option<(PathBuf, bool)>,  // File to write the screen to once drawn again, with colors if set
+    pub job: Option<(Job, JobPurpose)>,  // Command of `:run` or `:verify` running in the background, and what for
+    pub last_run: Option<String>,  // Arguments of the last `:run`, used again by `:run` without any
+    pub run_command: String,  // Template of the command `:run` runs, from the config file
+    pub pending_eval: Option<String>,  // Expression of an `:eval` to start once the screen shows it is evaluating
+    pub repl_command: String,  // REPL `:eval` evaluates expressions in, from the config file
+    pub pending_edit: Option<(PathBuf, usize)>,  // Spec and line to open in the editor, once the loop can leave the screen to it
+    pub verify_command: String,  // Template of the command `:verify` runs, from the config file
+    pub watch: Option<Watcher>,  // Directory watched for new traces, from `--watch-dir`
+    pub pending_open: Option<PathBuf>,  // Trace of the watch queue to explore instead of the current one
+    pub ignore: Vec<String>,  // Variables left out of the traces `:run` loads, from the config file
+    pub breakpoint: Option<(String, BTreeSet<usize>)>,  // Predicate set with `:break` and the states where it holds
+    pub reference: Option<Trace>,  // Trace given with `--reference`, or the one before the last `:run`, to compare states with
+    pub against_reference: bool,  // Highlight the differences from the same state of the reference instead of the previous state
+    pub same_states: Vec<usize>,  // First state holding the same values as each state
+    pub fingerprints: Vec<String>,  // Short hash of each state's values, shown in the header and `:states`
+    pub fold_duplicates: bool,  // Step over runs of states repeating the previous one with `←`/`→`
+    pub hide_constants: bool,  // Leave out of the tree what holds the same value in every state
+    pub constant_paths: BTreeSet<NodePath>,  // Variables and record fields holding the same value in every state
+    pub hide_counters: bool,  // Leave out of the tree the numbers that only grow, listed by `:counters` instead
+    pub counter_paths: BTreeSet<NodePath>,  // Variables and record fields holding a number that only grows
+    pub unified: bool,  // Diff mode shows a single unified panel instead of two side by side
+    pub stacked: bool,  // Diff panels are stacked top and bottom instead of side by side
+    pub lock_step: bool,  // Moving to another state in diff mode moves both panels, keeping the gap between them
+    pub scroll_sync: bool,  // Diff panels scroll together
+    pub other_scroll_offset: usize,  // First visible line of the unfocused diff panel when scrolling independently
+    pub pinned: PinnedExpansion,  // Nodes expanded again whenever auto-expansion resets the tree
+    diff_cache: RefCell<Option<(usize, usize, Rc<DiffResult>)>>,  // Diff of the last compared pair of states
+    removals_cache: RefCell<Option<(usize, usize, Rc<DiffResult>)>>,  // Removals of the last compared pair of states
+    reference_cache: RefCell<Option<(usize, usize, Rc<DiffResult>)>>,  // Differences of the last state compared with the reference
 }
 
 impl App {
-    pub fn new(trace: Trace, auto_expand: bool) -> Self {
+    pub fn new(trace: Trace, auto_expand: bool, config: Config) -> Self {
         Self {
+            same_states: trace.first_same_states(),
+            fingerprints: trace.states.iter().map(|state| state.fingerprint()).collect(),
+            constant_paths: constant_paths(&trace),
+            counter_paths: counters(&trace).into_iter().map(|counter| counter.path).collect(),
             trace,
             current_state: 0,
             should_quit: false,
             expansion: ExpansionState::new(),
+            history: ExpansionHistory::default(),
             cursor: 0,
             scroll_offset: 0,
             auto_expand,
+            saved_auto_expand: None,
             view_mode: ViewMode::Single,
             collapse_threshold: 1,  // Start with completely collapsed (hide all unchanged)
+            var_order: VarOrder::Declaration,
+            type_display: TypeDisplay::Inline,
+            group_modules: false,
+            prefixes: PrefixDisplay::Full,
+            hide_unchanged: HideUnchanged::Off,
+            guides: false,
+            diff_markers: false,
+            wrap: false,
+            formatters: config.formatters,
+            pending_key: None,
+            keymap: config.keys.unwrap_or_default(),
+            scroll_step: config.scroll_step.unwrap_or(DEFAULT_SCROLL_STEP).max(1),
+            scroll_padding: config.scroll_padding.unwrap_or(DEFAULT_SCROLL_PADDING),
+            cursor_target: None,
+            status: None,
+            prompt: None,
+            search: None,
+            filter: false,
+            panel: None,
+            marked_state: None,
+            tags: BTreeMap::new(),
+            hidden_vars: BTreeSet::new(),
+            snapshot: None,
+            job: None,
+            last_run: None,
+            run_command: config.run.unwrap_or_else(|| DEFAULT_RUN_COMMAND.to_string()),
+            pending_eval: None,
+            pending_edit: None,
+            verify_command: config.verify.unwrap_or_else(|| DEFAULT_VERIFY_COMMAND.to_string()),
+            watch: None,
+            pending_open: None,
+            repl_command: config.repl.unwrap_or_else(|| DEFAULT_REPL_COMMAND.to_string()),
+            ignore: config.ignore,
+            breakpoint: None,
+            reference: None,
+            against_reference: false,
+            fold_duplicates: false,
+            hide_constants: false,
+            hide_counters: false,
+            unified: false,
+            stacked: false,
+            lock_step: false,
+            scroll_sync: true,
+            other_scroll_offset: 0,
+            pinned: PinnedExpansion::default(),
+            diff_cache: RefCell::new(None),
+            removals_cache: RefCell::new(None),
+            reference_cache: RefCell::new(None),
+        }
+    }
+
+    /// Explore another trace from its first state, keeping the display settings and what is hidden, and highlight
+    /// its differences from the previous one, kept as the reference
+    fn replace_trace(&mut self, mut trace: Trace) {
+        trace.ignore(&self.ignore);
+        trace.aliases = std::mem::take(&mut self.trace.aliases);
+        self.same_states = trace.first_same_states();
+        self.fingerprints = trace.states.iter().map(|state| state.fingerprint()).collect();
+        self.constant_paths = constant_paths(&trace);
+        self.counter_paths = counters(&trace).into_iter().map(|counter| counter.path).collect();
+        self.reference = Some(std::mem::replace(&mut self.trace, trace));
+        self.against_reference = true;
+        self.current_state = 0;
+        self.cursor = 0;
+        self.scroll_offset = 0;
+        self.view_mode = ViewMode::Single;
+        self.expansion = ExpansionState::new();
+        self.history = ExpansionHistory::default();
+        self.search = None;
+        self.filter = false;
+        self.panel = None;
+        self.marked_state = None;
+        self.tags.clear();
+        self.breakpoint = None;
+        self.diff_cache = RefCell::new(None);
+        self.removals_cache = RefCell::new(None);
+        self.reference_cache = RefCell::new(None);
+        if self.auto_expand {
+            auto_expand_changes(self);
+        } else {
+            expand_pinned(self);
+        }
+    }
+
+    /// Expand or collapse nodes as the user asked, recording the change to undo it with `u`
+    fn change_expansion(&mut self, change: impl FnOnce(&mut ExpansionState)) {
+        let before = self.expansion.clone();
+        change(&mut self.expansion);
+        self.history.record(before, &self.expansion);
+    }
+
+    /// Go back to the expansion before the last change
+    fn undo_expansion(&mut self) {
+        if !self.history.undo(&mut self.expansion) {
+            self.status = Some("Nothing to undo".to_string());
+        }
+    }
+
+    /// Make the last undone expansion change again
+    fn redo_expansion(&mut self) {
+        if !self.history.redo(&mut self.expansion) {
+            self.status = Some("Nothing to redo".to_string());
+        }
+    }
+
+    /// Where the investigation stands, to save as a session
+    fn session(&self, cursor: Option<NodePath>) -> Session {
+        let diff = match self.view_mode {
+            ViewMode::Single => None,
+            ViewMode::Diff { left, right, .. } => Some((left, right)),
+        };
+        Session {
+            state: self.current_state,
+            expanded: self.expansion.snapshot().into_iter().collect(),
+            cursor,
+            marked_state: self.marked_state,
+            tags: self.tags.clone(),
+            diff,
+            hidden_vars: self.hidden_vars.clone(),
+            // Absolute, to reopen the trace from any directory
+            trace: Some(std::fs::canonicalize(&self.trace.path).unwrap_or_else(|_| self.trace.path.clone())),
+            trace_key: Some(self.trace.key.clone()),
+        }
+    }
+
+    /// What to remember about the trace for when it is opened again
+    fn trace_notes(&self) -> TraceNotes {
+        TraceNotes { marked_state: self.marked_state, tags: self.tags.clone(), prefixes: self.prefixes }
+    }
+
+    /// Restore the states marked and tagged, dropping those beyond the end of the trace
+    fn restore_marks(&mut self, marked_state: Option<usize>, tags: BTreeMap<usize, Vec<String>>) {
+        let last = self.trace.states.len().saturating_sub(1);
+        self.marked_state = marked_state.filter(|&marked| marked <= last);
+        self.tags = tags.into_iter().filter(|&(state, _)| state <= last).collect();
+    }
+
+    /// View toggles to remember for the next run
+    pub fn preferences(&self) -> Preferences {
+        Preferences {
+            auto_expand: self.saved_auto_expand,
+            wrap: self.wrap,
+            guides: self.guides,
+            var_order: self.var_order,
+            type_display: self.type_display,
+            group_modules: self.group_modules,
+            stacked: self.stacked,
+            unified: self.unified,
+        }
+    }
+
+    /// Restore the view toggles of the last run, except auto-expansion, set from the command line
+    fn apply_preferences(&mut self, preferences: Preferences) {
+        self.saved_auto_expand = preferences.auto_expand;
+        self.wrap = preferences.wrap;
+        self.guides = preferences.guides;
+        self.var_order = preferences.var_order;
+        self.type_display = preferences.type_display;
+        self.group_modules = preferences.group_modules;
+        self.stacked = preferences.stacked;
+        self.unified = preferences.unified;
+    }
+
+    /// Diff from one state to another, computed again only when comparing another pair of states
+    pub fn diff_between(&self, left: usize, right: usize) -> Rc<DiffResult> {
+        cached_diff(&self.diff_cache, left, right, || compute_diff_between(&self.trace, left, right))
+    }
+
+    /// Differences of a state from the same state of the reference trace, everything being added past its end
+    pub fn diff_from_reference(&self, idx: usize) -> Rc<DiffResult> {
+        cached_diff(&self.reference_cache, idx, idx, || match (self.reference.as_ref(), self.trace.states.get(idx)) {
+            (Some(reference), Some(state)) => match reference.states.get(idx) {
+                Some(other) => compute_diff(&other.values, &state.values),
+                None => compute_diff(&HashMap::new(), &state.values),
+            },
+            _ => DiffResult::default(),
+        })
+    }
+
+    /// What the left state has that the right one lacks or holds differently, seen from the left state
+    pub fn removals_between(&self, left: usize, right: usize) -> Rc<DiffResult> {
+        cached_diff(&self.removals_cache, left, right, || match (self.trace.states.get(left), self.trace.states.get(right)) {
+            (Some(left), Some(right)) => compute_removals(&left.values, &right.values),
+            _ => DiffResult::default(),
+        })
+    }
+
+    /// Set the search query, recomputing matches and revealing them in the tree
+    pub fn set_search(&mut self, query: &str) {
+        let matches = match self.trace.states.get(self.current_state) {
+            Some(state) => find_matches(state, query),
+            None => Vec::new(),
+        };
+        for path in &matches {
+            self.expansion.reveal(path);
         }
+        self.search = Some(Search { query: query.to_string(), matches });
+    }
+
+    /// Clear the search highlighting, along with the filter based on it
+    pub fn clear_search(&mut self) {
+        self.search = None;
+        self.filter = false;
     }
 
     /// Enter diff mode comparing current state with previous
     pub fn enter_diff_mode(&mut self) {
         let right = self.current_state;
         let left = if right > 0 { right - 1 } else { 0 };
-        self.view_mode = ViewMode::Diff { left, right, focus: DiffFocus::Right };
+        self.view_mode = ViewMode::Diff { left, right, focus: DiffFocus::Right, other_cursor: self.cursor };
+    }
+
+    /// Enter diff mode comparing the marked state with the current one
+    pub fn compare_with_marked(&mut self) {
+        match self.marked_state {
+            Some(marked) => {
+                self.view_mode = ViewMode::Diff {
+                    left: marked,
+                    right: self.current_state,
+                    focus: DiffFocus::Right,
+                    other_cursor: self.cursor,
+                };
+            }
+            None => self.status = Some("Mark a state with m first".to_string()),
+        }
     }
 
     /// Exit diff mode
@@ -72,34 +415,81 @@ impl App {
 
     /// Toggle focus in diff mode
     pub fn toggle_diff_focus(&mut self) {
-        if let ViewMode::Diff { left, right, focus } = self.view_mode {
+        if let ViewMode::Diff { left, right, focus, other_cursor } = self.view_mode {
             let new_focus = match focus {
                 DiffFocus::Left => DiffFocus::Right,
                 DiffFocus::Right => DiffFocus::Left,
             };
-            self.view_mode = ViewMode::Diff { left, right, focus: new_focus };
+            // Each panel keeps its own cursor
+            self.view_mode = ViewMode::Diff { left, right, focus: new_focus, other_cursor: self.cursor };
+            self.cursor = other_cursor;
+            // Each panel keeps its own position when scrolling independently
+            if !self.scroll_sync {
+                std::mem::swap(&mut self.scroll_offset, &mut self.other_scroll_offset);
+            }
+        }
+    }
+
+    /// Switch between scrolling the diff panels together and independently
+    /// Going back to scrolling together brings the unfocused panel in line with the focused one
+    pub fn toggle_scroll_sync(&mut self) {
+        self.scroll_sync = !self.scroll_sync;
+        self.other_scroll_offset = self.scroll_offset;
+    }
+
+    /// First visible line of a diff panel
+    fn panel_scroll_offset(&self, focused: bool) -> usize {
+        if focused || self.scroll_sync {
+            self.scroll_offset
+        } else {
+            self.other_scroll_offset
+        }
+    }
+
+    /// Scroll the unfocused diff panel by `delta` lines, if it scrolls independently
+    fn scroll_other_panel(&mut self, delta: isize, line_count: usize) {
+        if !self.scroll_sync {
+            let max_scroll = line_count.saturating_sub(1);
+            self.other_scroll_offset = self.other_scroll_offset.saturating_add_signed(delta).min(max_scroll);
         }
     }
 
     /// Ensure cursor is visible within the viewport
     pub fn ensure_cursor_visible(&mut self, viewport_height: usize) {
-        // Keep some padding at top/bottom
-        let padding = 2;
+        // Keep some padding at top/bottom, at most what leaves room for the cursor in between
+        let padding = self.scroll_padding.min(viewport_height.saturating_sub(1) / 2);
 
         if self.cursor < self.scroll_offset + padding {
             // Cursor is above viewport
             self.scroll_offset = self.cursor.saturating_sub(padding);
-        } else if self.cursor >= self.scroll_offset + viewport_height - padding {
-            // Cursor is below viewport
-            self.scroll_offset = self.cursor.saturating_sub(viewport_height - padding - 1);
+        } else if self.cursor >= (self.scroll_offset + viewport_height).saturating_sub(padding) {
+            // Cursor is below viewport (or there is no room to show it)
+            self.scroll_offset = self.cursor.saturating_sub(viewport_height.saturating_sub(padding + 1));
+        }
+    }
+}
+
+/// Diff of a pair of states from a single-entry cache, computed again only for another pair
+fn cached_diff(
+    cache: &RefCell<Option<(usize, usize, Rc<DiffResult>)>>,
+    left: usize,
+    right: usize,
+    compute: impl FnOnce() -> DiffResult,
+) -> Rc<DiffResult> {
+    if let Some((cached_left, cached_right, diff)) = cache.borrow().as_ref() {
+        if (*cached_left, *cached_right) == (left, right) {
+            return diff.clone();
         }
     }
+    let diff = Rc::new(compute());
+    *cache.borrow_mut() = Some((left, right, diff.clone()));
+    diff
 }
 
 /// Auto-adjust expansion to fill available vertical space
 /// Three-phase strategy: completely collapsed → partially collapsed → uncollapsed
 fn auto_adjust_expansion(app: &mut App, terminal_width: usize, viewport_height: usize) {
-    let diff = compute_diff_for_state(&app);
+    let diff = compute_diff_for_state(app);
     let changed_paths: Vec<_> = diff.changes.keys().cloned().collect();
 
     // Phase 1: Completely collapsed (threshold=1) - expand depth while hiding ALL unchanged
@@ -108,7 +498,7 @@ fn auto_adjust_expansion(app: &mut App, terminal_width: usize, viewport_height:
 
     const MAX_DEPTH: usize = 20;
     for depth in 1..=MAX_DEPTH {
-        let lines = build_tree_lines(&app, &diff, terminal_width, app.collapse_threshold);
+        let lines = build_tree_lines(app, &diff, terminal_width, app.collapse_threshold);
         let current_count = lines.len();
 
         if current_count >= viewport_height.saturating_sub(3) {
@@ -130,7 +520,7 @@ fn auto_adjust_expansion(app: &mut App, terminal_width: usize, viewport_height:
             continue;
         }
 
-        let lines = build_tree_lines(&app, &diff, terminal_width, app.collapse_threshold);
+        let lines = build_tree_lines(app, &diff, terminal_width, app.collapse_threshold);
         if lines.len() > viewport_height {
             app.expansion.restore(&last_good_snapshot);
             break;
@@ -140,7 +530,7 @@ fn auto_adjust_expansion(app: &mut App, terminal_width: usize, viewport_height:
     }
 
     // Phase 2: Try partially collapsed (threshold=3) with same expansion
-    let lines = build_tree_lines(&app, &diff, terminal_width, 3);
+    let lines = build_tree_lines(app, &diff, terminal_width, 3);
     if lines.len() <= viewport_height.saturating_sub(5) {
         // Fits with more detail! Use threshold 3
         app.collapse_threshold = 3;
@@ -150,7 +540,7 @@ fn auto_adjust_expansion(app: &mut App, terminal_width: usize, viewport_height:
     }
 
     // Phase 3: Try uncollapsed (threshold=MAX) with same expansion
-    let lines = build_tree_lines(&app, &diff, terminal_width, usize::MAX);
+    let lines = build_tree_lines(app, &diff, terminal_width, usize::MAX);
     if lines.len() <= viewport_height.saturating_sub(5) {
         // Fits with full detail! Use no collapsing
         app.collapse_threshold = usize::MAX;
@@ -158,16 +548,39 @@ fn auto_adjust_expansion(app: &mut App, terminal_width: usize, viewport_height:
     // Otherwise stick with threshold 3
 }
 
-/// Run the TUI application
-pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
+/// Run the TUI application, restoring and saving what is remembered about the trace under its key (see `trace_key`)
+/// and queuing the traces that appear in the watched directory, if any
+#[allow(clippy::too_many_arguments)]
+pub fn run(trace: Trace, auto_expand: bool, config: Config, theme: Theme, start_state: usize, pinned: PinnedExpansion, preferences: Preferences, session: Option<Session>, reference: Option<Trace>, watch: Option<Watcher>) -> Result<()> {
     // Setup terminal
     enable_raw_mode()?;
     io::stdout().execute(EnterAlternateScreen)?;
     io::stdout().execute(EnableMouseCapture)?;
     let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
 
-    let mut app = App::new(trace, auto_expand);
-    let theme = Theme::default();
+    let mut app = App::new(trace, auto_expand, config);
+    app.watch = watch;
+    app.diff_markers = theme.monochrome;
+    app.apply_preferences(preferences);
+    app.pinned = pinned;
+    expand_pinned(&mut app);
+    if start_state > 0 {
+        goto_state(&mut app, start_state);
+    }
+    let notes = load_trace_notes(&app.trace.key);
+    app.prefixes = notes.prefixes;
+    app.restore_marks(notes.marked_state, notes.tags);
+    if let Some(session) = session {
+        app.status = session.mismatch(&app.trace).map(|warning| format!("Session {}", warning));
+        resume_session(&mut app, session);
+    }
+    if let Some(reference) = reference {
+        app.status = Some(match app.trace.divergence(&reference) {
+            Some(idx) => format!("First difference from the reference at state {}, R lists the differences", idx + 1),
+            None => "Same states as the reference".to_string(),
+        });
+        app.reference = Some(reference);
+    }
 
     // Event loop
     while !app.should_quit {
@@ -176,10 +589,12 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
         let terminal_width = terminal_size.width as usize;
         let terminal_height = terminal_size.height as usize;
         // Viewport height depends on view mode
-        // Single: terminal height - header (1) - blank line (1)
-        // Diff: terminal height - header (1) - blank line (1) - panel borders (2)
+        // Single: terminal height - header (1) - status line (1) - timeline (1)
+        // Diff: terminal height - header (1) - status line (1) - panel borders (2)
+        // Stacked diff: the upper half of that space, minus its own borders (2)
         let viewport_height = match app.view_mode {
-            ViewMode::Single => terminal_height.saturating_sub(2),
+            ViewMode::Single => terminal_height.saturating_sub(3),
+            ViewMode::Diff { .. } if app.stacked && !app.unified => (terminal_height.saturating_sub(2) / 2).saturating_sub(2),
             ViewMode::Diff { .. } => terminal_height.saturating_sub(4),
         };
 
@@ -188,13 +603,28 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
             auto_adjust_expansion(&mut app, terminal_width, viewport_height);
         }
 
+        // Don't hide search matches in collapsed groups of unchanged items
+        if app.search.is_some() {
+            app.collapse_threshold = usize::MAX;
+        }
+
         // Build tree lines based on view mode
         // tree_lines: used for cursor navigation and Enter toggle
         // all_expandable_paths: used for expand_all (includes both panels in diff mode)
         let (tree_lines, line_count, all_expandable_paths) = match app.view_mode {
             ViewMode::Single => {
                 let diff = compute_diff_for_state(&app);
-                let lines = build_tree_lines(&app, &diff, terminal_width, app.collapse_threshold);
+                let mut lines = build_tree_lines(&app, &diff, terminal_width, app.collapse_threshold);
+                // The first state has no transition to compare with
+                if app.current_state > 0 || app.against_reference {
+                    lines = hide_unchanged(lines, &diff, app.hide_unchanged);
+                }
+                if let (true, Some(search)) = (app.filter, &app.search) {
+                    lines = filter_lines(lines, &search.matches);
+                }
+                if app.guides {
+                    add_guides(&mut lines);
+                }
                 let count = lines.len();
                 let paths: Vec<_> = lines.iter()
                     .filter(|l| l.expandable)
@@ -202,17 +632,18 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
                     .collect();
                 (lines, count, paths)
             }
-            ViewMode::Diff { left, right, focus } => {
+            ViewMode::Diff { left, right, focus, .. } => {
                 // In diff mode, use focused panel's lines for navigation (no collapsing in diff mode)
-                let empty_diff = DiffResult { changes: std::collections::HashMap::new() };
-                let panel_width = terminal_width / 2;
-                let left_lines = build_tree_lines_for_state(&app.trace, left, &app.expansion, &empty_diff, panel_width, usize::MAX);
-                let right_lines = build_tree_lines_for_state(&app.trace, right, &app.expansion, &empty_diff, panel_width, usize::MAX);
+                let (left_lines, right_lines) = if app.unified {
+                    (Vec::new(), unified_diff_lines(&app, left, right, terminal_width))
+                } else {
+                    diff_panel_lines(&app, left, right, terminal_width)
+                };
 
-                // Use focused panel for cursor navigation
+                // Use focused panel for cursor navigation (the unified panel is always focused)
                 let focused_lines = match focus {
-                    DiffFocus::Left => left_lines.clone(),
-                    DiffFocus::Right => right_lines.clone(),
+                    DiffFocus::Left if !app.unified => left_lines.clone(),
+                    _ => right_lines.clone(),
                 };
                 let count = focused_lines.len();
 
@@ -229,6 +660,13 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
             }
         };
 
+        // Move cursor to a requested path (e.g. after jumping to another state)
+        if let Some(target) = app.cursor_target.take() {
+            if let Some(i) = line_for_path(&tree_lines, &target) {
+                app.cursor = i;
+            }
+        }
+
         // Ensure cursor stays within bounds
         if app.cursor >= line_count && line_count > 0 {
             app.cursor = line_count - 1;
@@ -236,6 +674,18 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
 
         // Ensure cursor is visible in viewport
         app.ensure_cursor_visible(viewport_height);
+        let content_width = match app.view_mode {
+            ViewMode::Single => terminal_width,
+            ViewMode::Diff { .. } => diff_content_width(&app, terminal_width),
+        };
+        if app.wrap {
+            // Wrapped lines above the cursor may push it below the viewport
+            while app.scroll_offset < app.cursor
+                && app.cursor >= app.scroll_offset + visible_line_count(&app, &tree_lines, viewport_height, content_width)
+            {
+                app.scroll_offset += 1;
+            }
+        }
 
         let mut header_layout = HeaderLayout {
             prev_start: 0, prev_end: 0,
@@ -244,12 +694,61 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
             collapse_start: 0, collapse_end: 0,
             diff_start: 0, diff_end: 0,
         };
-        terminal.draw(|f| {
+        let frame = terminal.draw(|f| {
             header_layout = match app.view_mode {
                 ViewMode::Single => render(f, &app, &tree_lines, viewport_height, &theme),
-                ViewMode::Diff { left, right, focus } => render_diff(f, &app, left, right, focus, viewport_height, &theme),
+                ViewMode::Diff { left, right, focus, .. } => render_diff(f, &app, left, right, focus, viewport_height, &theme),
             };
+            if let Some(panel) = &app.panel {
+                render_panel(f, panel, &theme);
+            }
         })?;
+        if let Some((file, ansi)) = app.snapshot.take() {
+            let text = screen_text(frame.buffer, ansi);
+            app.status = Some(match std::fs::write(&file, text) {
+                Ok(()) => format!("Wrote the screen to {}", file.display()),
+                Err(e) => format!("Failed to write {}: {}", file.display(), e),
+            });
+            // Draw again to show the status
+            continue;
+        }
+        if let Some((file, line)) = app.pending_edit.take() {
+            // The editor gets the terminal until it exits
+            io::stdout().execute(DisableMouseCapture)?;
+            disable_raw_mode()?;
+            io::stdout().execute(LeaveAlternateScreen)?;
+            let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+            let status = shell_command(&format!("{} +{} {}", editor, line, shell_quote(&file))).status();
+            enable_raw_mode()?;
+            io::stdout().execute(EnterAlternateScreen)?;
+            io::stdout().execute(EnableMouseCapture)?;
+            terminal.clear()?;
+            app.status = Some(match status {
+                Ok(status) if status.success() => format!("Back from {}:{}", file.display(), line),
+                Ok(status) => format!("{} exited with {}", editor, status),
+                Err(e) => format!("Cannot run {}: {}", editor, e),
+            });
+            continue;
+        }
+        if let Some(expr) = app.pending_eval.take() {
+            eval_expression(&mut app, &expr);
+            continue;
+        }
+        if let Some(path) = app.pending_open.take() {
+            let Some(queued) = app.watch.as_mut().and_then(|watch| watch.queue.iter_mut().find(|queued| queued.path == path)) else {
+                continue;
+            };
+            match load_trace(&path) {
+                Ok(trace) => {
+                    queued.reviewed = true;
+                    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string();
+                    let loaded = format!("Loaded {} states from {}", trace.states.len(), name);
+                    load_run_trace(&mut app, trace, &loaded);
+                }
+                Err(e) => app.status = Some(format!("Failed to load {}: {:#}", path.display(), e)),
+            }
+            continue;
+        }
 
         let event_context = EventContext {
             tree_lines: &tree_lines,
@@ -257,12 +756,25 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
             line_count,
             viewport_height,
             terminal_width,
+            terminal_height,
             header_layout: &header_layout,
         };
 
+        // Check on the background job and scan the watched directory for new traces while waiting for input, only
+        // drawing again when the job finishes or a trace appears
+        if app.job.is_some() || app.watch.is_some() {
+            let mut news = false;
+            while !news && !event::poll(WATCH_INTERVAL)? {
+                news = finish_job(&mut app) || scan_watched(&mut app);
+            }
+            if news {
+                continue;
+            }
+        }
+
         match event::read()? {
             Event::Key(key) if key.kind == KeyEventKind::Press => {
-                handle_key_event(&mut app, key.code, &event_context);
+                handle_key_event(&mut app, key, &event_context);
             }
             Event::Mouse(mouse) => {
                 handle_mouse_event(&mut app, mouse, &event_context);
@@ -275,9 +787,53 @@ pub fn run(trace: Trace, auto_expand: bool) -> Result<()> {
     io::stdout().execute(DisableMouseCapture)?;
     disable_raw_mode()?;
     io::stdout().execute(LeaveAlternateScreen)?;
+
+    if let Err(e) = save_preferences(&app.preferences()) {
+        eprintln!("Warning: could not save preferences: {:#}", e);
+    }
+    if let Err(e) = save_trace_notes(&app.trace.key, &app.trace_notes()) {
+        eprintln!("Warning: could not save trace notes: {:#}", e);
+    }
     Ok(())
 }
 
+/// Let the user pick one of the traces opened recently, `None` if they quit instead
+pub fn pick_file(files: &[PathBuf], theme: &Theme) -> Result<Option<PathBuf>> {
+    enable_raw_mode()?;
+    io::stdout().execute(EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    let items = files.iter().map(|file| PanelItem { label: file.display().to_string(), state: 0, path: None, file: Some(file.clone()) }).collect();
+    let mut panel = ListPanel::new("Recent traces", items);
+    let picked = loop {
+        terminal.draw(|frame| render_panel(frame, &panel, theme))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let size = terminal.size()?;
+        let page = visible_rows(Rect::new(0, 0, size.width, size.height)).max(1) as isize;
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => break None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break None,
+            KeyCode::Up | KeyCode::Char('k') => panel.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => panel.move_selection(1),
+            KeyCode::PageUp => panel.move_selection(-page),
+            KeyCode::PageDown => panel.move_selection(page),
+            KeyCode::Home => panel.select_first(),
+            KeyCode::End => panel.select_last(),
+            KeyCode::Enter => break panel.items.get(panel.selected).and_then(|item| item.file.clone()),
+            _ => {}
+        }
+    };
+
+    disable_raw_mode()?;
+    io::stdout().execute(LeaveAlternateScreen)?;
+    Ok(picked)
+}
+
 /// Context needed for event handling
 struct EventContext<'a> {
     tree_lines: &'a [TreeLine],
@@ -285,116 +841,1652 @@ struct EventContext<'a> {
     line_count: usize,
     viewport_height: usize,
     terminal_width: usize,
+    terminal_height: usize,
     header_layout: &'a HeaderLayout,
 }
 
 /// Handle keyboard events
-fn handle_key_event(app: &mut App, key: KeyCode, ctx: &EventContext) {
+fn handle_key_event(app: &mut App, key: KeyEvent, ctx: &EventContext) {
+    app.status = None;
+
+    if app.prompt.is_some() {
+        handle_prompt_key(app, key.code, ctx);
+        return;
+    }
+
+    if app.panel.is_some() {
+        handle_panel_key(app, key.code, ctx);
+        return;
+    }
+
+    if let Some(prefix) = app.pending_key.take() {
+        handle_key_sequence(app, prefix, key.code, ctx);
+        return;
+    }
+
+    let key = match app.keymap {
+        Keymap::Vim => match vim_key(key) {
+            Some(VimKey::Alias(code)) => KeyEvent::new(code, KeyModifiers::NONE),
+            Some(VimKey::Prefix(prefix)) => {
+                app.pending_key = Some(prefix);
+                return;
+            }
+            Some(VimKey::HalfPage { down }) => {
+                scroll_half_page(app, ctx, down);
+                return;
+            }
+            None => key,
+        },
+        Keymap::Default => key,
+    };
+
     match app.view_mode {
         ViewMode::Single => handle_single_mode_key(app, key, ctx),
         ViewMode::Diff { .. } => handle_diff_mode_key(app, key, ctx),
     }
 }
 
-/// Handle keyboard events in single view mode
-fn handle_single_mode_key(app: &mut App, key: KeyCode, ctx: &EventContext) {
+/// Handle keys while typing in the status line prompt
+fn handle_prompt_key(app: &mut App, key: KeyCode, ctx: &EventContext) {
+    let Some(prompt) = app.prompt.as_mut() else {
+        return;
+    };
+    let kind = prompt.kind;
+
     match key {
-        KeyCode::Char('q') | KeyCode::Esc => app.should_quit = true,
-        KeyCode::Char('d') => app.enter_diff_mode(),
-        KeyCode::Left => handle_prev_state(app),
-        KeyCode::Right => handle_next_state(app),
-        KeyCode::Up => {
-            if app.cursor > 0 {
-                app.cursor -= 1;
+        KeyCode::Esc => {
+            app.prompt = None;
+            if kind == PromptKind::Search {
+                app.clear_search();
             }
         }
-        KeyCode::Down => {
-            if app.cursor + 1 < ctx.line_count {
-                app.cursor += 1;
+        KeyCode::Enter => {
+            let text = std::mem::take(&mut prompt.text);
+            app.prompt = None;
+            match kind {
+                PromptKind::Search => confirm_search(app, &text),
+                PromptKind::SearchAll => open_trace_search(app, &text),
+                PromptKind::Command => run_command(app, &text, ctx),
+                PromptKind::State => goto_state_number(app, &text),
             }
         }
-        KeyCode::PageUp => {
-            app.cursor = app.cursor.saturating_sub(ctx.viewport_height.saturating_sub(2));
-        }
-        KeyCode::PageDown => {
-            app.cursor = (app.cursor + ctx.viewport_height.saturating_sub(2)).min(ctx.line_count.saturating_sub(1));
-        }
-        KeyCode::Home => {
-            app.cursor = 0;
-        }
-        KeyCode::End => {
-            if ctx.line_count > 0 {
-                app.cursor = ctx.line_count - 1;
+        KeyCode::Backspace => {
+            prompt.text.pop();
+            let text = prompt.text.clone();
+            if kind == PromptKind::Search {
+                app.set_search(&text);
             }
         }
-        KeyCode::Enter => {
-            if let Some(line) = ctx.tree_lines.get(app.cursor) {
-                if line.expandable {
-                    app.expansion.toggle(&line.path);
-                }
+        KeyCode::Char(c) => {
+            prompt.text.push(c);
+            let text = prompt.text.clone();
+            if kind == PromptKind::Search {
+                app.set_search(&text);
             }
         }
-        KeyCode::Char('c') => {
-            app.expansion.clear();
-        }
-        KeyCode::Char('e') => {
-            app.expansion.expand_all(ctx.all_expandable_paths);
-        }
         _ => {}
     }
 }
 
-/// Handle keyboard events in diff view mode
-fn handle_diff_mode_key(app: &mut App, key: KeyCode, ctx: &EventContext) {
-    match key {
-        KeyCode::Char('q') | KeyCode::Esc => app.should_quit = true,
-        KeyCode::Char('d') => app.exit_diff_mode(),
-        KeyCode::Tab => app.toggle_diff_focus(),
-        KeyCode::Left => handle_prev_state(app),
-        KeyCode::Right => handle_next_state(app),
-        KeyCode::Up => {
-            if app.cursor > 0 {
-                app.cursor -= 1;
-            }
+/// Finish a search: move the cursor to the first match
+fn confirm_search(app: &mut App, query: &str) {
+    if query.is_empty() {
+        app.clear_search();
+        return;
+    }
+    app.set_search(query);
+    let Some(search) = &app.search else {
+        return;
+    };
+    match search.matches.first() {
+        Some(first) => {
+            app.status = Some(format!("{} match(es) for \"{}\"", search.matches.len(), query));
+            app.cursor_target = Some(first.clone());
         }
-        KeyCode::Down => {
-            if app.cursor + 1 < ctx.line_count {
-                app.cursor += 1;
-            }
+        None => {
+            app.status = Some(format!("No matches for \"{}\"", query));
+            app.clear_search();
         }
-        KeyCode::PageUp => {
-            app.cursor = app.cursor.saturating_sub(ctx.viewport_height.saturating_sub(4));
+    }
+}
+
+/// Move the cursor to the next/previous line showing a search match, wrapping around
+fn jump_to_match(app: &mut App, ctx: &EventContext, forward: bool) {
+    let Some(search) = &app.search else {
+        app.status = Some("No active search".to_string());
+        return;
+    };
+    let mut lines: Vec<usize> = matched_lines(ctx.tree_lines, &search.matches).into_iter().collect();
+    lines.sort_unstable();
+    if lines.is_empty() {
+        app.status = Some(format!("No matches for \"{}\" in this state", search.query));
+        return;
+    }
+
+    let target = if forward {
+        lines.iter().find(|&&i| i > app.cursor)
+    } else {
+        lines.iter().rev().find(|&&i| i < app.cursor)
+    };
+    let wrap_line = if forward { lines[0] } else { lines[lines.len() - 1] };
+    let line = target.copied().unwrap_or(wrap_line);
+
+    app.status = Some(if target.is_none() && line != app.cursor {
+        format!("Search wrapped to the {}", if forward { "top" } else { "bottom" })
+    } else {
+        let position = lines.iter().position(|&i| i == line).unwrap_or(0);
+        format!("Match {}/{} for \"{}\"", position + 1, lines.len(), search.query)
+    });
+    app.cursor = line;
+}
+
+/// Run a command typed after `:`
+fn run_command(app: &mut App, text: &str, ctx: &EventContext) {
+    let text = text.trim();
+    let (name, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    match name {
+        "" => {}
+        "get" => get_path(app, args.trim()),
+        "find" => find_state(app, args.trim()),
+        "findall" => find_all_states(app, args.trim()),
+        "session" => session_command(app, args.trim(), ctx),
+        "preset" => preset_command(app, args.trim()),
+        "tag" => tag_state(app, args.trim()),
+        "untag" => untag_state(app, args.trim()),
+        "snapshot" => snapshot_command(app, args.trim()),
+        "history" => show_history(app, args.trim()),
+        "stats" => show_stats(app),
+        "heatmap" => show_heatmap(app, ctx),
+        "break" => set_breakpoint(app, args.trim()),
+        "transitions" => show_transitions(app),
+        "states" => show_states(app, args.trim()),
+        "boundary" => find_boundary(app, args.trim()),
+        "counters" => counters_command(app, args.trim()),
+        "def" => open_definition(app, ctx, args.trim()),
+        "eval" if args.trim().is_empty() => app.status = Some("Usage: :eval EXPR".to_string()),
+        "eval" => {
+            app.status = Some(format!("Evaluating {} in {}...", args.trim(), app.repl_command));
+            app.pending_eval = Some(args.trim().to_string());
         }
-        KeyCode::PageDown => {
-            app.cursor = (app.cursor + ctx.viewport_height.saturating_sub(4)).min(ctx.line_count.saturating_sub(1));
+        "run" => {
+            // Without arguments, run again with the last ones
+            let args = match (args.trim(), &app.last_run) {
+                ("", Some(last)) => last.clone(),
+                (args, _) => args.to_string(),
+            };
+            if let Some((job, _)) = &app.job {
+                app.status = Some(format!("Wait for {} to finish", job.command_line()));
+                return;
+            }
+            let job = start_run(&app.run_command, &args);
+            app.status = Some(format!("Running {}...", job.command_line()));
+            app.job = Some((job, JobPurpose::Run(args)));
         }
-        KeyCode::Home => {
-            app.cursor = 0;
+        "verify" => verify_command(app, args.trim()),
+        "queue" => show_queue(app),
+        _ => app.status = Some(format!("Unknown command: {}", name)),
+    }
+}
+
+/// `:snapshot [--ansi] FILE`: write the screen to a file once the prompt is gone, as plain text or with colors
+fn snapshot_command(app: &mut App, args: &str) {
+    let (ansi, file) = match args.strip_prefix("--ansi") {
+        Some(file) if file.is_empty() || file.starts_with(char::is_whitespace) => (true, file.trim()),
+        _ => (false, args),
+    };
+    if file.is_empty() {
+        app.status = Some("Usage: :snapshot [--ansi] FILE".to_string());
+        return;
+    }
+    app.snapshot = Some((PathBuf::from(file), ansi));
+}
+
+/// `:session save NAME` / `:session load NAME`: save where the investigation stands, or resume a saved session
+fn session_command(app: &mut App, args: &str, ctx: &EventContext) {
+    let (action, name) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let name = name.trim();
+    let result = match action {
+        "save" => {
+            let cursor = ctx.tree_lines.get(app.cursor).map(|line| line.path.clone());
+            save_session(name, &app.session(cursor)).map(|()| format!("Saved session {}", name))
         }
-        KeyCode::End => {
-            if ctx.line_count > 0 {
-                app.cursor = ctx.line_count - 1;
+        "load" => load_session(name).map(|session| {
+            let mismatch = session.mismatch(&app.trace);
+            resume_session(app, session);
+            match mismatch {
+                Some(warning) => format!("Resumed session {}, {}", name, warning),
+                None => format!("Resumed session {}", name),
             }
+        }),
+        _ => Err(anyhow::anyhow!("Usage: :session save NAME or :session load NAME")),
+    };
+    app.status = Some(result.unwrap_or_else(|e| format!("{:#}", e)));
+}
+
+/// `:preset save NAME` / `:preset load NAME`: save the nodes expanded in the focused state under a name,
+/// or expand the nodes of a saved preset, also in another trace of the same spec
+fn preset_command(app: &mut App, args: &str) {
+    let (action, name) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let name = name.trim();
+    let Some(state) = app.trace.states.get(focused_state(app)) else {
+        return;
+    };
+    let result = match action {
+        "save" => {
+            // Paths by map key rather than by entry index, which differs from one trace to another
+            let mut paths: Vec<String> = app.expansion.snapshot().iter()
+                .filter(|path| value_at(state, path).is_some())
+                .map(|path| display_path(state, path))
+                .collect();
+            paths.sort();
+            save_preset(name, &paths).map(|()| format!("Saved {} expanded nodes as preset {}", paths.len(), name))
         }
-        KeyCode::Enter => {
-            if let Some(line) = ctx.tree_lines.get(app.cursor) {
-                if line.expandable {
-                    app.expansion.toggle(&line.path);
-                }
+        "load" => load_preset(name).map(|paths| {
+            let expanded: HashSet<NodePath> = paths.iter()
+                .filter_map(|text| parse_path(text).and_then(|query| query.resolve(state)).ok())
+                .collect();
+            let found = expanded.len();
+            let before = app.expansion.clone();
+            app.expansion.set_expanded(expanded);
+            app.history.record(before, &app.expansion);
+            format!("Applied preset {}: {}/{} nodes found", name, found, paths.len())
+        }),
+        _ => Err(anyhow::anyhow!("Usage: :preset save NAME or :preset load NAME")),
+    };
+    app.status = Some(result.unwrap_or_else(|e| format!("{:#}", e)));
+}
+
+/// `:tag LABEL`: label the current state, or list the tagged states without a label
+fn tag_state(app: &mut App, label: &str) {
+    if label.is_empty() {
+        show_tags(app);
+        return;
+    }
+    let state = focused_state(app);
+    let labels = app.tags.entry(state).or_default();
+    if !labels.iter().any(|l| l == label) {
+        labels.push(label.to_string());
+    }
+    app.status = Some(format!("Tagged state {} as {}", state + 1, label));
+}
+
+/// `:untag [LABEL]`: remove a label from the current state, or all of its labels
+fn untag_state(app: &mut App, label: &str) {
+    let state = focused_state(app);
+    let Some(labels) = app.tags.get_mut(&state) else {
+        app.status = Some(format!("State {} has no tags", state + 1));
+        return;
+    };
+    if label.is_empty() {
+        labels.clear();
+    } else if let Some(i) = labels.iter().position(|l| l == label) {
+        labels.remove(i);
+    } else {
+        app.status = Some(format!("State {} is not tagged {}", state + 1, label));
+        return;
+    }
+    if labels.is_empty() {
+        app.tags.remove(&state);
+    }
+}
+
+/// List the tagged states in a popup, to jump to one of them
+fn show_tags(app: &mut App) {
+    if app.tags.is_empty() {
+        app.status = Some("No tagged states, tag the current one with :tag LABEL".to_string());
+        return;
+    }
+    let state_width = app.trace.states.len().to_string().len();
+    let items = app.tags
+        .iter()
+        .map(|(&state, labels)| PanelItem {
+            label: format!(" {:>width$}  {}", state + 1, labels.join(", "), width = state_width),
+            state,
+            path: None,
+            file: None,
+        })
+        .collect();
+    let mut panel = ListPanel::new("Tagged states", items);
+    // Start on the current state, or the first tagged one after it
+    panel.selected = app.tags.keys().position(|&state| state >= app.current_state).unwrap_or(0);
+    app.panel = Some(panel);
+}
+
+/// List how each variable behaves over the trace, the ones changing most often first (`Enter` goes to its first change)
+fn show_stats(app: &mut App) {
+    let stats = var_stats(&app.trace);
+    let labels: Vec<&str> = stats.iter().map(|stats| app.trace.alias(&stats.name).unwrap_or(&stats.name)).collect();
+    let name_width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+    let items = stats
+        .iter()
+        .zip(&labels)
+        .map(|(stats, label)| PanelItem {
+            label: format!(" {:<width$}  {}", label, stats.summary(), width = name_width),
+            state: stats.first_change.unwrap_or(app.current_state),
+            path: Some(vec![stats.name.clone()]),
+            file: None,
+        })
+        .collect();
+    app.panel = Some(ListPanel::new("Variable statistics (Enter goes to the first change)", items));
+}
+
+/// List every transition with its action and the variables it changed (`Enter` compares its states side by side)
+fn show_transitions(app: &mut App) {
+    let state_width = app.trace.states.len().to_string().len();
+    let items = (1..app.trace.states.len())
+        .map(|idx| {
+            let state = &app.trace.states[idx];
+            let diff = compute_diff_between(&app.trace, idx - 1, idx);
+            let changed: Vec<&str> = state
+                .var_names(&app.trace.vars, VarOrder::Declaration)
+                .into_iter()
+                .filter(|name| diff.get(&vec![name.to_string()]) != DiffKind::Unchanged)
+                .map(|name| app.trace.alias(name).unwrap_or(name))
+                .collect();
+            let action = state.action_taken().map(|action| format!(" ({})", action)).unwrap_or_default();
+            let changed = if changed.is_empty() { "no changes".to_string() } else { changed.join(", ") };
+            PanelItem {
+                label: format!(" {:>width$} → {:>width$}{}  {}", idx, idx + 1, action, changed, width = state_width),
+                state: idx,
+                path: None,
+                file: None,
             }
+        })
+        .collect();
+    let mut panel = ListPanel::new("Transitions (Enter compares the states)", items);
+    panel.action = PanelAction::Compare;
+    panel.selected = app.current_state.saturating_sub(1);
+    app.panel = Some(panel);
+}
+
+/// Highlight the differences from the same state of the reference trace instead of the changes since the previous state
+fn toggle_against_reference(app: &mut App) {
+    if app.reference.is_none() {
+        app.status = Some("Open the explorer with --reference FILE, or :run again, to compare with another trace".to_string());
+        return;
+    }
+    app.against_reference = !app.against_reference;
+    app.status = Some(if app.against_reference { "Highlighting the differences from the reference" } else { "Highlighting the changes since the previous state" }.to_string());
+    if app.auto_expand {
+        auto_expand_changes(app);
+    }
+}
+
+/// Show or hide the variables and record fields holding the same value in every state
+fn toggle_constants(app: &mut App) {
+    if app.constant_paths.is_empty() {
+        app.status = Some("No variable or field holds the same value in every state".to_string());
+        return;
+    }
+    app.hide_constants = !app.hide_constants;
+    let count = app.constant_paths.len();
+    let noun = if count == 1 { "constant variable or field" } else { "constant variables and fields" };
+    app.status = Some(format!("{} {} {}", if app.hide_constants { "Hiding" } else { "Showing" }, count, noun));
+}
+
+/// `:counters [hide|show]`: list the numbers that only grow over the trace, or hide them from the tree or show them again
+fn counters_command(app: &mut App, args: &str) {
+    match args {
+        "" => show_counters(app),
+        "hide" | "show" if app.counter_paths.is_empty() => app.status = Some("No number only grows over the trace".to_string()),
+        "hide" | "show" => {
+            app.hide_counters = args == "hide";
+            let verb = if app.hide_counters { "Hiding" } else { "Showing" };
+            app.status = Some(format!("{} {} counter{}, :counters lists their values", verb, app.counter_paths.len(), if app.counter_paths.len() == 1 { "" } else { "s" }));
         }
-        KeyCode::Char('c') => {
-            app.expansion.clear();
+        _ => app.status = Some("Usage: :counters [hide|show]".to_string()),
+    }
+}
+
+/// List the numbers that only grow over the trace (heights, rounds, nonces) with each value they take and the state
+/// it starts in (`Enter` goes to the current state's value)
+fn show_counters(app: &mut App) {
+    let counters = counters(&app.trace);
+    if counters.is_empty() {
+        app.status = Some("No number only grows over the trace".to_string());
+        return;
+    }
+    let labels: Vec<String> = counters.iter().map(|counter| display_path(&app.trace.states[app.current_state], &counter.path)).collect();
+    let name_width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+    let items = counters
+        .iter()
+        .zip(&labels)
+        .map(|(counter, label)| PanelItem {
+            label: format!(" {:<width$}  {}", label, counter.summary(), width = name_width),
+            state: app.current_state,
+            path: Some(counter.path.clone()),
+            file: None,
+        })
+        .collect();
+    app.panel = Some(ListPanel::new("Counters (state: value it starts with)", items));
+}
+
+/// List the states with their fingerprint and action, only the ones whose fingerprint starts with `prefix` if any
+fn show_states(app: &mut App, prefix: &str) {
+    let state_width = app.trace.states.len().to_string().len();
+    let items: Vec<PanelItem> = app.trace.states
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| app.fingerprints[*idx].starts_with(&prefix.trim_start_matches('@').to_lowercase()))
+        .map(|(idx, state)| {
+            let same = match app.same_states[idx] {
+                first if first != idx => format!(" (= state {})", first + 1),
+                _ => String::new(),
+            };
+            let action = state.action_taken().map(|action| format!(" ({})", action)).unwrap_or_default();
+            let tags: String = app.tags.get(&idx).into_iter().flatten().map(|label| format!(" #{}", label)).collect();
+            PanelItem {
+                label: format!(" {:>width$}  @{}{}{}{}", idx + 1, app.fingerprints[idx], same, action, tags, width = state_width),
+                state: idx,
+                path: None,
+                file: None,
+            }
+        })
+        .collect();
+    match items.len() {
+        0 => app.status = Some(format!("No state has the fingerprint @{}", prefix.trim_start_matches('@'))),
+        1 if !prefix.is_empty() => goto_state(app, items[0].state),
+        _ => {
+            let mut panel = ListPanel::new("States", items);
+            panel.selected = panel.items.iter().position(|item| item.state >= app.current_state).unwrap_or(0);
+            app.panel = Some(panel);
+        }
+    }
+}
+
+/// Show a row per variable with a cell per state (or group of states), shaded by the number of values changed in it
+fn show_heatmap(app: &mut App, ctx: &EventContext) {
+    let counts = change_counts(&app.trace);
+    let labels: Vec<&str> = counts.iter().map(|(name, _)| app.trace.alias(name).unwrap_or(name)).collect();
+    let name_width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+    let inner_width = panel_area(Rect::new(0, 0, ctx.terminal_width as u16, ctx.terminal_height as u16)).width.saturating_sub(2) as usize;
+    let cells = inner_width.saturating_sub(name_width + 3).max(1);
+    let per_cell = app.trace.states.len().div_ceil(cells).max(1);
+
+    let items = counts
+        .iter()
+        .zip(&labels)
+        .map(|((name, var_counts), label)| PanelItem {
+            label: format!(" {:<width$}  {}", label, heat_row(var_counts, per_cell), width = name_width),
+            state: app.current_state,
+            path: Some(vec![name.clone()]),
+            file: None,
+        })
+        .collect();
+    let scale = if per_cell > 1 { format!(", {} states per cell", per_cell) } else { String::new() };
+    app.panel = Some(ListPanel::new(format!("Changed values per state (· 0, ░ 1, ▒ 2-3, ▓ 4-7, █ 8+{})", scale), items));
+}
+
+/// Go back to where a saved session stood; states beyond the end of the trace are clamped to the last one
+pub fn resume_session(app: &mut App, session: Session) {
+    let last = app.trace.states.len().saturating_sub(1);
+    goto_state(app, session.state.min(last));
+    app.expansion.set_expanded(session.expanded.into_iter().collect());
+    app.restore_marks(session.marked_state, session.tags);
+    app.view_mode = match session.diff {
+        Some((left, right)) => ViewMode::Diff { left: left.min(last), right: right.min(last), focus: DiffFocus::Right, other_cursor: 0 },
+        None => ViewMode::Single,
+    };
+    app.hidden_vars = session.hidden_vars;
+    app.cursor_target = session.cursor;
+}
+
+/// `:get PATH`: show the value at a path in the current state and move the cursor to it
+fn get_path(app: &mut App, text: &str) {
+    let Some(state) = app.trace.states.get(app.current_state) else {
+        return;
+    };
+    let path = match parse_path(text).and_then(|query| query.resolve(state)) {
+        Ok(path) => path,
+        Err(err) => {
+            app.status = Some(err.to_string());
+            return;
+        }
+    };
+
+    if let Some(value) = value_at(state, &path) {
+        app.status = Some(format!("{} = {}", display_path(state, &path), format_value_preview(value, 200)));
+    }
+    app.expansion.reveal(&path);
+    app.cursor_target = Some(path);
+}
+
+/// Parse a predicate, reporting errors in the status line
+fn parse_predicate(app: &mut App, text: &str) -> Option<Expr> {
+    if text.is_empty() {
+        app.status = Some("Expected a predicate, e.g. round == 0 && size(decided) > 1".to_string());
+        return None;
+    }
+    match parse_expr(text) {
+        Ok(expr) => Some(expr),
+        Err(err) => {
+            app.status = Some(err.to_string());
+            None
+        }
+    }
+}
+
+/// Tree path of the first path of a predicate that exists in a state
+fn predicate_target(expr: &Expr, state: &crate::loader::State) -> Option<NodePath> {
+    expr.paths().into_iter().find_map(|query| query.resolve(state).ok())
+}
+
+/// `:find EXPR`: jump to the next state (wrapping around) where the predicate holds
+fn find_state(app: &mut App, text: &str) {
+    let Some(expr) = parse_predicate(app, text) else {
+        return;
+    };
+    let current = app.current_state;
+    let states = &app.trace.states;
+    let mut order = (current + 1..states.len()).chain(0..(current + 1).min(states.len()));
+    let Some(idx) = order.find(|&i| expr.holds(&states[i])) else {
+        app.status = Some(format!("No state satisfies {}", text));
+        return;
+    };
+
+    goto_state(app, idx);
+    let wrapped = if idx <= current { " (wrapped)" } else { "" };
+    app.status = Some(format!("State {} satisfies {}{}", idx + 1, text, wrapped));
+    if let Some(path) = predicate_target(&expr, &app.trace.states[idx]) {
+        app.expansion.reveal(&path);
+        app.cursor_target = Some(path);
+    }
+}
+
+/// Spec the trace comes from, or nothing with a status telling why
+fn trace_spec(app: &mut App) -> Option<PathBuf> {
+    let Some(source) = &app.trace.meta.source else {
+        app.status = Some("The trace doesn't name its spec (source in #meta)".to_string());
+        return None;
+    };
+    let spec = app.trace.source_path();
+    if spec.is_none() {
+        app.status = Some(format!("Cannot find {}, next to the trace or in the current directory", source));
+    }
+    spec
+}
+
+/// `:def [NAME]`: open the spec the trace comes from in `$VISUAL` or `$EDITOR` at the declaration of a name, by default
+/// the variable or choice under the cursor, or else the action that produced the state
+fn open_definition(app: &mut App, ctx: &EventContext, name: &str) {
+    // A choice names its `nondet` binding
+    let at_cursor = ctx.tree_lines.get(app.cursor).and_then(|line| match line.path.as_slice() {
+        [root, choice, ..] if root == CHOICES_NODE => Some(choice),
+        [root, ..] => Some(root).filter(|name| module_of(&[name.to_string()]).is_none() && !name.starts_with('#')),
+        [] => None,
+    });
+    let name = match (name, at_cursor) {
+        ("", Some(var)) => var.rsplit("::").next().unwrap_or(var).to_string(),
+        ("", None) => match app.trace.states.get(focused_state(app)).map(State::action_taken) {
+            None => {
+                app.status = Some(format!("No state {} to find the action of", focused_state(app) + 1));
+                return;
+            }
+            Some(Some(action)) => action.to_string(),
+            Some(None) => {
+                app.status = Some("Put the cursor on a variable, or give a name: :def NAME".to_string());
+                return;
+            }
+        },
+        (name, _) => name.to_string(),
+    };
+    let Some(spec) = trace_spec(app) else {
+        return;
+    };
+    let line = std::fs::read_to_string(&spec).ok().and_then(|text| find_definition(&text, &name));
+    match line {
+        Some(line) => app.pending_edit = Some((spec, line)),
+        None => app.status = Some(format!("No declaration of {} in {}", name, spec.display())),
+    }
+}
+
+/// Explore the trace of the background job once it has finished, telling whether it has
+fn finish_job(app: &mut App) -> bool {
+    let Some((job, purpose)) = &app.job else {
+        return false;
+    };
+    match purpose {
+        JobPurpose::Run(args) => {
+            let Some(result) = job.run_trace() else {
+                return false;
+            };
+            let args = args.clone();
+            app.job = None;
+            match result {
+                Ok(trace) => {
+                    let loaded = format!("Loaded {} states from :run {}", trace.states.len(), args).trim_end().to_string();
+                    load_run_trace(app, trace, &loaded);
+                    app.last_run = Some(args);
+                }
+                Err(e) => app.status = Some(format!("{:#}", e)),
+            }
+        }
+        JobPurpose::Verify(spec, invariant) => {
+            let Some(result) = job.verify_trace() else {
+                return false;
+            };
+            let (spec, invariant) = (spec.clone(), invariant.clone());
+            app.job = None;
+            match result {
+                Ok(Some(trace)) => {
+                    let loaded = format!("{} is violated, loaded the {}-state counterexample", invariant, trace.states.len());
+                    load_run_trace(app, trace, &loaded);
+                }
+                Ok(None) => app.status = Some(format!("{} holds, no counterexample found in {}", invariant, spec.display())),
+                Err(e) => app.status = Some(format!("{:#}", e)),
+            }
+        }
+    }
+    true
+}
+
+/// Scan the watched directory for new traces, telling whether one appeared
+fn scan_watched(app: &mut App) -> bool {
+    let Some(watch) = app.watch.as_mut() else {
+        return false;
+    };
+    let new = watch.scan();
+    if new == 0 {
+        return false;
+    }
+    let newest = watch.queue.last().map(|queued| queued.path.file_name().unwrap_or_default().to_string_lossy().to_string()).unwrap_or_default();
+    let arrived = if new == 1 { format!("New trace {}", newest) } else { format!("{} new traces, the last {}", new, newest) };
+    app.status = Some(format!("{} in {}, W lists the {} to review", arrived, watch.dir.display(), watch.unreviewed()));
+    // Ring the terminal bell, to notice traces arriving while looking elsewhere
+    let _ = io::stdout().write_all(b"\x07").and_then(|()| io::stdout().flush());
+    true
+}
+
+/// Explore a trace from `:run` or `:verify` instead of the current one, which becomes the reference, and tell where
+/// they first differ after what `loaded` says
+fn load_run_trace(app: &mut App, trace: Trace, loaded: &str) {
+    // What is remembered about the previous trace is saved before the new one's is restored
+    let _ = save_trace_notes(&app.trace.key, &app.trace_notes());
+    app.replace_trace(trace);
+    let divergence = app.reference.as_ref().and_then(|previous| app.trace.divergence(previous));
+    let notes = load_trace_notes(&app.trace.key);
+    app.prefixes = notes.prefixes;
+    app.restore_marks(notes.marked_state, notes.tags);
+    app.status = Some(match divergence {
+        Some(idx) if idx < app.trace.states.len() => {
+            goto_state(app, idx);
+            format!("{}, first difference from the previous trace at state {}, B shows the changes instead", loaded, idx + 1)
+        }
+        Some(_) => format!("{}, the previous trace goes on after its last state", loaded),
+        None => format!("{}, the same states as the previous trace", loaded),
+    });
+}
+
+/// List the traces queued from the watched directory, the ones not opened yet marked with `●` (`Enter` explores one)
+fn show_queue(app: &mut App) {
+    let Some(watch) = &app.watch else {
+        app.status = Some("No directory is watched, start with --watch-dir DIR".to_string());
+        return;
+    };
+    if watch.queue.is_empty() {
+        app.status = Some(format!("No new trace in {} yet", watch.dir.display()));
+        return;
+    }
+    let names: Vec<String> = watch.queue.iter().map(|queued| queued.path.file_name().unwrap_or_default().to_string_lossy().to_string()).collect();
+    let name_width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+    let items = watch.queue
+        .iter()
+        .zip(&names)
+        .map(|(queued, name)| {
+            let marker = if queued.reviewed { ' ' } else { '●' };
+            let action = queued.last_action.as_ref().map(|action| format!(", last action: {}", action)).unwrap_or_default();
+            PanelItem {
+                label: format!(" {} {:<width$}  {} states{}", marker, name, queued.states, action, width = name_width),
+                state: app.current_state,
+                path: None,
+                file: Some(queued.path.clone()),
+            }
+        })
+        .collect();
+    let mut panel = ListPanel::new(format!("Traces in {} (Enter explores one)", watch.dir.display()), items);
+    panel.action = PanelAction::OpenQueued;
+    // Start on the oldest trace not reviewed yet
+    panel.selected = watch.queue.iter().position(|queued| !queued.reviewed).unwrap_or(watch.queue.len() - 1);
+    app.panel = Some(panel);
+}
+
+/// `:verify INVARIANT [ARGS]`: check an invariant of the spec the trace comes from, with more arguments for the
+/// command, and explore the counterexample if there is one
+fn verify_command(app: &mut App, args: &str) {
+    let (invariant, args) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    if invariant.is_empty() {
+        app.status = Some("Usage: :verify INVARIANT [ARGS]".to_string());
+        return;
+    }
+    let Some(spec) = trace_spec(app) else {
+        return;
+    };
+    if let Some((job, _)) = &app.job {
+        app.status = Some(format!("Wait for {} to finish", job.command_line()));
+        return;
+    }
+    app.status = Some(format!("Checking {} in {}...", invariant, spec.display()));
+    let job = start_verify(&app.verify_command, &spec, invariant, args.trim());
+    app.job = Some((job, JobPurpose::Verify(spec, invariant.to_string())));
+}
+
+/// Evaluate a Quint expression in the focused state with the REPL and show the result in a popup
+fn eval_expression(app: &mut App, expr: &str) {
+    let idx = focused_state(app);
+    let Some(state) = app.trace.states.get(idx) else {
+        app.status = Some(format!("No state {} to evaluate {} in", idx + 1, expr));
+        return;
+    };
+    match eval_in_repl(&app.repl_command, state, &app.trace.vars, expr) {
+        Ok(lines) => {
+            app.status = None;
+            let items = lines.into_iter().map(|line| PanelItem { label: format!(" {}", line), state: idx, path: None, file: None }).collect();
+            app.panel = Some(ListPanel::new(format!("{} in state {}", expr, idx + 1), items));
+        }
+        Err(e) => app.status = Some(format!("{:#}", e)),
+    }
+}
+
+/// `:boundary EXPR`: compare the last state where a predicate (like an invariant) holds with the first one where it
+/// no longer does, side by side
+fn find_boundary(app: &mut App, text: &str) {
+    let Some(expr) = parse_predicate(app, text) else {
+        return;
+    };
+    let Some(idx) = app.trace.states.iter().position(|state| !expr.holds(state)) else {
+        app.status = Some(format!("{} holds in every state", text));
+        return;
+    };
+
+    goto_state(app, idx);
+    if idx == 0 {
+        app.status = Some(format!("{} does not hold in the first state", text));
+    } else {
+        app.enter_diff_mode();
+        app.status = Some(format!("{} holds up to state {} and breaks in state {}", text, idx, idx + 1));
+    }
+    if let Some(path) = predicate_target(&expr, &app.trace.states[idx]) {
+        app.expansion.reveal(&path);
+        app.cursor_target = Some(path);
+    }
+}
+
+/// `:break EXPR`: mark the states where the predicate holds, to jump between them with `]m` and `[m`;
+/// without a predicate, clear the marks
+fn set_breakpoint(app: &mut App, text: &str) {
+    if text.is_empty() {
+        app.breakpoint = None;
+        app.status = Some("Breakpoint cleared".to_string());
+        return;
+    }
+    let Some(expr) = parse_predicate(app, text) else {
+        return;
+    };
+    let states: BTreeSet<usize> = app.trace.states.iter().enumerate().filter(|(_, state)| expr.holds(state)).map(|(idx, _)| idx).collect();
+    app.status = Some(match states.len() {
+        0 => format!("No state satisfies {}", text),
+        1 => format!("1 state satisfies {}, ]m and [m jump to it", text),
+        count => format!("{} states satisfy {}, ]m and [m jump between them", count, text),
+    });
+    app.breakpoint = Some((text.to_string(), states));
+}
+
+/// Go to the next (or previous) state where the `:break` predicate holds
+fn jump_to_breakpoint(app: &mut App, forward: bool) {
+    let Some((text, states)) = &app.breakpoint else {
+        app.status = Some("Set a breakpoint with :break EXPR first".to_string());
+        return;
+    };
+    let text = text.clone();
+    let next = if forward {
+        states.range(app.current_state + 1..).next()
+    } else {
+        states.range(..app.current_state).next_back()
+    };
+    match next.copied() {
+        Some(idx) => {
+            goto_state(app, idx);
+            app.status = Some(format!("State {} satisfies {}", idx + 1, text));
+        }
+        None => {
+            let direction = if forward { "after" } else { "before" };
+            app.status = Some(format!("No state satisfies {} {} state {}", text, direction, app.current_state + 1));
+        }
+    }
+}
+
+/// `:findall EXPR`: list all states where the predicate holds
+fn find_all_states(app: &mut App, text: &str) {
+    let Some(expr) = parse_predicate(app, text) else {
+        return;
+    };
+    let state_width = app.trace.states.len().to_string().len();
+    let items: Vec<PanelItem> = app.trace.states
+        .iter()
+        .enumerate()
+        .filter(|(_, state)| expr.holds(state))
+        .map(|(idx, state)| {
+            let action = state.action_taken().map(|action| format!(" ({})", action)).unwrap_or_default();
+            let values: Vec<String> = expr.paths()
+                .into_iter()
+                .filter_map(|query| {
+                    let path = query.resolve(state).ok()?;
+                    let value = value_at(state, &path)?;
+                    Some(format!("{} = {}", display_path(state, &path), format_value_preview(value, 40)))
+                })
+                .collect();
+            let same = match app.same_states[idx] {
+                first if first != idx => format!(" (= state {})", first + 1),
+                _ => String::new(),
+            };
+            PanelItem {
+                label: format!(" {:>width$}{}{}  {}", idx + 1, same, action, values.join(", "), width = state_width),
+                state: idx,
+                path: predicate_target(&expr, state),
+                file: None,
+            }
+        })
+        .collect();
+
+    if items.is_empty() {
+        app.status = Some(format!("No state satisfies {}", text));
+        return;
+    }
+    app.panel = Some(ListPanel::new(format!("States satisfying {}", text), items));
+}
+
+/// Maximum number of results listed by a search across all states
+const MAX_TRACE_SEARCH_RESULTS: usize = 5000;
+
+/// Search all states and list the results in a panel
+fn open_trace_search(app: &mut App, query: &str) {
+    if query.is_empty() {
+        return;
+    }
+    let matches = find_matches_in_trace(&app.trace, query);
+    if matches.is_empty() {
+        app.status = Some(format!("No matches for \"{}\" in any state", query));
+        return;
+    }
+
+    let items = location_items(&app.trace, &matches, true);
+    if matches.len() > items.len() {
+        app.status = Some(format!("Showing the first {} of {} matches", items.len(), matches.len()));
+    }
+    app.panel = Some(ListPanel::new(format!("\"{}\" in all states", query), items));
+}
+
+/// Panel items for (state, path) locations, optionally showing the value found there
+fn location_items(trace: &Trace, locations: &[(usize, NodePath)], with_values: bool) -> Vec<PanelItem> {
+    let state_width = trace.states.len().to_string().len();
+    locations
+        .iter()
+        .take(MAX_TRACE_SEARCH_RESULTS)
+        .map(|(idx, path)| {
+            let state = &trace.states[*idx];
+            let preview = value_at(state, path)
+                .filter(|_| with_values)
+                .map(|value| format!(" = {}", format_value_preview(value, 60)))
+                .unwrap_or_default();
+            PanelItem {
+                label: format!(" {:>width$}  {}{}", idx + 1, display_path(state, path), preview, width = state_width),
+                state: *idx,
+                path: Some(path.clone()),
+                file: None,
+            }
+        })
+        .collect()
+}
+
+/// List every place in the trace holding a value equal to the one under the cursor
+fn find_value_occurrences(app: &mut App, ctx: &EventContext) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let Some(value) = app.trace.states.get(app.current_state).and_then(|state| value_at(state, &line.path)) else {
+        return;
+    };
+
+    let occurrences = find_occurrences(&app.trace, value);
+    let items = location_items(&app.trace, &occurrences, false);
+    let title = format!("Occurrences of {}", format_value_preview(value, 40));
+    if occurrences.len() > items.len() {
+        app.status = Some(format!("Showing the first {} of {} occurrences", items.len(), occurrences.len()));
+    }
+
+    // Start on the occurrence under the cursor
+    let current = occurrences.iter().position(|(idx, path)| *idx == app.current_state && path == &line.path);
+    let mut panel = ListPanel::new(title, items);
+    panel.selected = current.filter(|&i| i < panel.items.len()).unwrap_or(0);
+    app.panel = Some(panel);
+}
+
+/// List the value at a path (in the `:get` syntax) in every state, marking the states where it changed,
+/// to follow how it evolved without stepping through the trace
+fn show_history(app: &mut App, text: &str) {
+    if text.is_empty() {
+        app.status = Some("Expected a path, e.g. :history system[\"n1\"].round".to_string());
+        return;
+    }
+    let query = match parse_path(text) {
+        Ok(query) => query,
+        Err(err) => {
+            app.status = Some(err.to_string());
+            return;
+        }
+    };
+    let history = path_history(&app.trace, &query);
+    if history.iter().all(|(path, _)| path.is_none()) {
+        app.status = Some(format!("{} is in no state", text));
+        return;
+    }
+
+    let state_width = app.trace.states.len().to_string().len();
+    let changes = history.iter().filter(|(_, kind)| *kind != DiffKind::Unchanged).count();
+    let items = history
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (path, kind))| {
+            let marker = match kind {
+                DiffKind::Unchanged => ' ',
+                DiffKind::Added => '+',
+                DiffKind::Removed => '-',
+                DiffKind::Modified => '~',
+            };
+            let value = path.as_ref()
+                .and_then(|path| value_at(&app.trace.states[idx], path))
+                .map(|value| format_value_preview(value, 100))
+                .unwrap_or_else(|| "(none)".to_string());
+            PanelItem { label: format!(" {:>width$} {} {}", idx + 1, marker, value, width = state_width), state: idx, path, file: None }
+        })
+        .collect();
+    let mut panel = ListPanel::new(format!("History of {}: {} change(s)", text, changes), items);
+    panel.selected = focused_state(app);
+    app.panel = Some(panel);
+}
+
+/// History of the node under the cursor, see `show_history`
+fn show_history_at_cursor(app: &mut App, ctx: &EventContext) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let Some(state) = app.trace.states.get(focused_state(app)) else {
+        return;
+    };
+    let text = display_path(state, &line.path);
+    show_history(app, &text);
+}
+
+/// Toggle showing the byte list under the cursor as a hex dump
+fn toggle_hex_dump(app: &mut App, ctx: &EventContext) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let path = line.path.clone();
+    let is_bytes = app.trace.states.get(focused_state(app))
+        .and_then(|state| value_at(state, &path))
+        .and_then(as_bytes)
+        .is_some();
+    if !is_bytes {
+        app.status = Some("Hex dump needs a list of integers from 0 to 255".to_string());
+        return;
+    }
+
+    app.change_expansion(|expansion| {
+        expansion.toggle_hex_dump(&path);
+        // Show the dump right away
+        if expansion.is_hex_dump(&path) && !expansion.is_expanded(&path) {
+            expansion.toggle(&path);
+        }
+    });
+}
+
+/// Show the ITF JSON of the node under the cursor in a popup, as written in the trace file
+fn show_raw_json(app: &mut App, ctx: &EventContext) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let state_idx = focused_state(app);
+    let Some(state) = app.trace.states.get(state_idx) else {
+        return;
+    };
+    let Some(value) = value_at(state, &line.path) else {
+        return;
+    };
+
+    // Nodes not read from the file, like #choices, are shown as they would be written, and so is every node
+    // when the file can't be read again
+    let source = app.trace.state_source(state).ok();
+    let json = match source.as_deref().and_then(|source| source_at(source, state, &line.path)) {
+        Some(source) => indent_json(source),
+        None => match serde_json::to_string_pretty(value) {
+            Ok(json) => json,
+            Err(e) => {
+                app.status = Some(format!("Cannot show ITF JSON: {}", e));
+                return;
+            }
+        },
+    };
+    // Every line leads back to the node, so that Enter closes the popup in place
+    let items = json
+        .lines()
+        .map(|text| PanelItem { label: text.to_string(), state: state_idx, path: Some(line.path.clone()), file: None })
+        .collect();
+    let title = format!("ITF JSON of {}", display_path(state, &line.path));
+    app.panel = Some(ListPanel::new(title, items));
+}
+
+/// Copy the node under the cursor to the clipboard, fully expanded as in the tree or, if `raw`, as ITF JSON
+fn copy_value(app: &mut App, ctx: &EventContext, raw: bool) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let Some(state) = app.trace.states.get(focused_state(app)) else {
+        return;
+    };
+    let Some(value) = value_at(state, &line.path) else {
+        app.status = Some("Nothing to copy here".to_string());
+        return;
+    };
+
+    let (text, what) = if raw {
+        match serde_json::to_string_pretty(value) {
+            Ok(json) => (json, "ITF JSON of "),
+            Err(e) => {
+                app.status = Some(format!("Cannot copy ITF JSON: {}", e));
+                return;
+            }
+        }
+    } else {
+        let mut expansion = ExpansionState::new();
+        expansion.expand_subtree(value, &line.path);
+        let lines = render_value("", value, line.path.clone(), &expansion, &DiffResult::default(), &app.formatters, 0, ctx.terminal_width, usize::MAX);
+        let text: Vec<String> = lines.iter().map(TreeLine::plain_text).collect();
+        (text.join("\n"), "")
+    };
+    let path = display_path(state, &line.path);
+    app.status = Some(match crate::clipboard::copy(&text) {
+        Ok(()) => format!("Copied {}{} to the clipboard", what, path),
+        Err(e) => format!("Cannot copy {}: {}", path, e),
+    });
+}
+
+/// List the paths changed since the previous state (or from the left to the right diff panel) in a popup
+fn show_changed_paths(app: &mut App) {
+    if app.against_reference && app.view_mode == ViewMode::Single {
+        show_reference_diff(app);
+        return;
+    }
+    let (prev_idx, idx, diff) = match app.view_mode {
+        ViewMode::Single => (app.current_state.saturating_sub(1), app.current_state, compute_diff_for_state(app)),
+        ViewMode::Diff { left, right, .. } => (left, right, app.diff_between(left, right)),
+    };
+    let (Some(prev), Some(state)) = (app.trace.states.get(prev_idx), app.trace.states.get(idx)) else {
+        app.status = Some(format!("No state {} to list the changes of", idx + 1));
+        return;
+    };
+    let items = changed_path_items(prev, state, idx, &diff);
+    if items.is_empty() {
+        app.status = Some(format!("No changes in state {}", idx + 1));
+        return;
+    }
+    let title = format!("Changes from state {} to {}", prev_idx + 1, idx + 1);
+    app.panel = Some(ListPanel::new(title, items));
+}
+
+/// List the differences between the focused state and the same state of the `--reference` trace
+fn show_reference_diff(app: &mut App) {
+    let Some(reference) = &app.reference else {
+        app.status = Some("Open the explorer with --reference FILE, or :run again, to compare with another trace".to_string());
+        return;
+    };
+    let idx = focused_state(app);
+    let Some(other) = reference.states.get(idx) else {
+        app.status = Some(format!("The reference has no state {}, it ends at state {}", idx + 1, reference.states.len()));
+        return;
+    };
+    let Some(state) = app.trace.states.get(idx) else {
+        app.status = Some(format!("No state {} to compare with the reference", idx + 1));
+        return;
+    };
+    let diff = compute_diff(&other.values, &state.values);
+    let items = changed_path_items(other, state, idx, &diff);
+    if items.is_empty() {
+        app.status = Some(format!("State {} is the same as in the reference", idx + 1));
+        return;
+    }
+    app.panel = Some(ListPanel::new(format!("Differences of state {} from the reference", idx + 1), items));
+}
+
+/// Panel items for the changes of a diff, in path order: added, removed and modified nodes
+/// (but not the collections and records only containing them) and removed map entries and set elements
+fn changed_path_items(prev: &State, state: &State, idx: usize, diff: &DiffResult) -> Vec<PanelItem> {
+    let mut changes: Vec<(&NodePath, String)> = Vec::new();
+
+    let own_changes = diff.own_changes();
+    for (path, kind) in &diff.changes {
+        let value = || value_at(state, path).map(|value| format!(" = {}", format_value_preview(value, 40))).unwrap_or_default();
+        match kind {
+            DiffKind::Added => changes.push((path, format!("+ {}{}", display_path(state, path), value()))),
+            DiffKind::Removed => changes.push((path, format!("- {}", display_path(prev, path)))),
+            DiffKind::Modified if own_changes.contains(path) => changes.push((path, format!("~ {}{}", display_path(state, path), value()))),
+            _ => {}
+        }
+    }
+    for (path, removed) in &diff.removed {
+        for item in removed {
+            let label = match item {
+                RemovedItem::Entry(key, _) => format!("- {}[{}]", display_path(state, path), format_value_preview(key, 40)),
+                RemovedItem::Element(element) => format!("- {} element {}", display_path(state, path), format_value_preview(element, 40)),
+            };
+            changes.push((path, label));
+        }
+    }
+
+    changes.sort_by(|(a, a_label), (b, b_label)| compare_paths(a, b).then_with(|| a_label.cmp(b_label)));
+    changes
+        .into_iter()
+        .map(|(path, label)| PanelItem { label: format!(" {}", label), state: idx, path: Some(path.clone()), file: None })
+        .collect()
+}
+
+/// Order of paths in the tree: segment by segment, item indices by value
+fn compare_paths(a: &NodePath, b: &NodePath) -> std::cmp::Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ordering = match (x.parse::<usize>(), y.parse::<usize>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Handle keys while a list panel is open
+fn handle_panel_key(app: &mut App, key: KeyCode, ctx: &EventContext) {
+    let Some(panel) = app.panel.as_mut() else {
+        return;
+    };
+    let page = visible_rows(Rect::new(0, 0, ctx.terminal_width as u16, ctx.terminal_height as u16)).max(1) as isize;
+
+    match key {
+        KeyCode::Esc | KeyCode::Char('q') => app.panel = None,
+        KeyCode::Up => panel.move_selection(-1),
+        KeyCode::Down => panel.move_selection(1),
+        KeyCode::PageUp => panel.move_selection(-page),
+        KeyCode::PageDown => panel.move_selection(page),
+        KeyCode::Home => panel.select_first(),
+        KeyCode::End => panel.select_last(),
+        KeyCode::Enter | KeyCode::Char(' ') if panel.action == PanelAction::ToggleVar => {
+            let selected = panel.selected;
+            if let Some(name) = panel.items.get(selected).and_then(|item| item.path.as_ref()).and_then(|path| path.first()).cloned() {
+                toggle_var(app, &name);
+                if let Some(item) = app.panel.as_mut().and_then(|panel| panel.items.get_mut(selected)) {
+                    item.label = var_menu_label(&app.hidden_vars, &name);
+                }
+            }
+        }
+        KeyCode::Enter if panel.action == PanelAction::OpenQueued => {
+            if let Some(item) = app.panel.take().and_then(|panel| panel.items.into_iter().nth(panel.selected)) {
+                app.pending_open = item.file;
+            }
+        }
+        KeyCode::Enter if panel.action == PanelAction::Compare => {
+            if let Some(item) = app.panel.take().and_then(|panel| panel.items.into_iter().nth(panel.selected)) {
+                goto_state(app, item.state);
+                app.enter_diff_mode();
+            }
+        }
+        KeyCode::Enter => {
+            if let Some(item) = app.panel.take().and_then(|panel| panel.items.into_iter().nth(panel.selected)) {
+                jump_to_item(app, item);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Show a hidden variable, or hide a shown one
+fn toggle_var(app: &mut App, name: &str) {
+    if !app.hidden_vars.remove(name) {
+        app.hidden_vars.insert(name.to_string());
+    }
+}
+
+/// Hide the variable of the node under the cursor
+fn hide_var_at_cursor(app: &mut App, ctx: &EventContext) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    // On a module group, all of its variables
+    if let Some(module) = module_of(&line.path) {
+        let Some(state) = app.trace.states.get(focused_state(app)) else {
+            return;
+        };
+        let names: Vec<String> = state.values.keys().filter(|name| in_module(name, module)).map(|name| name.to_string()).collect();
+        app.status = Some(format!("Hid the {} variables of {}, press v to show them again", names.len(), module));
+        app.hidden_vars.extend(names);
+        return;
+    }
+    let Some(name) = line.path.first().cloned() else {
+        return;
+    };
+    app.status = Some(format!("Hid {}, press v to show it again", name));
+    app.hidden_vars.insert(name);
+}
+
+/// Line of a variable in the `v` menu, checked when it is shown
+fn var_menu_label(hidden_vars: &BTreeSet<String>, name: &str) -> String {
+    let check = if hidden_vars.contains(name) { ' ' } else { 'x' };
+    format!(" [{}] {}", check, name)
+}
+
+/// List the variables of the focused state, to show or hide them with `Enter`
+fn show_var_menu(app: &mut App) {
+    let Some(state) = app.trace.states.get(focused_state(app)) else {
+        return;
+    };
+    let mut names: Vec<String> = state.var_names(&app.trace.vars, app.var_order).into_iter().map(str::to_string).collect();
+    names.extend(state_sections(state).into_iter().map(|(name, _)| name.to_string()));
+    // Hidden variables missing from this state can be shown again too
+    names.extend(app.hidden_vars.iter().filter(|name| !names.contains(name)).cloned().collect::<Vec<_>>());
+    let items = names
+        .into_iter()
+        .map(|name| PanelItem { label: var_menu_label(&app.hidden_vars, &name), state: app.current_state, path: Some(vec![name]), file: None })
+        .collect();
+    let mut panel = ListPanel::new("Variables (Enter shows or hides)", items);
+    panel.action = PanelAction::ToggleVar;
+    app.panel = Some(panel);
+}
+
+/// Select the state of a panel item and place the cursor on its node
+fn jump_to_item(app: &mut App, item: PanelItem) {
+    goto_state(app, item.state);
+    if let Some(path) = item.path {
+        app.expansion.reveal(&path);
+        app.cursor_target = Some(path);
+    }
+}
+
+/// Handle the second key of a two-key sequence
+fn handle_key_sequence(app: &mut App, prefix: char, key: KeyCode, ctx: &EventContext) {
+    match (prefix, key) {
+        ('g', KeyCode::Char('g')) => app.cursor = 0,
+        // With the vim keys `g` waits for a second key, which starts the state number
+        ('g', KeyCode::Char(c)) if c.is_ascii_digit() && app.view_mode == ViewMode::Single => {
+            app.prompt = Some(Prompt { kind: PromptKind::State, text: c.to_string() });
+        }
+        (']' | '[', KeyCode::Char('c')) if app.view_mode == ViewMode::Single => jump_to_path_change(app, ctx, prefix == ']'),
+        (']' | '[', KeyCode::Char('m')) => jump_to_breakpoint(app, prefix == ']'),
+        _ => {}
+    }
+}
+
+/// Change the mouse wheel step (`{`/`}`) or the cursor padding (`-`/`+`) by one line
+fn adjust_scrolling(app: &mut App, key: char, ctx: &EventContext) {
+    match key {
+        '{' => app.scroll_step = app.scroll_step.saturating_sub(1).max(1),
+        '}' => app.scroll_step += 1,
+        '-' => app.scroll_padding = app.scroll_padding.saturating_sub(1),
+        _ => app.scroll_padding = (app.scroll_padding + 1).min(ctx.viewport_height.saturating_sub(1) / 2),
+    }
+    app.status = Some(format!("Scroll step: {} lines, cursor padding: {} lines", app.scroll_step, app.scroll_padding));
+}
+
+/// Move the cursor and the view half a page down or up, like `Ctrl-d`/`Ctrl-u` in vim
+fn scroll_half_page(app: &mut App, ctx: &EventContext, down: bool) {
+    let step = (ctx.viewport_height / 2).max(1);
+    if down {
+        let max_scroll = ctx.line_count.saturating_sub(ctx.viewport_height);
+        app.scroll_offset = (app.scroll_offset + step).min(max_scroll);
+        app.cursor = (app.cursor + step).min(ctx.line_count.saturating_sub(1));
+    } else {
+        app.scroll_offset = app.scroll_offset.saturating_sub(step);
+        app.cursor = app.cursor.saturating_sub(step);
+    }
+}
+
+/// Jump to the next/previous state where the value under the cursor changed
+fn jump_to_path_change(app: &mut App, ctx: &EventContext, forward: bool) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let path = line.path.clone();
+
+    match find_path_change(&app.trace, app.current_state, &path, forward) {
+        Some(idx) => {
+            goto_state(app, idx);
+            app.expansion.reveal(&path);
+            app.cursor_target = Some(path);
+        }
+        None => {
+            let direction = if forward { "after" } else { "before" };
+            app.status = Some(format!("No change of {} {} state {}", path.join("."), direction, app.current_state + 1));
+        }
+    }
+}
+
+/// Whether the value at `path` in state `i` differs from the one in state `i - 1`
+fn path_changed_at(trace: &Trace, i: usize, path: &[String]) -> bool {
+    i > 0 && value_at(&trace.states[i], path) != value_at(&trace.states[i - 1], path)
+}
+
+/// Find the closest state after (or before) `from` where the value at `path` differs from its predecessor
+fn find_path_change(trace: &Trace, from: usize, path: &[String], forward: bool) -> Option<usize> {
+    let changed_at = |i: usize| path_changed_at(trace, i, path);
+    if forward {
+        (from + 1..trace.states.len()).find(|&i| changed_at(i))
+    } else {
+        (1..from).rev().find(|&i| changed_at(i))
+    }
+}
+
+/// Handle keyboard events in single view mode
+fn handle_single_mode_key(app: &mut App, key: KeyEvent, ctx: &EventContext) {
+    let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+    match key.code {
+        KeyCode::Esc if app.search.is_some() => app.clear_search(),
+        KeyCode::Char('q') | KeyCode::Esc => app.should_quit = true,
+        KeyCode::Char('d') => app.enter_diff_mode(),
+        KeyCode::Left if shift || app.fold_duplicates => skip_stuttering(app, false),
+        KeyCode::Right if shift || app.fold_duplicates => skip_stuttering(app, true),
+        KeyCode::Left => handle_prev_state(app),
+        KeyCode::Right => handle_next_state(app),
+        KeyCode::Char('<') => handle_first_state(app),
+        KeyCode::Char('>') => handle_last_state(app),
+        KeyCode::Char('=') => {
+            app.fold_duplicates = !app.fold_duplicates;
+            app.status = Some(if app.fold_duplicates { "←/→ step over repeated states" } else { "←/→ step through every state" }.to_string());
+        }
+        KeyCode::Up if app.cursor > 0 => {
+            app.cursor -= 1;
+        }
+        KeyCode::Down if app.cursor + 1 < ctx.line_count => {
+            app.cursor += 1;
+        }
+        KeyCode::PageUp => {
+            app.cursor = app.cursor.saturating_sub(ctx.viewport_height.saturating_sub(2));
+        }
+        KeyCode::PageDown => {
+            app.cursor = (app.cursor + ctx.viewport_height.saturating_sub(2)).min(ctx.line_count.saturating_sub(1));
+        }
+        KeyCode::Home => {
+            app.cursor = 0;
+        }
+        KeyCode::End if ctx.line_count > 0 => {
+            app.cursor = ctx.line_count - 1;
+        }
+        KeyCode::Enter => {
+            if let Some(line) = ctx.tree_lines.get(app.cursor) {
+                if line.expandable {
+                    app.change_expansion(|expansion| expansion.toggle(&line.path));
+                }
+            }
+        }
+        KeyCode::Char('c') => {
+            app.change_expansion(ExpansionState::clear);
         }
         KeyCode::Char('e') => {
-            app.expansion.expand_all(ctx.all_expandable_paths);
+            app.change_expansion(|expansion| expansion.expand_all(ctx.all_expandable_paths));
+        }
+        KeyCode::Char('o') => {
+            app.var_order = app.var_order.toggle();
+        }
+        KeyCode::Char('M') => app.group_modules = !app.group_modules,
+        KeyCode::Char('p') => app.prefixes = app.prefixes.next(),
+        KeyCode::Char('a') => {
+            app.auto_expand = !app.auto_expand;
+            app.saved_auto_expand = Some(app.auto_expand);
+        }
+        KeyCode::Char('t') => {
+            app.type_display = app.type_display.next();
+        }
+        KeyCode::Char('|') => {
+            app.guides = !app.guides;
+        }
+        KeyCode::Char('w') => {
+            app.wrap = !app.wrap;
+        }
+        KeyCode::Char(c @ ('{' | '}' | '-' | '+')) => adjust_scrolling(app, c, ctx),
+        KeyCode::Char(c @ (']' | '[')) => {
+            app.pending_key = Some(c);
+        }
+        KeyCode::Char('/') => {
+            app.prompt = Some(Prompt { kind: PromptKind::Search, text: String::new() });
+        }
+        KeyCode::Char('?') => {
+            app.prompt = Some(Prompt { kind: PromptKind::SearchAll, text: String::new() });
+        }
+        KeyCode::Char('g') => {
+            app.prompt = Some(Prompt { kind: PromptKind::State, text: String::new() });
+        }
+        KeyCode::Char(':') => {
+            app.prompt = Some(Prompt { kind: PromptKind::Command, text: String::new() });
         }
+        KeyCode::Char('P') => {
+            app.prompt = Some(Prompt { kind: PromptKind::Command, text: "snapshot ".to_string() });
+        }
+        KeyCode::Char('*') => expand_subtree_at_cursor(app, ctx),
+        KeyCode::Char('x') => collapse_subtree_at_cursor(app, ctx),
+        KeyCode::Char('X') => focus_at_cursor(app, ctx),
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => app.redo_expansion(),
+        KeyCode::Char('u') => app.undo_expansion(),
+        KeyCode::Char('r') => show_raw_json(app, ctx),
+        KeyCode::Char('y') => copy_value(app, ctx, false),
+        KeyCode::Char('Y') => copy_value(app, ctx, true),
+        KeyCode::Char('b') => toggle_hex_dump(app, ctx),
+        KeyCode::Char('C') => show_changed_paths(app),
+        KeyCode::Char('R') => show_reference_diff(app),
+        KeyCode::Char('K') => toggle_constants(app),
+        KeyCode::Char('E') => open_definition(app, ctx, ""),
+        KeyCode::Char('B') => toggle_against_reference(app),
+        KeyCode::Char('O') => show_counters(app),
+        KeyCode::Char('W') => show_queue(app),
+        KeyCode::Char('m') => {
+            app.marked_state = Some(app.current_state);
+            app.status = Some(format!("Marked state {}, press D on another state to compare", app.current_state + 1));
+        }
+        KeyCode::Char('D') => app.compare_with_marked(),
+        KeyCode::Char('T') => show_tags(app),
+        KeyCode::Char('H') => show_history_at_cursor(app, ctx),
+        KeyCode::Char('I') => show_stats(app),
+        KeyCode::Char('Z') => show_heatmap(app, ctx),
+        KeyCode::Char('A') => show_transitions(app),
+        KeyCode::Char('v') => show_var_menu(app),
+        KeyCode::Char('V') => hide_var_at_cursor(app, ctx),
+        KeyCode::Char('U') => {
+            app.hide_unchanged = app.hide_unchanged.next();
+            app.status = Some(match app.hide_unchanged {
+                HideUnchanged::Off => "Showing unchanged variables".to_string(),
+                HideUnchanged::Vars => "Hiding unchanged variables".to_string(),
+                HideUnchanged::Nodes => "Hiding unchanged variables and nodes".to_string(),
+            });
+        }
+        KeyCode::Char('f') => find_value_occurrences(app, ctx),
+        KeyCode::Char('F') => {
+            if app.search.is_some() {
+                app.filter = !app.filter;
+            } else {
+                app.status = Some("Search with / first to filter the tree".to_string());
+            }
+        }
+        KeyCode::Char('n') => jump_to_match(app, ctx, true),
+        KeyCode::Char('N') => jump_to_match(app, ctx, false),
         _ => {}
     }
 }
 
+/// Handle keyboard events in diff view mode
+fn handle_diff_mode_key(app: &mut App, key: KeyEvent, ctx: &EventContext) {
+    let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => app.should_quit = true,
+        KeyCode::Char('d') => app.exit_diff_mode(),
+        KeyCode::Tab => app.toggle_diff_focus(),
+        KeyCode::Char('S') => app.toggle_scroll_sync(),
+        KeyCode::Up if shift => app.scroll_other_panel(-1, ctx.line_count),
+        KeyCode::Down if shift => app.scroll_other_panel(1, ctx.line_count),
+        KeyCode::Char('i') => {
+            app.unified = !app.unified;
+            app.cursor = 0;
+            app.scroll_offset = 0;
+        }
+        KeyCode::Char('s') => app.stacked = !app.stacked,
+        KeyCode::Char('L') => app.lock_step = !app.lock_step,
+        KeyCode::Char('v') => show_var_menu(app),
+        KeyCode::Char(':') => {
+            app.prompt = Some(Prompt { kind: PromptKind::Command, text: String::new() });
+        }
+        KeyCode::Char('P') => {
+            app.prompt = Some(Prompt { kind: PromptKind::Command, text: "snapshot ".to_string() });
+        }
+        KeyCode::Left => handle_prev_state(app),
+        KeyCode::Right => handle_next_state(app),
+        KeyCode::Char('<') => handle_first_state(app),
+        KeyCode::Char('>') => handle_last_state(app),
+        KeyCode::Up if app.cursor > 0 => {
+            app.cursor -= 1;
+        }
+        KeyCode::Down if app.cursor + 1 < ctx.line_count => {
+            app.cursor += 1;
+        }
+        KeyCode::PageUp => {
+            app.cursor = app.cursor.saturating_sub(ctx.viewport_height.saturating_sub(4));
+        }
+        KeyCode::PageDown => {
+            app.cursor = (app.cursor + ctx.viewport_height.saturating_sub(4)).min(ctx.line_count.saturating_sub(1));
+        }
+        KeyCode::Home => {
+            app.cursor = 0;
+        }
+        KeyCode::End if ctx.line_count > 0 => {
+            app.cursor = ctx.line_count - 1;
+        }
+        KeyCode::Enter => {
+            if let Some(line) = ctx.tree_lines.get(app.cursor) {
+                if line.expandable {
+                    app.change_expansion(|expansion| expansion.toggle(&line.path));
+                }
+            }
+        }
+        KeyCode::Char('c') => {
+            app.change_expansion(ExpansionState::clear);
+        }
+        KeyCode::Char('e') => {
+            app.change_expansion(|expansion| expansion.expand_all(ctx.all_expandable_paths));
+        }
+        KeyCode::Char('o') => {
+            app.var_order = app.var_order.toggle();
+        }
+        KeyCode::Char('M') => app.group_modules = !app.group_modules,
+        KeyCode::Char('p') => app.prefixes = app.prefixes.next(),
+        KeyCode::Char('a') => {
+            app.auto_expand = !app.auto_expand;
+            app.saved_auto_expand = Some(app.auto_expand);
+        }
+        KeyCode::Char('t') => {
+            app.type_display = app.type_display.next();
+        }
+        KeyCode::Char('|') => {
+            app.guides = !app.guides;
+        }
+        KeyCode::Char('w') => {
+            app.wrap = !app.wrap;
+        }
+        KeyCode::Char(c @ ('{' | '}' | '-' | '+')) => adjust_scrolling(app, c, ctx),
+        KeyCode::Char('*') => expand_subtree_at_cursor(app, ctx),
+        KeyCode::Char('x') => collapse_subtree_at_cursor(app, ctx),
+        KeyCode::Char('X') => focus_at_cursor(app, ctx),
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => app.redo_expansion(),
+        KeyCode::Char('u') => app.undo_expansion(),
+        KeyCode::Char('r') => show_raw_json(app, ctx),
+        KeyCode::Char('y') => copy_value(app, ctx, false),
+        KeyCode::Char('Y') => copy_value(app, ctx, true),
+        KeyCode::Char('b') => toggle_hex_dump(app, ctx),
+        KeyCode::Char('C') => show_changed_paths(app),
+        KeyCode::Char('R') => show_reference_diff(app),
+        KeyCode::Char('K') => toggle_constants(app),
+        KeyCode::Char('E') => open_definition(app, ctx, ""),
+        KeyCode::Char('O') => show_counters(app),
+        KeyCode::Char('W') => show_queue(app),
+        _ => {}
+    }
+}
+
+/// Index of the state shown in the focused view (the right or left panel in diff mode)
+fn focused_state(app: &App) -> usize {
+    match app.view_mode {
+        ViewMode::Single => app.current_state,
+        ViewMode::Diff { left, focus: DiffFocus::Left, .. } if !app.unified => left,
+        ViewMode::Diff { right, .. } => right,
+    }
+}
+
+/// Expand the node under the cursor and everything below it
+fn expand_subtree_at_cursor(app: &mut App, ctx: &EventContext) {
+    let Some(line) = ctx.tree_lines.get(app.cursor) else {
+        return;
+    };
+    let path = line.path.clone();
+    let state_idx = focused_state(app);
+    let Some(value) = app.trace.states.get(state_idx).and_then(|state| value_at(state, &path)) else {
+        return;
+    };
+    let before = app.expansion.clone();
+    app.expansion.expand_subtree(value, &path);
+
+    // Unchanged items below may still be folded into groups: expand those too, level by level
+    if app.view_mode == ViewMode::Single {
+        let diff = compute_diff_for_state(app);
+        for _ in 0..MAX_GROUP_EXPANSION_ROUNDS {
+            let lines = build_tree_lines(app, &diff, ctx.terminal_width, app.collapse_threshold);
+            let folded: Vec<NodePath> = lines.into_iter()
+                .filter(|l| l.expandable && l.path.starts_with(&path) && !app.expansion.is_expanded(&l.path))
+                .map(|l| l.path)
+                .collect();
+            if folded.is_empty() {
+                break;
+            }
+            let state = &app.trace.states[state_idx];
+            for group_path in folded {
+                if let Some(value) = value_at(state, &group_path) {
+                    app.expansion.expand_subtree(value, &group_path);
+                }
+            }
+        }
+    }
+    app.history.record(before, &app.expansion);
+}
+
+/// Collapse the node under the cursor and everything below it
+fn collapse_subtree_at_cursor(app: &mut App, ctx: &EventContext) {
+    if let Some(line) = ctx.tree_lines.get(app.cursor) {
+        let path = line.path.clone();
+        app.change_expansion(|expansion| expansion.collapse_subtree(&path));
+        app.cursor_target = Some(path);
+    }
+}
+
+/// Collapse everything but the node under the cursor and its ancestors
+fn focus_at_cursor(app: &mut App, ctx: &EventContext) {
+    if let Some(line) = ctx.tree_lines.get(app.cursor) {
+        let path = line.path.clone();
+        app.change_expansion(|expansion| expansion.focus(&path, ctx.all_expandable_paths));
+        app.cursor_target = Some(path);
+    }
+}
+
+/// Upper bound on the rounds of expanding nested groups in `expand_subtree_at_cursor`
+const MAX_GROUP_EXPANSION_ROUNDS: usize = 20;
+
 /// Handle mouse events
 fn handle_mouse_event(app: &mut App, mouse: crossterm::event::MouseEvent, ctx: &EventContext) {
+    if app.panel.is_some() {
+        return;
+    }
+
     match mouse.kind {
         MouseEventKind::Down(MouseButton::Left) => {
             let row = mouse.row as usize;
@@ -413,59 +2505,170 @@ fn handle_mouse_event(app: &mut App, mouse: crossterm::event::MouseEvent, ctx: &
                 } else if col >= layout.next_start && col < layout.next_end {
                     handle_next_state(app);
                 } else if col >= layout.expand_start && col < layout.expand_end {
-                    app.expansion.expand_all(ctx.all_expandable_paths);
+                    app.change_expansion(|expansion| expansion.expand_all(ctx.all_expandable_paths));
                 } else if col >= layout.collapse_start && col < layout.collapse_end {
-                    app.expansion.clear();
+                    app.change_expansion(ExpansionState::clear);
                 }
+            } else if app.view_mode == ViewMode::Single && row + 1 == ctx.terminal_height {
+                handle_timeline_click(app, col, ctx);
             } else if row >= 2 {
                 handle_content_click(app, row, col, ctx);
             }
         }
+        MouseEventKind::ScrollUp if is_over_unfocused_panel(app, mouse.row as usize, mouse.column as usize, ctx) => {
+            app.scroll_other_panel(-(app.scroll_step as isize), ctx.line_count);
+        }
+        MouseEventKind::ScrollDown if is_over_unfocused_panel(app, mouse.row as usize, mouse.column as usize, ctx) => {
+            app.scroll_other_panel(app.scroll_step as isize, ctx.line_count);
+        }
         MouseEventKind::ScrollUp => {
-            app.scroll_offset = app.scroll_offset.saturating_sub(3);
-            if app.view_mode == ViewMode::Single {
-                if app.cursor >= app.scroll_offset + ctx.viewport_height {
-                    app.cursor = (app.scroll_offset + ctx.viewport_height).saturating_sub(1);
-                }
+            app.scroll_offset = app.scroll_offset.saturating_sub(app.scroll_step);
+            if app.view_mode == ViewMode::Single && app.cursor >= app.scroll_offset + ctx.viewport_height {
+                app.cursor = (app.scroll_offset + ctx.viewport_height).saturating_sub(1);
             }
         }
         MouseEventKind::ScrollDown => {
             let max_scroll = ctx.line_count.saturating_sub(ctx.viewport_height);
-            app.scroll_offset = (app.scroll_offset + 3).min(max_scroll);
-            if app.view_mode == ViewMode::Single {
-                if app.cursor < app.scroll_offset {
-                    app.cursor = app.scroll_offset;
-                }
+            app.scroll_offset = (app.scroll_offset + app.scroll_step).min(max_scroll);
+            if app.view_mode == ViewMode::Single && app.cursor < app.scroll_offset {
+                app.cursor = app.scroll_offset;
             }
         }
         _ => {}
     }
 }
 
+/// Whether a position is over the diff panel without focus, while the panels scroll independently
+fn is_over_unfocused_panel(app: &App, row: usize, col: usize, ctx: &EventContext) -> bool {
+    match app.view_mode {
+        ViewMode::Diff { focus, .. } if !app.scroll_sync && !app.unified => {
+            diff_panel_at(app, row, col, ctx).0 != focus
+        }
+        _ => false,
+    }
+}
+
+/// Diff panel under a screen position, along with the content row of the panel at that position
+/// (`None` on the header and the panel borders)
+fn diff_panel_at(app: &App, row: usize, col: usize, ctx: &EventContext) -> (DiffFocus, Option<usize>) {
+    // Row 0 = header, Row 1 = status, Row 2 = panel border, Row 3+ = content
+    let top_row = |row: usize| row.checked_sub(3).filter(|&r| r < ctx.viewport_height);
+    if app.stacked {
+        // The lower panel starts below the upper one's bottom border, with its own top border
+        let lower_start = ctx.viewport_height + 5;
+        if row + 1 >= lower_start {
+            (DiffFocus::Right, row.checked_sub(lower_start))
+        } else {
+            (DiffFocus::Left, top_row(row))
+        }
+    } else if col < ctx.terminal_width / 2 {
+        (DiffFocus::Left, top_row(row))
+    } else {
+        (DiffFocus::Right, top_row(row))
+    }
+}
+
+/// Switch to a state in single view mode
+fn goto_state(app: &mut App, idx: usize) {
+    app.current_state = idx;
+    app.cursor = 0;
+    app.scroll_offset = 0;
+    if app.auto_expand {
+        auto_expand_changes(app);
+    }
+    // Keep highlighting the active search in the new state
+    if let Some(search) = app.search.take() {
+        app.set_search(&search.query);
+    }
+}
+
+/// Go to a state by its number as shown in the header, counting from 1
+fn goto_state_number(app: &mut App, text: &str) {
+    match text.trim().parse::<usize>() {
+        Ok(number @ 1..) if number <= app.trace.states.len() => goto_state(app, number - 1),
+        _ => app.status = Some(format!("No state {}, the trace has states 1 to {}", text.trim(), app.trace.states.len())),
+    }
+}
+
+/// Move to the closest state after (or before) the current one whose values differ from it,
+/// skipping over stuttering steps
+fn skip_stuttering(app: &mut App, forward: bool) {
+    let states = &app.trace.states;
+    let Some(current) = states.get(app.current_state) else {
+        app.status = Some(format!("No state {} to skip the stuttering of", app.current_state + 1));
+        return;
+    };
+    let differs = |i: &usize| !states[*i].same_values(current);
+    let target = if forward {
+        (app.current_state + 1..states.len()).find(differs)
+    } else {
+        (0..app.current_state).rev().find(differs)
+    };
+
+    match target {
+        Some(idx) => {
+            let skipped = idx.abs_diff(app.current_state) - 1;
+            goto_state(app, idx);
+            if skipped > 0 {
+                app.status = Some(format!("Skipped {} stuttering state(s)", skipped));
+            }
+        }
+        None => {
+            let direction = if forward { "after" } else { "before" };
+            app.status = Some(format!("No differing state {} state {}", direction, app.current_state + 1));
+        }
+    }
+}
+
+/// Move the current state (or the focused diff panel) to the given state index
+fn set_focused_state(app: &mut App, idx: usize) {
+    match app.view_mode {
+        ViewMode::Single => goto_state(app, idx),
+        ViewMode::Diff { left, right, focus, other_cursor } => {
+            app.view_mode = match focus {
+                DiffFocus::Left => ViewMode::Diff { left: idx, right, focus, other_cursor },
+                DiffFocus::Right => ViewMode::Diff { left, right: idx, focus, other_cursor },
+            };
+            app.scroll_offset = 0;
+        }
+    }
+}
+
+/// Jump to the first state of the trace
+fn handle_first_state(app: &mut App) {
+    set_focused_state(app, 0);
+}
+
+/// Jump to the last state of the trace
+fn handle_last_state(app: &mut App) {
+    set_focused_state(app, app.trace.states.len().saturating_sub(1));
+}
+
 /// Navigate to previous state (used by both keyboard and mouse)
 fn handle_prev_state(app: &mut App) {
     match app.view_mode {
         ViewMode::Single => {
             if app.current_state > 0 {
-                app.current_state -= 1;
-                app.cursor = 0;
+                goto_state(app, app.current_state - 1);
+            }
+        }
+        ViewMode::Diff { left, right, focus, other_cursor } if app.lock_step => {
+            if left > 0 && right > 0 {
+                app.view_mode = ViewMode::Diff { left: left - 1, right: right - 1, focus, other_cursor };
                 app.scroll_offset = 0;
-                if app.auto_expand {
-                    auto_expand_changes(app);
-                }
             }
         }
-        ViewMode::Diff { left, right, focus } => {
+        ViewMode::Diff { left, right, focus, other_cursor } => {
             match focus {
                 DiffFocus::Left => {
                     if left > 0 {
-                        app.view_mode = ViewMode::Diff { left: left - 1, right, focus };
+                        app.view_mode = ViewMode::Diff { left: left - 1, right, focus, other_cursor };
                         app.scroll_offset = 0;
                     }
                 }
                 DiffFocus::Right => {
                     if right > 0 {
-                        app.view_mode = ViewMode::Diff { left, right: right - 1, focus };
+                        app.view_mode = ViewMode::Diff { left, right: right - 1, focus, other_cursor };
                         app.scroll_offset = 0;
                     }
                 }
@@ -478,27 +2681,28 @@ fn handle_prev_state(app: &mut App) {
 fn handle_next_state(app: &mut App) {
     match app.view_mode {
         ViewMode::Single => {
-            if app.current_state + 1 < app.trace.states.len() {
-                app.current_state += 1;
-                app.cursor = 0;
+            if let Some(next) = app.trace.next_index(app.current_state) {
+                goto_state(app, next);
+            }
+        }
+        // Both states move forward without wrapping around a loop, which would change the gap between them
+        ViewMode::Diff { left, right, focus, other_cursor } if app.lock_step => {
+            if left.max(right) + 1 < app.trace.states.len() {
+                app.view_mode = ViewMode::Diff { left: left + 1, right: right + 1, focus, other_cursor };
                 app.scroll_offset = 0;
-                if app.auto_expand {
-                    auto_expand_changes(app);
-                }
             }
         }
-        ViewMode::Diff { left, right, focus } => {
-            let max_state = app.trace.states.len().saturating_sub(1);
+        ViewMode::Diff { left, right, focus, other_cursor } => {
             match focus {
                 DiffFocus::Left => {
-                    if left < max_state {
-                        app.view_mode = ViewMode::Diff { left: left + 1, right, focus };
+                    if let Some(next) = app.trace.next_index(left) {
+                        app.view_mode = ViewMode::Diff { left: next, right, focus, other_cursor };
                         app.scroll_offset = 0;
                     }
                 }
                 DiffFocus::Right => {
-                    if right < max_state {
-                        app.view_mode = ViewMode::Diff { left, right: right + 1, focus };
+                    if let Some(next) = app.trace.next_index(right) {
+                        app.view_mode = ViewMode::Diff { left, right: next, focus, other_cursor };
                         app.scroll_offset = 0;
                     }
                 }
@@ -507,41 +2711,131 @@ fn handle_next_state(app: &mut App) {
     }
 }
 
+/// Width of the label in front of the timeline cells
+const TIMELINE_LABEL: &str = " Δ ";
+
+/// Number of states covered by each timeline cell so that the timeline fits the terminal
+fn timeline_states_per_cell(state_count: usize, terminal_width: usize) -> usize {
+    let cells = terminal_width.saturating_sub(TIMELINE_LABEL.chars().count() + 1).max(1);
+    state_count.div_ceil(cells).max(1)
+}
+
+/// Handle clicks on the timeline: jump to the first state of the clicked cell
+fn handle_timeline_click(app: &mut App, col: usize, ctx: &EventContext) {
+    let Some(cell) = col.checked_sub(TIMELINE_LABEL.chars().count()) else {
+        return;
+    };
+    let per_cell = timeline_states_per_cell(app.trace.states.len(), ctx.terminal_width);
+    let idx = cell * per_cell;
+    if idx < app.trace.states.len() {
+        let path = ctx.tree_lines.get(app.cursor).map(|l| l.path.clone());
+        goto_state(app, idx);
+        if let Some(path) = path {
+            app.expansion.reveal(&path);
+            app.cursor_target = Some(path);
+        }
+    }
+}
+
+/// Build the timeline strip for the node under the cursor:
+/// one cell per state (or group of states), marking those where its value changed and underlining the `:break` states.
+/// For a number, each cell is a sparkline block of its value (the largest one of the group), followed by their range if it fits
+fn timeline_line<'a>(app: &App, path: &[String], terminal_width: usize, theme: &Theme) -> ratatui::text::Line<'a> {
+    use ratatui::style::{Modifier, Style};
+    use ratatui::text::Span;
+
+    let state_count = app.trace.states.len();
+    let per_cell = timeline_states_per_cell(state_count, terminal_width);
+    let label_style = Style::default().fg(theme.status_fg);
+    let cells: Vec<(usize, usize)> = (0..state_count).step_by(per_cell).map(|start| (start, (start + per_cell).min(state_count))).collect();
+
+    let numeric = app.trace.states.get(app.current_state).and_then(|state| value_at(state, path)).and_then(numeric_value).is_some();
+    let values: Vec<Option<f64>> = if numeric {
+        cells
+            .iter()
+            .map(|&(start, end)| {
+                (start..end)
+                    .filter_map(|i| value_at(&app.trace.states[i], path).and_then(numeric_value))
+                    .reduce(f64::max)
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let blocks: Vec<char> = sparkline(&values).chars().collect();
+
+    let mut spans = vec![Span::styled(TIMELINE_LABEL, label_style)];
+    for (cell, &(start, end)) in cells.iter().enumerate() {
+        let changed = (start..end).any(|i| path_changed_at(&app.trace, i, path));
+        let is_current = (start..end).contains(&app.current_state);
+
+        let (symbol, mut style) = match (blocks.get(cell), changed) {
+            (Some(block), true) => (block.to_string(), Style::default().fg(theme.diff_modified)),
+            (Some(block), false) => (block.to_string(), label_style),
+            (None, true) => ("●".to_string(), Style::default().fg(theme.diff_modified)),
+            (None, false) => ("·".to_string(), label_style),
+        };
+        if app.breakpoint.as_ref().is_some_and(|(_, states)| states.range(start..end).next().is_some()) {
+            style = style.add_modifier(Modifier::UNDERLINED);
+        }
+        if is_current {
+            style = style.patch(theme.cursor_style());
+        }
+        spans.push(Span::styled(symbol, style));
+    }
+
+    if let Some((min, max)) = value_range(&values) {
+        let range = format!(" {}..{}", format_number(min), format_number(max));
+        if TIMELINE_LABEL.chars().count() + cells.len() + range.chars().count() <= terminal_width {
+            spans.push(Span::styled(range, label_style));
+        }
+    }
+
+    ratatui::text::Line::from(spans)
+}
+
 /// Handle clicks on tree content area
 fn handle_content_click(app: &mut App, row: usize, col: usize, ctx: &EventContext) {
     match app.view_mode {
         ViewMode::Single => {
-            let clicked_line = app.scroll_offset + (row - 2);
+            let clicked_line = line_at_row(app, ctx.tree_lines, row - 2, ctx.terminal_width);
             if clicked_line < ctx.line_count {
                 app.cursor = clicked_line;
                 if let Some(line) = ctx.tree_lines.get(clicked_line) {
                     if line.expandable {
-                        app.expansion.toggle(&line.path);
+                        app.change_expansion(|expansion| expansion.toggle(&line.path));
                     }
                 }
             }
         }
-        ViewMode::Diff { left, right, .. } => {
-            let half_width = ctx.terminal_width / 2;
-            let new_focus = if col < half_width {
-                DiffFocus::Left
-            } else {
-                DiffFocus::Right
-            };
-            app.view_mode = ViewMode::Diff { left, right, focus: new_focus };
-
-            // Row 0 = header, Row 1 = empty, Row 2 = panel border, Row 3+ = content
+        ViewMode::Diff { .. } if app.unified => {
+            // Row 0 = header, Row 1 = status, Row 2 = panel border, Row 3+ = content
             if row >= 3 {
-                let clicked_line = app.scroll_offset + (row - 3);
-                let empty_diff = DiffResult { changes: std::collections::HashMap::new() };
-                let state_idx = if new_focus == DiffFocus::Left { left } else { right };
-                let panel_lines = build_tree_lines_for_state(&app.trace, state_idx, &app.expansion, &empty_diff, half_width, usize::MAX);
+                let clicked_line = line_at_row(app, ctx.tree_lines, row - 3, ctx.terminal_width.saturating_sub(2));
+                if let Some(line) = ctx.tree_lines.get(clicked_line) {
+                    app.cursor = clicked_line;
+                    if line.expandable {
+                        app.change_expansion(|expansion| expansion.toggle(&line.path));
+                    }
+                }
+            }
+        }
+        ViewMode::Diff { left, right, focus, .. } => {
+            let (new_focus, panel_row) = diff_panel_at(app, row, col, ctx);
+            if focus != new_focus {
+                app.toggle_diff_focus();
+            }
+
+            if let Some(panel_row) = panel_row {
+                let (left_lines, right_lines) = diff_panel_lines(app, left, right, ctx.terminal_width);
+                let panel_lines = if new_focus == DiffFocus::Left { left_lines } else { right_lines };
+                let clicked_line = line_at_row(app, &panel_lines, panel_row, diff_content_width(app, ctx.terminal_width));
 
                 if clicked_line < panel_lines.len() {
                     app.cursor = clicked_line;
                     if let Some(line) = panel_lines.get(clicked_line) {
                         if line.expandable {
-                            app.expansion.toggle(&line.path);
+                            app.change_expansion(|expansion| expansion.toggle(&line.path));
                         }
                     }
                 }
@@ -550,18 +2844,16 @@ fn handle_content_click(app: &mut App, row: usize, col: usize, ctx: &EventContex
     }
 }
 
-/// Compute diff between current state and previous state
-fn compute_diff_for_state(app: &App) -> DiffResult {
-    use std::collections::HashMap;
-
+/// Diff between current state and previous state, or the same state of the reference when comparing with it
+fn compute_diff_for_state(app: &App) -> Rc<DiffResult> {
+    if app.against_reference {
+        return app.diff_from_reference(app.current_state);
+    }
     if app.current_state == 0 {
         // First state - no diff
-        return DiffResult { changes: HashMap::new() };
+        return Rc::new(DiffResult::default());
     }
-
-    let prev = &app.trace.states[app.current_state - 1].values;
-    let curr = &app.trace.states[app.current_state].values;
-    compute_diff(prev, curr)
+    app.diff_between(app.current_state - 1, app.current_state)
 }
 
 /// Auto-expand the tree to reveal all changes in the current state
@@ -572,20 +2864,34 @@ fn auto_expand_changes(app: &mut App) {
     let diff = compute_diff_for_state(app);
     let changed_paths: Vec<_> = diff.changes.keys().cloned().collect();
     app.expansion.expand_to_changes(&changed_paths);
+    expand_pinned(app);
 }
 
-/// Build tree lines for the current state
-fn build_tree_lines(app: &App, diff: &DiffResult, terminal_width: usize, collapse_threshold: usize) -> Vec<TreeLine> {
-    let mut tree_lines = Vec::new();
-    if let Some(state) = app.trace.states.get(app.current_state) {
-        for name in &app.trace.vars {
-            if let Some(value) = state.values.get(name) {
-                let path = vec![name.clone()];
-                tree_lines.extend(render_value(name, value, path, &app.expansion, diff, 0, terminal_width, collapse_threshold));
-            }
+/// Expand the nodes pinned on the command line in the current state
+/// They count as expanded by the user, so that the automatic expansion leaves them open
+fn expand_pinned(app: &mut App) {
+    let Some(state) = app.trace.states.get(app.current_state) else {
+        return;
+    };
+    if app.pinned.all {
+        for name in state.var_names(&app.trace.vars, app.var_order) {
+            app.expansion.expand_subtree(&state.values[name], &vec![name.to_string()]);
+        }
+        for (name, value) in state_sections(state) {
+            app.expansion.expand_subtree(value, &vec![name.to_string()]);
         }
     }
-    tree_lines
+    for query in &app.pinned.paths {
+        if let Ok(path) = query.resolve(state) {
+            let ancestors: Vec<NodePath> = (1..=path.len()).map(|len| path[..len].to_vec()).collect();
+            app.expansion.expand_all(&ancestors);
+        }
+    }
+}
+
+/// Build tree lines for the current state
+fn build_tree_lines(app: &App, diff: &DiffResult, terminal_width: usize, collapse_threshold: usize) -> Vec<TreeLine> {
+    build_tree_lines_for_state(app, app.current_state, diff, terminal_width, collapse_threshold)
 }
 
 /// Clickable regions in the header
@@ -609,18 +2915,10 @@ fn build_header<'a>(
     diff_btn_text: &str,
     theme: &Theme,
 ) -> (ratatui::text::Line<'a>, HeaderLayout) {
-    use ratatui::style::{Modifier, Style};
     use ratatui::text::Span;
 
-    let header_style = Style::default()
-        .bg(theme.header_bg)
-        .fg(theme.header_fg)
-        .add_modifier(Modifier::BOLD);
-
-    let button_style = Style::default()
-        .bg(theme.header_bg)
-        .fg(theme.button_fg)
-        .add_modifier(Modifier::BOLD);
+    let header_style = theme.header_style();
+    let button_style = theme.header_style().fg(theme.button_fg);
 
     let mut pos = 0;
 
@@ -632,14 +2930,14 @@ fn build_header<'a>(
     pos += prev_btn.chars().count();
     let prev_end = pos;
 
-    pos += state_text.len();
+    pos += state_text.chars().count();
 
     let next_start = pos;
     let next_btn = "[▶]";
     pos += next_btn.chars().count();
     let next_end = pos;
 
-    pos += middle_text.len();
+    pos += middle_text.chars().count();
 
     let expand_start = pos;
     let expand_btn = "[+all]";
@@ -693,101 +2991,280 @@ fn build_header<'a>(
     (header, layout)
 }
 
-fn render(frame: &mut Frame, app: &App, tree_lines: &[TreeLine], viewport_height: usize, theme: &Theme) -> HeaderLayout {
+/// Line below the header showing the status message or a pending key sequence, or else the full name of the
+/// variable under the cursor when it is shown shortened
+fn status_line<'a>(app: &App, theme: &Theme, cursor_line: Option<&TreeLine>) -> ratatui::text::Line<'a> {
     use ratatui::style::Style;
+
+    if let Some(prompt) = &app.prompt {
+        let symbol = match prompt.kind {
+            PromptKind::Search => "/",
+            PromptKind::SearchAll => "?",
+            PromptKind::Command => ":",
+            PromptKind::State => "Go to state: ",
+        };
+        return ratatui::text::Line::from(format!(" {}{}▏", symbol, prompt.text));
+    }
+
+    let text = match (&app.status, app.pending_key, &app.search) {
+        (Some(status), _, _) => format!(" {}", status),
+        (None, Some(prefix), _) => format!(" {}-", prefix),
+        (None, None, Some(search)) => {
+            let filtered = if app.filter { ", filtered" } else { "" };
+            format!(" /{} ({} matches{})", search.query, search.matches.len(), filtered)
+        }
+        // The real name of the variable under the cursor, when it is shown under another one
+        (None, None, None) => cursor_line
+            .and_then(|line| line.path.first())
+            .filter(|name| module_of(std::slice::from_ref(name)).is_none())
+            .filter(|name| app.trace.alias(name).is_some() || ((app.group_modules || app.prefixes != PrefixDisplay::Full) && name.contains("::")))
+            .map(|name| format!(" {}", name))
+            .unwrap_or_default(),
+    };
+    ratatui::text::Line::styled(text, Style::default().fg(theme.status_fg))
+}
+
+fn render(frame: &mut Frame, app: &App, tree_lines: &[TreeLine], viewport_height: usize, theme: &Theme) -> HeaderLayout {
+    use ratatui::style::{Modifier, Style};
     use ratatui::text::{Line, Span};
 
     // Build scroll indicator
+    let width = frame.area().width as usize;
     let total_lines = tree_lines.len();
-    let scroll_info = if total_lines > viewport_height {
-        format!(" [{}-{}/{}]", app.scroll_offset + 1, (app.scroll_offset + viewport_height).min(total_lines), total_lines)
+    let visible_count = visible_line_count(app, tree_lines, viewport_height, width);
+    let scroll_info = if total_lines > visible_count {
+        format!(" [{}-{}/{}]", app.scroll_offset + 1, app.scroll_offset + visible_count, total_lines)
     } else {
         String::new()
     };
 
     let auto_indicator = if app.auto_expand { " [auto]" } else { "" };
-    let state_text = format!(" State {}/{}{}{} ", app.current_state + 1, app.trace.states.len(), auto_indicator, scroll_info);
+    let order_indicator = if app.var_order == VarOrder::Alphabetical { " [a-z]" } else { "" };
+    let wrap_indicator = if app.wrap { " [wrap]" } else { "" };
+    let mark_indicator = app.marked_state.map(|marked| format!(" [m:{}]", marked + 1)).unwrap_or_default();
+    // Which of the `:break` states this is, if one
+    let break_indicator = match &app.breakpoint {
+        Some((_, states)) if states.contains(&app.current_state) => format!(" [◆ {}/{}]", states.range(..app.current_state).count() + 1, states.len()),
+        Some((_, states)) => format!(" [◇ {}]", states.len()),
+        None => String::new(),
+    };
+    let action_text = app.trace.states.get(app.current_state)
+        .and_then(|state| state.action_taken())
+        .map(|action| format!(" — action: {}", action))
+        .unwrap_or_default();
+    let same_text = match app.same_states.get(app.current_state) {
+        Some(&first) if first != app.current_state => format!(" (= state {})", first + 1),
+        _ => String::new(),
+    };
+    let fold_indicator = if app.fold_duplicates { " [fold]" } else { "" };
+    let reference_indicator = if app.against_reference { " [vs reference]" } else { "" };
+    let queue_indicator = match app.watch.as_ref().map(Watcher::unreviewed) {
+        Some(count) if count > 0 => format!(" [{} queued]", count),
+        _ => String::new(),
+    };
+    let job_indicator = if app.job.is_some() { " [running…]" } else { "" };
+    let constants_indicator = match (app.hide_constants, app.hide_counters) {
+        (true, true) => " [varying] [no counters]",
+        (true, false) => " [varying]",
+        (false, true) => " [no counters]",
+        (false, false) => "",
+    };
+    let tags_text: String = app.tags.get(&app.current_state).into_iter().flatten().map(|label| format!(" #{}", label)).collect();
+    let loop_text = match app.trace.cycle_target() {
+        Some(target) if app.current_state + 1 == app.trace.states.len() => {
+            let period = app.trace.states.len() - target;
+            format!(" ↺ loop to state {} (cycle of {} state{})", target + 1, period, if period == 1 { "" } else { "s" })
+        }
+        _ => String::new(),
+    };
+    let stats_text = if app.current_state > 0 || app.against_reference {
+        format!(" {}", compute_diff_for_state(app).stats())
+    } else {
+        String::new()
+    };
+    let fingerprint_text = app.fingerprints.get(app.current_state).map(|fingerprint| format!(" @{}", fingerprint)).unwrap_or_default();
+    let state_text = format!(" State {}/{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{} ", app.current_state + 1, app.trace.states.len(), fingerprint_text, same_text, action_text, tags_text, stats_text, loop_text, auto_indicator, order_indicator, wrap_indicator, fold_indicator, reference_indicator, queue_indicator, job_indicator, constants_indicator, mark_indicator, break_indicator, scroll_info);
     let middle_text = " | ";
 
     let (header, header_layout) = build_header(&state_text, middle_text, "[diff]", theme);
 
-    let mut lines: Vec<Line> = vec![header, Line::from("")];
+    let mut lines: Vec<Line> = vec![header, status_line(app, theme, tree_lines.get(app.cursor))];
 
     // Only render visible lines based on scroll offset
     let visible_lines = tree_lines
         .iter()
         .enumerate()
         .skip(app.scroll_offset)
-        .take(viewport_height);
+        .take(visible_count);
+
+    let search_lines = app.search.as_ref()
+        .map(|search| matched_lines(tree_lines, &search.matches))
+        .unwrap_or_default();
 
     // Render tree lines with cursor highlighting, diff colors, and syntax highlighting
     for (i, tree_line) in visible_lines {
         let is_selected = i == app.cursor;
-        let bg_color = if is_selected { Some(theme.cursor_bg) } else { None };
-
-        // Get base diff color
-        let diff_color = match tree_line.diff {
-            DiffKind::Added => Some(theme.diff_added),
-            DiffKind::Removed => Some(theme.diff_removed),
-            DiffKind::Modified => Some(theme.diff_modified),
-            DiffKind::Unchanged => None,
+        let line_style = if is_selected {
+            theme.cursor_style()
+        } else if search_lines.contains(&i) {
+            theme.search_match_style()
+        } else {
+            Style::default()
         };
 
-        // Build styled spans
-        let styled_spans: Vec<Span> = tree_line.spans.iter().map(|span| {
-            // Syntax color takes precedence for unchanged items, diff color for changed
-            let fg_color = if diff_color.is_some() {
-                diff_color
-            } else {
-                span.style.to_color()
-            };
-
-            let mut style = Style::default();
-            if let Some(fg) = fg_color {
-                style = style.fg(fg);
-            }
-            if let Some(bg) = bg_color {
-                style = style.bg(bg);
-            }
-            Span::styled(&span.text, style)
-        }).collect();
+        // Build styled spans, one line per screen row
+        for row in line_rows(app, tree_line, width) {
+            let styled_spans: Vec<Span> = row.into_iter().map(|span| {
+                let mut style = Style::default();
+                if let Some(fg) = span_color(tree_line, &span, theme) {
+                    style = style.fg(fg);
+                }
+                style = style.patch(line_style);
+                if is_struck_out(tree_line, &span) {
+                    style = style.add_modifier(Modifier::CROSSED_OUT);
+                }
+                Span::styled(span.text, style)
+            }).collect();
 
-        lines.push(Line::from(styled_spans));
+            lines.push(Line::from(styled_spans));
+        }
     }
 
     let paragraph = ratatui::widgets::Paragraph::new(lines);
     frame.render_widget(paragraph, frame.area());
 
+    // Timeline of the node under the cursor in the last row
+    let area = frame.area();
+    if let Some(line) = tree_lines.get(app.cursor) {
+        if area.height > 0 {
+            let timeline_area = Rect::new(area.x, area.y + area.height - 1, area.width, 1);
+            let timeline = timeline_line(app, &line.path, area.width as usize, theme);
+            frame.render_widget(ratatui::widgets::Paragraph::new(timeline), timeline_area);
+        }
+    }
+
     header_layout
 }
 
+/// Foreground color of a span: the diff color for changed lines (except guide lines),
+/// or only for the changed characters of a modified value when they are known
+fn span_color(line: &TreeLine, span: &StyledSpan, theme: &Theme) -> Option<Color> {
+    let diff_color = match line.diff {
+        DiffKind::Added => Some(theme.diff_added),
+        DiffKind::Removed => Some(theme.diff_removed),
+        DiffKind::Modified => Some(theme.diff_modified),
+        DiffKind::Unchanged => None,
+    };
+    let partial = line.spans.iter().any(|span| span.changed);
+    match diff_color {
+        Some(color) if span.style != SpanStyle::Guide && (span.changed || !partial) => Some(color),
+        _ => theme.syntax_color(span.style),
+    }
+}
+
+/// Whether a span of a line is struck out: the content of ghost lines for removed items,
+/// but not their indentation, guides and marker
+fn is_struck_out(line: &TreeLine, span: &StyledSpan) -> bool {
+    line.diff == DiffKind::Removed
+        && span.style != SpanStyle::Guide
+        && !span.text.trim_start_matches([' ', '-']).is_empty()
+}
+
+/// Screen rows of a tree line: split when wrapping long lines, otherwise one row cut off at the edge
+fn line_rows(app: &App, line: &TreeLine, width: usize) -> Vec<Vec<StyledSpan>> {
+    if app.wrap {
+        line.wrap(width)
+    } else {
+        vec![line.spans.clone()]
+    }
+}
+
+/// Number of tree lines from the scroll offset on that fit in the viewport
+fn visible_line_count(app: &App, lines: &[TreeLine], viewport_height: usize, width: usize) -> usize {
+    visible_line_count_from(app, lines, app.scroll_offset, viewport_height, width)
+}
+
+/// Number of lines fitting in the viewport when it starts at line `scroll_offset`
+fn visible_line_count_from(app: &App, lines: &[TreeLine], scroll_offset: usize, viewport_height: usize, width: usize) -> usize {
+    let remaining = lines.len().saturating_sub(scroll_offset);
+    if !app.wrap {
+        return remaining.min(viewport_height);
+    }
+
+    let mut rows = 0;
+    let mut count = 0;
+    for line in lines.iter().skip(scroll_offset) {
+        rows += line.row_count(width);
+        // A line taller than the viewport is still shown, cut off at the bottom
+        if rows > viewport_height && count > 0 {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Index of the tree line shown at a row of the viewport
+fn line_at_row(app: &App, lines: &[TreeLine], row: usize, width: usize) -> usize {
+    if !app.wrap {
+        return app.scroll_offset + row;
+    }
+
+    let mut rows = 0;
+    for (i, line) in lines.iter().enumerate().skip(app.scroll_offset) {
+        rows += line.row_count(width);
+        if row < rows {
+            return i;
+        }
+    }
+    lines.len()
+}
+
 /// Build tree lines for a specific state index
 fn build_tree_lines_for_state(
-    trace: &Trace,
+    app: &App,
     state_idx: usize,
-    expansion: &ExpansionState,
     diff: &DiffResult,
     terminal_width: usize,
     collapse_threshold: usize,
 ) -> Vec<TreeLine> {
-    let mut tree_lines = Vec::new();
-    if let Some(state) = trace.states.get(state_idx) {
-        for name in &trace.vars {
-            if let Some(value) = state.values.get(name) {
-                let path = vec![name.clone()];
-                tree_lines.extend(render_value(name, value, path, expansion, diff, 0, terminal_width, collapse_threshold));
-            }
-        }
+    let mut lines = render_state(
+        &app.trace,
+        state_idx,
+        &app.expansion,
+        diff,
+        &app.formatters,
+        app.var_order,
+        app.type_display,
+        app.group_modules,
+        app.prefixes,
+        terminal_width,
+        collapse_threshold,
+    );
+    if !app.hidden_vars.is_empty() || app.hide_constants || app.hide_counters {
+        let constant = |path: &[String]| {
+            (1..=path.len()).any(|len| {
+                (app.hide_constants && app.constant_paths.contains(&path[..len])) || (app.hide_counters && app.counter_paths.contains(&path[..len]))
+            })
+        };
+        let shown = |name: &str| !app.hidden_vars.contains(name) && !constant(&[name.to_string()]);
+        // Module groups go away with the last of their variables
+        let values = &app.trace.states[state_idx].values;
+        lines.retain(|line| match module_of(&line.path) {
+            Some(module) => values.keys().any(|name| in_module(name, module) && shown(name)),
+            None => line.path.first().is_none_or(|name| shown(name)) && !constant(&line.path),
+        });
+    }
+    if app.diff_markers {
+        add_modified_markers(&mut lines);
     }
-    tree_lines
+    lines
 }
 
 /// Compute diff between two specific states
 fn compute_diff_between(trace: &Trace, left_idx: usize, right_idx: usize) -> DiffResult {
-    use std::collections::HashMap;
-
     if left_idx >= trace.states.len() || right_idx >= trace.states.len() {
-        return DiffResult { changes: HashMap::new() };
+        return DiffResult::default();
     }
 
     let left = &trace.states[left_idx].values;
@@ -795,6 +3272,75 @@ fn compute_diff_between(trace: &Trace, left_idx: usize, right_idx: usize) -> Dif
     compute_diff(left, right)
 }
 
+/// Tree lines of the diff view panels: the left state with what is gone or modified in the right one, the right
+/// one with its changes from the left one (ghost lines for removed items included), both without collapsing
+/// unchanged items and aligned by path
+fn diff_panel_lines(app: &App, left: usize, right: usize, terminal_width: usize) -> (Vec<TreeLine>, Vec<TreeLine>) {
+    // Half of the terminal (or all of it when stacked) minus borders
+    let panel_width = if app.stacked { terminal_width } else { terminal_width / 2 }.saturating_sub(4);
+    let left_lines = build_tree_lines_for_state(app, left, &app.removals_between(left, right), panel_width, usize::MAX);
+    let right_lines = build_tree_lines_for_state(app, right, &app.diff_between(left, right), panel_width, usize::MAX);
+    align_lines(left_lines, right_lines)
+}
+
+/// Width inside the borders of a diff view panel
+fn diff_content_width(app: &App, terminal_width: usize) -> usize {
+    if app.stacked || app.unified {
+        terminal_width.saturating_sub(2)
+    } else {
+        (terminal_width / 2).saturating_sub(2)
+    }
+}
+
+/// Tree lines of the unified diff view: the right state with its changes from the left one,
+/// previous values of modified ones and collapsed unchanged items
+fn unified_diff_lines(app: &App, left: usize, right: usize, terminal_width: usize) -> Vec<TreeLine> {
+    let diff = app.diff_between(left, right);
+    // Full width minus borders
+    let lines = build_tree_lines_for_state(app, right, &diff, terminal_width.saturating_sub(2), 1);
+    match app.trace.states.get(right) {
+        Some(state) => add_previous_values(lines, &diff, state),
+        None => lines,
+    }
+}
+
+/// Styled rows of the visible lines of a diff view panel, highlighting the cursor if the panel is focused
+fn panel_content<'a>(app: &App, lines: &'a [TreeLine], width: usize, viewport_height: usize, focused: bool, theme: &Theme) -> Vec<Line<'a>> {
+    use ratatui::style::{Modifier, Style};
+
+    let scroll_offset = app.panel_scroll_offset(focused);
+    lines
+        .iter()
+        .enumerate()
+        .skip(scroll_offset)
+        .take(visible_line_count_from(app, lines, scroll_offset, viewport_height, width))
+        .flat_map(|(i, tree_line)| {
+            let is_cursor = focused && i == app.cursor;
+            let line_style = if is_cursor { theme.cursor_style() } else { Style::default() };
+            line_rows(app, tree_line, width).into_iter().map(move |row| {
+                let styled_spans: Vec<Span> = row.into_iter().map(|span| {
+                    let mut style = Style::default();
+                    if let Some(fg) = span_color(tree_line, &span, theme) {
+                        style = style.fg(fg);
+                    }
+                    style = style.patch(line_style);
+                    if is_struck_out(tree_line, &span) {
+                        style = style.add_modifier(Modifier::CROSSED_OUT);
+                    }
+                    Span::styled(span.text, style)
+                }).collect();
+                Line::from(styled_spans)
+            })
+        })
+        .collect()
+}
+
+/// Title of a diff view panel: the number and fingerprint of its state
+fn panel_title(app: &App, idx: usize) -> String {
+    let fingerprint_text = app.fingerprints.get(idx).map(|fingerprint| format!(" @{}", fingerprint)).unwrap_or_default();
+    format!(" State {}{} ", idx + 1, fingerprint_text)
+}
+
 /// Render side-by-side diff view
 fn render_diff(
     frame: &mut Frame,
@@ -806,108 +3352,106 @@ fn render_diff(
     theme: &Theme,
 ) -> HeaderLayout {
     use ratatui::style::Style;
-    use ratatui::text::{Line, Span};
     use ratatui::layout::{Layout, Constraint, Direction};
     use ratatui::widgets::{Block, Borders, Paragraph};
 
-    let state_text = format!(" State {} vs {} ", left_idx + 1, right_idx + 1);
-    let middle_text = " | Tab:switch | ";
+    // Compute diff: comparing left to right (what changed from left to right)
+    let diff_left_to_right = app.diff_between(left_idx, right_idx);
+
+    let sync_indicator = if app.scroll_sync || app.unified { "" } else { " [independent]" };
+    let lock_indicator = if app.lock_step { " [lock-step]" } else { "" };
+    // The action of the transition between adjacent states
+    let action_text = match app.trace.states.get(right_idx).and_then(State::action_taken) {
+        Some(action) if right_idx == left_idx + 1 => format!(" — action: {}", action),
+        _ => String::new(),
+    };
+    let state_text = format!(
+        " State {} vs {}{} {}{}{} ",
+        left_idx + 1,
+        right_idx + 1,
+        action_text,
+        diff_left_to_right.stats(),
+        sync_indicator,
+        lock_indicator
+    );
+    let middle_text = match (app.unified, app.stacked) {
+        (true, _) => " | i:split | ",
+        (false, true) => " | Tab:switch s:side i:unified | ",
+        (false, false) => " | Tab:switch s:stack i:unified | ",
+    };
 
     let (header, header_layout) = build_header(&state_text, middle_text, "[exit]", theme);
 
-    // Calculate panel width (half of terminal minus border)
     let area = frame.area();
-    let panel_width = (area.width as usize) / 2;
-
-    // Compute diff: comparing left to right (what changed from left to right)
-    let diff_left_to_right = compute_diff_between(&app.trace, left_idx, right_idx);
-
-    // Empty diff for showing states without diff coloring
-    let empty_diff = DiffResult { changes: std::collections::HashMap::new() };
-
-    // Build tree lines for each side
-    let left_lines = build_tree_lines_for_state(&app.trace, left_idx, &app.expansion, &empty_diff, panel_width.saturating_sub(4), usize::MAX);
-    let right_lines = build_tree_lines_for_state(&app.trace, right_idx, &app.expansion, &diff_left_to_right, panel_width.saturating_sub(4), usize::MAX);
-
-    // Split layout: header + two panels side by side
     let main_chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([Constraint::Length(1), Constraint::Length(1), Constraint::Min(0)])
         .split(area);
 
-    let panel_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-        .split(main_chunks[2]);
-
-    // Render header
+    // Render header and status
     frame.render_widget(Paragraph::new(header), main_chunks[0]);
+    frame.render_widget(Paragraph::new(status_line(app, theme, None)), main_chunks[1]);
 
     // Style for focused/unfocused borders
     let focused_style = Style::default().fg(theme.focused_border);
     let unfocused_style = Style::default().fg(theme.unfocused_border);
 
+    if app.unified {
+        // Single panel with the changes of the right state
+        let mut lines = unified_diff_lines(app, left_idx, right_idx, area.width as usize);
+        if app.guides {
+            add_guides(&mut lines);
+        }
+        let width = main_chunks[2].width.saturating_sub(2) as usize;
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(focused_style)
+            .title(format!(" State {} → {} ", left_idx + 1, right_idx + 1));
+        let content = panel_content(app, &lines, width, viewport_height, true, theme);
+        frame.render_widget(Paragraph::new(content).block(block), main_chunks[2]);
+        return header_layout;
+    }
+
+    // Build tree lines for each side
+    let (mut left_lines, mut right_lines) = diff_panel_lines(app, left_idx, right_idx, area.width as usize);
+    if app.guides {
+        add_guides(&mut left_lines);
+        add_guides(&mut right_lines);
+    }
+
+    // Two panels side by side below the header, or one above the other with the viewport height each
+    let panel_chunks = if app.stacked {
+        Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(viewport_height as u16 + 2), Constraint::Min(0)])
+            .split(main_chunks[2])
+    } else {
+        Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(main_chunks[2])
+    };
+
     let left_border_style = if focus == DiffFocus::Left { focused_style } else { unfocused_style };
     let right_border_style = if focus == DiffFocus::Right { focused_style } else { unfocused_style };
 
-    // Build left panel content with cursor highlighting
-    let left_content: Vec<Line> = left_lines
-        .iter()
-        .enumerate()
-        .skip(app.scroll_offset)
-        .take(viewport_height)
-        .map(|(i, tree_line)| {
-            let is_cursor = focus == DiffFocus::Left && i == app.cursor;
-            let bg_color = if is_cursor { Some(theme.cursor_bg) } else { None };
-            let styled_spans: Vec<Span> = tree_line.spans.iter().map(|span| {
-                let mut style = Style::default();
-                if let Some(bg) = bg_color {
-                    style = style.bg(bg);
-                }
-                Span::styled(&span.text, style)
-            }).collect();
-            Line::from(styled_spans)
-        })
-        .collect();
+    // Width inside the panel borders
+    let left_width = panel_chunks[0].width.saturating_sub(2) as usize;
+    let right_width = panel_chunks[1].width.saturating_sub(2) as usize;
 
-    // Build right panel content with diff highlighting and cursor
-    let right_content: Vec<Line> = right_lines
-        .iter()
-        .enumerate()
-        .skip(app.scroll_offset)
-        .take(viewport_height)
-        .map(|(i, tree_line)| {
-            let is_cursor = focus == DiffFocus::Right && i == app.cursor;
-            let bg_color = if is_cursor { Some(theme.cursor_bg) } else { None };
-            let diff_color = match tree_line.diff {
-                DiffKind::Added => Some(theme.diff_added),
-                DiffKind::Removed => Some(theme.diff_removed),
-                DiffKind::Modified => Some(theme.diff_modified),
-                DiffKind::Unchanged => None,
-            };
-            let styled_spans: Vec<Span> = tree_line.spans.iter().map(|span| {
-                let mut style = Style::default();
-                if let Some(fg) = diff_color {
-                    style = style.fg(fg);
-                }
-                if let Some(bg) = bg_color {
-                    style = style.bg(bg);
-                }
-                Span::styled(&span.text, style)
-            }).collect();
-            Line::from(styled_spans)
-        })
-        .collect();
+    // The left panel highlights what is removed or modified, the right one what is added or modified
+    let left_content = panel_content(app, &left_lines, left_width, viewport_height, focus == DiffFocus::Left, theme);
+    let right_content = panel_content(app, &right_lines, right_width, viewport_height, focus == DiffFocus::Right, theme);
 
     let left_block = Block::default()
         .borders(Borders::ALL)
         .border_style(left_border_style)
-        .title(format!(" State {} ", left_idx + 1));
+        .title(panel_title(app, left_idx));
 
     let right_block = Block::default()
         .borders(Borders::ALL)
         .border_style(right_border_style)
-        .title(format!(" State {} ", right_idx + 1));
+        .title(panel_title(app, right_idx));
 
     let left_para = Paragraph::new(left_content).block(left_block);
     let right_para = Paragraph::new(right_content).block(right_block);
@@ -917,3 +3461,124 @@ fn render_diff(
 
     header_layout
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn path(segments: &[&str]) -> NodePath {
+        segments.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// App exploring a trace without states
+    fn empty_app() -> App {
+        let file = std::env::temp_dir().join(format!("quint-trace-explorer-empty-{:?}.itf.json", std::thread::current().id()));
+        std::fs::write(&file, r##"{ "#meta": {}, "vars": ["x"], "states": [] }"##).unwrap();
+        let trace = load_trace(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        App::new(trace, true, Config::default())
+    }
+
+    #[test]
+    fn test_diff_view_without_states() {
+        let mut app = empty_app();
+        app.enter_diff_mode();
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(80, 24)).unwrap();
+        for unified in [false, true] {
+            app.unified = unified;
+            terminal.draw(|frame| {
+                render_diff(frame, &app, 0, 0, DiffFocus::Right, 20, &Theme::default());
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_state_commands_without_states() {
+        let mut app = empty_app();
+        skip_stuttering(&mut app, true);
+        assert_eq!(app.status.as_deref(), Some("No state 1 to skip the stuttering of"));
+
+        app.reference = Some(load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json")).unwrap());
+        show_reference_diff(&mut app);
+        assert!(app.panel.is_none());
+        assert_eq!(app.status.as_deref(), Some("No state 1 to compare with the reference"));
+    }
+
+    #[test]
+    fn test_ensure_cursor_visible_without_room() {
+        let mut app = empty_app();
+        app.scroll_padding = 0;
+        app.cursor = 5;
+        // A terminal too short for any tree line, e.g. the single view at height 3
+        app.ensure_cursor_visible(0);
+        assert_eq!(app.scroll_offset, 5);
+        app.cursor = 9;
+        app.ensure_cursor_visible(3);
+        assert_eq!(app.scroll_offset, 7);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_in_background() {
+        let mut app = empty_app();
+        app.run_command = "cp {args} {out}".to_string();
+        let example = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json");
+        let args = example.display().to_string();
+        app.job = Some((start_run(&app.run_command, &args), JobPurpose::Run(args.clone())));
+        while !finish_job(&mut app) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // The new trace is explored, the previous one becomes the reference
+        assert!(app.job.is_none());
+        assert_eq!(app.trace.states.len(), 8);
+        assert_eq!(app.reference.as_ref().map(|reference| reference.states.len()), Some(0));
+        assert_eq!(app.last_run, Some(args));
+    }
+
+    #[test]
+    fn test_compare_paths() {
+        use std::cmp::Ordering;
+
+        // Item indices compare by value, names as text, parents before their children
+        assert_eq!(compare_paths(&path(&["a", "9"]), &path(&["a", "10"])), Ordering::Less);
+        assert_eq!(compare_paths(&path(&["b"]), &path(&["a", "0"])), Ordering::Greater);
+        assert_eq!(compare_paths(&path(&["a"]), &path(&["a", "0"])), Ordering::Less);
+        assert_eq!(compare_paths(&path(&["a", "0"]), &path(&["a", "0"])), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_changed_path_items() {
+        let trace = load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json")).unwrap();
+        let (prev, state) = (&trace.states[0], &trace.states[1]);
+        let diff = compute_diff(&prev.values, &state.values);
+
+        let items = changed_path_items(prev, state, 1, &diff);
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        // The sets holding the changes are left out, removed elements come first, then the items that replaced them
+        assert_eq!(
+            labels,
+            [
+                " ~ bank_of_boat = \"W\"",
+                " - who_is_on_bank[\"E\"] element \"c2_OF_PERSON\"",
+                " - who_is_on_bank[\"E\"] element \"m2_OF_PERSON\"",
+                " + who_is_on_bank[\"W\"][0] = \"c2_OF_PERSON\"",
+                " + who_is_on_bank[\"W\"][1] = \"m2_OF_PERSON\"",
+            ]
+        );
+        assert!(items.iter().all(|item| item.state == 1));
+        assert_eq!(items[3].path, Some(path(&["who_is_on_bank", "1", "0"])));
+
+        assert!(changed_path_items(prev, prev, 0, &compute_diff(&prev.values, &prev.values)).is_empty());
+
+        // Without states there is nothing to list, in either view
+        let mut app = empty_app();
+        show_changed_paths(&mut app);
+        assert!(app.panel.is_none());
+        assert_eq!(app.status.as_deref(), Some("No state 1 to list the changes of"));
+        app.enter_diff_mode();
+        show_changed_paths(&mut app);
+        assert!(app.panel.is_none());
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+
+use base64::Engine;
+
+/// Copy text to the system clipboard through the terminal, with an OSC 52 escape sequence
+/// Works over SSH and in most terminal emulators, some of which need it enabled in their settings
+pub fn copy(text: &str) -> io::Result<()> {
+    let in_tmux = std::env::var_os("TMUX").is_some_and(|value| !value.is_empty());
+    let mut stdout = io::stdout();
+    stdout.write_all(clipboard_sequence(text, in_tmux).as_bytes())?;
+    stdout.flush()
+}
+
+/// Escape sequence setting the clipboard to the text
+fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64::engine::general_purpose::STANDARD.encode(text))
+}
+
+/// Escape sequence to write to the terminal: inside tmux, which doesn't forward OSC 52 from programs by default,
+/// wrapped in a passthrough sequence for the terminal tmux runs in (needs `allow-passthrough` since tmux 3.3)
+fn clipboard_sequence(text: &str, in_tmux: bool) -> String {
+    let sequence = osc52(text);
+    if in_tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(osc52("hello"), "\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(osc52(""), "\x1b]52;c;\x07");
+    }
+
+    #[test]
+    fn test_clipboard_sequence() {
+        assert_eq!(clipboard_sequence("hello", false), "\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(clipboard_sequence("hello", true), "\x1bPtmux;\x1b\x1b]52;c;aGVsbG8=\x07\x1b\\");
+    }
+}
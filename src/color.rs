@@ -0,0 +1,145 @@
+use clap::ValueEnum;
+use ratatui::style::Color;
+
+/// When to use colors, picked with `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Detect what the terminal supports, and respect `NO_COLOR`
+    #[default]
+    Auto,
+    /// Use the theme's colors as they are
+    Always,
+    /// No colors, changes are shown by their markers
+    Never,
+}
+
+impl ColorChoice {
+    /// Colors to use on the current terminal
+    pub fn support(self) -> ColorSupport {
+        match self {
+            ColorChoice::Auto => ColorSupport::detect(),
+            ColorChoice::Always => ColorSupport::TrueColor,
+            ColorChoice::Never => ColorSupport::None,
+        }
+    }
+}
+
+/// Colors a terminal can display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorSupport {
+    None,
+    /// The 16 ANSI colors
+    Basic,
+    /// The 256-color palette
+    Indexed,
+    /// Any RGB color
+    TrueColor,
+}
+
+impl ColorSupport {
+    /// Detect the colors of the terminal from the environment
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// `NO_COLOR` (https://no-color.org) turns colors off, `COLORTERM` announces RGB colors,
+    /// and `TERM` tells 256-color terminals from the others
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        if var("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorSupport::None;
+        }
+        if var("COLORTERM").is_some_and(|value| value == "truecolor" || value == "24bit") {
+            return ColorSupport::TrueColor;
+        }
+        match var("TERM") {
+            Some(term) if term == "dumb" => ColorSupport::None,
+            Some(term) if term.contains("256color") || term.contains("direct") => ColorSupport::Indexed,
+            Some(_) => ColorSupport::Basic,
+            // Windows terminals don't set TERM, and all support the 256-color palette
+            None if cfg!(windows) => ColorSupport::Indexed,
+            None => ColorSupport::Basic,
+        }
+    }
+
+    /// Closest color to `color` that the terminal can display
+    pub fn adapt(self, color: Color) -> Color {
+        let rgb = match color {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(index) if index >= 16 => indexed_rgb(index),
+            _ if self == ColorSupport::None => return Color::Reset,
+            _ => return color,
+        };
+        match self {
+            ColorSupport::None => Color::Reset,
+            ColorSupport::Basic => Color::Indexed(closest(0..=15, rgb)),
+            ColorSupport::Indexed => Color::Indexed(closest(16..=255, rgb)),
+            ColorSupport::TrueColor => color,
+        }
+    }
+}
+
+/// RGB values of the 16 ANSI colors, as xterm displays them
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+/// RGB value of a color of the 256-color palette: the ANSI colors, a 6x6x6 color cube and a gray ramp
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    let level = |v: u8| if v == 0 { 0 } else { 55 + 40 * v };
+    match index {
+        0..=15 => ANSI_RGB[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Palette index in the range whose color is the closest to `rgb`
+fn closest(indices: std::ops::RangeInclusive<u8>, (r, g, b): (u8, u8, u8)) -> u8 {
+    let distance = |index: &u8| {
+        let (ir, ig, ib) = indexed_rgb(*index);
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, ir) + d(g, ig) + d(b, ib)
+    };
+    indices.min_by_key(distance).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let detect = |vars: &[(&str, &str)]| {
+            ColorSupport::from_env(|name| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string()))
+        };
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), ColorSupport::Indexed);
+        assert_eq!(detect(&[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")]), ColorSupport::TrueColor);
+        assert_eq!(detect(&[("TERM", "xterm-256color"), ("NO_COLOR", "1")]), ColorSupport::None);
+        assert_eq!(detect(&[("TERM", "xterm-256color"), ("NO_COLOR", "")]), ColorSupport::Indexed);
+        assert_eq!(detect(&[("TERM", "linux")]), ColorSupport::Basic);
+        assert_eq!(detect(&[("TERM", "dumb")]), ColorSupport::None);
+    }
+
+    #[test]
+    fn test_adapt() {
+        let purple = Color::Indexed(56);
+        assert_eq!(ColorSupport::TrueColor.adapt(purple), purple);
+        assert_eq!(ColorSupport::Indexed.adapt(purple), purple);
+        assert_eq!(ColorSupport::Basic.adapt(purple), Color::Indexed(4));
+        assert_eq!(ColorSupport::None.adapt(purple), Color::Reset);
+
+        assert_eq!(ColorSupport::Indexed.adapt(Color::Rgb(95, 0, 215)), purple);
+        assert_eq!(ColorSupport::Indexed.adapt(Color::Rgb(128, 128, 128)), Color::Indexed(244));
+        assert_eq!(ColorSupport::Basic.adapt(Color::Rgb(250, 10, 10)), Color::Indexed(9));
+
+        assert_eq!(ColorSupport::Basic.adapt(Color::Green), Color::Green);
+        assert_eq!(ColorSupport::None.adapt(Color::Green), Color::Reset);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::keymap::Keymap;
+use crate::theme::ThemeName;
+
+/// User configuration, read from the file given with `--config` or else from the default config file
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Auto-expand changed variables when navigating between states (on when not set)
+    #[serde(default)]
+    pub auto_expand: Option<bool>,
+    /// Built-in theme of the explorer
+    #[serde(default)]
+    pub theme: Option<ThemeName>,
+    /// TOML file defining a custom theme, relative to the config file, instead of a built-in one
+    #[serde(default)]
+    pub theme_file: Option<PathBuf>,
+    /// Key bindings: `default` or `vim`
+    #[serde(default)]
+    pub keys: Option<Keymap>,
+    /// Lines scrolled by a mouse wheel step (3 when not set)
+    #[serde(default)]
+    pub scroll_step: Option<usize>,
+    /// Lines kept visible above and below the cursor (2 when not set)
+    #[serde(default)]
+    pub scroll_padding: Option<usize>,
+    /// Glob patterns of variables to leave out of the explorer, like `mbt::*`
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Names to show variables under instead of their own, like `action` for `mbt::actionTaken`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Command `:run` runs to get a trace, `{args}` standing for its arguments and `{out}` for the ITF file to write
+    /// (`quint run --out-itf {out} {args}` when not set)
+    #[serde(default)]
+    pub run: Option<String>,
+    /// REPL `:eval` evaluates expressions in, like `quint repl -r spec.qnt::Main` to use the spec's definitions
+    /// (`quint repl` when not set)
+    #[serde(default)]
+    pub repl: Option<String>,
+    /// Command `:verify` runs to check an invariant of the trace's spec, `{spec}` standing for the spec, `{invariant}`
+    /// for the invariant and `{args}` and `{out}` as in `run`
+    /// (`quint verify --invariant {invariant} --out-itf {out} {args} {spec}` when not set)
+    #[serde(default)]
+    pub verify: Option<String>,
+    #[serde(default)]
+    pub formatters: Formatters,
+}
+
+/// Custom formatting rules for tree nodes, the first matching rule applies
+#[derive(Default, Deserialize)]
+#[serde(transparent)]
+pub struct Formatters(Vec<FormatRule>);
+
+/// A formatting rule, selecting nodes by path pattern and/or sum type variant
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatRule {
+    /// Dot-separated path pattern, e.g. `*.timestamp`, where `*` matches any number of segments
+    #[serde(default)]
+    path: Option<String>,
+    /// Tag of the sum type variant the rule applies to
+    #[serde(default)]
+    tag: Option<String>,
+    format: Format,
+}
+
+/// How a matched node is displayed
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Integer seconds since the Unix epoch as an ISO 8601 date
+    Date,
+    /// Integer in hexadecimal
+    Hex,
+    /// Record fields substituted into a format string, e.g. `{src} -> {dst}`
+    Template(String),
+}
+
+/// Config file used when none is given: `quint-trace-explorer/config.toml` in `$XDG_CONFIG_HOME`,
+/// or else in `~/.config`
+pub fn default_config_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("quint-trace-explorer").join("config.toml"))
+}
+
+/// Config file given by the `QTE_CONFIG` environment variable, used instead of the default one
+pub fn env_config_path() -> Option<PathBuf> {
+    env::var_os("QTE_CONFIG").filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+/// Override settings of the config file with the `QTE_*` environment variables, for CI and shared
+/// containers; command line flags override these in turn
+pub fn apply_env(config: &mut Config) -> Result<()> {
+    apply_vars(config, |name| env::var(name).ok())
+}
+
+fn apply_vars(config: &mut Config, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+    let var = |name: &str| var(name).filter(|value| !value.is_empty()).map(|value| (name.to_string(), value));
+    let invalid = |(name, value): &(String, String)| anyhow::anyhow!("Invalid value for {}: `{}`", name, value);
+
+    if let Some(var) = var("QTE_AUTO_EXPAND") {
+        config.auto_expand = Some(match var.1.to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => return Err(invalid(&var)),
+        });
+    }
+    if let Some(var) = var("QTE_THEME") {
+        config.theme = Some(ThemeName::from_str(&var.1, true).map_err(|_| invalid(&var))?);
+        config.theme_file = None;
+    }
+    if let Some((_, path)) = var("QTE_THEME_FILE") {
+        config.theme_file = Some(PathBuf::from(path));
+    }
+    if let Some(var) = var("QTE_KEYS") {
+        config.keys = Some(Keymap::from_str(&var.1, true).map_err(|_| invalid(&var))?);
+    }
+    if let Some(var) = var("QTE_SCROLL_STEP") {
+        config.scroll_step = Some(var.1.parse().map_err(|_| invalid(&var))?);
+    }
+    if let Some(var) = var("QTE_SCROLL_PADDING") {
+        config.scroll_padding = Some(var.1.parse().map_err(|_| invalid(&var))?);
+    }
+    if let Some((_, patterns)) = var("QTE_IGNORE") {
+        config.ignore = patterns.split(',').map(|pattern| pattern.trim().to_string()).collect();
+    }
+    Ok(())
+}
+
+/// Load the configuration from a file, in JSON if its name ends with `.json` and in TOML otherwise
+pub fn load_config(path: &Path) -> Result<Config> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read config file: {}", path.display()))?;
+    let mut config: Config = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&contents).context(format!("Failed to parse config file: {}", path.display()))?
+    } else {
+        toml::from_str(&contents).context(format!("Failed to parse config file: {}", path.display()))?
+    };
+
+    for (i, rule) in config.formatters.0.iter().enumerate() {
+        if rule.path.is_none() && rule.tag.is_none() {
+            bail!("Formatter {} in {} needs a `path` or a `tag`", i + 1, path.display());
+        }
+    }
+    if let (Some(theme_file), Some(dir)) = (&config.theme_file, path.parent()) {
+        config.theme_file = Some(dir.join(theme_file));
+    }
+    Ok(config)
+}
+
+impl Formatters {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Format of the first rule matching a node's path and, for sum type values, its tag
+    pub fn format_for(&self, path: &[String], tag: Option<&str>) -> Option<&Format> {
+        self.0
+            .iter()
+            .find(|rule| {
+                let path_matches = match &rule.path {
+                    Some(pattern) => pattern_matches(&pattern.split('.').collect::<Vec<_>>(), path),
+                    None => true,
+                };
+                let tag_matches = match &rule.tag {
+                    Some(expected) => tag == Some(expected.as_str()),
+                    None => true,
+                };
+                path_matches && tag_matches
+            })
+            .map(|rule| &rule.format)
+    }
+}
+
+/// Match path segments against a pattern, where `*` matches any number of segments
+fn pattern_matches(pattern: &[&str], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"*", rest)) => (0..=path.len()).any(|skip| pattern_matches(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((first, path_rest)) => first == segment && pattern_matches(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(segments: &[&str]) -> Vec<String> {
+        segments.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_format_for() {
+        let formatters: Formatters = serde_json::from_str(
+            r#"[
+                { "path": "*.timestamp", "format": "date" },
+                { "path": "balances.*", "format": "hex" },
+                { "tag": "Transfer", "format": { "template": "{from} -> {to}" } }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(formatters.format_for(&path(&["timestamp"]), None), Some(&Format::Date));
+        assert_eq!(formatters.format_for(&path(&["s", "0", "value", "timestamp"]), None), Some(&Format::Date));
+        assert_eq!(formatters.format_for(&path(&["s", "timestamps"]), None), None);
+        assert_eq!(formatters.format_for(&path(&["balances", "3"]), None), Some(&Format::Hex));
+        assert_eq!(formatters.format_for(&path(&["balances"]), None), Some(&Format::Hex));
+        assert_eq!(
+            formatters.format_for(&path(&["pending", "1"]), Some("Transfer")),
+            Some(&Format::Template("{from} -> {to}".to_string()))
+        );
+        assert_eq!(formatters.format_for(&path(&["pending", "1"]), Some("Mint")), None);
+    }
+
+    #[test]
+    fn test_apply_vars() {
+        let vars = [("QTE_AUTO_EXPAND", "off"), ("QTE_THEME", "solarized"), ("QTE_SCROLL_STEP", "5"), ("QTE_IGNORE", "mbt::*, *::debug"), ("QTE_KEYS", "")];
+        let lookup = |vars: &[(&str, &str)], name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string());
+        let mut config = Config { theme_file: Some(PathBuf::from("theme.toml")), keys: Some(Keymap::Vim), ..Config::default() };
+        apply_vars(&mut config, |name| lookup(&vars, name)).unwrap();
+
+        assert_eq!(config.auto_expand, Some(false));
+        assert_eq!((config.theme, &config.theme_file), (Some(ThemeName::Solarized), &None));
+        assert_eq!(config.scroll_step, Some(5));
+        assert_eq!(config.ignore, vec!["mbt::*", "*::debug"]);
+        // Empty variables are ignored
+        assert_eq!(config.keys, Some(Keymap::Vim));
+
+        let error = apply_vars(&mut config, |name| lookup(&[("QTE_THEME", "neon")], name)).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid value for QTE_THEME: `neon`");
+        assert!(apply_vars(&mut config, |name| lookup(&[("QTE_AUTO_EXPAND", "maybe")], name)).is_err());
+    }
+
+    #[test]
+    fn test_load_toml_config() {
+        let file = env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+        fs::write(
+            &file,
+            r#"
+auto_expand = false
+theme = "high-contrast"
+keys = "vim"
+scroll_padding = 5
+ignore = ["mbt::*"]
+run = "quint test {args} --out-itf {out}"
+repl = "quint repl -r spec.qnt::Main"
+verify = "apalache-mc check --inv={invariant} {args} {spec}"
+
+[aliases]
+"mbt::actionTaken" = "action"
+
+[[formatters]]
+path = "*.timestamp"
+format = "date"
+
+[[formatters]]
+tag = "Transfer"
+format = { template = "{from} -> {to}" }
+"#,
+        )
+        .unwrap();
+        let config = load_config(&file);
+        fs::remove_file(&file).unwrap();
+
+        let config = config.expect("Failed to load config");
+        assert_eq!(config.auto_expand, Some(false));
+        assert_eq!(config.theme, Some(ThemeName::HighContrast));
+        assert_eq!(config.keys, Some(Keymap::Vim));
+        assert_eq!((config.scroll_step, config.scroll_padding), (None, Some(5)));
+        assert_eq!(config.ignore, vec!["mbt::*"]);
+        assert_eq!(config.run.as_deref(), Some("quint test {args} --out-itf {out}"));
+        assert_eq!(config.repl.as_deref(), Some("quint repl -r spec.qnt::Main"));
+        assert_eq!(config.verify.as_deref(), Some("apalache-mc check --inv={invariant} {args} {spec}"));
+        assert_eq!(config.aliases.get("mbt::actionTaken").map(String::as_str), Some("action"));
+        assert_eq!(config.formatters.format_for(&path(&["timestamp"]), None), Some(&Format::Date));
+        assert_eq!(
+            config.formatters.format_for(&path(&["pending"]), Some("Transfer")),
+            Some(&Format::Template("{from} -> {to}".to_string()))
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-use crate::tree::NodePath;
+use crate::tree::{NodePath, TreeLine};
 
 /// What changed at a node
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,8 +13,36 @@ pub enum DiffKind {
 }
 
 /// Diff information for the whole state
+#[derive(Default)]
 pub struct DiffResult {
     pub changes: HashMap<NodePath, DiffKind>,
+    /// Map entries and set elements that are gone, by the path of their collection
+    pub removed: HashMap<NodePath, Vec<RemovedItem>>,
+    /// Previous value of modified values, to show it next to the current one when it fits on a line (records, maps
+    /// and sets only when they are small)
+    pub previous: HashMap<NodePath, itf::Value>,
+}
+
+/// Number of changes of each kind, counting removed map entries and set elements
+/// but not the records and collections only modified by changes inside them
+#[derive(Debug, Default, PartialEq)]
+pub struct DiffStats {
+    pub added: usize,
+    pub modified: usize,
+    pub removed: usize,
+}
+
+impl std::fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "+{} ~{} -{}", self.added, self.modified, self.removed)
+    }
+}
+
+/// An item of a map or set in the previous state that is missing from the current one
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemovedItem {
+    Entry(itf::Value, itf::Value),
+    Element(itf::Value),
 }
 
 impl DiffResult {
@@ -21,32 +50,140 @@ impl DiffResult {
         self.changes.get(path).copied().unwrap_or(DiffKind::Unchanged)
     }
 
+    /// Removed entries or elements of the collection at a path
+    pub fn removed_at(&self, path: &NodePath) -> &[RemovedItem] {
+        self.removed.get(path).map_or(&[], |items| items.as_slice())
+    }
+
+    /// Nodes that changed by themselves, and not only because of changes inside them, in one pass over the changes
+    pub fn own_changes(&self) -> HashSet<&NodePath> {
+        // Ancestors of changed nodes, longest first: once one is known, so are the shorter ones
+        let mut changed_below: HashSet<&[String]> = HashSet::new();
+        for path in self.changes.keys() {
+            for len in (0..path.len()).rev() {
+                if !changed_below.insert(&path[..len]) {
+                    break;
+                }
+            }
+        }
+        self.changes
+            .iter()
+            .filter(|(path, kind)| **kind != DiffKind::Unchanged && !self.removed.contains_key(*path) && !changed_below.contains(path.as_slice()))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    pub fn stats(&self) -> DiffStats {
+        let own_changes = self.own_changes();
+        let mut stats = DiffStats::default();
+        for (path, kind) in &self.changes {
+            match kind {
+                DiffKind::Added => stats.added += 1,
+                DiffKind::Removed => stats.removed += 1,
+                DiffKind::Modified if own_changes.contains(path) => stats.modified += 1,
+                _ => {}
+            }
+        }
+        stats.removed += self.removed.values().map(Vec::len).sum::<usize>();
+        stats
+    }
+
+    /// Whether items were removed from the collection at a path or from one inside it
+    pub fn has_removed_below(&self, path: &NodePath) -> bool {
+        self.removed.keys().any(|removed_path| removed_path.starts_with(path))
+    }
+}
+
+/// Parts of the state left out of the tree because they did not change in the transition, cycled with `U`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HideUnchanged {
+    #[default]
+    Off,
+    /// Variables whose value did not change
+    Vars,
+    /// Also the fields, entries and items that did not change inside the changed variables
+    Nodes,
+}
+
+impl HideUnchanged {
+    pub fn next(self) -> Self {
+        match self {
+            HideUnchanged::Off => HideUnchanged::Vars,
+            HideUnchanged::Vars => HideUnchanged::Nodes,
+            HideUnchanged::Nodes => HideUnchanged::Off,
+        }
+    }
+}
+
+/// Lines of a state's tree without its unchanged parts. Module groups stay when one of their variables changed,
+/// and the whole value of a node that changed by itself is kept
+pub fn hide_unchanged(lines: Vec<TreeLine>, diff: &DiffResult, hide: HideUnchanged) -> Vec<TreeLine> {
+    match hide {
+        HideUnchanged::Off => lines,
+        HideUnchanged::Vars => lines
+            .into_iter()
+            .filter(|line| line.diff != DiffKind::Unchanged || line.path.first().is_some_and(|name| diff.get(&vec![name.clone()]) != DiffKind::Unchanged))
+            .collect(),
+        HideUnchanged::Nodes => {
+            let own_changes: HashSet<&[String]> = diff.own_changes().into_iter().map(|path| path.as_slice()).collect();
+            lines
+                .into_iter()
+                .filter(|line| {
+                    line.diff != DiffKind::Unchanged
+                        || diff.get(&line.path) != DiffKind::Unchanged
+                        || (1..=line.path.len()).any(|len| own_changes.contains(&line.path[..len]))
+                })
+                .collect()
+        }
+    }
 }
 
 /// Compare two states and return what changed
 pub fn compute_diff(
-    prev: &HashMap<String, itf::Value>,
-    curr: &HashMap<String, itf::Value>,
+    prev: &HashMap<Rc<str>, Rc<itf::Value>>,
+    curr: &HashMap<Rc<str>, Rc<itf::Value>>,
 ) -> DiffResult {
-    let mut changes = HashMap::new();
+    let mut diff = DiffResult::default();
 
     let prev_keys: HashSet<_> = prev.keys().collect();
     let curr_keys: HashSet<_> = curr.keys().collect();
 
     for key in prev_keys.difference(&curr_keys) {
-        changes.insert(vec![(*key).clone()], DiffKind::Removed);
+        diff.changes.insert(vec![key.to_string()], DiffKind::Removed);
     }
 
     for key in curr_keys.difference(&prev_keys) {
-        changes.insert(vec![(*key).clone()], DiffKind::Added);
+        diff.changes.insert(vec![key.to_string()], DiffKind::Added);
     }
 
     for key in prev_keys.intersection(&curr_keys) {
-        let path = vec![(*key).clone()];
-        diff_value(&prev[*key], &curr[*key], path, &mut changes);
+        // Values shared between states are unchanged without comparing them
+        if Rc::ptr_eq(&prev[*key], &curr[*key]) {
+            continue;
+        }
+        let path = vec![key.to_string()];
+        diff_value(&prev[*key], &curr[*key], path, &mut diff);
     }
 
-    DiffResult { changes }
+    diff
+}
+
+/// Compare two states from the side of the previous one, to highlight it next to the current one:
+/// what the current state lacks is marked removed and modified values are compared with their
+/// current value (kept in `previous`), while what only the current state has is left out
+pub fn compute_removals(
+    prev: &HashMap<Rc<str>, Rc<itf::Value>>,
+    curr: &HashMap<Rc<str>, Rc<itf::Value>>,
+) -> DiffResult {
+    let mut diff = compute_diff(curr, prev);
+    diff.changes.retain(|_, kind| *kind != DiffKind::Removed);
+    for kind in diff.changes.values_mut() {
+        if *kind == DiffKind::Added {
+            *kind = DiffKind::Removed;
+        }
+    }
+    diff.removed.clear();
+    diff
 }
 
 /// Recursively diff two values
@@ -54,36 +191,62 @@ fn diff_value(
     prev: &itf::Value,
     curr: &itf::Value,
     path: NodePath,
-    changes: &mut HashMap<NodePath, DiffKind>,
+    diff: &mut DiffResult,
 ) {
     if prev == curr {
         return;
     }
 
+    // Records, maps and sets are compared item by item below: copying a large one would copy the whole value
+    // again at every level above a change, for a line it never fits on
+    let mut budget = MAX_PREVIOUS_NODES;
+    let recursed = matches!(prev, itf::Value::Record(_) | itf::Value::Map(_) | itf::Value::Set(_));
+    if !recursed || node_count_within(prev, &mut budget) {
+        diff.previous.insert(path.clone(), prev.clone());
+    }
     match (prev, curr) {
         (itf::Value::Record(prev_fields), itf::Value::Record(curr_fields)) => {
-            changes.insert(path.clone(), DiffKind::Modified);
-            diff_record(prev_fields, curr_fields, path, changes);
+            diff.changes.insert(path.clone(), DiffKind::Modified);
+            diff_record(prev_fields, curr_fields, path, diff);
         }
         (itf::Value::Map(prev_pairs), itf::Value::Map(curr_pairs)) => {
-            changes.insert(path.clone(), DiffKind::Modified);
-            diff_map(prev_pairs, curr_pairs, path, changes);
+            diff.changes.insert(path.clone(), DiffKind::Modified);
+            diff_map(prev_pairs, curr_pairs, path, diff);
         }
         (itf::Value::Set(prev_items), itf::Value::Set(curr_items)) => {
-            changes.insert(path.clone(), DiffKind::Modified);
-            diff_set(prev_items, curr_items, path, changes);
+            diff.changes.insert(path.clone(), DiffKind::Modified);
+            diff_set(prev_items, curr_items, path, diff);
         }
         _ => {
-            changes.insert(path, DiffKind::Modified);
+            diff.changes.insert(path, DiffKind::Modified);
         }
     }
 }
 
+/// Most nodes of a record, map or set whose previous value is kept
+const MAX_PREVIOUS_NODES: usize = 100;
+
+/// Whether a value has at most `budget` nodes, counting them down without going through larger values
+fn node_count_within(value: &itf::Value, budget: &mut usize) -> bool {
+    if *budget == 0 {
+        return false;
+    }
+    *budget -= 1;
+    match value {
+        itf::Value::Record(fields) => fields.iter().all(|(_, value)| node_count_within(value, budget)),
+        itf::Value::Map(pairs) => pairs.iter().all(|(key, value)| node_count_within(key, budget) && node_count_within(value, budget)),
+        itf::Value::Set(items) => items.iter().all(|item| node_count_within(item, budget)),
+        itf::Value::List(items) => items.iter().all(|item| node_count_within(item, budget)),
+        itf::Value::Tuple(items) => items.iter().all(|item| node_count_within(item, budget)),
+        _ => true,
+    }
+}
+
 fn diff_record(
     prev: &itf::value::Record,
     curr: &itf::value::Record,
     path: NodePath,
-    changes: &mut HashMap<NodePath, DiffKind>,
+    diff: &mut DiffResult,
 ) {
     let prev_keys: HashSet<_> = prev.iter().map(|(k, _)| k).collect();
     let curr_keys: HashSet<_> = curr.iter().map(|(k, _)| k).collect();
@@ -91,20 +254,20 @@ fn diff_record(
     for key in prev_keys.difference(&curr_keys) {
         let mut child_path = path.clone();
         child_path.push((*key).clone());
-        changes.insert(child_path, DiffKind::Removed);
+        diff.changes.insert(child_path, DiffKind::Removed);
     }
 
     for key in curr_keys.difference(&prev_keys) {
         let mut child_path = path.clone();
         child_path.push((*key).clone());
-        changes.insert(child_path, DiffKind::Added);
+        diff.changes.insert(child_path, DiffKind::Added);
     }
 
     for key in prev_keys.intersection(&curr_keys) {
-        if let (Some(prev_val), Some(curr_val)) = (prev.get(*key), curr.get(*key)) {
+        if let (Some(prev_val), Some(curr_val)) = (prev.get(key), curr.get(key)) {
             let mut child_path = path.clone();
             child_path.push((*key).clone());
-            diff_value(prev_val, curr_val, child_path, changes);
+            diff_value(prev_val, curr_val, child_path, diff);
         }
     }
 }
@@ -113,11 +276,21 @@ fn diff_map(
     prev: &itf::value::Map<itf::Value, itf::Value>,
     curr: &itf::value::Map<itf::Value, itf::Value>,
     path: NodePath,
-    changes: &mut HashMap<NodePath, DiffKind>,
+    diff: &mut DiffResult,
 ) {
     let prev_map: HashMap<_, _> = prev.iter().collect();
     let curr_vec: Vec<_> = curr.iter().collect();
 
+    let curr_keys: HashSet<_> = curr.iter().map(|(key, _)| key).collect();
+    let removed: Vec<_> = prev
+        .iter()
+        .filter(|(key, _)| !curr_keys.contains(key))
+        .map(|(key, value)| RemovedItem::Entry(key.clone(), value.clone()))
+        .collect();
+    if !removed.is_empty() {
+        diff.removed.insert(path.clone(), removed);
+    }
+
     for (i, (curr_key, curr_val)) in curr_vec.iter().enumerate() {
         let mut child_path = path.clone();
         child_path.push(format!("{}", i));
@@ -125,11 +298,11 @@ fn diff_map(
         match prev_map.get(curr_key) {
             Some(prev_val) => {
                 if *prev_val != *curr_val {
-                    diff_value(*prev_val, *curr_val, child_path, changes);
+                    diff_value(prev_val, curr_val, child_path, diff);
                 }
             }
             None => {
-                changes.insert(child_path, DiffKind::Added);
+                diff.changes.insert(child_path, DiffKind::Added);
             }
         }
     }
@@ -139,18 +312,135 @@ fn diff_set(
     prev: &itf::value::Set<itf::Value>,
     curr: &itf::value::Set<itf::Value>,
     path: NodePath,
-    changes: &mut HashMap<NodePath, DiffKind>,
+    diff: &mut DiffResult,
 ) {
-    let prev_vec: Vec<_> = prev.iter().collect();
-    let curr_vec: Vec<_> = curr.iter().collect();
+    let removed: Vec<_> = prev
+        .iter()
+        .filter(|item| !curr.contains(item))
+        .map(|item| RemovedItem::Element(item.clone()))
+        .collect();
+    if !removed.is_empty() {
+        diff.removed.insert(path.clone(), removed);
+    }
 
-    for (i, curr_item) in curr_vec.iter().enumerate() {
-        let mut child_path = path.clone();
-        child_path.push(format!("{}", i));
+    for (i, curr_item) in curr.iter().enumerate() {
+        if !prev.contains(curr_item) {
+            let mut child_path = path.clone();
+            child_path.push(format!("{}", i));
+            diff.changes.insert(child_path, DiffKind::Added);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(json: serde_json::Value) -> HashMap<Rc<str>, Rc<itf::Value>> {
+        let serde_json::Value::Object(vars) = json else {
+            panic!("state must be an object");
+        };
+        vars.into_iter()
+            .map(|(name, value)| (Rc::from(name.as_str()), Rc::new(serde_json::from_value(value).unwrap())))
+            .collect()
+    }
+
+    #[test]
+    fn test_stats() {
+        let prev = state(serde_json::json!({
+            "balances": { "#map": [["alice", 1], ["bob", 2]] },
+            "owners": { "#set": ["alice"] },
+            "round": 3,
+            "old": true
+        }));
+        let curr = state(serde_json::json!({
+            "balances": { "#map": [["bob", 5], ["carol", 1]] },
+            "owners": { "#set": ["alice"] },
+            "round": 4,
+            "new": true
+        }));
+        let diff = compute_diff(&prev, &curr);
+
+        // `balances` itself is only modified by the changes of its entries
+        let own_changes = diff.own_changes();
+        assert!(!own_changes.contains(&vec!["balances".to_string()]));
+        assert!(own_changes.contains(&vec!["balances".to_string(), "0".to_string()]));
+        assert_eq!(diff.stats(), DiffStats { added: 2, modified: 2, removed: 2 });
+        assert_eq!(diff.stats().to_string(), "+2 ~2 -2");
+        assert_eq!(compute_diff(&curr, &curr).stats(), DiffStats::default());
+    }
 
-        let exists_in_prev = prev_vec.iter().any(|p| p == curr_item);
-        if !exists_in_prev {
-            changes.insert(child_path, DiffKind::Added);
+    #[test]
+    fn test_hide_unchanged() {
+        use crate::config::Formatters;
+        use crate::tree::{render_value, ExpansionState};
+
+        let prev = state(serde_json::json!({
+            "balances": { "#map": [["alice", 1], ["bob", 2]] },
+            "log": ["started the first round"],
+            "round": 3
+        }));
+        let curr = state(serde_json::json!({
+            "balances": { "#map": [["alice", 1], ["bob", 5]] },
+            "log": ["started the first round", "proposed a value"],
+            "round": 3
+        }));
+        let diff = compute_diff(&prev, &curr);
+        let mut expansion = ExpansionState::new();
+        let mut lines = Vec::new();
+        for name in ["balances", "log", "round"] {
+            let path = vec![name.to_string()];
+            expansion.expand_all(std::slice::from_ref(&path));
+            lines.extend(render_value(name, &curr[name], path, &expansion, &diff, &Formatters::default(), 0, 40, usize::MAX));
         }
+        let shown = |hide: HideUnchanged| hide_unchanged(lines.clone(), &diff, hide).iter().map(|line| line.plain_text()).collect::<Vec<_>>();
+
+        assert_eq!(shown(HideUnchanged::Off).len(), lines.len());
+        assert_eq!(shown(HideUnchanged::Vars), shown(HideUnchanged::Off)[..lines.len() - 1]);
+        // The list changed as a whole, so all of its items are kept
+        assert_eq!(shown(HideUnchanged::Nodes), ["balances: Map(", "    \"bob\" -> 5", ")", "log: [", "  [0]: \"started the first round\"", "  [1]: \"proposed a value\"", "]"]);
+        assert_eq!(HideUnchanged::Off.next().next().next(), HideUnchanged::Off);
+    }
+
+    #[test]
+    fn test_previous_values() {
+        let items = |round: u64| serde_json::json!({ "#map": (0..200).map(|i| serde_json::json!([i, { "round": round, "id": i }])).collect::<Vec<_>>() });
+        let prev = state(serde_json::json!({ "nodes": items(1), "small": { "round": 1 } }));
+        let curr = state(serde_json::json!({ "nodes": items(2), "small": { "round": 2 } }));
+        let diff = compute_diff(&prev, &curr);
+        let path = |segments: &[&str]| segments.iter().map(|s| s.to_string()).collect::<NodePath>();
+
+        // Large collections are not copied, their small items and leaves are
+        assert!(!diff.previous.contains_key(&path(&["nodes"])));
+        assert!(diff.previous.contains_key(&path(&["nodes", "7"])));
+        assert_eq!(diff.previous[&path(&["nodes", "7", "round"])], itf::Value::Number(1));
+        assert!(diff.previous.contains_key(&path(&["small"])));
+    }
+
+    #[test]
+    fn test_removals() {
+        let prev = state(serde_json::json!({
+            "balances": { "#map": [["alice", 1], ["bob", 2]] },
+            "round": 3,
+            "old": true
+        }));
+        let curr = state(serde_json::json!({
+            "balances": { "#map": [["bob", 5], ["carol", 1]] },
+            "round": 4,
+            "new": true
+        }));
+        let diff = compute_removals(&prev, &curr);
+        let path = |segments: &[&str]| segments.iter().map(|s| s.to_string()).collect::<NodePath>();
+
+        // Paths are those of the previous state, where alice is the first entry and bob the second
+        assert_eq!(diff.get(&path(&["old"])), DiffKind::Removed);
+        assert_eq!(diff.get(&path(&["balances", "0"])), DiffKind::Removed);
+        assert_eq!(diff.get(&path(&["balances", "1"])), DiffKind::Modified);
+        assert_eq!(diff.get(&path(&["round"])), DiffKind::Modified);
+        assert_eq!(diff.previous[&path(&["round"])], itf::Value::Number(4));
+        assert!(diff.previous.contains_key(&path(&["balances"])));
+        // Carol and `new` only exist in the current state
+        assert_eq!(diff.get(&path(&["new"])), DiffKind::Unchanged);
+        assert!(diff.removed.is_empty());
     }
 }
@@ -0,0 +1,922 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+
+use crate::config::Formatters;
+use crate::diff::{compute_diff, DiffKind, DiffResult, RemovedItem};
+use crate::loader::{State, Trace, VarOrder};
+use crate::query::{Expr, PathQuery, Step};
+use crate::stats::var_stats;
+use crate::tree::{add_previous_values, display_path, format_value_inline, format_value_preview, render_state, render_value, state_sections, value_at, ExpansionState, NodePath, PrefixDisplay, SpanStyle, TreeLine, TypeDisplay};
+
+/// Width the trees of exported states are laid out for
+const EXPORT_WIDTH: usize = 100;
+
+/// Field names holding the sender of a message
+const SENDER_FIELDS: [&str; 4] = ["sender", "src", "source", "from"];
+
+/// Field names holding the receiver of a message
+const RECEIVER_FIELDS: [&str; 4] = ["receiver", "dst", "dest", "to"];
+
+/// Page of the HTML export, with `{{title}}` and `{{states}}` placeholders
+const HTML_TEMPLATE: &str = include_str!("export.html");
+
+/// The tree of a state, or of the node at `path` in it, as plain text: expanded `depth` levels deep,
+/// or entirely without a depth, and laid out as in the explorer without collapsed groups
+pub fn state_tree(trace: &Trace, formatters: &Formatters, state_idx: usize, path: Option<&NodePath>, depth: Option<usize>) -> String {
+    let state = &trace.states[state_idx];
+    let depth = depth.unwrap_or(usize::MAX);
+    let mut expansion = ExpansionState::new();
+    let lines = match path {
+        Some(path) => {
+            let Some(value) = value_at(state, path) else {
+                return String::new();
+            };
+            expansion.expand_to_depth(value, path, depth);
+            render_value(&display_path(state, path), value, path.clone(), &expansion, &DiffResult::default(), formatters, 0, EXPORT_WIDTH, usize::MAX)
+        }
+        None => {
+            for name in state.var_names(&trace.vars, VarOrder::Declaration) {
+                expansion.expand_to_depth(&state.values[name], &vec![name.to_string()], depth);
+            }
+            for (name, value) in state_sections(state) {
+                expansion.expand_to_depth(value, &vec![name.to_string()], depth);
+            }
+            render_state(trace, state_idx, &expansion, &DiffResult::default(), formatters, VarOrder::Declaration, TypeDisplay::Hidden, false, PrefixDisplay::Full, EXPORT_WIDTH, usize::MAX)
+        }
+    };
+    lines.iter().map(|line| format!("{}\n", line.plain_text())).collect()
+}
+
+/// States where a predicate holds, each with the values of the paths it refers to
+pub fn query_matches<'a>(trace: &'a Trace, expr: &Expr) -> Vec<(usize, Vec<(String, &'a itf::Value)>)> {
+    trace
+        .states
+        .iter()
+        .enumerate()
+        .filter(|(_, state)| expr.holds(state))
+        .map(|(idx, state)| (idx, predicate_values(state, expr)))
+        .collect()
+}
+
+/// Those of the given states where a predicate does not hold, each with the values of the paths it refers to
+pub fn assertion_failures<'a>(trace: &'a Trace, expr: &Expr, states: &[usize]) -> Vec<(usize, Vec<(String, &'a itf::Value)>)> {
+    states
+        .iter()
+        .filter(|&&idx| !expr.holds(&trace.states[idx]))
+        .map(|&idx| (idx, predicate_values(&trace.states[idx], expr)))
+        .collect()
+}
+
+/// Values of the paths a predicate refers to in a state, with their path
+fn predicate_values<'a>(state: &'a State, expr: &Expr) -> Vec<(String, &'a itf::Value)> {
+    expr.paths()
+        .into_iter()
+        .filter_map(|query| {
+            let path = query.resolve(state).ok()?;
+            Some((display_path(state, &path), value_at(state, &path)?))
+        })
+        .collect()
+}
+
+/// Matches of a query (or assertion failures) as text, a line per state with its number, action and values,
+/// as `:findall` lists them
+pub fn query_text(trace: &Trace, matches: &[(usize, Vec<(String, &itf::Value)>)]) -> String {
+    let mut text = String::new();
+    for (idx, values) in matches {
+        let _ = write!(text, "State {}", idx + 1);
+        if let Some(action) = trace.states[*idx].action_taken() {
+            let _ = write!(text, " ({})", action);
+        }
+        let values: Vec<String> = values.iter().map(|(path, value)| format!("{} = {}", path, format_value_preview(value, EXPORT_WIDTH))).collect();
+        if !values.is_empty() {
+            let _ = write!(text, ": {}", values.join(", "));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Matches of a query as a JSON array of `{ "state", "action", "values" }` objects, with state numbers
+/// counted from 1 and values in ITF JSON keyed by their path
+pub fn query_json(trace: &Trace, matches: &[(usize, Vec<(String, &itf::Value)>)]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&matches_json(trace, matches)?)?)
+}
+
+/// Outcome of an assertion as a JSON object: the predicate, whether it holds, the number of states checked
+/// and the states where it does not hold, as `query_json` lists matches
+pub fn assertion_json(trace: &Trace, predicate: &str, checked: usize, failures: &[(usize, Vec<(String, &itf::Value)>)]) -> Result<String> {
+    let outcome = serde_json::json!({
+        "predicate": predicate,
+        "holds": failures.is_empty(),
+        "checked": checked,
+        "failures": matches_json(trace, failures)?,
+    });
+    Ok(serde_json::to_string_pretty(&outcome)?)
+}
+
+/// States with the values of the paths a predicate refers to as `{ "state", "action", "values" }` objects
+fn matches_json(trace: &Trace, matches: &[(usize, Vec<(String, &itf::Value)>)]) -> Result<Vec<serde_json::Value>> {
+    let mut results = Vec::new();
+    for (idx, values) in matches {
+        let mut fields = serde_json::Map::new();
+        for (path, value) in values {
+            fields.insert(path.clone(), serde_json::to_value(value)?);
+        }
+        results.push(serde_json::json!({
+            "state": idx + 1,
+            "action": trace.states[*idx].action_taken(),
+            "values": fields,
+        }));
+    }
+    Ok(results)
+}
+
+/// The changes from one state to another as a unified diff patch of the later state's tree, laid out
+/// as in the unified diff view: changes expanded, unchanged items collapsed, removed items and previous
+/// values on `-` lines, additions and new values on `+` lines
+pub fn transition_patch(trace: &Trace, formatters: &Formatters, from: usize, to: usize) -> String {
+    let (lines, diff) = transition_lines(trace, formatters, from, to);
+    let mut patch = format!("--- state {}\n+++ state {}\n", from + 1, to + 1);
+    for line in &lines {
+        patch.push_str(&patch_line(line, &diff));
+        patch.push('\n');
+    }
+    patch
+}
+
+/// The changes from one state to another as JSON: the change counts, then a change per node that was
+/// added, removed or modified by itself (not only by changes inside it), with its path and ITF JSON values
+pub fn transition_json(trace: &Trace, from: usize, to: usize) -> Result<String> {
+    let (before, after) = (&trace.states[from], &trace.states[to]);
+    let diff = compute_diff(&before.values, &after.values);
+    let json = |value: Option<&itf::Value>| serde_json::to_value(value);
+
+    let mut paths: Vec<&NodePath> = diff.changes.keys().chain(diff.removed.keys()).collect();
+    paths.sort_by_key(|path| path.iter().map(|segment| (segment.parse::<usize>().ok(), segment.clone())).collect::<Vec<_>>());
+    paths.dedup();
+
+    let own_changes = diff.own_changes();
+    let mut changes = Vec::new();
+    for path in paths {
+        match diff.get(path) {
+            DiffKind::Added => changes.push(serde_json::json!({ "path": display_path(after, path), "kind": "added", "value": json(value_at(after, path))? })),
+            DiffKind::Removed => changes.push(serde_json::json!({ "path": display_path(before, path), "kind": "removed", "value": json(value_at(before, path))? })),
+            DiffKind::Modified if own_changes.contains(path) => changes.push(serde_json::json!({
+                "path": display_path(after, path),
+                "kind": "modified",
+                "old": json(diff.previous.get(path))?,
+                "new": json(value_at(after, path))?,
+            })),
+            _ => {}
+        }
+        for item in diff.removed_at(path) {
+            changes.push(match item {
+                RemovedItem::Entry(key, value) => serde_json::json!({
+                    "path": format!("{}[{}]", display_path(after, path), format_value_preview(key, 40)),
+                    "kind": "removed",
+                    "key": json(Some(key))?,
+                    "value": json(Some(value))?,
+                }),
+                RemovedItem::Element(value) => serde_json::json!({ "path": display_path(after, path), "kind": "removed", "value": json(Some(value))? }),
+            });
+        }
+    }
+
+    let stats = diff.stats();
+    let report = serde_json::json!({
+        "from": from + 1,
+        "to": to + 1,
+        "stats": { "added": stats.added, "modified": stats.modified, "removed": stats.removed },
+        "changes": changes,
+    });
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
+/// A Markdown document describing a trace: its metadata, a table summarizing the transitions,
+/// then a section per state with the tree of the first state and the changed variables of the others
+pub fn markdown_report(trace: &Trace, formatters: &Formatters, title: &str) -> String {
+    let mut report = format!("# {}\n\n", title);
+    if let Some(source) = &trace.meta.source {
+        let _ = writeln!(report, "- Source: `{}`", source);
+    }
+    if let Some(description) = &trace.meta.description {
+        let _ = writeln!(report, "- Description: {}", description);
+    }
+    let _ = writeln!(report, "- States: {}", trace.states.len());
+    if let Some(first) = trace.states.first() {
+        let names: Vec<String> = first.var_names(&trace.vars, VarOrder::Declaration).iter().map(|name| format!("`{}`", trace.alias(name).unwrap_or(name))).collect();
+        let _ = writeln!(report, "- Variables: {}", names.join(", "));
+    }
+    if let Some(target) = trace.loop_target() {
+        let _ = writeln!(report, "- Loops back to state {} after the last state", target + 1);
+    } else if let Some(repeated) = trace.repeated_last() {
+        let _ = writeln!(report, "- The last state repeats state {}: cycle of {} state(s) from state {}", repeated + 1, trace.states.len() - repeated - 1, repeated + 2);
+    }
+
+    report.push_str("\n## Summary\n\n| State | Action | Changes | Changed variables |\n|------:|--------|---------|-------------------|\n");
+    let mut sections = String::new();
+    for (idx, state) in trace.states.iter().enumerate() {
+        let action = state.action_taken().unwrap_or("");
+        let heading = match state.action_taken() {
+            Some(action) => format!("## State {} ({})", idx + 1, action),
+            None => format!("## State {}", idx + 1),
+        };
+        if idx == 0 {
+            let _ = writeln!(report, "| 1 | {} | initial state | |", action);
+            let lines = render_state(trace, 0, &ExpansionState::new(), &DiffResult::default(), formatters, VarOrder::Declaration, TypeDisplay::Hidden, false, PrefixDisplay::Full, EXPORT_WIDTH, usize::MAX);
+            let tree: Vec<String> = lines.iter().map(TreeLine::plain_text).collect();
+            let _ = write!(sections, "\n{}\n\n```\n{}\n```\n", heading, tree.join("\n"));
+            continue;
+        }
+
+        let (lines, diff) = transition_lines(trace, formatters, idx - 1, idx);
+        let changed: HashSet<&str> = diff.changes.keys().chain(diff.removed.keys()).map(|path| path[0].as_str()).collect();
+        let names: Vec<String> = state
+            .var_names(&trace.vars, VarOrder::Declaration)
+            .into_iter()
+            .filter(|name| changed.contains(name))
+            .map(|name| format!("`{}`", trace.alias(name).unwrap_or(name)))
+            .collect();
+        let _ = writeln!(report, "| {} | {} | {} | {} |", idx + 1, action, diff.stats(), names.join(", "));
+
+        if changed.is_empty() {
+            let _ = write!(sections, "\n{}\n\nNo changes.\n", heading);
+            continue;
+        }
+        let tree: Vec<String> = lines
+            .iter()
+            .filter(|line| line.path.first().is_some_and(|name| changed.contains(name.as_str())))
+            .map(|line| patch_line(line, &diff))
+            .collect();
+        let _ = write!(sections, "\n{}\n\n```diff\n{}\n```\n", heading, tree.join("\n"));
+    }
+    report.push_str(&sections);
+    report
+}
+
+/// The contents of `markdown_report` as JSON, for other tools: the metadata, the statistics of each variable as
+/// the `I` list shows them, and the action, change counts and changed variables of each transition
+pub fn report_json(trace: &Trace) -> Result<String> {
+    let name = |name: &str| trace.alias(name).unwrap_or(name).to_string();
+    let variables: Vec<String> = match trace.states.first() {
+        Some(first) => first.var_names(&trace.vars, VarOrder::Declaration).into_iter().map(name).collect(),
+        None => Vec::new(),
+    };
+    let stats: Vec<serde_json::Value> = var_stats(trace)
+        .into_iter()
+        .map(|stats| {
+            serde_json::json!({
+                "name": name(&stats.name),
+                "distinct": stats.distinct,
+                "changes": stats.changes,
+                "first_change": stats.first_change.map(|idx| idx + 1),
+                "range": stats.range,
+                "sizes": stats.sizes,
+            })
+        })
+        .collect();
+
+    let mut transitions = Vec::new();
+    for (idx, state) in trace.states.iter().enumerate().skip(1) {
+        let diff = compute_diff(&trace.states[idx - 1].values, &state.values);
+        let changed: HashSet<&str> = diff.changes.keys().chain(diff.removed.keys()).map(|path| path[0].as_str()).collect();
+        let names: Vec<String> = state
+            .var_names(&trace.vars, VarOrder::Declaration)
+            .into_iter()
+            .filter(|name| changed.contains(name))
+            .map(name)
+            .collect();
+        let counts = diff.stats();
+        transitions.push(serde_json::json!({
+            "state": idx + 1,
+            "action": state.action_taken(),
+            "stats": { "added": counts.added, "modified": counts.modified, "removed": counts.removed },
+            "changed": names,
+        }));
+    }
+
+    let report = serde_json::json!({
+        "source": trace.meta.source,
+        "description": trace.meta.description,
+        "states": trace.states.len(),
+        "variables": variables,
+        "loop": trace.cycle_target().map(|idx| idx + 1),
+        "stats": stats,
+        "transitions": transitions,
+    });
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
+/// A self-contained HTML page showing one state at a time, with buttons (or arrow keys) to move between
+/// states: the whole tree, collapsible, with the changes from the previous state colored and expanded
+pub fn html_page(trace: &Trace, formatters: &Formatters, title: &str) -> String {
+    let mut states = String::new();
+    for (idx, state) in trace.states.iter().enumerate() {
+        let diff = match idx.checked_sub(1) {
+            Some(prev) => compute_diff(&trace.states[prev].values, &state.values),
+            None => DiffResult::default(),
+        };
+        // Every node is rendered, only the branches leading to changes start open
+        let mut expansion = ExpansionState::new();
+        for (name, value) in &state.values {
+            expansion.expand_subtree(value, &vec![name.to_string()]);
+        }
+        for (name, value) in state_sections(state) {
+            expansion.expand_subtree(value, &vec![name.to_string()]);
+        }
+        let mut open = ExpansionState::new();
+        open.expand_to_changes(&diff.changes.keys().cloned().collect::<Vec<_>>());
+        let lines = render_state(trace, idx, &expansion, &diff, formatters, VarOrder::Declaration, TypeDisplay::Inline, false, PrefixDisplay::Full, EXPORT_WIDTH, usize::MAX);
+
+        let mut label = format!("State {}/{}", idx + 1, trace.states.len());
+        if let Some(action) = state.action_taken() {
+            let _ = write!(label, " ({})", action);
+        }
+        if idx > 0 {
+            let _ = write!(label, " {}", diff.stats());
+        }
+        let _ = write!(states, "<section class=\"state\" data-label=\"{}\">\n{}</section>\n", html_escape(&label), html_tree(&lines, &open));
+    }
+    HTML_TEMPLATE.replace("{{title}}", &html_escape(title)).replace("{{states}}", &states)
+}
+
+/// Nest the lines of a fully expanded tree into `<details>` elements, one per expandable node,
+/// holding the lines that follow it down to its closing delimiter
+fn html_tree(lines: &[TreeLine], open: &ExpansionState) -> String {
+    let mut html = String::new();
+    let mut groups: Vec<&NodePath> = Vec::new();
+    for line in lines {
+        while groups.last().is_some_and(|group| !line.path.starts_with(group)) {
+            groups.pop();
+            html.push_str("</div></details>");
+        }
+        if line.expandable {
+            let open_attr = if open.is_expanded(&line.path) { " open" } else { "" };
+            let _ = write!(html, "<details{}><summary>{}</summary><div>", open_attr, html_line(line));
+            groups.push(&line.path);
+        } else {
+            let _ = write!(html, "<div>{}</div>", html_line(line));
+        }
+    }
+    for _ in groups {
+        html.push_str("</div></details>");
+    }
+    html.push('\n');
+    html
+}
+
+/// Spans of a tree line with the classes of their syntax and diff colors, as the explorer draws them;
+/// the expand icon is left to the page, to follow the state of its `<details>` element
+fn html_line(line: &TreeLine) -> String {
+    let partial = line.spans.iter().any(|span| span.changed);
+    let mut html = String::new();
+    for (i, span) in line.spans.iter().enumerate() {
+        let mut classes = Vec::new();
+        match span.style {
+            SpanStyle::Key => classes.push("key"),
+            SpanStyle::Punctuation => classes.push("punct"),
+            SpanStyle::String => classes.push("str"),
+            SpanStyle::Number => classes.push("num"),
+            SpanStyle::Boolean => classes.push("bool"),
+            SpanStyle::Type => classes.push("type"),
+            SpanStyle::Default | SpanStyle::Guide => {}
+        }
+        if span.changed || !partial {
+            match line.diff {
+                DiffKind::Added => classes.push("added"),
+                DiffKind::Removed => classes.push("removed"),
+                DiffKind::Modified => classes.push("modified"),
+                DiffKind::Unchanged => {}
+            }
+        }
+        if line.diff == DiffKind::Removed && !span.text.trim_start_matches([' ', '-']).is_empty() {
+            classes.push("struck");
+        }
+
+        let mut text = html_escape(&span.text);
+        if i == 0 && line.expandable {
+            text = text.replacen("▼ ", "<span class=\"icon\"></span>", 1);
+        }
+        if classes.is_empty() {
+            html.push_str(&text);
+        } else {
+            let _ = write!(html, "<span class=\"{}\">{}</span>", classes.join(" "), text);
+        }
+    }
+    html
+}
+
+/// Escape text for HTML content and attribute values
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A Graphviz DOT graph of the trace: a node per distinct state (states holding the same values share
+/// one), labelled with its numbers and the action that first led to it, or else the variables it changed,
+/// and an edge per transition, including the dashed one back to the loop start of a lasso
+pub fn dot_graph(trace: &Trace) -> String {
+    // Node of each state: the index of the first state holding the same values
+    let nodes = trace.first_same_states();
+
+    let mut dot = String::from("digraph trace {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for (idx, state) in trace.states.iter().enumerate().filter(|(idx, _)| nodes[*idx] == *idx) {
+        let states: Vec<usize> = (0..nodes.len()).filter(|&i| nodes[i] == idx).collect();
+        let mut label = format!("State {}", state_ranges(&states));
+        let description = match (state.action_taken(), idx.checked_sub(1)) {
+            (Some(action), _) => action.to_string(),
+            (None, Some(prev)) => {
+                let diff = compute_diff(&trace.states[prev].values, &state.values);
+                let changed: HashSet<&str> = diff.changes.keys().map(|path| path[0].as_str()).collect();
+                let names: Vec<&str> = state.var_names(&trace.vars, VarOrder::Declaration).into_iter().filter(|name| changed.contains(name)).collect();
+                names.join("\n")
+            }
+            (None, None) => String::new(),
+        };
+        if !description.is_empty() {
+            label.push('\n');
+            label.push_str(&description);
+        }
+        let _ = writeln!(dot, "  s{} [label=\"{}\"];", idx + 1, dot_escape(&label));
+    }
+
+    let mut edges = HashSet::new();
+    for pair in nodes.windows(2) {
+        if edges.insert((pair[0], pair[1])) {
+            let _ = writeln!(dot, "  s{} -> s{};", pair[0] + 1, pair[1] + 1);
+        }
+    }
+    if let (Some(target), Some(&last)) = (trace.loop_target(), nodes.last()) {
+        let _ = writeln!(dot, "  s{} -> s{} [style=dashed, label=\"loop\"];", last + 1, nodes[target] + 1);
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// State numbers of increasing state indices, with runs of consecutive states written as ranges (`2, 5-9`)
+fn state_ranges(indices: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &idx in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == idx => *end = idx,
+            _ => ranges.push((idx, idx)),
+        }
+    }
+    let texts: Vec<String> = ranges
+        .iter()
+        .map(|&(start, end)| if start == end { (start + 1).to_string() } else { format!("{}-{}", start + 1, end + 1) })
+        .collect();
+    texts.join(", ")
+}
+
+/// Escape text for a quoted DOT string, where line breaks are written `\n`
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// A Mermaid sequence diagram of the messages exchanged over the trace: an arrow from sender to receiver
+/// for each message when it first appears, under a note with the number and action of its state
+/// Messages are read from `var`, or else from the first variable (or record field of one) holding some
+pub fn mermaid_sequence(trace: &Trace, var: Option<&PathQuery>) -> Result<String> {
+    let query = match var {
+        Some(query) => query.clone(),
+        None => match find_message_variable(trace) {
+            Some(query) => query,
+            None => bail!("No variable holding messages with sender and receiver fields, pick one with --var"),
+        },
+    };
+
+    // Messages of each state that were not there in the previous one
+    let mut sent: Vec<(usize, Vec<Message>)> = Vec::new();
+    let mut previous = Vec::new();
+    for (idx, state) in trace.states.iter().enumerate() {
+        let messages = query.value(state).map(messages_in).unwrap_or_default();
+        let new: Vec<Message> = messages.iter().filter(|message| !previous.contains(*message)).cloned().collect();
+        if !new.is_empty() {
+            sent.push((idx, new));
+        }
+        previous = messages;
+    }
+    if sent.is_empty() {
+        bail!("No messages with sender and receiver fields in the variable");
+    }
+
+    let mut participants: Vec<&str> = Vec::new();
+    for message in sent.iter().flat_map(|(_, messages)| messages) {
+        for name in [&message.sender, &message.receiver] {
+            if !participants.contains(&name.as_str()) {
+                participants.push(name);
+            }
+        }
+    }
+    participants.sort();
+    let id = |name: &str| participants.iter().position(|p| *p == name).unwrap_or(0) + 1;
+
+    let mut diagram = String::from("sequenceDiagram\n");
+    for (i, name) in participants.iter().enumerate() {
+        let _ = writeln!(diagram, "    participant P{} as {}", i + 1, mermaid_escape(name));
+    }
+    let span = if participants.len() > 1 { format!("P1,P{}", participants.len()) } else { "P1".to_string() };
+    for (idx, messages) in &sent {
+        let mut note = format!("State {}", idx + 1);
+        if let Some(action) = trace.states[*idx].action_taken() {
+            let _ = write!(note, " ({})", action);
+        }
+        let _ = writeln!(diagram, "    Note over {}: {}", span, mermaid_escape(&note));
+        for message in messages {
+            let _ = writeln!(diagram, "    P{}->>P{}: {}", id(&message.sender), id(&message.receiver), mermaid_escape(&message.label()));
+        }
+    }
+    Ok(diagram)
+}
+
+/// A message of a sequence diagram
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    sender: String,
+    receiver: String,
+    value: itf::Value,
+}
+
+impl Message {
+    /// Read a message from a record, or a sum type wrapping one, with sender and receiver fields
+    /// The receiver is `receiver` instead when the message sits in a map from receivers to messages
+    fn parse(value: &itf::Value, receiver: Option<&itf::Value>) -> Option<Message> {
+        let fields = message_fields(value)?;
+        let field = |names: &[&str]| names.iter().find_map(|name| fields.get(name));
+        Some(Message {
+            sender: participant_name(field(&SENDER_FIELDS)?),
+            receiver: participant_name(receiver.or_else(|| field(&RECEIVER_FIELDS))?),
+            value: value.clone(),
+        })
+    }
+
+    /// The message without its sender and receiver, on one line
+    fn label(&self) -> String {
+        let strip = |fields: &itf::value::Record| -> itf::value::Record {
+            fields
+                .iter()
+                .filter(|(name, _)| !SENDER_FIELDS.contains(&name.as_str()) && !RECEIVER_FIELDS.contains(&name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()
+        };
+        let stripped = match &self.value {
+            itf::Value::Record(fields) => match fields.get("value") {
+                Some(itf::Value::Record(inner)) if fields.len() == 2 && fields.contains_key("tag") => {
+                    let mut wrapper = fields.clone();
+                    wrapper.insert("value".to_string(), itf::Value::Record(strip(inner)));
+                    itf::Value::Record(wrapper)
+                }
+                _ => itf::Value::Record(strip(fields)),
+            },
+            other => other.clone(),
+        };
+        format_value_preview(&stripped, 80)
+    }
+}
+
+/// Fields of a message record, looking inside sum types like `Vote({ src: "p1", ... })`
+fn message_fields(value: &itf::Value) -> Option<&itf::value::Record> {
+    let itf::Value::Record(fields) = value else {
+        return None;
+    };
+    match fields.get("value") {
+        Some(itf::Value::Record(inner)) if fields.len() == 2 && fields.contains_key("tag") => Some(inner),
+        _ => Some(fields),
+    }
+}
+
+/// Messages in a set or list of them, or in a map from receivers to sets or lists of them
+fn messages_in(value: &itf::Value) -> Vec<Message> {
+    let items = |value: &itf::Value| -> Vec<itf::Value> {
+        match value {
+            itf::Value::Set(items) => items.iter().cloned().collect(),
+            itf::Value::List(items) => items.clone(),
+            _ => Vec::new(),
+        }
+    };
+    match value {
+        itf::Value::Map(pairs) => pairs
+            .iter()
+            .flat_map(|(receiver, messages)| items(messages).into_iter().filter_map(move |m| Message::parse(&m, Some(receiver))))
+            .collect(),
+        _ => items(value).iter().filter_map(|m| Message::parse(m, None)).collect(),
+    }
+}
+
+/// Path of the first variable, or record field inside one, holding messages in some state
+fn find_message_variable(trace: &Trace) -> Option<PathQuery> {
+    fn search(value: &itf::Value, steps: &mut Vec<Step>) -> bool {
+        if !messages_in(value).is_empty() {
+            return true;
+        }
+        let itf::Value::Record(fields) = value else {
+            return false;
+        };
+        for (name, field) in fields.iter() {
+            steps.push(Step::Field(name.clone()));
+            if search(field, steps) {
+                return true;
+            }
+            steps.pop();
+        }
+        false
+    }
+
+    trace.states.iter().find_map(|state| {
+        state.var_names(&trace.vars, VarOrder::Declaration).into_iter().find_map(|var| {
+            let mut steps = Vec::new();
+            let value = state.values.get(var)?;
+            search(value, &mut steps).then(|| PathQuery { var: var.to_string(), steps })
+        })
+    })
+}
+
+/// Name of a participant: strings as they are, other values on one line
+fn participant_name(value: &itf::Value) -> String {
+    match value {
+        itf::Value::String(s) => s.clone(),
+        other => format_value_preview(other, 40),
+    }
+}
+
+/// Escape text for a Mermaid diagram, where `#` and `;` start entity codes and statement ends
+fn mermaid_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' => escaped.push_str("#35;"),
+            ';' => escaped.push_str("#59;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A CSV table of values over the trace: a `state` column with the state numbers, then a column per path
+/// (named by its text) with the value it points at in each state, left empty where it does not resolve
+/// Fails with the reason of the first state if a path resolves in none
+pub fn csv_table(trace: &Trace, paths: &[(String, PathQuery)]) -> Result<String> {
+    for (text, query) in paths {
+        if let Err(e) = query.check_in_states(&trace.states) {
+            bail!("{} is in no state: {:#}", text, e);
+        }
+    }
+
+    let mut header = vec!["state".to_string()];
+    header.extend(paths.iter().map(|(text, _)| csv_escape(text)));
+    let mut csv = header.join(",");
+    csv.push('\n');
+
+    for (idx, state) in trace.states.iter().enumerate() {
+        let mut row = vec![(idx + 1).to_string()];
+        row.extend(paths.iter().map(|(_, query)| query.value(state).map(|value| csv_escape(&csv_value(value))).unwrap_or_default()));
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// A value as a CSV cell: scalars as they are, so tools read them as numbers, booleans or text, composite
+/// values on one line, or as compact ITF JSON when they hold maps
+fn csv_value(value: &itf::Value) -> String {
+    match value {
+        itf::Value::String(s) => s.clone(),
+        other => format_value_inline(other).unwrap_or_else(|| serde_json::to_string(other).unwrap_or_default()),
+    }
+}
+
+/// Quote a CSV cell if it holds separators, quotes or line breaks
+fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Lines of the later state of a transition laid out as in the unified diff view, with its diff
+fn transition_lines(trace: &Trace, formatters: &Formatters, from: usize, to: usize) -> (Vec<TreeLine>, DiffResult) {
+    let diff = compute_diff(&trace.states[from].values, &trace.states[to].values);
+    let mut expansion = ExpansionState::new();
+    expansion.expand_to_changes(&diff.changes.keys().cloned().collect::<Vec<_>>());
+    let lines = render_state(trace, to, &expansion, &diff, formatters, VarOrder::Declaration, TypeDisplay::Hidden, false, PrefixDisplay::Full, EXPORT_WIDTH, 1);
+    (add_previous_values(lines, &diff, &trace.states[to]), diff)
+}
+
+/// A tree line with its change marker moved to the first column: `-` for removed items and previous values,
+/// `+` for additions, new values and everything inside added values, a space for unchanged context
+fn patch_line(line: &TreeLine, diff: &DiffResult) -> String {
+    let text = line.plain_text();
+    let body = text.trim_start_matches(' ');
+    let indent = &text[..text.len() - body.len()];
+    let inside_added = (1..=line.path.len()).any(|len| diff.changes.get(&line.path[..len]) == Some(&DiffKind::Added));
+
+    let (marker, rest) = match line.diff {
+        DiffKind::Removed => ('-', body.strip_prefix("- ").unwrap_or(body)),
+        DiffKind::Added | DiffKind::Modified if body.starts_with("+ ") => ('+', &body[2..]),
+        _ if inside_added => ('+', body),
+        _ => (' ', body),
+    };
+    format!("{}{}{}", marker, indent, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_trace;
+    use crate::query::{parse_expr, parse_path};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_state_tree() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("DecideNonProposerTest0.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        let tree = state_tree(&trace, &Formatters::default(), 3, None, Some(0));
+        assert_eq!(tree.lines().next(), Some("system: Map(1 entries)"));
+        let tree = state_tree(&trace, &Formatters::default(), 3, None, None);
+        assert!(tree.contains("\n      step: \"prevote\"\n"));
+
+        let node = parse_path("system[\"Josef\"]").unwrap().resolve(&trace.states[3]).unwrap();
+        let tree = state_tree(&trace, &Formatters::default(), 3, Some(&node), None);
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], "system[\"Josef\"]: {");
+        assert_eq!(lines[6], "  step: \"prevote\"");
+    }
+
+    #[test]
+    fn test_query() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("DecideNonProposerTest0.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        let expr = parse_expr("system[\"Josef\"].step == \"decided\"").unwrap();
+        let matches = query_matches(&trace, &expr);
+        assert_eq!(query_text(&trace, &matches), "State 8: system[\"Josef\"].step = \"decided\"\nState 9: system[\"Josef\"].step = \"decided\"\n");
+
+        let json: serde_json::Value = serde_json::from_str(&query_json(&trace, &matches).unwrap()).unwrap();
+        assert_eq!(json[0], serde_json::json!({ "state": 8, "action": null, "values": { "system[\"Josef\"].step": "decided" } }));
+        assert_eq!(json.as_array().map(Vec::len), Some(2));
+
+        let expr = parse_expr("size(system) > 1").unwrap();
+        assert!(query_matches(&trace, &expr).is_empty());
+    }
+
+    #[test]
+    fn test_assertion_failures() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("DecideNonProposerTest0.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+        let all: Vec<usize> = (0..trace.states.len()).collect();
+
+        let expr = parse_expr("system[\"Josef\"].height >= 1 || _Event.name == \"Initial\"").unwrap();
+        assert!(assertion_failures(&trace, &expr, &all).is_empty());
+
+        let expr = parse_expr("system[\"Josef\"].round == 0").unwrap();
+        let failures = assertion_failures(&trace, &expr, &all);
+        assert!(!failures.is_empty());
+        assert_eq!(failures.len() + query_matches(&trace, &expr).len(), all.len());
+        assert!(assertion_failures(&trace, &expr, &[0]).is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&assertion_json(&trace, "round == 0", all.len(), &failures).unwrap()).unwrap();
+        assert_eq!((&json["holds"], &json["checked"]), (&serde_json::json!(false), &serde_json::json!(all.len())));
+        assert_eq!(json["failures"].as_array().map(Vec::len), Some(failures.len()));
+        assert_eq!(json["failures"][0]["state"], failures[0].0 + 1);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&assertion_json(&trace, "true", 1, &[]).unwrap()).unwrap()["holds"], true);
+    }
+
+    #[test]
+    fn test_transition_patch() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        // Two people cross from the east bank to the west one with the boat
+        let patch = transition_patch(&trace, &Formatters::default(), 0, 1);
+        let expected = [
+            "--- state 1",
+            "+++ state 2",
+            "-bank_of_boat: \"E\"",
+            "+bank_of_boat: \"W\"",
+            " who_is_on_bank: Map(",
+            "     \"E\" ->",
+            "     Set(",
+            "       \"c1_OF_PERSON\"",
+            "       \"m1_OF_PERSON\"",
+            "-      \"c2_OF_PERSON\"",
+            "-      \"m2_OF_PERSON\"",
+            "     )",
+            "-    \"W\" -> Set()",
+            "+    \"W\" -> Set(\"c2_OF_PERSON\", \"m2_OF_PERSON\")",
+            " )",
+        ];
+        assert_eq!(patch.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_transition_json() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        let json: serde_json::Value = serde_json::from_str(&transition_json(&trace, 0, 1).unwrap()).unwrap();
+        assert_eq!(json["from"], 1);
+        assert_eq!(json["stats"], serde_json::json!({ "added": 2, "modified": 1, "removed": 2 }));
+        assert_eq!(json["changes"][0], serde_json::json!({ "path": "bank_of_boat", "kind": "modified", "old": "E", "new": "W" }));
+        let kinds: Vec<&str> = json["changes"].as_array().unwrap().iter().map(|change| change["kind"].as_str().unwrap()).collect();
+        assert_eq!(kinds, ["modified", "removed", "removed", "added", "added"]);
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        let report = markdown_report(&trace, &Formatters::default(), "Crossing");
+        assert!(report.starts_with("# Crossing\n"));
+        assert!(report.contains("| 2 |  | +2 ~1 -2 | `bank_of_boat`, `who_is_on_bank` |\n"));
+        assert_eq!(report.matches("\n## State ").count(), trace.states.len());
+        // Transitions show the patch of their changed variables
+        assert!(report.contains("## State 2\n\n```diff\n-bank_of_boat: \"E\"\n+bank_of_boat: \"W\"\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&report_json(&trace).unwrap()).unwrap();
+        assert_eq!(json["states"], trace.states.len());
+        assert_eq!(json["variables"], serde_json::json!(["bank_of_boat", "who_is_on_bank"]));
+        assert_eq!(json["transitions"][0], serde_json::json!({
+            "state": 2,
+            "action": null,
+            "stats": { "added": 2, "modified": 1, "removed": 2 },
+            "changed": ["bank_of_boat", "who_is_on_bank"],
+        }));
+        assert_eq!(json["stats"].as_array().map(Vec::len), Some(2));
+        assert_eq!(json["stats"][0]["first_change"], 2);
+    }
+
+    #[test]
+    fn test_html_page() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        let page = html_page(&trace, &Formatters::default(), "<Crossing>");
+        assert!(page.contains("<title>&lt;Crossing&gt;</title>"));
+        assert_eq!(page.matches("<section class=\"state\"").count(), trace.states.len());
+        assert!(page.contains("data-label=\"State 2/"));
+        // Every group is closed, and the removed set elements are struck out
+        assert_eq!(page.matches("<details").count(), page.matches("</details>").count());
+        assert!(page.contains("<span class=\"str removed struck\">&quot;c2_OF_PERSON&quot;</span>"));
+    }
+
+    #[test]
+    fn test_dot_graph() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("consensus.itf.json");
+        let mut trace = load_trace(&path).expect("Failed to load trace");
+
+        // The trace goes through 9 distinct states
+        let dot = dot_graph(&trace);
+        assert!(dot.starts_with("digraph trace {\n"));
+        assert_eq!(dot.matches("[label=").count(), 9);
+        assert!(!dot.contains("style=dashed"));
+
+        trace.loop_index = Some(1);
+        let dot = dot_graph(&trace);
+        assert!(dot.ends_with("s2 [style=dashed, label=\"loop\"];\n}\n"));
+        assert!(dot.contains("s9 [label=\"State 9-21\\n"));
+        assert_eq!(state_ranges(&[0, 2, 3, 4, 7]), "1, 3-5, 8");
+        assert_eq!(dot_escape("say \"hi\"\n"), "say \\\"hi\\\"\\n");
+    }
+
+    #[test]
+    fn test_csv_table() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("DecideNonProposerTest0.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        let paths: Vec<(String, PathQuery)> = ["system[\"Josef\"].step", "_Event"].iter().map(|text| (text.to_string(), parse_path(text).unwrap())).collect();
+        let csv = csv_table(&trace, &paths).expect("Failed to export values");
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), trace.states.len() + 1);
+        assert_eq!(lines[0], "state,\"system[\"\"Josef\"\"].step\",_Event");
+        assert_eq!(lines[1], "1,newRound,\"{ height: -1, name: \"\"Initial\"\", round: -1, value: \"\"\"\", vr: -1 }\"");
+
+        let paths = vec![("nope".to_string(), parse_path("nope").unwrap())];
+        assert!(csv_table(&trace, &paths).is_err());
+        assert_eq!(csv_escape("plain"), "plain");
+    }
+
+    #[test]
+    fn test_mermaid_sequence() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("tendermint.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        // Messages are found in the map from receivers to message sets of the choreo state
+        let diagram = mermaid_sequence(&trace, None).expect("Failed to export messages");
+        let lines: Vec<&str> = diagram.lines().collect();
+        assert_eq!(lines[..7], ["sequenceDiagram", "    participant P1 as p1", "    participant P2 as p2", "    participant P3 as p3", "    participant P4 as p4", "    Note over P1,P4: State 1", "    P4->>P1: PreCommit({ id: Some({ hashed: \"v0\" }), round: 0 })"]);
+        let state2 = lines.iter().position(|line| *line == "    Note over P1,P4: State 2").expect("No note for state 2");
+        assert_eq!(lines[state2 + 1], "    P1->>P1: PreVote({ id: Some({ hashed: \"v0\" }), round: 0 })");
+
+        let query = parse_path("tendermint::choreo::s.messages").unwrap();
+        assert_eq!(mermaid_sequence(&trace, Some(&query)).unwrap(), diagram);
+        let query = parse_path("tendermint::choreo::s.extensions").unwrap();
+        assert!(mermaid_sequence(&trace, Some(&query)).is_err());
+        assert_eq!(mermaid_escape("a; #1"), "a#59; #35;1");
+    }
+}
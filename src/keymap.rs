@@ -0,0 +1,65 @@
+use clap::ValueEnum;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+/// Key bindings, picked with `--keys` or `keys` in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Keymap {
+    /// Arrow keys, Page Up/Down and Home/End
+    #[default]
+    Default,
+    /// Also `hjkl`, `gg`/`G` and `Ctrl-d`/`Ctrl-u`
+    Vim,
+}
+
+/// What a key of the vim keymap does, when it has no meaning in the default keymap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VimKey {
+    /// Same as another key of the default keymap
+    Alias(KeyCode),
+    /// First key of `gg`, or of `g` and a state number
+    Prefix(char),
+    /// Scroll half a page down (`Ctrl-d`) or up (`Ctrl-u`)
+    HalfPage { down: bool },
+}
+
+/// Vim binding of a key, `None` for keys that keep their default meaning
+pub fn vim_key(key: KeyEvent) -> Option<VimKey> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        return match key.code {
+            KeyCode::Char('d') => Some(VimKey::HalfPage { down: true }),
+            KeyCode::Char('u') => Some(VimKey::HalfPage { down: false }),
+            _ => None,
+        };
+    }
+    let alias = match key.code {
+        KeyCode::Char('h') => KeyCode::Left,
+        KeyCode::Char('j') => KeyCode::Down,
+        KeyCode::Char('k') => KeyCode::Up,
+        KeyCode::Char('l') => KeyCode::Right,
+        KeyCode::Char('G') => KeyCode::End,
+        KeyCode::Char('g') => return Some(VimKey::Prefix('g')),
+        _ => return None,
+    };
+    Some(VimKey::Alias(alias))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vim_key() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert_eq!(vim_key(key(KeyCode::Char('j'))), Some(VimKey::Alias(KeyCode::Down)));
+        assert_eq!(vim_key(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT)), Some(VimKey::Alias(KeyCode::End)));
+        assert_eq!(vim_key(key(KeyCode::Char('g'))), Some(VimKey::Prefix('g')));
+        assert_eq!(vim_key(ctrl('d')), Some(VimKey::HalfPage { down: true }));
+        assert_eq!(vim_key(ctrl('u')), Some(VimKey::HalfPage { down: false }));
+        assert_eq!(vim_key(key(KeyCode::Char('d'))), None);
+        assert_eq!(vim_key(key(KeyCode::Char('/'))), None);
+        assert_eq!(vim_key(ctrl('j')), None);
+    }
+}
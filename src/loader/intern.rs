@@ -0,0 +1,42 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Deduplicates variable names and whole variable values while loading a trace
+///
+/// States where a variable holds the same value point to a single copy of it,
+/// which also lets `compute_diff` skip comparing them. Nested values are not
+/// shared: `itf::Value` owns its items, so a variable that changed keeps its own
+/// copy of every item, and a large map with one entry changing per state is
+/// copied in full every state. For the same reason, field names and strings
+/// inside values are not interned either.
+#[derive(Default)]
+pub struct Interner {
+    names: HashSet<Rc<str>>,
+    values: HashSet<Rc<itf::Value>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the shared copy of a variable name
+    pub fn name(&mut self, name: &str) -> Rc<str> {
+        if let Some(existing) = self.names.get(name) {
+            return existing.clone();
+        }
+        let name: Rc<str> = Rc::from(name);
+        self.names.insert(name.clone());
+        name
+    }
+
+    /// Get the shared copy of a variable's value, dropping the given one if already known
+    pub fn value(&mut self, value: itf::Value) -> Rc<itf::Value> {
+        if let Some(existing) = self.values.get(&value) {
+            return existing.clone();
+        }
+        let value = Rc::new(value);
+        self.values.insert(value.clone());
+        value
+    }
+}
@@ -1,26 +1,378 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use super::intern::Interner;
+use crate::session::trace_key;
 
 /// A parsed ITF trace using itf::Value for state values
 pub struct Trace {
-    #[allow(dead_code)]
     pub meta: itf::trace::Meta,
-    #[allow(dead_code)]
     pub vars: Vec<String>,
     pub states: Vec<State>,
-    #[allow(dead_code)]
     pub loop_index: Option<u64>,
+    /// Names variables are shown under instead of their own, from the `aliases` of the config file
+    pub aliases: HashMap<String, String>,
+    /// File the trace was loaded from
+    pub path: PathBuf,
+    /// Hash of the file's contents, which the notes about the trace are saved under (see `trace_key`)
+    pub key: String,
+}
+
+impl Trace {
+    /// Declared type of a variable from `#meta.varTypes`, if present
+    pub fn var_type(&self, name: &str) -> Option<&str> {
+        self.meta.var_types.get(name).map(String::as_str)
+    }
+
+    /// State the trace loops back to after its last state, if it is a lasso
+    pub fn loop_target(&self) -> Option<usize> {
+        self.loop_index
+            .map(|i| i as usize)
+            .filter(|i| *i < self.states.len())
+    }
+
+    /// Earlier state the last state repeats, when the trace has no `loop` field: it is then a lasso all the same,
+    /// whose cycle goes on from the state after the repeated one
+    pub fn repeated_last(&self) -> Option<usize> {
+        if self.loop_index.is_some() {
+            return None;
+        }
+        let (last, earlier) = self.states.split_last()?;
+        earlier.iter().position(|state| state.same_values(last))
+    }
+
+    /// State the cycle goes back to after the last state: the loop start, or the one following the state
+    /// the last state repeats
+    pub fn cycle_target(&self) -> Option<usize> {
+        self.loop_target().or_else(|| self.repeated_last().map(|idx| idx + 1))
+    }
+
+    /// State following `idx`, wrapping to the start of the cycle after the last state
+    pub fn next_index(&self, idx: usize) -> Option<usize> {
+        if idx + 1 < self.states.len() {
+            Some(idx + 1)
+        } else {
+            self.cycle_target()
+        }
+    }
+
+    /// Drop the variables matching none of the glob patterns (`*` for any text, `?` for any character),
+    /// except the action of `--mbt` traces. Fails if a pattern matches no variable
+    pub fn focus(&mut self, patterns: &[String]) -> Result<()> {
+        let mut names: Vec<Rc<str>> = self.states.iter().flat_map(|state| state.values.keys().cloned()).collect();
+        names.sort();
+        names.dedup();
+        if let Some(pattern) = patterns.iter().find(|pattern| !names.iter().any(|name| glob_matches(pattern, name))) {
+            bail!("No variable matches {}", pattern);
+        }
+
+        self.keep_matching(patterns);
+        Ok(())
+    }
+
+    /// Drop the variables matching none of the glob patterns like `focus`, without failing over patterns that
+    /// match no variable, to compare a trace with one focused on the same patterns
+    pub fn keep_matching(&mut self, patterns: &[String]) {
+        self.retain_vars(|name| name == MBT_ACTION_VAR || patterns.iter().any(|pattern| glob_matches(pattern, name)));
+    }
+
+    /// Name a variable is shown under instead of its own, if it has an alias
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    /// Spec the trace was generated from (`source` in its `#meta`), as given or else next to the trace file
+    pub fn source_path(&self) -> Option<PathBuf> {
+        let source = Path::new(self.meta.source.as_deref()?);
+        let beside = self.path.parent().map(|dir| dir.join(source));
+        std::iter::once(source.to_path_buf()).chain(beside).find(|path| path.is_file())
+    }
+
+    /// Index of the first state holding the same values as each state, its own index if it is the first one
+    pub fn first_same_states(&self) -> Vec<usize> {
+        let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut firsts = Vec::with_capacity(self.states.len());
+        for (idx, state) in self.states.iter().enumerate() {
+            let candidates = seen.entry(state.values_hash()).or_default();
+            let first = candidates.iter().copied().find(|&other| self.states[other].same_values(state));
+            if first.is_none() {
+                candidates.push(idx);
+            }
+            firsts.push(first.unwrap_or(idx));
+        }
+        firsts
+    }
+
+    /// First state where the trace and a reference trace differ, either in their values or because only one of
+    /// them has it; none if they hold the same states
+    pub fn divergence(&self, reference: &Trace) -> Option<usize> {
+        (0..self.states.len().max(reference.states.len())).find(|&i| match (self.states.get(i), reference.states.get(i)) {
+            (Some(state), Some(other)) => !state.same_values(other),
+            _ => true,
+        })
+    }
+
+    /// Drop the variables matching any of the glob patterns
+    pub fn ignore(&mut self, patterns: &[String]) {
+        self.retain_vars(|name| !patterns.iter().any(|pattern| glob_matches(pattern, name)));
+    }
+
+    /// Keep only the variables whose name passes `keep`
+    fn retain_vars(&mut self, keep: impl Fn(&str) -> bool) {
+        self.vars.retain(|name| keep(name));
+        for state in &mut self.states {
+            state.values.retain(|name, _| keep(name));
+        }
+    }
+
+    /// ITF JSON of a range of states as a trace of its own, keeping the trace `#meta` and the states' own,
+    /// with their indices renumbered. The loop is kept (shifted) only if the range ends with the last state and contains the loop start
+    pub fn excerpt_json(&self, range: RangeInclusive<usize>) -> Result<String> {
+        let (start, end) = (*range.start(), *range.end());
+        let states = self.states.get(range).context("State range out of bounds")?;
+
+        let mut meta = serde_json::to_value(&self.meta).context("Failed to serialize #meta")?;
+        if let serde_json::Value::Object(fields) = &mut meta {
+            fields.retain(|_, value| !value.is_null());
+        }
+
+        let mut raw = serde_json::Map::new();
+        raw.insert("#meta".to_string(), meta);
+        raw.insert("vars".to_string(), serde_json::to_value(&self.vars)?);
+        raw.insert("states".to_string(), serde_json::Value::Array(states.iter().enumerate().map(|(i, state)| state.to_json(i, &self.vars)).collect::<Result<_>>()?));
+        if let Some(target) = self.loop_target().filter(|&target| target >= start && end + 1 == self.states.len()) {
+            raw.insert("loop".to_string(), (target - start).into());
+        }
+        Ok(serde_json::to_string_pretty(&raw)?)
+    }
+
+    /// JSON text of a state as written in the trace file, read again from the file so that it isn't kept in
+    /// memory. Fails if the file changed since the trace was loaded
+    pub fn state_source(&self, state: &State) -> Result<String> {
+        #[derive(Deserialize)]
+        struct RawStates<'a> {
+            #[serde(borrow)]
+            states: Vec<&'a RawValue>,
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .context(format!("Failed to read file: {}", self.path.display()))?;
+        if trace_key(contents.as_bytes()) != self.key {
+            bail!("{} changed since it was loaded", self.path.display());
+        }
+        let raw: RawStates = serde_json::from_str(&contents).context("Failed to parse ITF JSON structure")?;
+        let source = raw.states.get(state.index as usize).context(format!("No state {} in the trace file", state.index))?;
+        Ok(source.get().to_string())
+    }
 }
 
 /// A single state in the trace
 pub struct State {
-    #[allow(dead_code)]
+    /// Position of the state in the trace file
     pub index: u64,
-    pub values: HashMap<String, itf::Value>,
+    /// Values are shared with other states where the variable holds the same value
+    pub values: HashMap<Rc<str>, Rc<itf::Value>>,
+    /// The state's own `#meta` object, if any
+    pub meta: Option<itf::Value>,
+    /// Action that produced the state, kept even when its variable is ignored
+    pub action: Option<String>,
+    /// Values picked by the `nondet` bindings of that action, by name, kept even when their variable is ignored
+    pub choices: Option<itf::Value>,
+}
+
+/// Variable holding the action taken to reach a state in Quint `--mbt` traces
+const MBT_ACTION_VAR: &str = "mbt::actionTaken";
+
+/// Variable holding the values picked by `nondet` bindings in Quint `--mbt` traces
+const MBT_PICKS_VAR: &str = "mbt::nondetPicks";
+
+/// Names of the `#meta` fields holding the values picked by `nondet` bindings
+const PICKS_NAMES: [&str; 2] = ["nondetPicks", "nondet_picks"];
+
+/// Unqualified names of the variables or `#meta` fields holding the action taken
+const ACTION_NAMES: [&str; 3] = ["actionTaken", "action_taken", "action"];
+
+/// Name of an action value: a string, or the tag of a sum type variant
+fn action_name(value: &itf::Value) -> Option<String> {
+    match value {
+        itf::Value::String(action) => Some(action.clone()),
+        itf::Value::Record(fields) => match fields.get("tag") {
+            Some(itf::Value::String(tag)) => Some(tag.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Action that produced a state: the `mbt::actionTaken` variable, else a variable named `actionTaken` or `action_taken`
+/// in any module, else the `actionTaken` or `action` field of `#meta`
+fn find_action(values: &HashMap<Rc<str>, Rc<itf::Value>>, meta: Option<&itf::Value>) -> Option<String> {
+    if let Some(value) = values.get(MBT_ACTION_VAR) {
+        return action_name(value);
+    }
+    let mut qualified: Vec<(&Rc<str>, &Rc<itf::Value>)> = values
+        .iter()
+        .filter(|(name, _)| name.contains("::") && ACTION_NAMES[..2].contains(&name.rsplit("::").next().unwrap_or_default()))
+        .collect();
+    qualified.sort_by(|a, b| a.0.cmp(b.0));
+    if let Some((_, value)) = qualified.first() {
+        return action_name(value);
+    }
+    match meta {
+        Some(itf::Value::Record(fields)) => ACTION_NAMES.iter().find_map(|name| fields.get(name)).and_then(action_name),
+        _ => None,
+    }
+}
+
+/// Values picked by the `nondet` bindings of the action that produced a state: those of the `mbt::nondetPicks`
+/// variable, else of the `nondetPicks` field of `#meta`. Bindings the action didn't reach (`None`) are left out and
+/// the others unwrapped from their `Some`
+fn find_choices(values: &HashMap<Rc<str>, Rc<itf::Value>>, meta: Option<&itf::Value>) -> Option<itf::Value> {
+    let picks = match (values.get(MBT_PICKS_VAR), meta) {
+        (Some(picks), _) => picks.as_ref(),
+        (None, Some(itf::Value::Record(fields))) => PICKS_NAMES.iter().find_map(|name| fields.get(name))?,
+        _ => return None,
+    };
+    let itf::Value::Record(picks) = picks else {
+        return None;
+    };
+    let choices: itf::value::Record = picks
+        .iter()
+        .filter_map(|(name, pick)| match pick {
+            itf::Value::Record(option) if option.len() == 2 && option.get("tag") == Some(&itf::Value::String("None".to_string())) => None,
+            itf::Value::Record(option) if option.len() == 2 && option.get("tag") == Some(&itf::Value::String("Some".to_string())) => {
+                option.get("value").map(|value| (name.clone(), value.clone()))
+            }
+            _ => Some((name.clone(), pick.clone())),
+        })
+        .collect();
+    (!choices.is_empty()).then_some(itf::Value::Record(choices))
+}
+
+/// Order in which top-level variables are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VarOrder {
+    /// Order of the trace's `vars` array
+    #[default]
+    Declaration,
+    /// Sorted by variable name
+    Alphabetical,
+}
+
+impl VarOrder {
+    /// Switch to the other ordering
+    pub fn toggle(self) -> Self {
+        match self {
+            VarOrder::Declaration => VarOrder::Alphabetical,
+            VarOrder::Alphabetical => VarOrder::Declaration,
+        }
+    }
+}
+
+impl State {
+    /// Name of the action that produced this state (MBT metadata)
+    /// Looks at the `mbt::actionTaken` variable first, then at `#meta`
+    pub fn action_taken(&self) -> Option<&str> {
+        self.action.as_deref()
+    }
+
+    /// ITF JSON object of the state at position `index` of a trace, with its `#meta` if any
+    fn to_json(&self, index: usize, vars: &[String]) -> Result<serde_json::Value> {
+        let mut object = serde_json::Map::new();
+        if let Some(meta) = &self.meta {
+            let mut meta = serde_json::to_value(meta)?;
+            if let Some(old) = meta.get_mut("index") {
+                *old = index.into();
+            }
+            object.insert("#meta".to_string(), meta);
+        }
+        for name in self.var_names(vars, VarOrder::Declaration) {
+            let value = serde_json::to_value(self.values[name].as_ref()).context(format!("Failed to serialize variable '{}'", name))?;
+            object.insert(name.to_string(), value);
+        }
+        Ok(serde_json::Value::Object(object))
+    }
+
+    /// Whether both states hold exactly the same variable values (ignoring `#meta`)
+    pub fn same_values(&self, other: &State) -> bool {
+        self.values == other.values
+    }
+
+    /// Hash of the variable values, the same for states with the same values
+    pub fn values_hash(&self) -> u64 {
+        let mut values: Vec<(&Rc<str>, &Rc<itf::Value>)> = self.values.iter().collect();
+        values.sort_by(|a, b| a.0.cmp(b.0));
+        let mut hasher = DefaultHasher::new();
+        values.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Short hash of the variable values to refer to the state by, the same for states with the same values and
+    /// across runs: FNV-1a of the values in ITF JSON, by variable name
+    pub fn fingerprint(&self) -> String {
+        let mut names: Vec<&Rc<str>> = self.values.keys().collect();
+        names.sort();
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for name in names {
+            let json = serde_json::to_string(self.values[name].as_ref()).unwrap_or_default();
+            for byte in name.bytes().chain([0]).chain(json.bytes()).chain([0]) {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+        format!("{:08x}", (hash >> 32) as u32 ^ hash as u32)
+    }
+
+    /// Metadata worth showing in the tree: `#meta` with anything beyond the state index
+    pub fn display_meta(&self) -> Option<&itf::Value> {
+        match &self.meta {
+            Some(itf::Value::Record(fields)) if fields.iter().any(|(k, _)| k != "index") => self.meta.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Variable names of this state in display order
+    /// Declaration order follows `vars`, with undeclared variables appended sorted by name
+    pub fn var_names<'a>(&'a self, vars: &'a [String], order: VarOrder) -> Vec<&'a str> {
+        let mut sorted: Vec<&str> = self.values.keys().map(|name| name.as_ref()).collect();
+        sorted.sort();
+
+        match order {
+            VarOrder::Alphabetical => sorted,
+            VarOrder::Declaration => {
+                let mut names: Vec<&str> = vars
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|name| self.values.contains_key(*name))
+                    .collect();
+                names.extend(sorted.into_iter().filter(|name| !vars.iter().any(|v| v == name)));
+                names
+            }
+        }
+    }
+}
+
+/// Whether a name matches a glob pattern, where `*` stands for any text and `?` for any character
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
 }
 
 /// Raw trace structure for initial JSON parsing
@@ -44,11 +396,12 @@ pub fn load_trace(path: &Path) -> Result<Trace> {
     let raw: RawTrace = serde_json::from_str(&contents)
         .context("Failed to parse ITF JSON structure")?;
 
+    let mut interner = Interner::new();
     let states: Vec<State> = raw
         .states
         .into_iter()
         .enumerate()
-        .map(|(i, state_json)| parse_state(i, state_json))
+        .map(|(i, state_json)| parse_state(i, state_json, &mut interner))
         .collect::<Result<Vec<_>>>()?;
 
     Ok(Trace {
@@ -56,29 +409,41 @@ pub fn load_trace(path: &Path) -> Result<Trace> {
         vars: raw.vars,
         states,
         loop_index: raw.loop_index,
+        aliases: HashMap::new(),
+        path: path.to_path_buf(),
+        key: trace_key(contents.as_bytes()),
     })
 }
 
 /// Parse a single state from its JSON representation
-fn parse_state(index: usize, json: serde_json::Value) -> Result<State> {
+fn parse_state(index: usize, json: serde_json::Value, interner: &mut Interner) -> Result<State> {
     let mut values = HashMap::new();
+    let mut meta = None;
 
-    if let Some(obj) = json.as_object() {
+    if let serde_json::Value::Object(obj) = json {
         for (key, val) in obj {
             if key == "#meta" {
+                let meta_value: itf::Value = serde_json::from_value(val)
+                    .context(format!("Failed to parse #meta in state {}", index))?;
+                meta = Some(meta_value);
                 continue;
             }
 
-            let itf_value: itf::Value = serde_json::from_value(val.clone())
+            let itf_value: itf::Value = serde_json::from_value(val)
                 .context(format!("Failed to parse variable '{}' in state {}", key, index))?;
 
-            values.insert(key.clone(), itf_value);
+            values.insert(interner.name(&key), interner.value(itf_value));
         }
     }
 
+    let action = find_action(&values, meta.as_ref());
+    let choices = find_choices(&values, meta.as_ref());
     Ok(State {
         index: index as u64,
         values,
+        meta,
+        action,
+        choices,
     })
 }
 
@@ -108,6 +473,10 @@ mod tests {
         // Check variables
         assert_eq!(trace.vars, vec!["bank_of_boat", "who_is_on_bank"]);
 
+        // Check variable types
+        assert_eq!(trace.var_type("bank_of_boat"), Some("Str"));
+        assert_eq!(trace.var_type("who_is_on_bank"), Some("Str -> Set(PERSON)"));
+
         // Check state count
         assert_eq!(trace.states.len(), 6);
 
@@ -117,7 +486,7 @@ mod tests {
         assert!(state0.values.contains_key("who_is_on_bank"));
 
         // Check bank_of_boat is a string "E" in state 0
-        if let itf::Value::String(s) = &state0.values["bank_of_boat"] {
+        if let itf::Value::String(s) = state0.values["bank_of_boat"].as_ref() {
             assert_eq!(s, "E");
         } else {
             panic!("Expected bank_of_boat to be a String");
@@ -125,7 +494,7 @@ mod tests {
 
         // Check who_is_on_bank is a Map
         assert!(matches!(
-            &state0.values["who_is_on_bank"],
+            state0.values["who_is_on_bank"].as_ref(),
             itf::Value::Map(_)
         ));
     }
@@ -141,13 +510,14 @@ mod tests {
 
         // Check variables
         assert_eq!(trace.vars, vec!["value"]);
+        assert_eq!(trace.var_type("value"), None);
 
         // Check state count
         assert_eq!(trace.states.len(), 3);
 
         // State 0: value = { tag: "None", value: {} }
         let state0 = &trace.states[0];
-        if let itf::Value::Record(rec) = &state0.values["value"] {
+        if let itf::Value::Record(rec) = state0.values["value"].as_ref() {
             // Check tag field
             if let Some(itf::Value::String(tag)) = rec.get("tag") {
                 assert_eq!(tag, "None");
@@ -160,7 +530,7 @@ mod tests {
 
         // State 1: value = { tag: "Some", value: 40 }
         let state1 = &trace.states[1];
-        if let itf::Value::Record(rec) = &state1.values["value"] {
+        if let itf::Value::Record(rec) = state1.values["value"].as_ref() {
             if let Some(itf::Value::String(tag)) = rec.get("tag") {
                 assert_eq!(tag, "Some");
             }
@@ -216,6 +586,258 @@ mod tests {
         assert!(!trace.vars.is_empty());
     }
 
+    #[test]
+    fn test_load_mbt_metadata() {
+        let path = example_path("dining.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        assert_eq!(trace.states[0].action_taken(), Some("init"));
+        assert_eq!(trace.states[1].action_taken(), Some("becomeHungry"));
+
+        // The action is kept when its variable is ignored
+        let mut trace = load_trace(&path).expect("Failed to load trace");
+        trace.ignore(&["mbt::*".to_string()]);
+        assert!(!trace.states[1].values.contains_key(MBT_ACTION_VAR));
+        assert_eq!(trace.states[1].action_taken(), Some("becomeHungry"));
+
+        // So are the nondet picks, unwrapped from their Some
+        let itf::Value::Record(choices) = trace.states[1].choices.as_ref().expect("No choices") else {
+            panic!("Choices are not a record");
+        };
+        let Some(itf::Value::BigInt(p)) = choices.get("p") else {
+            panic!("Expected the pick of p to be a BigInt");
+        };
+        assert_eq!(p.to_string(), "1");
+
+        // #meta is kept, but an index-only #meta is not worth displaying
+        assert!(trace.states[0].meta.is_some());
+        assert!(trace.states[0].display_meta().is_none());
+    }
+
+    #[test]
+    fn test_parse_state_meta_action() {
+        let json = serde_json::json!({
+            "#meta": { "index": 3, "action": "step" },
+            "x": { "#bigint": "1" }
+        });
+        let state = parse_state(3, json, &mut Interner::new()).expect("Failed to parse state");
+
+        assert_eq!(state.action_taken(), Some("step"));
+        assert!(state.display_meta().is_some());
+        assert!(!state.values.contains_key("#meta"));
+
+        let json = serde_json::json!({
+            "app::action_taken": { "tag": "Propose", "value": { "round": { "#bigint": "2" } } },
+            "x": { "#bigint": "1" }
+        });
+        let state = parse_state(0, json, &mut Interner::new()).expect("Failed to parse state");
+        assert_eq!(state.action_taken(), Some("Propose"));
+        assert!(state.choices.is_none());
+
+        // Picks in #meta, where a binding the action didn't reach is left out
+        let json = serde_json::json!({
+            "#meta": { "index": 1, "nondetPicks": { "v": { "tag": "Some", "value": "n2" }, "r": { "tag": "None", "value": {} } } },
+            "x": { "#bigint": "1" }
+        });
+        let state = parse_state(1, json, &mut Interner::new()).expect("Failed to parse state");
+        let Some(itf::Value::Record(choices)) = &state.choices else {
+            panic!("No choices");
+        };
+        assert_eq!(choices.iter().collect::<Vec<_>>(), vec![(&"v".to_string(), &itf::Value::String("n2".to_string()))]);
+    }
+
+    #[test]
+    fn test_identical_values_are_shared() {
+        let path = example_path("MissionariesAndCannibals.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        // The boat goes E -> W -> E, so states 0 and 2 hold the same value
+        let boat0 = &trace.states[0].values["bank_of_boat"];
+        let boat2 = &trace.states[2].values["bank_of_boat"];
+        assert_eq!(boat0, boat2);
+        assert!(Rc::ptr_eq(boat0, boat2));
+
+        // Variable names are shared between states too
+        let name0 = trace.states[0].values.keys().find(|k| k.as_ref() == "bank_of_boat").unwrap();
+        let name1 = trace.states[1].values.keys().find(|k| k.as_ref() == "bank_of_boat").unwrap();
+        assert!(Rc::ptr_eq(name0, name1));
+    }
+
+    #[test]
+    fn test_next_index_wraps_to_loop() {
+        let path = example_path("MissionariesAndCannibals.itf.json");
+        let mut trace = load_trace(&path).expect("Failed to load trace");
+
+        assert_eq!(trace.next_index(0), Some(1));
+        assert_eq!(trace.next_index(5), None);
+
+        trace.loop_index = Some(2);
+        assert_eq!(trace.next_index(4), Some(5));
+        assert_eq!(trace.next_index(5), Some(2));
+
+        // Out-of-range loop indices are ignored
+        trace.loop_index = Some(6);
+        assert_eq!(trace.next_index(5), None);
+    }
+
+    #[test]
+    fn test_same_values() {
+        let path = example_path("MissionariesAndCannibals.itf.json");
+        let trace = load_trace(&path).expect("Failed to load trace");
+
+        assert!(trace.states[0].same_values(&trace.states[0]));
+        assert!(!trace.states[0].same_values(&trace.states[1]));
+    }
+
+    #[test]
+    fn test_var_names_order() {
+        let path = example_path("MissionariesAndCannibals.itf.json");
+        let mut trace = load_trace(&path).expect("Failed to load trace");
+        trace.vars = vec!["who_is_on_bank".to_string(), "bank_of_boat".to_string()];
+
+        let state0 = &trace.states[0];
+        assert_eq!(
+            state0.var_names(&trace.vars, VarOrder::Declaration),
+            vec!["who_is_on_bank", "bank_of_boat"]
+        );
+        assert_eq!(
+            state0.var_names(&trace.vars, VarOrder::Alphabetical),
+            vec!["bank_of_boat", "who_is_on_bank"]
+        );
+
+        // Without a `vars` array, fall back to sorted names
+        assert_eq!(
+            state0.var_names(&[], VarOrder::Declaration),
+            vec!["bank_of_boat", "who_is_on_bank"]
+        );
+    }
+
+    #[test]
+    fn test_excerpt_json() {
+        let path = example_path("tendermint.itf.json");
+        let mut trace = load_trace(&path).expect("Failed to load trace");
+        trace.loop_index = Some(5);
+        let last = trace.states.len() - 1;
+
+        // The excerpt loads back as a trace of the same states, with the loop shifted
+        let out = std::env::temp_dir().join(format!("excerpt-{}.itf.json", std::process::id()));
+        fs::write(&out, trace.excerpt_json(3..=last).unwrap()).unwrap();
+        let excerpt = load_trace(&out).expect("Failed to load excerpt");
+        fs::remove_file(&out).unwrap();
+        assert_eq!(excerpt.meta, trace.meta);
+        assert_eq!(excerpt.vars, trace.vars);
+        assert_eq!(excerpt.states.len(), last - 2);
+        assert!(excerpt.states.iter().zip(&trace.states[3..]).all(|(a, b)| a.same_values(b)));
+        assert_eq!(excerpt.loop_index, Some(2));
+
+        // The loop is dropped when the excerpt stops before the end or starts after the loop start
+        let json: serde_json::Value = serde_json::from_str(&trace.excerpt_json(3..=last - 1).unwrap()).unwrap();
+        assert!(json.get("loop").is_none());
+        let json: serde_json::Value = serde_json::from_str(&trace.excerpt_json(6..=last).unwrap()).unwrap();
+        assert!(json.get("loop").is_none());
+        assert!(trace.excerpt_json(3..=last + 1).is_err());
+    }
+
+    #[test]
+    fn test_focus() {
+        let path = example_path("clock.itf.json");
+        let mut trace = load_trace(&path).expect("Failed to load trace");
+
+        trace.focus(&["*::m?ssages".to_string(), "*time".to_string()]).unwrap();
+        assert_eq!(trace.vars, vec!["clock_sync4::clock_sync::time", "clock_sync4::clock_sync::messages"]);
+        assert!(trace.states.iter().all(|state| state.values.len() == 2));
+
+        assert!(trace.focus(&["nope*".to_string()]).is_err());
+        trace.ignore(&["*::time".to_string(), "nope".to_string()]);
+        assert_eq!(trace.vars, vec!["clock_sync4::clock_sync::messages"]);
+        assert!(glob_matches("a*b*", "aXbY"));
+        assert!(!glob_matches("a?", "a"));
+    }
+
+    #[test]
+    fn test_divergence() {
+        let trace = load_trace(&example_path("clock.itf.json")).unwrap();
+        assert_eq!(trace.divergence(&trace), None);
+
+        let mut reference = load_trace(&example_path("clock.itf.json")).unwrap();
+        reference.states.truncate(5);
+        assert_eq!(trace.divergence(&reference), Some(5));
+        assert_eq!(reference.divergence(&trace), Some(5));
+
+        reference.states.remove(3);
+        assert_eq!(trace.divergence(&reference), Some(3));
+    }
+
+    #[test]
+    fn test_divergence_focused_reference() {
+        let patterns = ["*::time".to_string(), "*::m?ssages".to_string()];
+        let mut trace = load_trace(&example_path("clock.itf.json")).unwrap();
+        trace.focus(&patterns).unwrap();
+
+        // The reference lacks a focused variable: its other variables are left out all the same
+        let mut reference = load_trace(&example_path("clock.itf.json")).unwrap();
+        reference.ignore(&["*::messages".to_string()]);
+        assert!(reference.focus(&patterns).is_err());
+        assert_eq!(reference.vars.len(), 2);
+        reference.keep_matching(&patterns);
+        assert_eq!(reference.vars, vec!["clock_sync4::clock_sync::time"]);
+
+        trace.ignore(&["*::messages".to_string()]);
+        assert_eq!(trace.divergence(&reference), None);
+    }
+
+    #[test]
+    fn test_repeated_last() {
+        let mut trace = load_trace(&example_path("clock.itf.json")).unwrap();
+        assert_eq!((trace.repeated_last(), trace.cycle_target()), (None, None));
+
+        // The last state repeats state 3: the cycle goes on with state 4
+        trace.states.extend(load_trace(&example_path("clock.itf.json")).unwrap().states.into_iter().nth(2));
+        let last = trace.states.len() - 1;
+        assert_eq!((trace.repeated_last(), trace.cycle_target(), trace.next_index(last)), (Some(2), Some(3), Some(3)));
+
+        // A declared loop takes precedence
+        trace.loop_index = Some(1);
+        assert_eq!((trace.repeated_last(), trace.cycle_target()), (None, Some(1)));
+    }
+
+    #[test]
+    fn test_first_same_states() {
+        let mut trace = load_trace(&example_path("clock.itf.json")).unwrap();
+        assert_eq!(trace.first_same_states(), (0..trace.states.len()).collect::<Vec<_>>());
+
+        // A state repeated later points back to its first occurrence
+        trace.states.extend(load_trace(&example_path("clock.itf.json")).unwrap().states.into_iter().take(2));
+        let firsts = trace.first_same_states();
+        assert_eq!(firsts[firsts.len() - 2..], [0, 1]);
+    }
+
+    #[test]
+    fn test_source_path() {
+        let trace = load_trace(&example_path("clock.itf.json")).unwrap();
+        assert_eq!(trace.meta.source.as_deref(), Some("clockSync6.qnt"));
+        assert_eq!(trace.source_path(), None);
+
+        let dir = std::env::temp_dir().join(format!("source-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(example_path("clock.itf.json"), dir.join("clock.itf.json")).unwrap();
+        fs::write(dir.join("clockSync6.qnt"), "module clockSync6 {}").unwrap();
+        let source = load_trace(&dir.join("clock.itf.json")).unwrap().source_path();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(source, Some(dir.join("clockSync6.qnt")));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let trace = load_trace(&example_path("clock.itf.json")).unwrap();
+        let again = load_trace(&example_path("clock.itf.json")).unwrap();
+        let fingerprints: Vec<String> = trace.states.iter().map(State::fingerprint).collect();
+        assert_eq!(fingerprints[0].len(), 8);
+        assert_eq!(fingerprints, again.states.iter().map(State::fingerprint).collect::<Vec<_>>());
+        assert_eq!(fingerprints.iter().collect::<std::collections::HashSet<_>>().len(), trace.states.len());
+        assert_eq!(fingerprints[0], "3bff6908");
+    }
+
     #[test]
     fn test_nonexistent_file() {
         let path = example_path("nonexistent.itf.json");
@@ -1,3 +1,5 @@
+mod intern;
+#[allow(clippy::module_inception)]
 mod loader;
 
-pub use loader::{load_trace, Trace};
+pub use loader::{load_trace, State, Trace, VarOrder};
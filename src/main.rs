@@ -1,46 +1,632 @@
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::io::IsTerminal;
 use std::path::PathBuf;
 
 mod app;
+mod clipboard;
+mod color;
+mod config;
 mod diff;
+mod export;
+mod keymap;
 mod loader;
+mod panel;
+mod prefs;
+mod query;
+mod quint;
+mod recent;
+mod search;
+mod session;
+mod snapshot;
+mod sparkline;
+mod stats;
 mod theme;
 mod tree;
+mod watch;
 
-use loader::load_trace;
+use app::PinnedExpansion;
+use color::ColorChoice;
+use config::{apply_env, default_config_path, env_config_path, load_config, Config};
+use keymap::Keymap;
+use loader::{load_trace, Trace};
+use prefs::load_preferences;
+use query::{parse_expr, parse_path, Expr, PathQuery};
+use recent::{add_recent, load_recent};
+use session::{load_session, Session};
+use theme::{load_theme, Theme, ThemeName};
+use tree::root_value;
+use watch::{newest_trace, Watcher};
 
 #[derive(Parser, Debug)]
 #[command(name = "quint-trace-explorer")]
 #[command(about = "Interactive CLI tool for exploring Quint/Apalache ITF traces")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to the ITF trace file (JSON)
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the ITF trace file (JSON), picked among the recently opened ones when not given
     #[arg(value_name = "FILE")]
-    trace_file: PathBuf,
+    trace_file: Option<PathBuf>,
 
-    /// Auto-expand changed variables when navigating between states
-    #[arg(short, long, default_value_t = true)]
+    /// Auto-expand changed variables when navigating between states (the default)
+    #[arg(short, long)]
     auto_expand: bool,
+
+    /// Keep the expanded nodes when navigating between states
+    #[arg(long, conflicts_with = "auto_expand")]
+    no_auto_expand: bool,
+
+    /// Path to a config file (TOML, or JSON for a `.json` file), instead of the default one
+    #[arg(short, long, value_name = "CONFIG")]
+    config: Option<PathBuf>,
+
+    /// State to open the explorer at, numbered from 1 as in the explorer
+    #[arg(long, value_name = "N", conflicts_with = "start_at_end")]
+    start_state: Option<usize>,
+
+    /// Open the explorer at the last state
+    #[arg(long)]
+    start_at_end: bool,
+
+    /// Trace known to be good, to open the explorer at the first state where the trace differs from it
+    /// and list the differences with `R`
+    #[arg(long, value_name = "FILE")]
+    reference: Option<PathBuf>,
+
+    /// Color theme, instead of the one from the config file or the dark one
+    #[arg(long, value_enum)]
+    theme: Option<ThemeName>,
+
+    /// TOML file defining a custom color theme
+    #[arg(long, value_name = "PATH", conflicts_with = "theme")]
+    theme_file: Option<PathBuf>,
+
+    /// When to use colors: `auto` detects what the terminal supports and respects NO_COLOR
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Key bindings, instead of the ones from the config file or the default ones
+    #[arg(long, value_enum)]
+    keys: Option<Keymap>,
+
+    /// Only show the variables matching a glob pattern like `*::consensus::*`, repeated for more patterns
+    #[arg(long = "focus", value_name = "VAR")]
+    focus: Vec<String>,
+
+    /// Resume the session saved with `:session save NAME` in the explorer, on the trace it was saved on unless
+    /// another one is given
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["start_state", "start_at_end"])]
+    session: Option<String>,
+
+    /// Keep every node expanded
+    #[arg(long)]
+    expand_all: bool,
+
+    /// Keep the node at a path like `system["n1"].mempool` expanded, repeated for more nodes
+    #[arg(long = "expand", value_name = "PATH")]
+    expand: Vec<String>,
+
+    /// Queue the `*.itf.json` traces appearing in a directory, like those of a long `quint run`, to review them
+    /// with `W` as they arrive; without a FILE, start with the newest one there
+    #[arg(long, value_name = "DIR")]
+    watch_dir: Option<PathBuf>,
+}
+
+/// Commands that print to stdout instead of opening the explorer
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the changes from one state to another as a unified diff patch
+    Diff {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// State to compare from, numbered from 1 as in the explorer
+        from: usize,
+
+        /// State to compare to
+        to: usize,
+
+        /// Output format: a patch, or JSON with the path and values of each change
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// Path to a config file with custom value formatters and variable aliases, instead of the default one
+        #[arg(short, long, value_name = "CONFIG")]
+        config: Option<PathBuf>,
+    },
+    /// Write the values at some paths in each state as CSV, to plot them in other tools
+    ExportCsv {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// Path of a value to export, like `system["n1"].height`, repeated for more columns
+        #[arg(long = "path", value_name = "PATH", required = true)]
+        paths: Vec<String>,
+
+        /// CSV file to write, instead of printing to stdout
+        #[arg(value_name = "OUT")]
+        output: Option<PathBuf>,
+    },
+    /// Print a self-contained HTML page to browse the trace, to share it with people without the explorer
+    ExportHtml {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// Path to a config file with custom value formatters and variable aliases, instead of the default one
+        #[arg(short, long, value_name = "CONFIG")]
+        config: Option<PathBuf>,
+    },
+    /// Print the graph of the trace's states and transitions in Graphviz DOT format
+    ExportDot {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+    },
+    /// Print a Mermaid sequence diagram of the messages sent over the trace
+    ExportMermaid {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// Variable holding the messages, as a path like `s.messages`, instead of the first one found
+        #[arg(long, value_name = "PATH")]
+        var: Option<String>,
+    },
+    /// Print the tree of a state, or of a value in it, as shown in the explorer
+    Show {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// State to show, numbered from 1 as in the explorer
+        state: usize,
+
+        /// Path of the value to show, like `system["n1"].mempool`, instead of the whole state
+        #[arg(long, value_name = "PATH")]
+        path: Option<String>,
+
+        /// Number of levels to expand, instead of all of them
+        #[arg(long, value_name = "D")]
+        depth: Option<usize>,
+
+        /// Path to a config file with custom value formatters and variable aliases, instead of the default one
+        #[arg(short, long, value_name = "CONFIG")]
+        config: Option<PathBuf>,
+    },
+    /// Print the states where a predicate holds, with the values it refers to, for scripts and CI checks
+    /// Exits with status 1 when no state matches
+    Query {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// Predicate in the syntax of `:find`, like `system["n1"].height > 3 && round == 0`
+        #[arg(value_name = "EXPR")]
+        predicate: String,
+
+        /// Output format: a line per state, or a JSON array
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Check that a predicate holds in every state (or in one of them), for trace sanity checks in CI
+    /// Exits with status 1, listing the states where it does not hold, when it fails
+    Assert {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// Predicate in the syntax of `:find`, like `size(decided) <= 1`
+        #[arg(value_name = "EXPR")]
+        predicate: String,
+
+        /// Only check this state, numbered from 1 as in the explorer
+        #[arg(long, value_name = "N", conflicts_with = "last")]
+        state: Option<usize>,
+
+        /// Only check the last state
+        #[arg(long)]
+        last: bool,
+
+        /// Output format: a message and the failing states, or a JSON object with the outcome and failing states, the
+        /// machine-readable check of a trace (there is no separate `validate` command)
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Save a range of states as a new ITF trace, to share an excerpt of a long one
+    Trim {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// First state to keep, numbered from 1 as in the explorer
+        from: usize,
+
+        /// Last state to keep
+        to: usize,
+
+        /// ITF file to write, instead of printing to stdout
+        #[arg(value_name = "OUT")]
+        output: Option<PathBuf>,
+    },
+    /// Print a Markdown report of the trace: metadata, a summary table and the changes of each state
+    Report {
+        /// Path to the ITF trace file (JSON)
+        #[arg(value_name = "FILE")]
+        trace_file: PathBuf,
+
+        /// Output format: Markdown, or JSON with the metadata, variable statistics and transitions, the statistics for
+        /// other tools (there is no separate `stats` command)
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// Path to a config file with custom value formatters and variable aliases, instead of the default one
+        #[arg(short, long, value_name = "CONFIG")]
+        config: Option<PathBuf>,
+    },
+}
+
+/// Output format of the commands printing results for other tools
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
 }
 
 fn main() {
     let args = Args::parse();
 
-    println!("Loading trace from: {:?}", args.trace_file);
+    if let Some(command) = args.command {
+        run_command(command);
+        return;
+    }
+
+    // Piped to a file or a pager, print the tree instead of opening the explorer
+    let interactive = std::io::stdout().is_terminal();
+    let mut config = config_or_exit(args.config.as_ref());
+    if args.theme.is_some() {
+        config.theme = args.theme;
+        config.theme_file = None;
+    }
+    if args.theme_file.is_some() {
+        config.theme_file = args.theme_file;
+    }
+    config.keys = args.keys.or(config.keys);
+    let theme = theme_or_exit(&config).for_terminal(args.color.support());
 
-    if !args.trace_file.exists() {
-        eprintln!("Error: File not found: {:?}", args.trace_file);
+    // Traces already in the watched directory are not queued
+    let watch = args.watch_dir.as_ref().map(|dir| Watcher::new(dir).unwrap_or_else(|e| {
+        eprintln!("Error: {:#}", e);
         std::process::exit(1);
+    }));
+    let session = args.session.as_deref().map(session_or_exit);
+    // A session reopens the trace it was saved on
+    let saved_trace = session.as_ref().and_then(|session| session.trace.clone());
+    let trace_file = match (args.trace_file.or(saved_trace), &args.watch_dir) {
+        (Some(trace_file), _) => trace_file,
+        (None, Some(dir)) => newest_trace(dir).unwrap_or_else(|e| {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }),
+        (None, None) => pick_recent_or_exit(interactive, &theme),
+    };
+    if interactive {
+        println!("Loading trace from: {:?}", trace_file);
+        println!("Loading trace...");
     }
 
-    println!("Loading trace...");
+    let mut trace = trace_or_exit(&trace_file);
+    trace.ignore(&config.ignore);
+    trace.aliases = config.aliases.clone();
+    if !args.focus.is_empty() {
+        if let Err(e) = trace.focus(&args.focus) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+    // Compared with the same variables left out
+    let reference = args.reference.as_ref().map(|path| {
+        let mut reference = trace_or_exit(path);
+        reference.ignore(&config.ignore);
+        if !args.focus.is_empty() {
+            reference.keep_matching(&args.focus);
+        }
+        reference
+    });
+    let last = trace.states.len().saturating_sub(1);
+    let start_state = match (args.start_state, &session) {
+        (Some(number), _) => state_index_or_exit(&trace, number),
+        (None, Some(session)) => session.state.min(last),
+        (None, None) if args.start_at_end => last,
+        (None, None) => reference.as_ref().and_then(|reference| trace.divergence(reference)).map_or(0, |idx| idx.min(last)),
+    };
+    if !interactive {
+        if !trace.states.is_empty() {
+            print!("{}", export::state_tree(&trace, &config.formatters, start_state, None, None));
+        }
+        return;
+    }
+    let pinned = PinnedExpansion { all: args.expand_all, paths: args.expand.iter().map(|text| path_or_exit(&trace, text)).collect() };
+    // The list of recent files is a convenience, not worth failing over
+    let _ = add_recent(&trace_file);
+    let preferences = load_preferences();
+    let auto_expand = args.auto_expand || (!args.no_auto_expand && preferences.auto_expand.or(config.auto_expand).unwrap_or(true));
+    if let Err(e) = app::run(trace, auto_expand, config, theme, start_state, pinned, preferences, session, reference, watch) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
 
-    match load_trace(&args.trace_file) {
-        Ok(trace) => {
-            if let Err(e) = app::run(trace, args.auto_expand) {
-                eprintln!("Error: {}", e);
+fn run_command(command: Command) {
+    match command {
+        Command::Diff { trace_file, from, to, format, config } => {
+            let config = config_or_exit(config.as_ref());
+            let mut trace = trace_or_exit(&trace_file);
+            trace.aliases = config.aliases;
+            let from = state_index_or_exit(&trace, from);
+            let to = state_index_or_exit(&trace, to);
+            match format {
+                Format::Text => print!("{}", export::transition_patch(&trace, &config.formatters, from, to)),
+                Format::Json => println!("{}", json_or_exit(export::transition_json(&trace, from, to))),
+            }
+        }
+        Command::Report { trace_file, format, config } => {
+            let config = config_or_exit(config.as_ref());
+            let mut trace = trace_or_exit(&trace_file);
+            trace.aliases = config.aliases;
+            let name = trace_file.file_name().unwrap_or(trace_file.as_os_str()).to_string_lossy();
+            match format {
+                Format::Text => print!("{}", export::markdown_report(&trace, &config.formatters, &format!("Trace {}", name))),
+                Format::Json => println!("{}", json_or_exit(export::report_json(&trace))),
+            }
+        }
+        Command::ExportDot { trace_file } => {
+            let trace = trace_or_exit(&trace_file);
+            print!("{}", export::dot_graph(&trace));
+        }
+        Command::ExportMermaid { trace_file, var } => {
+            let trace = trace_or_exit(&trace_file);
+            let query = match var.as_deref().map(parse_path).transpose() {
+                Ok(query) => query,
+                Err(e) => {
+                    eprintln!("Error: Invalid path: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            match export::mermaid_sequence(&trace, query.as_ref()) {
+                Ok(diagram) => print!("{}", diagram),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::ExportCsv { trace_file, paths, output } => {
+            let trace = trace_or_exit(&trace_file);
+            let queries = match paths.into_iter().map(|text| parse_path(&text).map(|query| (text, query))).collect::<anyhow::Result<Vec<_>>>() {
+                Ok(queries) => queries,
+                Err(e) => {
+                    eprintln!("Error: Invalid path: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            let csv = match export::csv_table(&trace, &queries) {
+                Ok(csv) => csv,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            write_or_print(output.as_ref(), &csv);
+        }
+        Command::Show { trace_file, state, path, depth, config } => {
+            let config = config_or_exit(config.as_ref());
+            let mut trace = trace_or_exit(&trace_file);
+            trace.aliases = config.aliases;
+            let state = state_index_or_exit(&trace, state);
+            let path = match path.as_deref().map(|text| parse_path(text).and_then(|query| query.resolve(&trace.states[state]))).transpose() {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            print!("{}", export::state_tree(&trace, &config.formatters, state, path.as_ref(), depth));
+        }
+        Command::Query { trace_file, predicate, format } => {
+            let trace = trace_or_exit(&trace_file);
+            let expr = expr_or_exit(&predicate);
+            let matches = export::query_matches(&trace, &expr);
+            match format {
+                Format::Text => print!("{}", export::query_text(&trace, &matches)),
+                Format::Json => println!("{}", json_or_exit(export::query_json(&trace, &matches))),
+            }
+            if matches.is_empty() {
                 std::process::exit(1);
             }
         }
+        Command::Assert { trace_file, predicate, state, last, format } => {
+            let trace = trace_or_exit(&trace_file);
+            if trace.states.is_empty() {
+                eprintln!("Error: The trace has no states to check {} in", predicate);
+                std::process::exit(1);
+            }
+            let mut expr = expr_or_exit(&predicate);
+            // `last.x == 0` checks the last state, unless the trace has a variable named `last`
+            let last = if root_value(&trace.states[0], "last").is_none() {
+                match expr.strip_last_prefix() {
+                    Ok(prefixed) => last || prefixed,
+                    Err(e) => {
+                        eprintln!("Error: Invalid predicate: {:#}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                last
+            };
+            let states: Vec<usize> = match state {
+                Some(number) => vec![state_index_or_exit(&trace, number)],
+                None if last => trace.states.len().checked_sub(1).into_iter().collect(),
+                None => (0..trace.states.len()).collect(),
+            };
+            let failures = export::assertion_failures(&trace, &expr, &states);
+            if format == Format::Json {
+                println!("{}", json_or_exit(export::assertion_json(&trace, &predicate, states.len(), &failures)));
+                if !failures.is_empty() {
+                    std::process::exit(1);
+                }
+            } else if failures.is_empty() {
+                println!("{} holds in {} state(s)", predicate, states.len());
+            } else {
+                eprintln!("Assertion failed: {} does not hold in {} state(s)", predicate, failures.len());
+                eprint!("{}", export::query_text(&trace, &failures));
+                std::process::exit(1);
+            }
+        }
+        Command::Trim { trace_file, from, to, output } => {
+            let trace = trace_or_exit(&trace_file);
+            let from = state_index_or_exit(&trace, from);
+            let to = state_index_or_exit(&trace, to);
+            if from > to {
+                eprintln!("Error: State {} comes after state {}", from + 1, to + 1);
+                std::process::exit(1);
+            }
+            match trace.excerpt_json(from..=to) {
+                Ok(json) => write_or_print(output.as_ref(), &format!("{}\n", json)),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::ExportHtml { trace_file, config } => {
+            let config = config_or_exit(config.as_ref());
+            let mut trace = trace_or_exit(&trace_file);
+            trace.aliases = config.aliases;
+            let name = trace_file.file_name().unwrap_or(trace_file.as_os_str()).to_string_lossy();
+            print!("{}", export::html_page(&trace, &config.formatters, &format!("Trace {}", name)));
+        }
+    }
+}
+
+/// Write a command's output to a file if one is given, or else print it, exiting with an error message on failure
+fn write_or_print(output: Option<&PathBuf>, text: &str) {
+    match output {
+        Some(output) => {
+            if let Err(e) = std::fs::write(output, text) {
+                eprintln!("Error writing {:?}: {}", output, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+}
+
+/// JSON output of a command, exiting with an error message if values could not be serialized
+fn json_or_exit(json: anyhow::Result<String>) -> String {
+    json.unwrap_or_else(|e| {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Parse a predicate, exiting with an error message if it is invalid
+fn expr_or_exit(text: &str) -> Expr {
+    match parse_expr(text) {
+        Ok(expr) => expr,
+        Err(e) => {
+            eprintln!("Error: Invalid predicate: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse a path, exiting with an error message if it is invalid or points at nothing in any state
+fn path_or_exit(trace: &Trace, text: &str) -> PathQuery {
+    let query = match parse_path(text) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("Error: Invalid path {}: {:#}", text, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = query.check_in_states(&trace.states) {
+        eprintln!("Error: {} is in no state: {:#}", text, e);
+        std::process::exit(1);
+    }
+    query
+}
+
+/// Load the config file if one is given (with `--config` or `QTE_CONFIG`), or else the default one if it exists,
+/// overridden by the `QTE_*` environment variables, exiting with an error message if it is invalid
+fn config_or_exit(path: Option<&PathBuf>) -> Config {
+    let env_path = env_config_path();
+    let default_path = default_config_path().filter(|path| path.exists());
+    let config = match path.or(env_path.as_ref()).or(default_path.as_ref()) {
+        Some(path) => load_config(path),
+        None => Ok(Config::default()),
+    };
+    match config.and_then(|mut config| apply_env(&mut config).map(|()| config)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading config: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Theme from the config, loading its theme file if any, exiting with an error message if it is invalid
+fn theme_or_exit(config: &Config) -> Theme {
+    match &config.theme_file {
+        Some(path) => match load_theme(path) {
+            Ok(theme) => theme,
+            Err(e) => {
+                eprintln!("Error loading theme: {:#}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Theme::builtin(config.theme.unwrap_or_default()),
+    }
+}
+
+/// Trace picked among the recently opened ones, exiting with clap's usage error when there are none to pick from
+fn pick_recent_or_exit(interactive: bool, theme: &Theme) -> PathBuf {
+    let recent = if interactive { load_recent() } else { Vec::new() };
+    if recent.is_empty() {
+        Args::command().error(ErrorKind::MissingRequiredArgument, "the following required arguments were not provided:\n  <FILE>").exit();
+    }
+    match app::pick_file(&recent, theme) {
+        Ok(Some(trace_file)) => trace_file,
+        Ok(None) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Load a saved session, exiting with an error message if there is none with that name
+fn session_or_exit(name: &str) -> Session {
+    match load_session(name) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Load the trace file, exiting with an error message if it is missing or invalid
+fn trace_or_exit(path: &PathBuf) -> Trace {
+    if !path.exists() {
+        eprintln!("Error: File not found: {:?}", path);
+        std::process::exit(1);
+    }
+    match load_trace(path) {
+        Ok(trace) => trace,
         Err(e) => {
             eprintln!("Error loading trace: {}", e);
             std::process::exit(1);
@@ -48,3 +634,12 @@ fn main() {
     }
 }
 
+/// Index of a state given by its number (from 1), exiting with an error message if there is no such state
+fn state_index_or_exit(trace: &Trace, number: usize) -> usize {
+    if number == 0 || number > trace.states.len() {
+        eprintln!("Error: No state {}, the trace has states 1 to {}", number, trace.states.len());
+        std::process::exit(1);
+    }
+    number - 1
+}
+
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use ratatui::layout::Rect;
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Borders, Clear, Paragraph};
+
+use crate::theme::Theme;
+use crate::tree::NodePath;
+
+/// An entry of a list panel, pointing at a state and optionally a node in it, or at a trace file in lists of traces
+pub struct PanelItem {
+    pub label: String,
+    pub state: usize,
+    pub path: Option<NodePath>,
+    pub file: Option<PathBuf>,
+}
+
+/// What `Enter` does on the selected item of a list panel
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PanelAction {
+    /// Close the panel and go to the item's state and node
+    #[default]
+    Jump,
+    /// Show or hide the variable at the item's path, keeping the panel open
+    ToggleVar,
+    /// Close the panel and compare the item's state with the previous one in the side-by-side view
+    Compare,
+    /// Close the panel and explore the trace of the watched directory's queue at the selected position
+    OpenQueued,
+}
+
+/// A selectable list shown as a popup on top of the tree
+pub struct ListPanel {
+    pub title: String,
+    pub items: Vec<PanelItem>,
+    pub selected: usize,
+    pub action: PanelAction,
+}
+
+impl ListPanel {
+    pub fn new(title: impl Into<String>, items: Vec<PanelItem>) -> Self {
+        Self { title: title.into(), items, selected: 0, action: PanelAction::default() }
+    }
+
+    /// Move the selection by `delta` items, staying within bounds
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.items.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    pub fn select_first(&mut self) {
+        self.selected = 0;
+    }
+
+    pub fn select_last(&mut self) {
+        self.selected = self.items.len().saturating_sub(1);
+    }
+}
+
+/// Area of the popup: centered, taking most of the screen below the header
+pub fn panel_area(area: Rect) -> Rect {
+    let width = area.width.saturating_sub(area.width / 10).max(20).min(area.width);
+    let height = area.height.saturating_sub(4).max(3).min(area.height);
+    let x = area.x + (area.width - width) / 2;
+    let y = area.y + (area.height - height).min(2);
+    Rect::new(x, y, width, height)
+}
+
+/// Number of list rows visible inside the popup
+pub fn visible_rows(area: Rect) -> usize {
+    panel_area(area).height.saturating_sub(2) as usize
+}
+
+/// Render a list panel as a popup, scrolled so that the selection is visible
+pub fn render_panel(frame: &mut Frame, panel: &ListPanel, theme: &Theme) {
+    let area = panel_area(frame.area());
+    let rows = visible_rows(frame.area());
+    let offset = (panel.selected + 1).saturating_sub(rows);
+
+    let lines: Vec<Line> = panel
+        .items
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .take(rows)
+        .map(|(i, item)| {
+            let style = if i == panel.selected {
+                theme.cursor_style()
+            } else {
+                Style::default()
+            };
+            Line::styled(item.label.clone(), style)
+        })
+        .collect();
+
+    let title = format!(" {} ({}/{}) ", panel.title, panel.selected + 1, panel.items.len());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.focused_border))
+        .title(title);
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::loader::VarOrder;
+use crate::tree::TypeDisplay;
+
+/// View toggles changed in the explorer, remembered across runs so that it reopens the way it was left
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// Auto-expand changed variables, once toggled with `a`, unless `--auto-expand` or `--no-auto-expand` is given
+    pub auto_expand: Option<bool>,
+    pub wrap: bool,
+    pub guides: bool,
+    pub var_order: VarOrder,
+    pub type_display: TypeDisplay,
+    pub group_modules: bool,
+    /// Side-by-side view: panels stacked top and bottom
+    pub stacked: bool,
+    /// Side-by-side view: a single unified diff panel
+    pub unified: bool,
+}
+
+/// Directory of the files kept between runs: `quint-trace-explorer` in `$XDG_STATE_HOME`, or else in `~/.local/state`
+pub fn state_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(dir.join("quint-trace-explorer"))
+}
+
+/// File the preferences are kept in
+fn preferences_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("state.toml"))
+}
+
+/// Preferences saved by the last run, the defaults when there are none or they can't be read
+pub fn load_preferences() -> Preferences {
+    preferences_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Save the preferences for the next run
+pub fn save_preferences(preferences: &Preferences) -> Result<()> {
+    let Some(path) = preferences_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, toml::to_string(preferences)?).context(format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferences_format() {
+        let preferences = Preferences {
+            auto_expand: Some(false),
+            wrap: true,
+            var_order: VarOrder::Alphabetical,
+            type_display: TypeDisplay::Hidden,
+            ..Preferences::default()
+        };
+        let text = toml::to_string(&preferences).unwrap();
+        assert!(text.contains("var_order = \"alphabetical\"\n"));
+        assert_eq!(toml::from_str::<Preferences>(&text).unwrap(), preferences);
+
+        // Files from older versions miss newer fields
+        let partial: Preferences = toml::from_str("wrap = true").unwrap();
+        assert_eq!(partial, Preferences { wrap: true, ..Preferences::default() });
+    }
+}
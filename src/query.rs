@@ -0,0 +1,683 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use anyhow::{bail, Result};
+
+use crate::loader::State;
+use crate::tree::{display_path, root_value, value_at, NodePath};
+
+/// One step of a path query below the variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// `.field` of a record
+    Field(String),
+    /// `[key]` of a map, or `[n]` of a list, tuple or set
+    Index(itf::Value),
+}
+
+/// A path like `system["n1"].mempool[2].tx`, in the syntax shown by `display_path`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathQuery {
+    pub var: String,
+    pub steps: Vec<Step>,
+}
+
+impl PathQuery {
+    /// Resolve the query to the tree path of the node it points at in a state
+    pub fn resolve(&self, state: &State) -> Result<NodePath> {
+        let Some(mut value) = root_value(state, &self.var) else {
+            bail!("No variable '{}'", self.var);
+        };
+
+        let mut path = vec![self.var.clone()];
+        for step in &self.steps {
+            let (segment, child) = match (step, value) {
+                (Step::Field(name), itf::Value::Record(fields)) => match fields.get(name) {
+                    Some(child) => (name.clone(), child),
+                    None => bail!("No field '{}' in {}", name, display_path(state, &path)),
+                },
+                (Step::Index(key), itf::Value::Map(pairs)) => {
+                    match pairs.iter().enumerate().find(|(_, (k, _))| values_eq(k, key)) {
+                        Some((i, (_, child))) => (i.to_string(), child),
+                        None => bail!("No key {} in {}", format_literal(key), display_path(state, &path)),
+                    }
+                }
+                (Step::Index(index), itf::Value::List(items)) => {
+                    let i = position(index)?;
+                    (i.to_string(), items.get(i).ok_or_else(|| out_of_range(i, items.len()))?)
+                }
+                (Step::Index(index), itf::Value::Tuple(items)) => {
+                    let i = position(index)?;
+                    (i.to_string(), items.iter().nth(i).ok_or_else(|| out_of_range(i, items.len()))?)
+                }
+                (Step::Index(index), itf::Value::Set(items)) => {
+                    let i = position(index)?;
+                    (i.to_string(), items.iter().nth(i).ok_or_else(|| out_of_range(i, items.iter().count()))?)
+                }
+                (Step::Field(name), _) => bail!("Cannot take field '{}' of a non-record value", name),
+                (Step::Index(key), _) => bail!("Cannot index a non-collection value with {}", format_literal(key)),
+            };
+            path.push(segment);
+            value = child;
+        }
+
+        Ok(path)
+    }
+
+    /// Value the query points at in a state, if any
+    pub fn value<'a>(&self, state: &'a State) -> Option<&'a itf::Value> {
+        value_at(state, &self.resolve(state).ok()?)
+    }
+
+    /// Check that the query points at a node in some state, failing with the reason it does not in the first one
+    /// otherwise, to report a mistyped path
+    pub fn check_in_states(&self, states: &[State]) -> Result<()> {
+        let mut resolved = states.iter().map(|state| self.resolve(state));
+        match resolved.next() {
+            Some(Err(e)) if !resolved.any(|path| path.is_ok()) => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Comparison operators of predicates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A predicate over a state, e.g. `system["n1"].height > 3 && round == 0`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(itf::Value),
+    Path(PathQuery),
+    Size(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    In(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Whether the predicate holds in a state
+    /// Comparisons involving a path missing from the state, or values of different kinds, are false
+    pub fn holds(&self, state: &State) -> bool {
+        matches!(self.eval(state).as_deref(), Some(itf::Value::Bool(true)))
+    }
+
+    fn eval<'a>(&'a self, state: &'a State) -> Option<Cow<'a, itf::Value>> {
+        let boolean = |b: bool| Some(Cow::Owned(itf::Value::Bool(b)));
+        match self {
+            Expr::Literal(value) => Some(Cow::Borrowed(value)),
+            Expr::Path(query) => query.value(state).map(Cow::Borrowed),
+            Expr::Size(inner) => {
+                let size = size(&*inner.eval(state)?)?;
+                Some(Cow::Owned(itf::Value::Number(size as i64)))
+            }
+            Expr::Not(inner) => boolean(!inner.holds(state)),
+            Expr::And(lhs, rhs) => boolean(lhs.holds(state) && rhs.holds(state)),
+            Expr::Or(lhs, rhs) => boolean(lhs.holds(state) || rhs.holds(state)),
+            Expr::Compare(lhs, op, rhs) => boolean(compare(&*lhs.eval(state)?, *op, &*rhs.eval(state)?)?),
+            Expr::In(item, collection) => boolean(contains(&*collection.eval(state)?, &*item.eval(state)?)),
+        }
+    }
+
+    /// Paths the predicate refers to, in order of appearance
+    pub fn paths(&self) -> Vec<&PathQuery> {
+        match self {
+            Expr::Literal(_) => Vec::new(),
+            Expr::Path(query) => vec![query],
+            Expr::Size(inner) | Expr::Not(inner) => inner.paths(),
+            Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) | Expr::Compare(lhs, _, rhs) | Expr::In(lhs, rhs) => {
+                let mut paths = lhs.paths();
+                paths.extend(rhs.paths());
+                paths
+            }
+        }
+    }
+
+    fn paths_mut(&mut self) -> Vec<&mut PathQuery> {
+        match self {
+            Expr::Literal(_) => Vec::new(),
+            Expr::Path(query) => vec![query],
+            Expr::Size(inner) | Expr::Not(inner) => inner.paths_mut(),
+            Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) | Expr::Compare(lhs, _, rhs) | Expr::In(lhs, rhs) => {
+                let mut paths = lhs.paths_mut();
+                paths.extend(rhs.paths_mut());
+                paths
+            }
+        }
+    }
+
+    /// Drop the `last.` in front of the paths of a predicate over the last state, like `last.x == 0`, returning
+    /// whether there was one; fails when only some of the paths have it
+    pub fn strip_last_prefix(&mut self) -> Result<bool> {
+        let mut paths = self.paths_mut();
+        let prefixed = paths.iter().filter(|query| query.var == "last" && matches!(query.steps.first(), Some(Step::Field(_)))).count();
+        if prefixed == 0 {
+            return Ok(false);
+        }
+        if prefixed < paths.len() {
+            bail!("Either all paths or none start with 'last.'");
+        }
+        for query in paths.iter_mut() {
+            let Step::Field(var) = query.steps.remove(0) else {
+                unreachable!("checked above");
+            };
+            query.var = var;
+        }
+        Ok(true)
+    }
+}
+
+/// Decimal digits of a small or big integer
+fn as_integer(value: &itf::Value) -> Option<String> {
+    match value {
+        itf::Value::Number(n) => Some(n.to_string()),
+        itf::Value::BigInt(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Compare integers given as decimal digits without leading zeros
+fn compare_integers(a: &str, b: &str) -> Ordering {
+    let magnitude = |x: &str, y: &str| x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+    match (a.strip_prefix('-'), b.strip_prefix('-')) {
+        (Some(a), Some(b)) => magnitude(b, a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => magnitude(a, b),
+    }
+}
+
+/// Tag of a sum type value (a record with `tag` and `value` fields)
+fn sum_tag(value: &itf::Value) -> Option<&str> {
+    let itf::Value::Record(fields) = value else {
+        return None;
+    };
+    match fields.get("tag") {
+        Some(itf::Value::String(tag)) if fields.iter().all(|(k, _)| k == "tag" || k == "value") => Some(tag),
+        _ => None,
+    }
+}
+
+/// Equality used by queries: small and big integers compare by value,
+/// and a sum type value equals a string naming its tag
+fn values_eq(lhs: &itf::Value, rhs: &itf::Value) -> bool {
+    if let (Some(a), Some(b)) = (as_integer(lhs), as_integer(rhs)) {
+        return a == b;
+    }
+    match (lhs, rhs) {
+        (itf::Value::Tuple(a), itf::Value::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| values_eq(x, y))
+        }
+        (value, itf::Value::String(tag)) | (itf::Value::String(tag), value) if sum_tag(value).is_some() => {
+            sum_tag(value) == Some(tag.as_str())
+        }
+        _ => lhs == rhs,
+    }
+}
+
+/// Evaluate a comparison; ordering is only defined between integers and between strings
+fn compare(lhs: &itf::Value, op: CompareOp, rhs: &itf::Value) -> Option<bool> {
+    let ordering = || -> Option<Ordering> {
+        match (lhs, rhs) {
+            (itf::Value::String(a), itf::Value::String(b)) => Some(a.cmp(b)),
+            _ => Some(compare_integers(&as_integer(lhs)?, &as_integer(rhs)?)),
+        }
+    };
+    Some(match op {
+        CompareOp::Eq => values_eq(lhs, rhs),
+        CompareOp::Ne => !values_eq(lhs, rhs),
+        CompareOp::Lt => ordering()?.is_lt(),
+        CompareOp::Le => ordering()?.is_le(),
+        CompareOp::Gt => ordering()?.is_gt(),
+        CompareOp::Ge => ordering()?.is_ge(),
+    })
+}
+
+/// Membership: items of sets, lists and tuples, keys of maps, fields of records, substrings of strings
+fn contains(collection: &itf::Value, item: &itf::Value) -> bool {
+    match (collection, item) {
+        (itf::Value::Set(items), _) => items.iter().any(|x| values_eq(x, item)),
+        (itf::Value::List(items), _) => items.iter().any(|x| values_eq(x, item)),
+        (itf::Value::Tuple(items), _) => items.iter().any(|x| values_eq(x, item)),
+        (itf::Value::Map(pairs), _) => pairs.iter().any(|(k, _)| values_eq(k, item)),
+        (itf::Value::Record(fields), itf::Value::String(name)) => fields.contains_key(name),
+        (itf::Value::String(text), itf::Value::String(part)) => text.contains(part.as_str()),
+        _ => false,
+    }
+}
+
+/// Number of items of a collection, or characters of a string
+fn size(value: &itf::Value) -> Option<usize> {
+    match value {
+        itf::Value::Set(items) => Some(items.iter().count()),
+        itf::Value::List(items) => Some(items.len()),
+        itf::Value::Tuple(items) => Some(items.len()),
+        itf::Value::Map(pairs) => Some(pairs.iter().count()),
+        itf::Value::Record(fields) => Some(fields.len()),
+        itf::Value::String(text) => Some(text.chars().count()),
+        _ => None,
+    }
+}
+
+/// Position given by an index literal
+fn position(index: &itf::Value) -> Result<usize> {
+    match index {
+        itf::Value::Number(n) if *n >= 0 => Ok(*n as usize),
+        _ => bail!("Expected a non-negative index, got {}", format_literal(index)),
+    }
+}
+
+fn out_of_range(i: usize, len: usize) -> anyhow::Error {
+    anyhow::anyhow!("Index {} out of range (length {})", i, len)
+}
+
+/// Render a literal the way it is written in a query
+fn format_literal(value: &itf::Value) -> String {
+    match value {
+        itf::Value::String(s) => format!("{:?}", s),
+        itf::Value::Tuple(items) => {
+            let parts: Vec<String> = items.iter().map(format_literal).collect();
+            format!("({})", parts.join(", "))
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+/// Parse a path query
+pub fn parse_path(text: &str) -> Result<PathQuery> {
+    let mut parser = Parser::new(text);
+    let var = parser.identifier()?;
+    let query = parser.path(var)?;
+    parser.end()?;
+    Ok(query)
+}
+
+/// Parse a predicate
+pub fn parse_expr(text: &str) -> Result<Expr> {
+    let mut parser = Parser::new(text);
+    let expr = parser.or_expr()?;
+    parser.end()?;
+    Ok(expr)
+}
+
+/// Character-level parser for queries
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Self { chars: text.chars().collect(), pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `c` (after whitespace) if it is next
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume `text` (after whitespace) if it is next
+    fn eat_str(&mut self, text: &str) -> bool {
+        self.skip_whitespace();
+        let len = text.chars().count();
+        let matches = self.chars.len() >= self.pos + len && self.chars[self.pos..self.pos + len].iter().copied().eq(text.chars());
+        if matches {
+            self.pos += len;
+        }
+        matches
+    }
+
+    /// Consume a keyword like `in`, which must not be followed by more name characters
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let start = self.pos;
+        if self.eat_str(keyword) && !self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            return true;
+        }
+        self.pos = start;
+        false
+    }
+
+    /// Fail unless all input was consumed
+    fn end(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) => bail!("Unexpected '{}' at position {}", c, self.pos + 1),
+            None => Ok(()),
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if !self.eat(c) {
+            match self.peek() {
+                Some(found) => bail!("Expected '{}' at position {}, found '{}'", c, self.pos + 1, found),
+                None => bail!("Expected '{}' at end of input", c),
+            }
+        }
+        Ok(())
+    }
+
+    /// Identifier: variable or field name, possibly qualified like `mbt::actionTaken`
+    fn identifier(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let qualified = c == ':' && self.chars.get(self.pos + 1) == Some(&':');
+            if qualified {
+                self.pos += 2;
+            } else if c.is_alphanumeric() || c == '_' || (c == '#' && self.pos == start) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.pos == start {
+            match self.peek() {
+                Some(c) => bail!("Expected a name at position {}, found '{}'", self.pos + 1, c),
+                None => bail!("Expected a name at end of input"),
+            }
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Steps of a path following its variable name
+    fn path(&mut self, var: String) -> Result<PathQuery> {
+        let mut steps = Vec::new();
+        loop {
+            if self.eat('.') {
+                steps.push(Step::Field(self.identifier()?));
+            } else if self.eat('[') {
+                steps.push(Step::Index(self.literal()?));
+                self.expect(']')?;
+            } else {
+                break;
+            }
+        }
+        Ok(PathQuery { var, steps })
+    }
+
+    fn or_expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.and_expr()?;
+        while self.eat_str("||") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and_expr()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.not_expr()?;
+        while self.eat_str("&&") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.not_expr()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        if self.peek() == Some('!') && self.chars.get(self.pos + 1) != Some(&'=') {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not_expr()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let lhs = self.operand()?;
+        const OPERATORS: [(&str, CompareOp); 6] = [
+            ("==", CompareOp::Eq),
+            ("!=", CompareOp::Ne),
+            ("<=", CompareOp::Le),
+            (">=", CompareOp::Ge),
+            ("<", CompareOp::Lt),
+            (">", CompareOp::Gt),
+        ];
+        for (symbol, op) in OPERATORS {
+            if self.eat_str(symbol) {
+                return Ok(Expr::Compare(Box::new(lhs), op, Box::new(self.operand()?)));
+            }
+        }
+        if self.eat_keyword("in") {
+            return Ok(Expr::In(Box::new(lhs), Box::new(self.operand()?)));
+        }
+        Ok(lhs)
+    }
+
+    /// Literal, path, `size(...)` or parenthesized expression
+    fn operand(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => {
+                let start = self.pos;
+                self.pos += 1;
+                let inner = self.or_expr()?;
+                if self.eat(')') {
+                    return Ok(inner);
+                }
+                // Not a group: parse it again as a tuple literal
+                self.pos = start;
+                Ok(Expr::Literal(self.literal()?))
+            }
+            Some(c) if c == '"' || c == '-' || c.is_ascii_digit() => Ok(Expr::Literal(self.literal()?)),
+            Some(c) if c.is_alphabetic() || c == '_' || c == '#' => {
+                let name = self.identifier()?;
+                match name.as_str() {
+                    "true" => Ok(Expr::Literal(itf::Value::Bool(true))),
+                    "false" => Ok(Expr::Literal(itf::Value::Bool(false))),
+                    "size" if self.eat('(') => {
+                        let inner = self.or_expr()?;
+                        self.expect(')')?;
+                        Ok(Expr::Size(Box::new(inner)))
+                    }
+                    _ => Ok(Expr::Path(self.path(name)?)),
+                }
+            }
+            Some(c) => bail!("Expected a value at position {}, found '{}'", self.pos + 1, c),
+            None => bail!("Expected a value at end of input"),
+        }
+    }
+
+    /// Literal: string, integer, boolean or a tuple of literals
+    fn literal(&mut self) -> Result<itf::Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.string(),
+            Some('(') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if !self.eat(')') {
+                    loop {
+                        items.push(self.literal()?);
+                        if self.eat(')') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(itf::Value::Tuple(items.into()))
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.integer(),
+            Some(c) if c.is_alphabetic() => match self.identifier()?.as_str() {
+                "true" => Ok(itf::Value::Bool(true)),
+                "false" => Ok(itf::Value::Bool(false)),
+                other => bail!("Unknown literal '{}' (strings must be quoted)", other),
+            },
+            Some(c) => bail!("Expected a literal at position {}, found '{}'", self.pos + 1, c),
+            None => bail!("Expected a literal at end of input"),
+        }
+    }
+
+    fn string(&mut self) -> Result<itf::Value> {
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(itf::Value::String(text));
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c) => text.push(c),
+                        None => bail!("Unterminated string"),
+                    }
+                    self.pos += 1;
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+                None => bail!("Unterminated string"),
+            }
+        }
+    }
+
+    fn integer(&mut self) -> Result<itf::Value> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        if let Ok(n) = digits.parse() {
+            return Ok(itf::Value::Number(n));
+        }
+        // Too large for a number: read it the way ITF writes big integers
+        match serde_json::from_value(serde_json::json!({ "#bigint": digits })) {
+            Ok(n) => Ok(itf::Value::BigInt(n)),
+            Err(_) => bail!("Invalid integer '{}'", digits),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_trace;
+    use std::path::PathBuf;
+
+    fn example_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("examples")
+            .join(name)
+    }
+
+    #[test]
+    fn test_parse_path() {
+        let query = parse_path(r#"system["n1"].mempool[2].tx"#).unwrap();
+        assert_eq!(query.var, "system");
+        assert_eq!(
+            query.steps,
+            vec![
+                Step::Index(itf::Value::String("n1".to_string())),
+                Step::Field("mempool".to_string()),
+                Step::Index(itf::Value::Number(2)),
+                Step::Field("tx".to_string()),
+            ]
+        );
+
+        assert_eq!(parse_path("mbt::actionTaken").unwrap().var, "mbt::actionTaken");
+        assert_eq!(parse_path("#meta . index").unwrap().steps, vec![Step::Field("index".to_string())]);
+        assert!(parse_path("s[\"n1\"").is_err());
+        assert!(parse_path("s[n1]").is_err());
+        assert!(parse_path("s x").is_err());
+    }
+
+    #[test]
+    fn test_resolve_round_trips_display_path() {
+        let trace = load_trace(&example_path("MissionariesAndCannibals.itf.json")).expect("Failed to load trace");
+        let state = &trace.states[0];
+
+        let query = parse_path(r#"who_is_on_bank["E"][2]"#).unwrap();
+        let path = query.resolve(state).unwrap();
+        assert_eq!(path, vec!["who_is_on_bank".to_string(), "0".to_string(), "2".to_string()]);
+        assert_eq!(parse_path(&display_path(state, &path)).unwrap(), query);
+
+        assert!(parse_path(r#"who_is_on_bank["X"]"#).unwrap().resolve(state).is_err());
+        assert!(parse_path(r#"who_is_on_bank["E"][4]"#).unwrap().resolve(state).is_err());
+        assert!(parse_path("bank_of_boat.x").unwrap().resolve(state).is_err());
+        assert!(parse_path("nothing").unwrap().resolve(state).is_err());
+
+        // A path only has to point at a node in some state
+        let query = parse_path(r#"who_is_on_bank["W"][0]"#).unwrap();
+        assert!(query.resolve(state).is_err() && query.check_in_states(&trace.states).is_ok());
+        let e = parse_path("nothing").unwrap().check_in_states(&trace.states).unwrap_err();
+        assert_eq!(e.to_string(), "No variable 'nothing'");
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        let expr = parse_expr(r#"system["n1"].height > 3 && round == 0 || !done"#).unwrap();
+        let Expr::Or(lhs, rhs) = expr else {
+            panic!("|| should bind weaker than &&");
+        };
+        assert!(matches!(*lhs, Expr::And(..)));
+        assert!(matches!(*rhs, Expr::Not(_)));
+        assert_eq!(parse_expr("a != 1").unwrap().paths().len(), 1);
+        assert!(matches!(parse_expr("(1, \"x\") in s").unwrap(), Expr::In(..)));
+        assert!(matches!(parse_expr("size(s) >= 2").unwrap(), Expr::Compare(_, CompareOp::Ge, _)));
+
+        assert!(parse_expr("a = 1").is_err());
+        assert!(parse_expr("a &&").is_err());
+        assert!(parse_expr("(a == 1").is_err());
+    }
+
+    #[test]
+    fn test_strip_last_prefix() {
+        let mut expr = parse_expr(r#"last.system["n1"].height > 3 && size(last.queue) == 0"#).unwrap();
+        assert!(expr.strip_last_prefix().unwrap());
+        assert_eq!(expr, parse_expr(r#"system["n1"].height > 3 && size(queue) == 0"#).unwrap());
+
+        let mut expr = parse_expr("x == 0").unwrap();
+        assert!(!expr.strip_last_prefix().unwrap());
+        assert!(parse_expr("last.x == y").unwrap().strip_last_prefix().is_err());
+    }
+
+    #[test]
+    fn test_predicates_over_trace() {
+        let trace = load_trace(&example_path("clock.itf.json")).expect("Failed to load trace");
+        let matching = |text: &str| -> Vec<usize> {
+            let expr = parse_expr(text).unwrap();
+            trace.states.iter().enumerate().filter(|(_, s)| expr.holds(s)).map(|(i, _)| i).collect()
+        };
+
+        // Big integers compare by value
+        assert_eq!(matching("clock_sync4::clock_sync::time == 0"), vec![0]);
+        assert_eq!(matching("clock_sync4::clock_sync::time > 15000000000000000000000000000000000000000000000000000000000000000000000000000"), vec![2, 3, 4, 5, 6, 7]);
+
+        // Sum types compare with their tag, sizes and membership
+        let sent = r#"clock_sync4::clock_sync::states["p1"].control_state == "Sent""#;
+        assert_eq!(matching(sent), vec![4, 5, 6, 7]);
+        assert_eq!(matching("size(clock_sync4::clock_sync::messages) >= 3"), vec![6, 7]);
+        assert_eq!(matching(&format!("!({}) && \"p4\" in clock_sync4::clock_sync::states", sent)), vec![0, 1, 2, 3]);
+
+        // Missing paths make comparisons false rather than failing
+        assert!(matching(r#"clock_sync4::clock_sync::states["p9"].diffs == 0"#).is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::loader::{load_trace, State, Trace, VarOrder};
+use crate::tree::{classify_sum_type, SumTypeVariant};
+
+/// Command `:run` runs when the config file has no `run` template
+pub const DEFAULT_RUN_COMMAND: &str = "quint run --out-itf {out} {args}";
+
+/// Command `:verify` runs when the config file has no `verify` template
+pub const DEFAULT_VERIFY_COMMAND: &str = "quint verify --invariant {invariant} --out-itf {out} {args} {spec}";
+
+/// REPL `:eval` evaluates expressions in when the config file has no `repl` command
+pub const DEFAULT_REPL_COMMAND: &str = "quint repl";
+
+/// Prompt of the Quint REPL, before the result of each input
+const REPL_PROMPT: &str = ">>> ";
+
+/// Command line of a `run` template, with `{args}` replaced by the arguments of `:run` and `{out}` by the file the
+/// trace is written to
+pub fn command_line(template: &str, args: &str, out: &Path) -> String {
+    template.replace("{out}", &shell_quote(out)).replace("{args}", args).trim().to_string()
+}
+
+/// A path quoted for the shell
+pub fn shell_quote(path: &Path) -> String {
+    shell_word(&path.display().to_string())
+}
+
+/// Text quoted for the shell as a single word
+fn shell_word(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Command running a command line in the shell
+pub fn shell_command(command_line: &str) -> Command {
+    let mut command = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    command.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command_line);
+    command
+}
+
+/// A command writing a trace to a temporary ITF file, run on another thread so that the screen keeps responding
+pub struct Job {
+    command_line: String,
+    out: PathBuf,
+    output: Receiver<std::io::Result<Output>>,
+}
+
+impl Job {
+    /// Start the command line made for the temporary ITF file
+    fn start(command_line: impl Fn(&Path) -> String) -> Job {
+        // Numbered, for jobs not to write over each other's trace
+        static STARTED: AtomicUsize = AtomicUsize::new(0);
+        let number = STARTED.fetch_add(1, Ordering::Relaxed);
+        let out = std::env::temp_dir().join(format!("quint-trace-explorer-run-{}-{}.itf.json", std::process::id(), number));
+        let _ = fs::remove_file(&out);
+        let command_line = command_line(&out);
+        let (sender, output) = mpsc::channel();
+        let shell_line = command_line.clone();
+        std::thread::spawn(move || {
+            // Its output is captured so it doesn't mess up the screen
+            let _ = sender.send(shell_command(&shell_line).stdin(Stdio::null()).output());
+        });
+        Job { command_line, out, output }
+    }
+
+    /// Command line the job runs
+    pub fn command_line(&self) -> &str {
+        &self.command_line
+    }
+
+    /// The output of the command and the trace it wrote if any, once it has exited
+    fn finished(&self) -> Option<Result<(&str, Output, Option<Trace>)>> {
+        let output = match self.output.try_recv() {
+            Ok(output) => output.context(format!("Failed to run {}", self.command_line)),
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(anyhow!("{} stopped without exiting", self.command_line)),
+        };
+        Some(output.and_then(|output| {
+            if !self.out.exists() {
+                return Ok((self.command_line.as_str(), output, None));
+            }
+            let trace = load_trace(&self.out);
+            let _ = fs::remove_file(&self.out);
+            Ok((self.command_line.as_str(), output, Some(trace?)))
+        }))
+    }
+
+    /// The trace of a `:run` job, once it has finished. The command failing is fine as long as it wrote a trace, like
+    /// `quint run` finding a violation
+    pub fn run_trace(&self) -> Option<Result<Trace>> {
+        Some(self.finished()?.and_then(|(command_line, output, trace)| match trace {
+            Some(trace) => Ok(trace),
+            None => bail!("{} wrote no trace ({}): {}", command_line, output.status, last_line(&output)),
+        }))
+    }
+
+    /// The counterexample of a `:verify` job once it has finished, or nothing when the command succeeded without one:
+    /// the invariant holds
+    pub fn verify_trace(&self) -> Option<Result<Option<Trace>>> {
+        Some(self.finished()?.and_then(|(command_line, output, trace)| match trace {
+            None if !output.status.success() => bail!("{} failed ({}): {}", command_line, output.status, last_line(&output)),
+            trace => Ok(trace),
+        }))
+    }
+}
+
+/// Start the `:run` template with the given arguments in the background
+pub fn start_run(template: &str, args: &str) -> Job {
+    Job::start(|out| command_line(template, args, out))
+}
+
+/// Start the `:verify` template in the background, to check an invariant of a spec
+pub fn start_verify(template: &str, spec: &Path, invariant: &str, args: &str) -> Job {
+    let template = template.replace("{spec}", &shell_quote(spec)).replace("{invariant}", &shell_word(invariant));
+    Job::start(|out| command_line(&template, args, out))
+}
+
+/// Last line a command printed, on stderr first, to tell why it failed
+fn last_line(output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stderr.lines().chain(stdout.lines()).rfind(|line| !line.trim().is_empty()).unwrap_or("no output").trim().to_string()
+}
+
+/// Quint expression of a value, like `Set(1, 2)`, `Map("a" -> 1)` or `Some({ x: 1 })`
+pub fn quint_literal(value: &itf::Value) -> Result<String> {
+    let list = |items: Vec<&itf::Value>| -> Result<String> { Ok(items.into_iter().map(quint_literal).collect::<Result<Vec<_>>>()?.join(", ")) };
+    Ok(match value {
+        itf::Value::Bool(b) => b.to_string(),
+        itf::Value::Number(n) => n.to_string(),
+        itf::Value::BigInt(n) => n.to_string(),
+        itf::Value::String(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+        itf::Value::List(items) => format!("[{}]", list(items.iter().collect())?),
+        itf::Value::Set(items) => format!("Set({})", list(items.iter().collect())?),
+        itf::Value::Tuple(items) if items.iter().count() >= 2 => format!("({})", list(items.iter().collect())?),
+        itf::Value::Tuple(items) => format!("Tup({})", list(items.iter().collect())?),
+        itf::Value::Map(pairs) => {
+            let pairs = pairs.iter().map(|(key, value)| Ok(format!("{} -> {}", quint_literal(key)?, quint_literal(value)?))).collect::<Result<Vec<_>>>()?;
+            format!("Map({})", pairs.join(", "))
+        }
+        itf::Value::Record(fields) => match classify_sum_type(fields) {
+            Some(SumTypeVariant::Unit(tag)) => tag.to_string(),
+            Some(SumTypeVariant::WithValue(tag, value)) => format!("{}({})", tag, quint_literal(value)?),
+            None => {
+                let fields = fields.iter().map(|(name, value)| Ok(format!("{}: {}", name, quint_literal(value)?))).collect::<Result<Vec<_>>>()?;
+                format!("{{ {} }}", fields.join(", "))
+            }
+        },
+        itf::Value::Unserializable(_) => bail!("A value can't be written in Quint"),
+    })
+}
+
+/// Module a REPL command line loads with `-r FILE::MODULE` or `--require FILE::MODULE`, the empty name when it loads
+/// the last module of a file, none when it loads no spec
+fn loaded_module(command_line: &str) -> Option<String> {
+    let words: Vec<&str> = command_line.split_whitespace().collect();
+    let spec = words.iter().enumerate().find_map(|(i, word)| match *word {
+        "-r" | "--require" => words.get(i + 1).copied(),
+        word => word.strip_prefix("--require="),
+    })?;
+    Some(spec.rsplit_once("::").map(|(_, module)| module.to_string()).unwrap_or_default())
+}
+
+/// Lines to type in the REPL to evaluate an expression in a state. With a spec loaded, an action assigns its variables
+/// (named without the loaded module) so that its definitions see the state, otherwise each variable is defined as a
+/// `val` named after the last part of its name
+fn repl_input(state: &State, vars: &[String], expr: &str, module: Option<&str>) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    let mut assignments = Vec::new();
+    for name in state.var_names(vars, VarOrder::Declaration) {
+        let literal = quint_literal(&state.values[name]).context(format!("Cannot evaluate with {}", name))?;
+        match module {
+            Some(module) => {
+                let name = name.strip_prefix(module).and_then(|name| name.strip_prefix("::")).filter(|_| !module.is_empty()).unwrap_or(name);
+                assignments.push(format!("{}' = {}", name, literal));
+            }
+            None => lines.push(format!("val {} = {}", name.rsplit("::").next().unwrap_or(name), literal)),
+        }
+    }
+    if module.is_some() {
+        lines.push(format!("all {{ {} }}", assignments.join(", ")));
+    }
+    lines.push(expr.to_string());
+    Ok(lines)
+}
+
+/// Result of the last of `inputs` lines in the transcript of a REPL session, or the first error of the ones before
+fn repl_result(stdout: &str, inputs: usize) -> Result<String> {
+    let Some((_, transcript)) = stdout.split_once(REPL_PROMPT) else {
+        return Ok(stdout.trim().to_string());
+    };
+    let outputs: Vec<&str> = transcript.split(REPL_PROMPT).map(str::trim).collect();
+    // Definitions print nothing and actions `true`
+    if let Some(error) = outputs.iter().take(inputs.saturating_sub(1)).find(|output| !output.is_empty() && **output != "true") {
+        bail!("{}", error);
+    }
+    Ok(outputs.get(inputs - 1).copied().unwrap_or_default().to_string())
+}
+
+/// Evaluate a Quint expression in a state with the REPL the command line starts, returning the lines of the result
+pub fn eval_in_repl(command_line: &str, state: &State, vars: &[String], expr: &str) -> Result<Vec<String>> {
+    let inputs = repl_input(state, vars, expr, loaded_module(command_line).as_deref())?;
+    let mut child = shell_command(command_line)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(format!("Failed to run {}", command_line))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The REPL exits at the end of its input
+        stdin.write_all(format!("{}\n", inputs.join("\n")).as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} failed ({}): {}", command_line, output.status, stderr.lines().rfind(|line| !line.trim().is_empty()).unwrap_or("no output").trim());
+    }
+    Ok(repl_result(&stdout, inputs.len())?.lines().map(str::to_string).collect())
+}
+
+/// Keywords starting the declarations of a Quint module
+const DECLARATION_KEYWORDS: [&str; 9] = ["var", "const", "val", "def", "action", "temporal", "run", "type", "nondet"];
+
+/// Line number (from 1) of the first declaration of a name in a Quint spec, like `var time: int` for `time`
+pub fn find_definition(spec: &str, name: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    spec.lines().position(|line| {
+        let line = line.trim_start();
+        let line = line.strip_prefix("pure ").unwrap_or(line).trim_start();
+        DECLARATION_KEYWORDS.iter().any(|keyword| {
+            line.strip_prefix(keyword)
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .and_then(|rest| rest.trim_start().strip_prefix(name))
+                .is_some_and(|rest| !rest.starts_with(is_ident))
+        })
+    })
+    .map(|idx| idx + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// The result of a background job, once it has finished
+    fn wait<T>(finished: impl Fn() -> Option<T>) -> T {
+        loop {
+            if let Some(result) = finished() {
+                return result;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_command_line() {
+        let out = Path::new("/tmp/out.itf.json");
+        assert_eq!(command_line(DEFAULT_RUN_COMMAND, "spec.qnt --max-steps 5", out), "quint run --out-itf '/tmp/out.itf.json' spec.qnt --max-steps 5");
+        assert_eq!(command_line("quint test {args} --out-itf {out}", "", Path::new("/tmp/it's.json")), r"quint test  --out-itf '/tmp/it'\''s.json'");
+    }
+
+    #[test]
+    fn test_quint_literal() {
+        let value = |json| serde_json::from_value::<itf::Value>(json).unwrap();
+        let literal = |json| quint_literal(&value(json)).unwrap();
+        assert_eq!(literal(serde_json::json!({ "#set": [{ "#bigint": "1" }, { "#bigint": "-2" }] })), "Set(-2, 1)");
+        assert_eq!(literal(serde_json::json!({ "#map": [["a", [true]]] })), r#"Map("a" -> [true])"#);
+        assert_eq!(literal(serde_json::json!({ "#tup": ["x", { "tag": "None", "value": { "#tup": [] } }] })), r#"("x", None)"#);
+        assert_eq!(literal(serde_json::json!({ "tag": "Some", "value": { "n": 1, "s": "say \"hi\"" } })), r#"Some({ n: 1, s: "say \"hi\"" })"#);
+        assert_eq!(literal(serde_json::json!({ "#set": [] })), "Set()");
+    }
+
+    #[test]
+    fn test_repl_input() {
+        let trace = load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json")).unwrap();
+        let state = &trace.states[0];
+        let lines = repl_input(state, &trace.vars, "time + 1", None).unwrap();
+        assert_eq!(lines[0], "val time = 0");
+        assert_eq!(lines.last().map(String::as_str), Some("time + 1"));
+
+        assert_eq!(loaded_module("quint repl -r spec.qnt::clock_sync4"), Some("clock_sync4".to_string()));
+        assert_eq!(loaded_module("quint repl --require=spec.qnt"), Some(String::new()));
+        assert_eq!(loaded_module("quint repl"), None);
+        let lines = repl_input(state, &trace.vars, "Inv", Some("clock_sync4")).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("all { clock_sync::time' = 0, "), "{}", lines[0]);
+    }
+
+    #[test]
+    fn test_find_definition() {
+        let spec = "module clock {\n  // time flows\n  var timeout: int\n  var time: int\n  pure def  step(t) = t + 1\n  action tick = time' = step(time)\n}";
+        assert_eq!(find_definition(spec, "time"), Some(4));
+        assert_eq!(find_definition(spec, "step"), Some(5));
+        assert_eq!(find_definition(spec, "tick"), Some(6));
+        assert_eq!(find_definition(spec, "flows"), None);
+    }
+
+    #[test]
+    fn test_repl_result() {
+        let transcript = "Quint REPL 0.22.0\nType \".exit\" to exit\n>>> true\n>>> Set(1,\n  2)\n>>> ";
+        assert_eq!(repl_result(transcript, 2).unwrap(), "Set(1,\n  2)");
+        let transcript = "Quint REPL\n>>> static analysis error: Name 'x' not found\n>>> \n>>> ";
+        assert_eq!(repl_result(transcript, 2).unwrap_err().to_string(), "static analysis error: Name 'x' not found");
+        assert_eq!(repl_result("42\n", 1).unwrap(), "42");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_trace() {
+        let example = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json");
+        let job = start_run("cp {args} {out}", &example.display().to_string());
+        let trace = wait(|| job.run_trace()).unwrap();
+        assert_eq!(trace.states.len(), 8);
+        assert_eq!(trace.key, crate::session::trace_key(&fs::read(&example).unwrap()));
+
+        let job = start_run("echo oops >&2; exit 3", "");
+        let error = wait(|| job.run_trace()).err().unwrap();
+        assert!(format!("{:#}", error).ends_with("wrote no trace (exit status: 3): oops"), "{:#}", error);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_trace() {
+        let example = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json");
+        let template = "[ {invariant} != inv ] || cp {spec} {out} {args}";
+        let verify = |template: &str, spec: &Path, invariant: &str, args: &str| {
+            let job = start_verify(template, spec, invariant, args);
+            wait(|| job.verify_trace())
+        };
+        let trace = verify(template, &example, "inv", "").unwrap().expect("No counterexample");
+        assert_eq!(trace.states.len(), 8);
+
+        // The invariant holds, or the command fails without writing a trace
+        assert!(verify(template, &example, "other", "").unwrap().is_none());
+        // The invariant is a single word, not more shell commands
+        assert!(verify(template, &example, "x; exit 7", "").unwrap().is_none());
+        let error = verify("echo '[error] parse' >&2; exit 1", &example, "inv", "").err().unwrap();
+        assert!(format!("{:#}", error).ends_with("failed (exit status: 1): [error] parse"), "{:#}", error);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::prefs::state_dir;
+
+/// Number of traces remembered in the list of recent files
+const MAX_RECENT: usize = 20;
+
+/// File the traces opened in the explorer are listed in, most recent first
+fn recent_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("recent.json"))
+}
+
+/// Traces opened recently that still exist, most recent first
+pub fn load_recent() -> Vec<PathBuf> {
+    recent_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str::<Vec<PathBuf>>(&contents).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Put a trace at the top of the list of recent files
+pub fn add_recent(trace_file: &Path) -> Result<()> {
+    let Some(path) = recent_path() else {
+        return Ok(());
+    };
+    // Absolute, so that the trace can be reopened from another directory
+    let trace_file = trace_file.canonicalize().context(format!("Failed to resolve {}", trace_file.display()))?;
+    let recent = with_recent(load_recent(), trace_file);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&recent)?).context(format!("Failed to write {}", path.display()))
+}
+
+/// List of recent files after opening `trace_file`: moved or added to the top, dropping the oldest ones
+fn with_recent(mut recent: Vec<PathBuf>, trace_file: PathBuf) -> Vec<PathBuf> {
+    recent.retain(|path| *path != trace_file);
+    recent.insert(0, trace_file);
+    recent.truncate(MAX_RECENT);
+    recent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_recent() {
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(with_recent(Vec::new(), PathBuf::from("/a.itf.json")), paths(&["/a.itf.json"]));
+        assert_eq!(with_recent(paths(&["/a", "/b", "/c"]), PathBuf::from("/c")), paths(&["/c", "/a", "/b"]));
+
+        let full: Vec<PathBuf> = (0..MAX_RECENT).map(|i| PathBuf::from(format!("/{}", i))).collect();
+        let recent = with_recent(full, PathBuf::from("/new"));
+        assert_eq!(recent.len(), MAX_RECENT);
+        assert_eq!(recent[0], PathBuf::from("/new"));
+        assert!(!recent.contains(&PathBuf::from(format!("/{}", MAX_RECENT - 1))));
+    }
+}
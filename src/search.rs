@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diff::DiffKind;
+use crate::loader::{State, Trace};
+use crate::query::PathQuery;
+use crate::tree::{state_sections, value_at, NodePath, TreeLine};
+
+/// Find all paths in a state whose name, key or leaf value contains the query
+/// Matching is case-insensitive; map entries match on their key as a whole
+pub fn find_matches(state: &State, query: &str) -> Vec<NodePath> {
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return matches;
+    }
+    let query = query.to_lowercase();
+
+    let mut names: Vec<&str> = state.values.keys().map(|name| name.as_ref()).collect();
+    names.sort();
+    for name in names {
+        let path = vec![name.to_string()];
+        if contains(name, &query) {
+            matches.push(path.clone());
+        }
+        search_value(&state.values[name], path, &query, &mut matches);
+    }
+    for (name, value) in state_sections(state) {
+        search_value(value, vec![name.to_string()], &query, &mut matches);
+    }
+
+    matches
+}
+
+/// Find matches in every state of the trace, as (state index, path) pairs in state order
+pub fn find_matches_in_trace(trace: &Trace, query: &str) -> Vec<(usize, NodePath)> {
+    trace
+        .states
+        .iter()
+        .enumerate()
+        .flat_map(|(i, state)| find_matches(state, query).into_iter().map(move |path| (i, path)))
+        .collect()
+}
+
+/// The node at a path in every state, `None` where there is none, and how its value changed from the previous state
+/// The path is resolved in each state, so that map entries are found by key wherever they are in the map
+pub fn path_history(trace: &Trace, query: &PathQuery) -> Vec<(Option<NodePath>, DiffKind)> {
+    let mut previous: Option<&itf::Value> = None;
+    trace
+        .states
+        .iter()
+        .enumerate()
+        .map(|(i, state)| {
+            let path = query.resolve(state).ok();
+            let value = path.as_ref().and_then(|path| value_at(state, path));
+            let kind = match (previous, value) {
+                _ if i == 0 => DiffKind::Unchanged,
+                (None, Some(_)) => DiffKind::Added,
+                (Some(_), None) => DiffKind::Removed,
+                (Some(old), Some(new)) if old != new => DiffKind::Modified,
+                _ => DiffKind::Unchanged,
+            };
+            previous = value;
+            (path, kind)
+        })
+        .collect()
+}
+
+/// Find every place in the trace holding a value structurally equal to `target`,
+/// as (state index, path) pairs; a map key equal to the target yields its entry's path
+pub fn find_occurrences(trace: &Trace, target: &itf::Value) -> Vec<(usize, NodePath)> {
+    let mut occurrences = Vec::new();
+    for (i, state) in trace.states.iter().enumerate() {
+        let mut paths = Vec::new();
+        let mut names: Vec<&str> = state.values.keys().map(|name| name.as_ref()).collect();
+        names.sort();
+        for name in names {
+            collect_occurrences(&state.values[name], vec![name.to_string()], target, &mut paths);
+        }
+        for (name, value) in state_sections(state) {
+            collect_occurrences(value, vec![name.to_string()], target, &mut paths);
+        }
+        occurrences.extend(paths.into_iter().map(|path| (i, path)));
+    }
+    occurrences
+}
+
+/// Recursively collect the paths of values equal to `target`
+fn collect_occurrences(value: &itf::Value, path: NodePath, target: &itf::Value, paths: &mut Vec<NodePath>) {
+    if value == target {
+        // A value cannot contain another copy of itself, no need to look inside
+        paths.push(path);
+        return;
+    }
+    match value {
+        itf::Value::Record(fields) => {
+            for (field_name, field_value) in fields.iter() {
+                let mut child_path = path.clone();
+                child_path.push(field_name.clone());
+                collect_occurrences(field_value, child_path, target, paths);
+            }
+        }
+        itf::Value::Map(pairs) => {
+            for (i, (key, val)) in pairs.iter().enumerate() {
+                let mut entry_path = path.clone();
+                entry_path.push(format!("{}", i));
+                if key == target {
+                    paths.push(entry_path.clone());
+                }
+                collect_occurrences(val, entry_path, target, paths);
+            }
+        }
+        itf::Value::Set(items) => occurrences_in_items(items.iter(), path, target, paths),
+        itf::Value::List(items) => occurrences_in_items(items.iter(), path, target, paths),
+        itf::Value::Tuple(items) => occurrences_in_items(items.iter(), path, target, paths),
+        _ => {}
+    }
+}
+
+fn occurrences_in_items<'a>(items: impl Iterator<Item = &'a itf::Value>, path: NodePath, target: &itf::Value, paths: &mut Vec<NodePath>) {
+    for (i, item) in items.enumerate() {
+        let mut child_path = path.clone();
+        child_path.push(format!("{}", i));
+        collect_occurrences(item, child_path, target, paths);
+    }
+}
+
+/// Recursively collect matches inside a value
+fn search_value(value: &itf::Value, path: NodePath, query: &str, matches: &mut Vec<NodePath>) {
+    match value {
+        itf::Value::Record(fields) => {
+            for (field_name, field_value) in fields.iter() {
+                let mut child_path = path.clone();
+                child_path.push(field_name.clone());
+                if contains(field_name, query) {
+                    matches.push(child_path.clone());
+                }
+                search_value(field_value, child_path, query, matches);
+            }
+        }
+        itf::Value::Map(pairs) => {
+            for (i, (key, val)) in pairs.iter().enumerate() {
+                let mut entry_path = path.clone();
+                entry_path.push(format!("{}", i));
+                if value_contains(key, query) {
+                    matches.push(entry_path.clone());
+                }
+                // Map values render under the entry's own path
+                search_value(val, entry_path, query, matches);
+            }
+        }
+        itf::Value::Set(items) => search_items(items.iter(), path, query, matches),
+        itf::Value::List(items) => search_items(items.iter(), path, query, matches),
+        itf::Value::Tuple(items) => search_items(items.iter(), path, query, matches),
+        _ => {
+            if leaf_text(value).is_some_and(|text| contains(&text, query)) {
+                matches.push(path);
+            }
+        }
+    }
+}
+
+fn search_items<'a>(items: impl Iterator<Item = &'a itf::Value>, path: NodePath, query: &str, matches: &mut Vec<NodePath>) {
+    for (i, item) in items.enumerate() {
+        let mut child_path = path.clone();
+        child_path.push(format!("{}", i));
+        search_value(item, child_path, query, matches);
+    }
+}
+
+/// Whether any name or leaf inside a value contains the query
+fn value_contains(value: &itf::Value, query: &str) -> bool {
+    let mut matches = Vec::new();
+    search_value(value, Vec::new(), query, &mut matches);
+    !matches.is_empty()
+}
+
+/// Searchable text of a leaf value (strings without quotes)
+fn leaf_text(value: &itf::Value) -> Option<String> {
+    match value {
+        itf::Value::Bool(b) => Some(b.to_string()),
+        itf::Value::Number(n) => Some(n.to_string()),
+        itf::Value::String(s) => Some(s.clone()),
+        itf::Value::BigInt(n) => Some(n.to_string()),
+        itf::Value::Unserializable(u) => Some(format!("{:?}", u)),
+        _ => None,
+    }
+}
+
+/// Case-insensitive substring check against an already lowercased query
+fn contains(text: &str, query: &str) -> bool {
+    text.to_lowercase().contains(query)
+}
+
+/// Indices of the rendered lines showing each match
+/// A match hidden inside an inline or collapsed value is shown on the deepest visible line containing it
+pub fn matched_lines(lines: &[TreeLine], matches: &[NodePath]) -> HashSet<usize> {
+    let mut first_line: HashMap<&[String], usize> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        first_line.entry(line.path.as_slice()).or_insert(i);
+    }
+
+    matches
+        .iter()
+        .filter_map(|path| (1..=path.len()).rev().find_map(|len| first_line.get(&path[..len]).copied()))
+        .collect()
+}
+
+/// Keep only the lines on a branch containing a match: ancestors of matches (for context),
+/// the matched nodes themselves and everything below them
+pub fn filter_lines(lines: Vec<TreeLine>, matches: &[NodePath]) -> Vec<TreeLine> {
+    let matched: HashSet<&[String]> = matches.iter().map(|path| path.as_slice()).collect();
+    let ancestors: HashSet<&[String]> = matches
+        .iter()
+        .flat_map(|path| (1..path.len()).map(move |len| &path[..len]))
+        .collect();
+
+    lines
+        .into_iter()
+        .filter(|line| {
+            ancestors.contains(line.path.as_slice())
+                || (1..=line.path.len()).any(|len| matched.contains(&line.path[..len]))
+        })
+        .collect()
+}
+
+/// Index of the line showing a path (or its deepest visible ancestor)
+pub fn line_for_path(lines: &[TreeLine], path: &NodePath) -> Option<usize> {
+    matched_lines(lines, std::slice::from_ref(path)).into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_trace;
+    use crate::query::parse_path;
+    use std::path::PathBuf;
+
+    fn example_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("examples")
+            .join(name)
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let trace = load_trace(&example_path("MissionariesAndCannibals.itf.json")).expect("Failed to load trace");
+        let state0 = &trace.states[0];
+
+        // Variable names
+        assert_eq!(find_matches(state0, "BOAT"), vec![vec!["bank_of_boat".to_string()]]);
+
+        // Map keys match the whole entry, set items match individually
+        let matches = find_matches(state0, "W");
+        assert!(matches.contains(&vec!["who_is_on_bank".to_string()]));
+        assert!(matches.contains(&vec!["who_is_on_bank".to_string(), "1".to_string()]));
+
+        let matches = find_matches(state0, "c1_of");
+        assert_eq!(matches, vec![vec!["who_is_on_bank".to_string(), "0".to_string(), "0".to_string()]]);
+
+        assert!(find_matches(state0, "").is_empty());
+    }
+
+    #[test]
+    fn test_find_matches_in_trace() {
+        let trace = load_trace(&example_path("MissionariesAndCannibals.itf.json")).expect("Failed to load trace");
+
+        // c2 is on exactly one bank in every state, starting on the east one
+        let matches = find_matches_in_trace(&trace, "c2_of");
+        assert_eq!(matches.first(), Some(&(0, vec!["who_is_on_bank".to_string(), "0".to_string(), "1".to_string()])));
+        assert!(matches.contains(&(1, vec!["who_is_on_bank".to_string(), "1".to_string(), "0".to_string()])));
+        assert!(matches.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(matches.iter().filter(|(_, path)| path.len() == 3).count(), trace.states.len());
+
+        assert!(find_matches_in_trace(&trace, "nobody").is_empty());
+    }
+
+    #[test]
+    fn test_filter_lines() {
+        use crate::config::Formatters;
+        use crate::diff::DiffResult;
+        use crate::tree::{render_value, ExpansionState};
+
+        let trace = load_trace(&example_path("MissionariesAndCannibals.itf.json")).expect("Failed to load trace");
+        let state0 = &trace.states[0];
+        let matches = find_matches(state0, "c1_of");
+
+        let mut expansion = ExpansionState::new();
+        for path in &matches {
+            expansion.reveal(path);
+        }
+        let diff = DiffResult::default();
+        let mut lines = Vec::new();
+        for name in ["bank_of_boat", "who_is_on_bank"] {
+            lines.extend(render_value(name, &state0.values[name], vec![name.to_string()], &expansion, &diff, &Formatters::default(), 0, 80, usize::MAX));
+        }
+
+        let filtered = filter_lines(lines.clone(), &matches);
+        assert!(filtered.len() < lines.len());
+        assert!(filtered.iter().all(|line| line.path[0] == "who_is_on_bank"));
+        assert!(filtered.iter().all(|line| line.path.len() < 3 || line.path == matches[0]));
+        assert!(filtered.iter().any(|line| line.path == matches[0]));
+        assert!(filter_lines(lines, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_find_occurrences() {
+        let trace = load_trace(&example_path("MissionariesAndCannibals.itf.json")).expect("Failed to load trace");
+
+        // Each person is on exactly one bank in every state
+        let person = itf::Value::String("c2_OF_PERSON".to_string());
+        let occurrences = find_occurrences(&trace, &person);
+        assert_eq!(occurrences.len(), trace.states.len());
+        assert_eq!(occurrences[0], (0, vec!["who_is_on_bank".to_string(), "0".to_string(), "1".to_string()]));
+
+        // Map keys point at their entry
+        let bank = itf::Value::String("W".to_string());
+        let occurrences = find_occurrences(&trace, &bank);
+        assert!(occurrences.contains(&(0, vec!["who_is_on_bank".to_string(), "1".to_string()])));
+        assert!(occurrences.contains(&(1, vec!["bank_of_boat".to_string()])));
+
+        // Whole values are found without descending into them
+        let sets = trace.states[0].values["who_is_on_bank"].as_ref().clone();
+        assert_eq!(find_occurrences(&trace, &sets), vec![(0, vec!["who_is_on_bank".to_string()])]);
+    }
+
+    #[test]
+    fn test_path_history() {
+        let trace = load_trace(&example_path("MissionariesAndCannibals.itf.json")).expect("Failed to load trace");
+
+        // The boat crosses the river in every step
+        let history = path_history(&trace, &parse_path("bank_of_boat").unwrap());
+        assert_eq!(history.len(), trace.states.len());
+        assert_eq!(history[0], (Some(vec!["bank_of_boat".to_string()]), DiffKind::Unchanged));
+        assert!(history[1..].iter().all(|(_, kind)| *kind == DiffKind::Modified));
+
+        // Map entries are found by key
+        let history = path_history(&trace, &parse_path("who_is_on_bank[\"W\"]").unwrap());
+        assert_eq!(history[0].0, Some(vec!["who_is_on_bank".to_string(), "1".to_string()]));
+        assert_eq!(history[1].1, DiffKind::Modified);
+
+        let history = path_history(&trace, &parse_path("who_is_on_bank[\"X\"]").unwrap());
+        assert!(history.iter().all(|entry| *entry == (None, DiffKind::Unchanged)));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::loader::Trace;
+use crate::prefs::state_dir;
+use crate::tree::{NodePath, PrefixDisplay};
+
+/// Where an investigation stood, saved with `:session save NAME` and resumed with `--session NAME`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// Index of the state shown in the single view
+    pub state: usize,
+    pub expanded: BTreeSet<NodePath>,
+    /// Path of the node under the cursor
+    pub cursor: Option<NodePath>,
+    /// State marked with `m`
+    #[serde(default)]
+    pub marked_state: Option<usize>,
+    /// Labels given to states with `:tag`
+    #[serde(default)]
+    pub tags: BTreeMap<usize, Vec<String>>,
+    /// States compared in the side-by-side view, if it was open
+    #[serde(default)]
+    pub diff: Option<(usize, usize)>,
+    /// Variables hidden with `V` or the `v` menu
+    #[serde(default)]
+    pub hidden_vars: BTreeSet<String>,
+    /// Trace file the session was saved on, reopened by `--session` when no trace is given
+    #[serde(default)]
+    pub trace: Option<PathBuf>,
+    /// Hash of that file's contents (see `trace_key`), to tell whether a trace is the one the session was saved on
+    #[serde(default)]
+    pub trace_key: Option<String>,
+}
+
+impl Session {
+    /// Warning for resuming the session on a trace other than the one it was saved on, whose states and nodes may
+    /// not match; none for sessions saved without their trace
+    pub fn mismatch(&self, trace: &Trace) -> Option<String> {
+        let key = self.trace_key.as_deref()?;
+        if key == trace.key {
+            return None;
+        }
+        Some(match &self.trace {
+            Some(path) => format!("saved on another trace ({}), states and nodes may not match", path.display()),
+            None => "saved on another trace, states and nodes may not match".to_string(),
+        })
+    }
+}
+
+/// What is remembered about a trace, saved on quit and restored when the same trace is opened again
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TraceNotes {
+    /// State marked with `m`
+    #[serde(default)]
+    pub marked_state: Option<usize>,
+    /// Labels given to states with `:tag`
+    #[serde(default)]
+    pub tags: BTreeMap<usize, Vec<String>>,
+    /// How the module prefixes of variable names are shown, picked with `p`
+    #[serde(default)]
+    pub prefixes: PrefixDisplay,
+}
+
+impl TraceNotes {
+    pub fn is_empty(&self) -> bool {
+        *self == TraceNotes::default()
+    }
+}
+
+/// File something is saved in under a name: `KIND/NAME.json` in the state directory, like `sessions/NAME.json`
+fn saved_path(kind: &str, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) || name.starts_with('.') {
+        bail!("Invalid name `{}`: use letters, digits, `-`, `_` and `.`", name);
+    }
+    match state_dir() {
+        Some(dir) => Ok(dir.join(kind).join(format!("{}.json", name))),
+        None => bail!("No directory to save {} in: set $HOME or $XDG_STATE_HOME", kind),
+    }
+}
+
+/// Save as JSON under a name, replacing what was saved with that name before
+fn save_named(kind: &str, name: &str, value: &impl Serialize) -> Result<()> {
+    let path = saved_path(kind, name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(value)?).context(format!("Failed to write {}", path.display()))
+}
+
+/// Load what was saved under a name, `what` naming it in errors
+fn load_named<T: DeserializeOwned>(kind: &str, what: &str, name: &str) -> Result<T> {
+    let path = saved_path(kind, name)?;
+    let contents = fs::read_to_string(&path).context(format!("No {} named `{}` ({})", what, name, path.display()))?;
+    serde_json::from_str(&contents).context(format!("Invalid {} file: {}", what, path.display()))
+}
+
+/// Save a session under a name, replacing any session saved with that name before
+pub fn save_session(name: &str, session: &Session) -> Result<()> {
+    save_named("sessions", name, session)
+}
+
+/// Load the session saved under a name
+pub fn load_session(name: &str) -> Result<Session> {
+    load_named("sessions", "session", name)
+}
+
+/// Save the paths of expanded nodes, like `system["n1"].mempool`, as an expansion preset
+pub fn save_preset(name: &str, paths: &[String]) -> Result<()> {
+    save_named("presets", name, &paths)
+}
+
+/// Load the paths of the expansion preset saved under a name
+pub fn load_preset(name: &str) -> Result<Vec<String>> {
+    load_named("presets", "preset", name)
+}
+
+/// Name the notes about a trace are saved under: a hash of the file's contents (64-bit FNV-1a, stable across
+/// versions), so that they follow the trace when it is moved but not when it is regenerated with other states
+pub fn trace_key(contents: &[u8]) -> String {
+    let hash = contents.iter().fold(0xcbf29ce484222325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    format!("{:016x}", hash)
+}
+
+/// Directory the notes about traces were saved in when they only held marked and tagged states, still read so
+/// that they are not lost, and moved to `traces` when saved again
+const OLD_NOTES_KIND: &str = "bookmarks";
+
+/// Notes saved about a trace, the defaults when there are none or they can't be read
+pub fn load_trace_notes(key: &str) -> TraceNotes {
+    load_named("traces", "trace notes", key)
+        .or_else(|_| load_named(OLD_NOTES_KIND, "trace notes", key))
+        .unwrap_or_default()
+}
+
+/// Save the notes about a trace, removing the file once there is nothing left to remember
+pub fn save_trace_notes(key: &str, notes: &TraceNotes) -> Result<()> {
+    remove_saved(OLD_NOTES_KIND, key)?;
+    if notes.is_empty() {
+        return remove_saved("traces", key);
+    }
+    save_named("traces", key, notes)
+}
+
+/// Remove what was saved under a name, if anything
+fn remove_saved(kind: &str, name: &str) -> Result<()> {
+    let path = saved_path(kind, name)?;
+    if path.exists() {
+        fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_format() {
+        let path = |segments: &[&str]| segments.iter().map(|s| s.to_string()).collect::<NodePath>();
+        let session = Session {
+            state: 4,
+            expanded: BTreeSet::from([path(&["system"]), path(&["system", "\"n1\""])]),
+            cursor: Some(path(&["system", "\"n1\"", "round"])),
+            marked_state: Some(1),
+            tags: BTreeMap::from([(4, vec!["violation".to_string()])]),
+            diff: Some((1, 4)),
+            hidden_vars: BTreeSet::from(["_rng".to_string()]),
+            trace: Some(PathBuf::from("/traces/tendermint.itf.json")),
+            trace_key: Some(trace_key(b"{}")),
+        };
+        let text = serde_json::to_string(&session).unwrap();
+        assert_eq!(serde_json::from_str::<Session>(&text).unwrap(), session);
+
+        let minimal: Session = serde_json::from_str(r#"{ "state": 0, "expanded": [], "cursor": null }"#).unwrap();
+        assert_eq!((minimal.marked_state, minimal.diff, minimal.trace, minimal.trace_key), (None, None, None, None));
+        assert!(minimal.tags.is_empty() && minimal.hidden_vars.is_empty());
+    }
+
+    #[test]
+    fn test_session_mismatch() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let trace = crate::loader::load_trace(&path.join("clock.itf.json")).unwrap();
+        let session = |trace_key: Option<String>| Session {
+            state: 0,
+            expanded: BTreeSet::new(),
+            cursor: None,
+            marked_state: None,
+            tags: BTreeMap::new(),
+            diff: None,
+            hidden_vars: BTreeSet::new(),
+            trace: Some(path.join("tendermint.itf.json")),
+            trace_key,
+        };
+
+        assert_eq!(session(Some(trace.key.clone())).mismatch(&trace), None);
+        assert_eq!(session(None).mismatch(&trace), None);
+        let other = crate::loader::load_trace(&path.join("tendermint.itf.json")).unwrap();
+        let warning = session(Some(other.key)).mismatch(&trace).expect("No warning");
+        assert!(warning.contains("tendermint.itf.json"));
+    }
+
+    #[test]
+    fn test_trace_notes() {
+        assert!(TraceNotes::default().is_empty());
+        let notes = TraceNotes { prefixes: PrefixDisplay::Short, ..TraceNotes::default() };
+        assert!(!notes.is_empty());
+        let text = serde_json::to_string(&notes).unwrap();
+        assert_eq!(serde_json::from_str::<TraceNotes>(&text).unwrap(), notes);
+        assert_eq!(serde_json::from_str::<TraceNotes>(r#"{ "marked_state": 2 }"#).unwrap().marked_state, Some(2));
+    }
+
+    #[test]
+    fn test_trace_key() {
+        assert_eq!(trace_key(b""), "cbf29ce484222325");
+        assert_eq!(trace_key(b"a"), "af63dc4c8601ec8c");
+        assert_ne!(trace_key(br#"{"states":[]}"#), trace_key(br#"{"states":[{}]}"#));
+        assert!(saved_path("traces", &trace_key(b"{}")).is_ok());
+    }
+
+    #[test]
+    fn test_saved_name() {
+        assert!(saved_path("sessions", "round-3_lock.v2").is_ok());
+        assert!(saved_path("sessions", "").is_err());
+        assert!(saved_path("sessions", "../config").is_err());
+        assert!(saved_path("presets", "a/b").is_err());
+        assert!(saved_path("presets", ".hidden").is_err());
+    }
+}
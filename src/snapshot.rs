@@ -0,0 +1,91 @@
+use crossterm::style::{Attribute, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::Command;
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Span;
+
+/// Attributes written for the modifiers of a cell, in the order of their SGR codes
+const ATTRIBUTES: [(Modifier, Attribute); 6] = [
+    (Modifier::BOLD, Attribute::Bold),
+    (Modifier::DIM, Attribute::Dim),
+    (Modifier::ITALIC, Attribute::Italic),
+    (Modifier::UNDERLINED, Attribute::Underlined),
+    (Modifier::REVERSED, Attribute::Reverse),
+    (Modifier::CROSSED_OUT, Attribute::CrossedOut),
+];
+
+/// Text of the screen as drawn, one line per row, with ANSI escape sequences for the colors if `ansi`
+pub fn screen_text(buffer: &Buffer, ansi: bool) -> String {
+    let width = buffer.area.width as usize;
+    let mut text = String::new();
+    for row in buffer.content.chunks(width.max(1)) {
+        let mut line = String::new();
+        let mut style = Style::default();
+        // Cells hidden by the wide character before them
+        let mut skip = 0;
+        for cell in row {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            skip = Span::raw(cell.symbol()).width().saturating_sub(1);
+            if ansi && plain_style(cell.style()) != style {
+                style = plain_style(cell.style());
+                write_style(&mut line, style);
+            }
+            line.push_str(cell.symbol());
+        }
+        if ansi {
+            if style != Style::default() {
+                write_style(&mut line, Style::default());
+            }
+        } else {
+            line.truncate(line.trim_end().len());
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+/// Style with the colors that are the terminal's own left unset, to tell when the style changes
+fn plain_style(style: Style) -> Style {
+    let set = |color: Option<Color>| color.filter(|&color| color != Color::Reset);
+    Style { fg: set(style.fg), bg: set(style.bg), add_modifier: style.add_modifier, ..Style::default() }
+}
+
+/// Escape sequences switching to a style, starting from the default one
+fn write_style(out: &mut String, style: Style) {
+    let _ = SetAttribute(Attribute::Reset).write_ansi(out);
+    if let Some(fg) = style.fg {
+        let _ = SetForegroundColor(fg.into()).write_ansi(out);
+    }
+    if let Some(bg) = style.bg {
+        let _ = SetBackgroundColor(bg.into()).write_ansi(out);
+    }
+    for (modifier, attribute) in ATTRIBUTES {
+        if style.add_modifier.contains(modifier) {
+            let _ = SetAttribute(attribute).write_ansi(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::layout::Rect;
+
+    #[test]
+    fn test_screen_text() {
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 8, 2));
+        buffer.set_string(0, 0, "a: ", Style::default());
+        buffer.set_string(3, 0, "1", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD));
+        buffer.set_string(0, 1, "状態 x", Style::default());
+
+        assert_eq!(screen_text(&buffer, false), "a: 1\n状態 x\n");
+        assert_eq!(
+            screen_text(&buffer, true),
+            "a: \x1b[0m\x1b[38;5;2m\x1b[1m1\x1b[0m    \n状態 x  \n"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+/// Block characters of a sparkline, from the lowest value to the highest
+const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A number as a float, to place it on a sparkline
+pub fn numeric_value(value: &itf::Value) -> Option<f64> {
+    match value {
+        itf::Value::Number(n) => Some(*n as f64),
+        itf::Value::BigInt(n) => n.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Smallest and largest of the values, if there are any
+pub fn value_range(values: &[Option<f64>]) -> Option<(f64, f64)> {
+    values.iter().flatten().fold(None, |range, &value| match range {
+        None => Some((value, value)),
+        Some((min, max)) => Some((min.min(value), max.max(value))),
+    })
+}
+
+/// A value of a sparkline's range, in scientific notation when it is too large to read at a glance
+pub fn format_number(value: f64) -> String {
+    if value.abs() < 1e15 {
+        format!("{}", value)
+    } else {
+        format!("{:.2e}", value)
+    }
+}
+
+/// A block per value, its height scaled from the smallest value to the largest, and a space for missing values
+pub fn sparkline(values: &[Option<f64>]) -> String {
+    let Some((min, max)) = value_range(values) else {
+        return " ".repeat(values.len());
+    };
+    values
+        .iter()
+        .map(|value| match value {
+            Some(_) if max == min => LEVELS[0],
+            Some(value) => LEVELS[(((value - min) / (max - min)) * (LEVELS.len() - 1) as f64).round() as usize],
+            None => ' ',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[Some(0.0), Some(7.0), Some(3.5), None, Some(1.0)]), "▁█▅ ▂");
+        assert_eq!(sparkline(&[Some(2.0), Some(2.0)]), "▁▁");
+        assert_eq!(sparkline(&[None, None]), "  ");
+        assert_eq!(value_range(&[None, Some(-3.0), Some(4.0)]), Some((-3.0, 4.0)));
+        assert_eq!(numeric_value(&itf::Value::Number(-5)), Some(-5.0));
+        assert_eq!((format_number(-42.0), format_number(1.5e20)), ("-42".to_string(), "1.50e20".to_string()));
+        assert_eq!(numeric_value(&itf::Value::String("5".to_string())), None);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{BTreeSet, HashSet};
+use std::rc::Rc;
+
+use crate::diff::compute_diff;
+use crate::loader::{Trace, VarOrder};
+use crate::sparkline::{format_number, numeric_value, value_range};
+use crate::tree::format_value_preview;
+
+/// How a variable's value behaves over the trace
+#[derive(Debug, PartialEq)]
+pub struct VarStats {
+    pub name: String,
+    /// Number of different values it takes
+    pub distinct: usize,
+    /// Number of states where it differs from the previous one
+    pub changes: usize,
+    /// First state where it differs from the previous one
+    pub first_change: Option<usize>,
+    /// Smallest and largest value, when it is always a number
+    pub range: Option<(f64, f64)>,
+    /// Smallest and largest size, when it is always a set, map or list
+    pub sizes: Option<(usize, usize)>,
+}
+
+impl VarStats {
+    /// Summary of the statistics after the variable's name, like `3 values, changed in 2 states, 0..4`
+    pub fn summary(&self) -> String {
+        let noun = |count: usize, one: &str, many: &str| format!("{} {}", count, if count == 1 { one } else { many });
+        let mut parts = vec![noun(self.distinct, "value", "values"), format!("changed in {}", noun(self.changes, "state", "states"))];
+        if let Some((min, max)) = self.range {
+            parts.push(format!("{}..{}", format_number(min), format_number(max)));
+        }
+        if let Some((min, max)) = self.sizes {
+            parts.push(format!("size {}..{}", min, max));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Number of items of a collection
+fn collection_size(value: &itf::Value) -> Option<usize> {
+    match value {
+        itf::Value::Set(items) => Some(items.iter().count()),
+        itf::Value::List(items) => Some(items.len()),
+        itf::Value::Map(pairs) => Some(pairs.iter().count()),
+        _ => None,
+    }
+}
+
+/// Shades of the cells of a heatmap, from no change to the most changes
+const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+/// Variables of the trace in declaration order, including the ones missing from some states
+fn trace_var_names(trace: &Trace) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    for state in &trace.states {
+        for name in state.var_names(&trace.vars, VarOrder::Declaration) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Number of values changed, added or removed in each variable by the transition to each state (none in the first one)
+pub fn change_counts(trace: &Trace) -> Vec<(String, Vec<usize>)> {
+    let names = trace_var_names(trace);
+    let mut counts: Vec<(String, Vec<usize>)> = names.iter().map(|name| (name.to_string(), vec![0; trace.states.len()])).collect();
+    for idx in 1..trace.states.len() {
+        let diff = compute_diff(&trace.states[idx - 1].values, &trace.states[idx].values);
+        let own_changes = diff.own_changes();
+        for (name, var_counts) in &mut counts {
+            let changed = own_changes.iter().filter(|path| path[0] == *name).count();
+            let removed: usize = diff.removed.iter().filter(|(path, _)| path[0] == *name).map(|(_, items)| items.len()).sum();
+            var_counts[idx] = changed + removed;
+        }
+    }
+    counts
+}
+
+/// A heatmap row of a variable: a shade per group of `per_cell` states, darker the more values changed in them
+pub fn heat_row(counts: &[usize], per_cell: usize) -> String {
+    counts
+        .chunks(per_cell.max(1))
+        .map(|cell| match cell.iter().sum::<usize>() {
+            0 => SHADES[0],
+            1 => SHADES[1],
+            2..=3 => SHADES[2],
+            4..=7 => SHADES[3],
+            _ => SHADES[4],
+        })
+        .collect()
+}
+
+/// Paths of the variables, and of the record fields of the others, holding the same value in every state of the trace
+pub fn constant_paths(trace: &Trace) -> BTreeSet<Vec<String>> {
+    let mut paths = BTreeSet::new();
+    for name in trace_var_names(trace) {
+        if let Some(values) = var_values(trace, name) {
+            add_constant_paths(vec![name.to_string()], &values, &mut paths);
+        }
+    }
+    paths
+}
+
+/// Values of each field the values have in common, when they are all records
+fn common_fields<'a>(values: &[&'a itf::Value]) -> Vec<(String, Vec<&'a itf::Value>)> {
+    let records: Option<Vec<&itf::value::Record>> = values
+        .iter()
+        .map(|value| match value {
+            // Sum type variants are shown as such rather than as records
+            itf::Value::Record(fields) if !(fields.len() == 2 && fields.get("tag").is_some() && fields.get("value").is_some()) => Some(fields),
+            _ => None,
+        })
+        .collect();
+    let Some(records) = records.filter(|records| !records.is_empty()) else {
+        return Vec::new();
+    };
+    records[0]
+        .iter()
+        .filter_map(|(field, _)| {
+            let field_values: Option<Vec<&itf::Value>> = records.iter().map(|fields| fields.get(field)).collect();
+            Some((field.clone(), field_values?))
+        })
+        .collect()
+}
+
+/// Values of a variable in every state, if it is in all of them
+fn var_values<'a>(trace: &'a Trace, name: &str) -> Option<Vec<&'a itf::Value>> {
+    trace.states.iter().map(|state| state.values.get(name).map(|value| value.as_ref())).collect()
+}
+
+/// Add the path of values that are all the same, or else the constant paths of their common record fields
+fn add_constant_paths(path: Vec<String>, values: &[&itf::Value], paths: &mut BTreeSet<Vec<String>>) {
+    if values.windows(2).all(|pair| pair[0] == pair[1]) {
+        paths.insert(path);
+        return;
+    }
+    for (field, field_values) in common_fields(values) {
+        let mut field_path = path.clone();
+        field_path.push(field);
+        add_constant_paths(field_path, &field_values, paths);
+    }
+}
+
+/// A number that only ever grows over the trace, like a height, a round or a nonce
+#[derive(Debug, PartialEq)]
+pub struct Counter {
+    pub path: Vec<String>,
+    /// Each value it takes, with the first state holding it
+    pub steps: Vec<(usize, itf::Value)>,
+}
+
+impl Counter {
+    /// Each value after the number of the state it starts in, like `1: 0, 4: 1, 9: 2`
+    pub fn summary(&self) -> String {
+        let format = |value: &itf::Value| match numeric_value(value) {
+            // Huge numbers (like timestamps in nanoseconds) in scientific notation
+            Some(number) => format_number(number),
+            None => format_value_preview(value, 20),
+        };
+        self.steps.iter().map(|(idx, value)| format!("{}: {}", idx + 1, format(value))).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Variables and record fields holding a number in every state, which increases at least once and never decreases
+pub fn counters(trace: &Trace) -> Vec<Counter> {
+    let mut counters = Vec::new();
+    for name in trace_var_names(trace) {
+        if let Some(values) = var_values(trace, name) {
+            add_counters(vec![name.to_string()], &values, &mut counters);
+        }
+    }
+    counters
+}
+
+/// Add a counter for the values if they make one, or else the counters of their common record fields
+fn add_counters(path: Vec<String>, values: &[&itf::Value], counters: &mut Vec<Counter>) {
+    let numbers: Option<Vec<f64>> = values.iter().map(|value| numeric_value(value)).collect();
+    if let Some(numbers) = numbers {
+        if numbers.windows(2).all(|pair| pair[0] <= pair[1]) && numbers.first() != numbers.last() {
+            let steps = (0..values.len())
+                .filter(|&idx| idx == 0 || values[idx] != values[idx - 1])
+                .map(|idx| (idx, values[idx].clone()))
+                .collect();
+            counters.push(Counter { path, steps });
+        }
+        return;
+    }
+    for (field, field_values) in common_fields(values) {
+        let mut field_path = path.clone();
+        field_path.push(field);
+        add_counters(field_path, &field_values, counters);
+    }
+}
+
+/// Statistics of each variable of the trace, the ones changing most often first
+pub fn var_stats(trace: &Trace) -> Vec<VarStats> {
+    let mut stats: Vec<VarStats> = trace_var_names(trace)
+        .into_iter()
+        .map(|name| {
+            let values: Vec<Option<&Rc<itf::Value>>> = trace.states.iter().map(|state| state.values.get(name)).collect();
+            let changed: Vec<usize> = (1..values.len())
+                .filter(|&i| match (values[i - 1], values[i]) {
+                    (Some(prev), Some(curr)) => !Rc::ptr_eq(prev, curr) && prev != curr,
+                    (prev, curr) => prev.is_some() != curr.is_some(),
+                })
+                .collect();
+            let present: Vec<&itf::Value> = values.iter().flatten().map(|value| value.as_ref()).collect();
+            let distinct: HashSet<&itf::Value> = present.iter().copied().collect();
+            let numbers: Option<Vec<Option<f64>>> = present.iter().map(|value| numeric_value(value).map(Some)).collect();
+            let sizes: Option<Vec<usize>> = present.iter().map(|value| collection_size(value)).collect();
+            VarStats {
+                name: name.to_string(),
+                distinct: distinct.len(),
+                changes: changed.len(),
+                first_change: changed.first().copied(),
+                range: numbers.and_then(|numbers| value_range(&numbers)),
+                sizes: sizes.and_then(|sizes| Some((*sizes.iter().min()?, *sizes.iter().max()?))),
+            }
+        })
+        .collect();
+    stats.sort_by_key(|stats| std::cmp::Reverse(stats.changes));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_trace;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_var_stats() {
+        let trace = load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json")).unwrap();
+        let stats = var_stats(&trace);
+        let names: Vec<&str> = stats.iter().map(|stats| stats.name.as_str()).collect();
+        assert_eq!(names, ["clock_sync4::clock_sync::states", "clock_sync4::clock_sync::messages", "clock_sync4::clock_sync::time"]);
+        assert_eq!((stats[0].distinct, stats[0].changes, stats[0].first_change, stats[0].sizes), (8, 7, Some(1), Some((4, 4))));
+        assert_eq!((stats[1].distinct, stats[1].changes, stats[1].range, stats[1].sizes), (5, 4, None, Some((0, 4))));
+        assert_eq!((stats[2].distinct, stats[2].changes, stats[2].range.map(|(min, _)| min), stats[2].sizes), (4, 3, Some(0.0), None));
+
+        let summary = VarStats { name: "n".to_string(), distinct: 1, changes: 0, first_change: None, range: Some((3.0, 3.0)), sizes: None }.summary();
+        assert_eq!(summary, "1 value, changed in 0 states, 3..3");
+    }
+
+    #[test]
+    fn test_constant_paths() {
+        let mut trace = load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json")).unwrap();
+        assert!(constant_paths(&trace).is_empty());
+
+        for (idx, state) in trace.states.iter_mut().enumerate() {
+            let config: itf::Value = serde_json::from_value(serde_json::json!({ "n": 3, "round": idx, "peers": { "#set": ["p1"] } })).unwrap();
+            state.values.insert("N".into(), Rc::new(itf::Value::Number(4)));
+            state.values.insert("config".into(), Rc::new(config));
+        }
+        let paths: Vec<Vec<String>> = constant_paths(&trace).into_iter().collect();
+        assert_eq!(paths, [vec!["N"], vec!["config", "n"], vec!["config", "peers"]]);
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut trace = load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json")).unwrap();
+        for (idx, state) in trace.states.iter_mut().enumerate() {
+            let consensus: itf::Value = serde_json::from_value(serde_json::json!({ "height": idx / 3, "round": idx % 2, "n": 4 })).unwrap();
+            state.values.insert("consensus".into(), Rc::new(consensus));
+        }
+        let counters = counters(&trace);
+        let paths: Vec<&[String]> = counters.iter().map(|counter| counter.path.as_slice()).collect();
+        assert_eq!(paths, [vec!["clock_sync4::clock_sync::time".to_string()], vec!["consensus".to_string(), "height".to_string()]]);
+        assert_eq!(counters[1].summary(), "1: 0, 4: 1, 7: 2");
+    }
+
+    #[test]
+    fn test_heatmap() {
+        assert_eq!(heat_row(&[0, 1, 2, 5, 9, 0], 1), "·░▒▓█·");
+        assert_eq!(heat_row(&[0, 1, 2, 5, 9], 2), "░▓█");
+
+        let trace = load_trace(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json")).unwrap();
+        let counts = change_counts(&trace);
+        let (name, time) = counts.iter().find(|(name, _)| name.ends_with("::time")).unwrap();
+        assert_eq!(name, "clock_sync4::clock_sync::time");
+        assert_eq!(time[0], 0);
+        assert_eq!(time.iter().filter(|&&count| count > 0).count(), 3);
+    }
+}
@@ -1,4 +1,29 @@
-use ratatui::style::Color;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+use crate::color::ColorSupport;
+use crate::tree::SpanStyle;
+
+/// Built-in themes, picked with `--theme` or `theme` in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    /// For dark terminal backgrounds
+    #[default]
+    Dark,
+    /// For light terminal backgrounds
+    Light,
+    /// Solarized dark palette
+    Solarized,
+    /// Bright colors on black, for low vision or washed out screens
+    HighContrast,
+}
 
 /// Theme configuration for the application
 #[derive(Clone)]
@@ -8,6 +33,9 @@ pub struct Theme {
     pub header_fg: Color,
     pub button_fg: Color,
 
+    // Status line below the header
+    pub status_fg: Color,
+
     // Panel borders (diff mode)
     pub focused_border: Color,
     pub unfocused_border: Color,
@@ -19,16 +47,28 @@ pub struct Theme {
 
     // Cursor/selection
     pub cursor_bg: Color,
+    pub search_match_bg: Color,
 
-    // Syntax highlighting (future use)
-    #[allow(dead_code)]
+    // Syntax highlighting
+    pub syntax_key: Color,
+    pub syntax_punctuation: Color,
     pub syntax_string: Color,
-    #[allow(dead_code)]
     pub syntax_number: Color,
-    #[allow(dead_code)]
     pub syntax_boolean: Color,
+    pub syntax_type: Color,
+    pub guide_fg: Color,
+
+    /// No colors: the header and cursor are shown in reverse video, search matches underlined
+    pub monochrome: bool,
 }
 
+/// Names of the color fields, as written in theme files
+const COLOR_FIELDS: [&str; 18] = [
+    "header_bg", "header_fg", "button_fg", "status_fg", "focused_border", "unfocused_border",
+    "diff_added", "diff_removed", "diff_modified", "cursor_bg", "search_match_bg",
+    "syntax_key", "syntax_punctuation", "syntax_string", "syntax_number", "syntax_boolean", "syntax_type", "guide_fg",
+];
+
 impl Default for Theme {
     fn default() -> Self {
         Self {
@@ -37,6 +77,9 @@ impl Default for Theme {
             header_fg: Color::White,
             button_fg: Color::Yellow,
 
+            // Status line
+            status_fg: Color::Gray,
+
             // Panel borders
             focused_border: Color::Cyan,
             unfocused_border: Color::DarkGray,
@@ -46,13 +89,248 @@ impl Default for Theme {
             diff_removed: Color::Red,
             diff_modified: Color::Yellow,
 
-            // Cursor
+            // Cursor and search matches
             cursor_bg: Color::DarkGray,
+            search_match_bg: Color::Indexed(24),
 
             // Syntax highlighting
+            syntax_key: Color::White,
+            syntax_punctuation: Color::Gray,
             syntax_string: Color::Cyan,
             syntax_number: Color::Magenta,
             syntax_boolean: Color::Blue,
+            syntax_type: Color::DarkGray,
+            guide_fg: Color::Indexed(240),
+
+            monochrome: false,
+        }
+    }
+}
+
+impl Theme {
+    /// One of the built-in themes
+    pub fn builtin(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Dark => Self::default(),
+            ThemeName::Light => Self::light(),
+            ThemeName::Solarized => Self::solarized(),
+            ThemeName::HighContrast => Self::high_contrast(),
+        }
+    }
+
+    fn light() -> Self {
+        Self {
+            header_bg: Color::Indexed(61),
+            header_fg: Color::White,
+            button_fg: Color::Indexed(229),
+            status_fg: Color::Indexed(240),
+            focused_border: Color::Blue,
+            unfocused_border: Color::Indexed(250),
+            diff_added: Color::Indexed(28),
+            diff_removed: Color::Indexed(160),
+            diff_modified: Color::Indexed(130),
+            cursor_bg: Color::Indexed(254),
+            search_match_bg: Color::Indexed(153),
+            syntax_key: Color::Black,
+            syntax_punctuation: Color::Indexed(242),
+            syntax_string: Color::Indexed(30),
+            syntax_number: Color::Indexed(127),
+            syntax_boolean: Color::Indexed(25),
+            syntax_type: Color::Indexed(245),
+            guide_fg: Color::Indexed(250),
+            monochrome: false,
+        }
+    }
+
+    fn solarized() -> Self {
+        // Solarized accent colors and dark background tones (base02 to base1)
+        let (base02, base01, base1, base3) = (Color::Rgb(7, 54, 66), Color::Rgb(88, 110, 117), Color::Rgb(147, 161, 161), Color::Rgb(253, 246, 227));
+        let (yellow, red, magenta, violet) = (Color::Rgb(181, 137, 0), Color::Rgb(220, 50, 47), Color::Rgb(211, 54, 130), Color::Rgb(108, 113, 196));
+        let (blue, cyan, green) = (Color::Rgb(38, 139, 210), Color::Rgb(42, 161, 152), Color::Rgb(133, 153, 0));
+        Self {
+            header_bg: violet,
+            header_fg: base3,
+            button_fg: base3,
+            status_fg: base1,
+            focused_border: cyan,
+            unfocused_border: base01,
+            diff_added: green,
+            diff_removed: red,
+            diff_modified: yellow,
+            cursor_bg: base02,
+            search_match_bg: Color::Rgb(18, 78, 110),
+            syntax_key: base1,
+            syntax_punctuation: base01,
+            syntax_string: cyan,
+            syntax_number: magenta,
+            syntax_boolean: blue,
+            syntax_type: base01,
+            guide_fg: base02,
+            monochrome: false,
         }
     }
+
+    fn high_contrast() -> Self {
+        Self {
+            header_bg: Color::White,
+            header_fg: Color::Black,
+            button_fg: Color::Blue,
+            status_fg: Color::White,
+            focused_border: Color::LightYellow,
+            unfocused_border: Color::Gray,
+            diff_added: Color::LightGreen,
+            diff_removed: Color::LightRed,
+            diff_modified: Color::LightYellow,
+            cursor_bg: Color::Blue,
+            search_match_bg: Color::Magenta,
+            syntax_key: Color::White,
+            syntax_punctuation: Color::White,
+            syntax_string: Color::LightCyan,
+            syntax_number: Color::LightMagenta,
+            syntax_boolean: Color::LightBlue,
+            syntax_type: Color::Gray,
+            guide_fg: Color::Gray,
+            monochrome: false,
+        }
+    }
+
+    /// The theme with its colors replaced by the closest ones the terminal can display,
+    /// or without colors at all
+    pub fn for_terminal(mut self, support: ColorSupport) -> Self {
+        for name in COLOR_FIELDS {
+            if let Some(color) = self.color_mut(name) {
+                *color = support.adapt(*color);
+            }
+        }
+        self.monochrome = support == ColorSupport::None;
+        self
+    }
+
+    /// Style of the header bar
+    pub fn header_style(&self) -> Style {
+        let style = Style::default().bg(self.header_bg).fg(self.header_fg).add_modifier(Modifier::BOLD);
+        if self.monochrome { style.add_modifier(Modifier::REVERSED) } else { style }
+    }
+
+    /// Style of the line under the cursor
+    pub fn cursor_style(&self) -> Style {
+        if self.monochrome { Style::default().add_modifier(Modifier::REVERSED) } else { Style::default().bg(self.cursor_bg) }
+    }
+
+    /// Style of the lines matching the search
+    pub fn search_match_style(&self) -> Style {
+        if self.monochrome { Style::default().add_modifier(Modifier::UNDERLINED) } else { Style::default().bg(self.search_match_bg) }
+    }
+
+    /// Color field of the theme by its name in theme files
+    fn color_mut(&mut self, name: &str) -> Option<&mut Color> {
+        Some(match name {
+            "header_bg" => &mut self.header_bg,
+            "header_fg" => &mut self.header_fg,
+            "button_fg" => &mut self.button_fg,
+            "status_fg" => &mut self.status_fg,
+            "focused_border" => &mut self.focused_border,
+            "unfocused_border" => &mut self.unfocused_border,
+            "diff_added" => &mut self.diff_added,
+            "diff_removed" => &mut self.diff_removed,
+            "diff_modified" => &mut self.diff_modified,
+            "cursor_bg" => &mut self.cursor_bg,
+            "search_match_bg" => &mut self.search_match_bg,
+            "syntax_key" => &mut self.syntax_key,
+            "syntax_punctuation" => &mut self.syntax_punctuation,
+            "syntax_string" => &mut self.syntax_string,
+            "syntax_number" => &mut self.syntax_number,
+            "syntax_boolean" => &mut self.syntax_boolean,
+            "syntax_type" => &mut self.syntax_type,
+            "guide_fg" => &mut self.guide_fg,
+            _ => return None,
+        })
+    }
+
+    /// Foreground color of a syntax highlighted span (None keeps the terminal default)
+    pub fn syntax_color(&self, style: SpanStyle) -> Option<Color> {
+        match style {
+            SpanStyle::Default => None,
+            SpanStyle::Key => Some(self.syntax_key),
+            SpanStyle::Punctuation => Some(self.syntax_punctuation),
+            SpanStyle::String => Some(self.syntax_string),
+            SpanStyle::Number => Some(self.syntax_number),
+            SpanStyle::Boolean => Some(self.syntax_boolean),
+            SpanStyle::Type => Some(self.syntax_type),
+            SpanStyle::Guide => Some(self.guide_fg),
+        }
+    }
+}
+
+/// Load a theme from a TOML file: `base`, the built-in theme to start from (dark if not set), then colors
+/// replacing those of the base theme by field name, as color names (`light-blue`), hex values (`#5f00d7`)
+/// or 256-color palette indices (`56`)
+pub fn load_theme(path: &Path) -> Result<Theme> {
+    let contents = fs::read_to_string(path).context(format!("Failed to read theme file: {}", path.display()))?;
+    parse_theme(&contents).context(format!("Invalid theme file: {}", path.display()))
+}
+
+fn parse_theme(contents: &str) -> Result<Theme> {
+    let mut table: toml::Table = toml::from_str(contents)?;
+    let base = match table.remove("base") {
+        Some(base) => ThemeName::deserialize(base)?,
+        None => ThemeName::default(),
+    };
+
+    let mut theme = Theme::builtin(base);
+    for (name, value) in table {
+        let Some(color) = theme.color_mut(&name) else {
+            bail!("Unknown theme color `{}`", name);
+        };
+        let parsed = match &value {
+            toml::Value::String(text) => Color::from_str(text).ok(),
+            toml::Value::Integer(index) => u8::try_from(*index).ok().map(Color::Indexed),
+            _ => None,
+        };
+        match parsed {
+            Some(parsed) => *color = parsed,
+            None => bail!("Invalid color for `{}`: {}", name, value),
+        }
+    }
+    Ok(theme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_theme() {
+        let theme = parse_theme(
+            r##"
+base = "light"
+header_bg = "#5f00d7"
+diff_added = "bright-green"
+cursor_bg = 236
+"##,
+        )
+        .unwrap();
+        assert_eq!(theme.header_bg, Color::Rgb(0x5f, 0x00, 0xd7));
+        assert_eq!(theme.diff_added, Color::LightGreen);
+        assert_eq!(theme.cursor_bg, Color::Indexed(236));
+        assert_eq!(theme.diff_removed, Theme::builtin(ThemeName::Light).diff_removed);
+
+        assert_eq!(parse_theme("").unwrap().header_bg, Theme::default().header_bg);
+        assert!(parse_theme("header_background = \"red\"").is_err());
+        assert!(parse_theme("header_bg = \"reddish\"").is_err());
+        assert!(parse_theme("base = \"neon\"").is_err());
+        assert!(COLOR_FIELDS.iter().all(|name| theme.clone().color_mut(name).is_some()));
+    }
+
+    #[test]
+    fn test_for_terminal() {
+        let theme = Theme::builtin(ThemeName::Solarized).for_terminal(ColorSupport::Basic);
+        assert!(COLOR_FIELDS.iter().all(|name| matches!(theme.clone().color_mut(name), Some(Color::Indexed(0..=15)))));
+        assert!(!theme.monochrome);
+
+        let theme = Theme::default().for_terminal(ColorSupport::None);
+        assert_eq!(theme.diff_added, Color::Reset);
+        assert!(theme.monochrome);
+        assert_eq!(theme.cursor_style(), Style::default().add_modifier(Modifier::REVERSED));
+    }
 }
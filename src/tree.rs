@@ -1,8 +1,12 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
-use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 
-use crate::diff::{DiffKind, DiffResult};
+use crate::config::{Format, Formatters};
+use crate::diff::{DiffKind, DiffResult, RemovedItem};
+use crate::loader::{State, Trace, VarOrder};
 
 // Display thresholds as percentages of available width
 const INLINE_PERCENT: usize = 80;   // Use 80% of available width for inline content
@@ -39,10 +43,318 @@ impl DisplayThresholds {
 /// Path to a node in the tree (e.g., ["system", "v1", "state"])
 pub type NodePath = Vec<String>;
 
-/// Tracks which nodes are expanded
+/// Characters drawn by `add_guides` in the indentation of tree lines
+const GUIDE_CHARS: [char; 3] = ['│', '├', '└'];
+
+/// Name of the tree node showing a state's `#meta`
+pub const META_NODE: &str = "#meta";
+
+/// Name of the tree node showing the values picked by the `nondet` bindings of a state's action
+pub const CHOICES_NODE: &str = "#choices";
+
+/// Value Apalache uses as the payload of variants without one
+const APALACHE_UNIT: &str = "U_OF_UNIT";
+
+/// Prefix of the path segment used for collapsed groups of unchanged items
+const COLLAPSED_PREFIX: &str = "__collapsed_";
+
+/// Prefix of the path segment of module groups, followed by the qualified name of the module
+const MODULE_PREFIX: &str = "::module::";
+
+/// Separator of the modules in qualified variable names, like `propeller::choreo::s`
+const MODULE_SEPARATOR: &str = "::";
+
+/// Path of the group of the variables of a module, like `propeller::choreo`
+fn module_path(module: &str) -> NodePath {
+    vec![format!("{}{}", MODULE_PREFIX, module)]
+}
+
+/// Qualified name of the module whose group a path points at, if it does
+pub fn module_of(path: &[String]) -> Option<&str> {
+    match path {
+        [segment] => segment.strip_prefix(MODULE_PREFIX),
+        _ => None,
+    }
+}
+
+fn is_module_path(path: &[String]) -> bool {
+    module_of(path).is_some()
+}
+
+/// Whether a variable belongs to a module or to one of its submodules
+pub fn in_module(name: &str, module: &str) -> bool {
+    name.strip_prefix(module).is_some_and(|rest| rest.starts_with(MODULE_SEPARATOR))
+}
+
+/// Range of item indices covered by a collapsed group segment
+fn collapsed_range(segment: &str) -> Option<(usize, usize)> {
+    let (start, end) = segment.strip_prefix(COLLAPSED_PREFIX)?.split_once('_')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+/// Resolve a path (relative to `value`) to the sub-value it points at
+/// Map entries, set items, list and tuple items are addressed by index,
+/// a collapsed group resolves to the collection containing it
+pub fn resolve_path<'a>(value: &'a itf::Value, path: &[String]) -> Option<&'a itf::Value> {
+    let Some((segment, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    if segment.starts_with(COLLAPSED_PREFIX) {
+        return Some(value);
+    }
+
+    let child = match value {
+        itf::Value::Record(fields) => fields.get(segment)?,
+        itf::Value::Map(pairs) => pairs.iter().nth(segment.parse().ok()?)?.1,
+        itf::Value::Set(items) => items.iter().nth(segment.parse().ok()?)?,
+        itf::Value::List(items) => items.get(segment.parse::<usize>().ok()?)?,
+        itf::Value::Tuple(items) => items.iter().nth(segment.parse().ok()?)?,
+        _ => return None,
+    };
+    resolve_path(child, rest)
+}
+
+/// Sub-node of the JSON text of `value` that a path (relative to it) points at, as `resolve_path` resolves it
+/// Map entries and set items are addressed in the order of the parsed value, so they are matched by value
+fn raw_node<'a>(raw: &'a RawValue, value: &itf::Value, path: &[String]) -> Option<&'a RawValue> {
+    let Some((segment, rest)) = path.split_first() else {
+        return Some(raw);
+    };
+    if segment.starts_with(COLLAPSED_PREFIX) {
+        return Some(raw);
+    }
+
+    let parsed = |raw: &RawValue| serde_json::from_str::<itf::Value>(raw.get()).ok();
+    let (raw_child, child) = match value {
+        itf::Value::Record(fields) => {
+            let mut object: HashMap<String, &RawValue> = serde_json::from_str(raw.get()).ok()?;
+            (object.remove(segment)?, fields.get(segment)?)
+        }
+        itf::Value::Map(pairs) => {
+            let (key, child) = pairs.iter().nth(segment.parse().ok()?)?;
+            let mut object: HashMap<String, Vec<(&RawValue, &RawValue)>> = serde_json::from_str(raw.get()).ok()?;
+            let raw_pairs = object.remove("#map")?;
+            (raw_pairs.into_iter().find(|(raw_key, _)| parsed(raw_key).as_ref() == Some(key))?.1, child)
+        }
+        itf::Value::Set(items) => {
+            let child = items.iter().nth(segment.parse().ok()?)?;
+            let mut object: HashMap<String, Vec<&RawValue>> = serde_json::from_str(raw.get()).ok()?;
+            (object.remove("#set")?.into_iter().find(|raw_item| parsed(raw_item).as_ref() == Some(child))?, child)
+        }
+        itf::Value::List(items) => {
+            let index: usize = segment.parse().ok()?;
+            let raw_items: Vec<&RawValue> = serde_json::from_str(raw.get()).ok()?;
+            (*raw_items.get(index)?, items.get(index)?)
+        }
+        itf::Value::Tuple(items) => {
+            let index: usize = segment.parse().ok()?;
+            let mut object: HashMap<String, Vec<&RawValue>> = serde_json::from_str(raw.get()).ok()?;
+            (*object.remove("#tup")?.get(index)?, items.iter().nth(index)?)
+        }
+        _ => return None,
+    };
+    raw_node(raw_child, child, rest)
+}
+
+/// JSON text laid out one value per line, keeping its keys, order and literals as they are
+pub fn indent_json(text: &str) -> String {
+    let mut out = String::new();
+    let mut depth = 0;
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    let newline = |out: &mut String, depth: usize| {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    };
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '{' | '[' => {
+                out.push(c);
+                // Keep empty collections on one line
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if let Some(close) = chars.next_if(|next| matches!((c, next), ('{', '}') | ('[', ']'))) {
+                    out.push(close);
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Nodes shown after the variables of a state, by name: the choices of its action and its `#meta`, when there is
+/// something to show
+pub fn state_sections(state: &State) -> Vec<(&'static str, &itf::Value)> {
+    let choices = state.choices.as_ref().map(|choices| (CHOICES_NODE, choices));
+    let meta = state.display_meta().map(|meta| (META_NODE, meta));
+    choices.into_iter().chain(meta).collect()
+}
+
+/// Value of a variable, or of a section like `#meta`, in the given state
+pub fn root_value<'a>(state: &'a State, root: &str) -> Option<&'a itf::Value> {
+    match state_sections(state).into_iter().find(|(name, _)| *name == root) {
+        Some((_, value)) => Some(value),
+        None => state.values.get(root).map(|value| value.as_ref()),
+    }
+}
+
+/// Value at a tree path (starting with the variable name) in the given state
+pub fn value_at<'a>(state: &'a State, path: &[String]) -> Option<&'a itf::Value> {
+    let (root, rest) = path.split_first()?;
+    resolve_path(root_value(state, root)?, rest)
+}
+
+/// JSON text of the node at a tree path within `source`, the JSON text of the state as found in the trace file
+/// (see `Trace::state_source`), `None` for nodes not read from it like `#choices`
+pub fn source_at<'a>(source: &'a str, state: &State, path: &[String]) -> Option<&'a str> {
+    let (root, rest) = path.split_first()?;
+    let value = match root.as_str() {
+        META_NODE => state.meta.as_ref()?,
+        name => state.values.get(name)?,
+    };
+    let object: HashMap<String, &RawValue> = serde_json::from_str(source).ok()?;
+    raw_node(object.get(root.as_str())?, value, rest).map(RawValue::get)
+}
+
+/// Human-readable form of a tree path, e.g. `s.messages["p1"][0]`
+/// Map entries show their key, set, list and tuple items their index
+pub fn display_path(state: &State, path: &[String]) -> String {
+    let Some((root, rest)) = path.split_first() else {
+        return String::new();
+    };
+    let mut text = root.clone();
+    let mut value = value_at(state, std::slice::from_ref(root));
+
+    for segment in rest {
+        if segment.starts_with(COLLAPSED_PREFIX) {
+            break;
+        }
+        match value {
+            Some(itf::Value::Record(_)) => text.push_str(&format!(".{}", segment)),
+            Some(itf::Value::Map(pairs)) => {
+                match segment.parse().ok().and_then(|i: usize| pairs.iter().nth(i)) {
+                    Some((key, _)) => text.push_str(&format!("[{}]", format_value_preview(key, 40))),
+                    None => text.push_str(&format!("[{}]", segment)),
+                }
+            }
+            _ => text.push_str(&format!("[{}]", segment)),
+        }
+        value = value.and_then(|v| resolve_path(v, std::slice::from_ref(segment)));
+    }
+
+    text
+}
+
+/// Single-line rendering of a value, falling back to a short summary if it is too long
+pub fn format_value_preview(value: &itf::Value, max_len: usize) -> String {
+    format_value_full(value, max_len).unwrap_or_else(|| format_value_collapsed(value, max_len))
+}
+
+/// Single-line rendering of a whole value, however long, or None if it holds a map (maps only render as trees)
+pub fn format_value_inline(value: &itf::Value) -> Option<String> {
+    format_value_full(value, usize::MAX)
+}
+
+/// Collect the paths of a node and its descendants that can be expanded, as `render_value` lays them out
+fn collect_expandable_paths(value: &itf::Value, path: &NodePath, paths: &mut Vec<NodePath>) {
+    match value {
+        itf::Value::Record(fields) => match classify_sum_type(fields) {
+            Some(SumTypeVariant::Unit(_)) => {}
+            Some(SumTypeVariant::WithValue(_, inner_value)) => {
+                paths.push(path.clone());
+                let mut value_path = path.clone();
+                value_path.push("value".to_string());
+                collect_expandable_children(inner_value, &value_path, paths);
+            }
+            None => {
+                paths.push(path.clone());
+                collect_expandable_children(value, path, paths);
+            }
+        },
+        // Long strings expand to show their full content
+        itf::Value::String(_) => paths.push(path.clone()),
+        itf::Value::Map(_) | itf::Value::Set(_) | itf::Value::List(_) | itf::Value::Tuple(_) => {
+            paths.push(path.clone());
+            collect_expandable_children(value, path, paths);
+        }
+        _ => {}
+    }
+}
+
+/// Collect expandable paths below a value whose children are rendered under `path` (see `render_value_children`)
+fn collect_expandable_children(value: &itf::Value, path: &NodePath, paths: &mut Vec<NodePath>) {
+    let child = |segment: String| {
+        let mut child_path = path.clone();
+        child_path.push(segment);
+        child_path
+    };
+    match value {
+        itf::Value::Record(fields) => {
+            for (field_name, field_value) in fields.iter() {
+                collect_expandable_paths(field_value, &child(field_name.clone()), paths);
+            }
+        }
+        itf::Value::Map(pairs) => {
+            for (i, (_, val)) in pairs.iter().enumerate() {
+                // Map values render their children directly under the entry
+                let entry_path = child(i.to_string());
+                paths.push(entry_path.clone());
+                collect_expandable_children(val, &entry_path, paths);
+            }
+        }
+        itf::Value::Set(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_expandable_paths(item, &child(i.to_string()), paths);
+            }
+        }
+        itf::Value::List(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_expandable_paths(item, &child(i.to_string()), paths);
+            }
+        }
+        itf::Value::Tuple(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_expandable_paths(item, &child(i.to_string()), paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Tracks which nodes are expanded, and which byte lists are shown as hex dumps
+#[derive(Clone, PartialEq)]
 pub struct ExpansionState {
     expanded: HashSet<NodePath>,
     manual_overrides: HashSet<NodePath>, // Paths explicitly toggled by user
+    hex_dumps: HashSet<NodePath>,
+    collapsed_modules: HashSet<NodePath>, // Module groups, expanded unless collapsed by the user
 }
 
 impl ExpansionState {
@@ -50,15 +362,26 @@ impl ExpansionState {
         Self {
             expanded: HashSet::new(),
             manual_overrides: HashSet::new(),
+            hex_dumps: HashSet::new(),
+            collapsed_modules: HashSet::new(),
         }
     }
 
     pub fn is_expanded(&self, path: &NodePath) -> bool {
+        if is_module_path(path) {
+            return !self.collapsed_modules.contains(path);
+        }
         self.expanded.contains(path)
     }
 
     /// Toggle expansion manually (user action)
     pub fn toggle(&mut self, path: &NodePath) {
+        if is_module_path(path) {
+            if !self.collapsed_modules.remove(path) {
+                self.collapsed_modules.insert(path.clone());
+            }
+            return;
+        }
         if self.expanded.contains(path) {
             self.expanded.remove(path);
         } else {
@@ -68,6 +391,17 @@ impl ExpansionState {
         self.manual_overrides.insert(path.clone());
     }
 
+    /// Toggle showing the byte list at a path as a hex dump
+    pub fn toggle_hex_dump(&mut self, path: &NodePath) {
+        if !self.hex_dumps.remove(path) {
+            self.hex_dumps.insert(path.clone());
+        }
+    }
+
+    pub fn is_hex_dump(&self, path: &NodePath) -> bool {
+        self.hex_dumps.contains(path)
+    }
+
     /// Check if a path was manually overridden by user
     pub fn is_manual(&self, path: &NodePath) -> bool {
         self.manual_overrides.contains(path)
@@ -94,6 +428,16 @@ impl ExpansionState {
         }
     }
 
+    /// Expand all ancestors of a path so that it becomes visible
+    pub fn reveal(&mut self, path: &NodePath) {
+        if let Some(name) = path.first() {
+            self.collapsed_modules.retain(|module| !module_of(module).is_some_and(|module| in_module(name, module)));
+        }
+        for i in 1..path.len() {
+            self.expanded.insert(path[0..i].to_vec());
+        }
+    }
+
     /// Clear all expansions and manual overrides
     pub fn clear(&mut self) {
         self.expanded.clear();
@@ -103,11 +447,74 @@ impl ExpansionState {
     /// Expand all given paths (used for expand-all command)
     pub fn expand_all(&mut self, paths: &[NodePath]) {
         for path in paths {
+            if is_module_path(path) {
+                self.collapsed_modules.remove(path);
+                continue;
+            }
             self.expanded.insert(path.clone());
             self.manual_overrides.insert(path.clone());
         }
     }
 
+    /// Expand a node and all of its descendants (user action)
+    /// For a collapsed group, `value` is the collection holding it and only the group's items are expanded
+    pub fn expand_subtree(&mut self, value: &itf::Value, path: &NodePath) {
+        let mut paths = Vec::new();
+        match path.last().and_then(|segment| collapsed_range(segment)) {
+            Some((start, end)) => {
+                let parent = &path[..path.len() - 1];
+                let mut children = Vec::new();
+                collect_expandable_children(value, &parent.to_vec(), &mut children);
+                paths.push(path.clone());
+                paths.extend(children.into_iter().filter(|child| {
+                    child[parent.len()].parse::<usize>().is_ok_and(|i| (start..=end).contains(&i))
+                }));
+            }
+            None => collect_expandable_paths(value, path, &mut paths),
+        }
+        self.expand_all(&paths);
+    }
+
+    /// Expand a node and its descendants down to `depth` levels below it, leaving deeper ones collapsed
+    pub fn expand_to_depth(&mut self, value: &itf::Value, path: &NodePath, depth: usize) {
+        let mut paths = Vec::new();
+        collect_expandable_paths(value, path, &mut paths);
+        paths.retain(|p| p.len() - path.len() < depth);
+        self.expand_all(&paths);
+    }
+
+    /// Collapse a node and everything below it (user action)
+    pub fn collapse_subtree(&mut self, path: &NodePath) {
+        self.collapse_where(|p| p.starts_with(path));
+        self.manual_overrides.insert(path.clone());
+    }
+
+    /// Collapse everything except the ancestors of a node and the node's own subtree (user action)
+    /// `visible` are the currently shown expandable paths, kept collapsed as well so that
+    /// auto-expansion does not reopen them
+    pub fn focus(&mut self, path: &NodePath, visible: &[NodePath]) {
+        let outside = |p: &NodePath| !path.starts_with(p) && !p.starts_with(path);
+        self.collapse_where(outside);
+        for p in visible.iter().filter(|p| outside(p)) {
+            self.manual_overrides.insert(p.clone());
+        }
+    }
+
+    /// Collapse all expanded paths matching a predicate, as manual overrides
+    fn collapse_where(&mut self, predicate: impl Fn(&NodePath) -> bool) {
+        let collapsed: Vec<NodePath> = self.expanded.iter().filter(|p| predicate(p)).cloned().collect();
+        for p in collapsed {
+            self.expanded.remove(&p);
+            self.manual_overrides.insert(p);
+        }
+    }
+
+    /// Expand exactly these nodes, as if the user had toggled them, like when resuming a saved session
+    pub fn set_expanded(&mut self, paths: HashSet<NodePath>) {
+        self.manual_overrides = paths.clone();
+        self.expanded = paths;
+    }
+
     /// Save current expansion state (for backtracking)
     pub fn snapshot(&self) -> HashSet<NodePath> {
         self.expanded.clone()
@@ -188,68 +595,548 @@ pub struct TreeLine {
 pub struct StyledSpan {
     pub text: String,
     pub style: SpanStyle,
+    /// Part of a modified value that differs from the previous state
+    pub changed: bool,
 }
 
 /// Style types for syntax highlighting
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SpanStyle {
     Default,
-    #[allow(dead_code)]
+    /// Name of the node: variable, field or item label
+    Key,
+    /// Brackets, separators and arrows
+    Punctuation,
     String,
-    #[allow(dead_code)]
     Number,
-    #[allow(dead_code)]
     Boolean,
+    /// Variable type annotation from #meta
+    Type,
+    /// Indentation guide lines
+    Guide,
+}
+
+impl StyledSpan {
+    fn new(text: impl Into<String>, style: SpanStyle) -> Self {
+        Self { text: text.into(), style, changed: false }
+    }
+
+    /// Copy of the span with other text, keeping its style
+    fn with_text(&self, text: impl Into<String>) -> Self {
+        Self { text: text.into(), ..*self }
+    }
+
+    fn default(text: impl Into<String>) -> Self {
+        Self::new(text, SpanStyle::Default)
+    }
 }
 
-impl SpanStyle {
-    pub fn to_color(&self) -> Option<Color> {
+impl TreeLine {
+    /// Create a TreeLine with default (unstyled) spans from text
+    fn with_default_spans(path: NodePath, text: String, expandable: bool, diff: DiffKind) -> Self {
+        let spans = vec![StyledSpan::default(text)];
+        Self { path, expandable, diff, spans }
+    }
+
+    /// Create a TreeLine with its text split into syntax highlighted spans
+    fn highlighted(path: NodePath, text: String, expandable: bool, diff: DiffKind) -> Self {
+        let spans = highlight(&text);
+        Self { path, expandable, diff, spans }
+    }
+
+    /// Split the line into rows of at most `width` characters for soft wrapping
+    /// Continuation rows are indented two levels deeper than the line itself
+    pub fn wrap(&self, width: usize) -> Vec<Vec<StyledSpan>> {
+        let width = width.max(1);
+        let continuation = self.continuation_indent(width);
+        let mut rows = vec![Vec::new()];
+        let mut used = 0;
+
+        for span in &self.spans {
+            let mut text = String::new();
+            for c in span.text.chars() {
+                if used == width {
+                    rows.last_mut().unwrap().push(span.with_text(std::mem::take(&mut text)));
+                    rows.push(vec![StyledSpan::default(" ".repeat(continuation))]);
+                    used = continuation;
+                }
+                text.push(c);
+                used += 1;
+            }
+            if !text.is_empty() {
+                rows.last_mut().unwrap().push(span.with_text(text));
+            }
+        }
+        rows
+    }
+
+    /// Number of rows `wrap` splits the line into
+    pub fn row_count(&self, width: usize) -> usize {
+        let width = width.max(1);
+        let len: usize = self.spans.iter().map(|span| span.text.chars().count()).sum();
+        if len <= width {
+            return 1;
+        }
+        let per_row = width - self.continuation_indent(width);
+        1 + (len - width).div_ceil(per_row)
+    }
+
+    /// Text of the line without its expand/collapse icon, to use it outside of the tree view
+    pub fn plain_text(&self) -> String {
+        let text: String = self.spans.iter().map(|span| span.text.as_str()).collect();
+        let lead = text.len() - text.trim_start_matches([' ', '+', '-', '~']).len();
+        let body = &text[lead..];
+        match body.strip_prefix("▼ ").or_else(|| body.strip_prefix("▶ ")) {
+            Some(rest) => format!("{}{}", &text[..lead], rest),
+            None => text,
+        }
+    }
+
+    /// Mark the characters of a modified string or integer value, rendered at the end of the line,
+    /// that differ from its previous value; nothing is marked when characters were only deleted
+    fn mark_changed(&mut self, diff: &DiffResult, value: &itf::Value) {
+        let Some(previous) = diff.previous.get(&self.path) else {
+            return;
+        };
+        let is_leaf = |value: &itf::Value| matches!(value, itf::Value::String(_) | itf::Value::Number(_) | itf::Value::BigInt(_));
+        if !is_leaf(previous) || !is_leaf(value) {
+            return;
+        }
+        let (Some(old), Some(new)) = (format_value_full(previous, usize::MAX), format_value_full(value, usize::MAX)) else {
+            return;
+        };
+        let text: String = self.spans.iter().map(|span| span.text.as_str()).collect();
+        if !text.ends_with(&new) {
+            return;
+        }
+
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        let text_len = text.chars().count();
+        let (start, end) = (text_len - new.len() + prefix, text_len - suffix);
+        if start == end {
+            return;
+        }
+
+        // Split the spans at the boundaries of the changed characters
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        for span in &self.spans {
+            let chars: Vec<char> = span.text.chars().collect();
+            let span_end = offset + chars.len();
+            let cuts = [offset, start.clamp(offset, span_end), end.clamp(offset, span_end), span_end];
+            for (i, bounds) in cuts.windows(2).enumerate() {
+                if bounds[0] < bounds[1] {
+                    let mut piece = span.with_text(chars[bounds[0] - offset..bounds[1] - offset].iter().collect::<String>());
+                    piece.changed = i == 1;
+                    spans.push(piece);
+                }
+            }
+            offset = span_end;
+        }
+        self.spans = spans;
+    }
+
+    /// Indentation of continuation rows, leaving at least half of the width for content
+    fn continuation_indent(&self, width: usize) -> usize {
+        let indent = self.spans
+            .iter()
+            .flat_map(|span| span.text.chars())
+            .take_while(|c| *c == ' ' || GUIDE_CHARS.contains(c))
+            .count();
+        (indent + 2 * INDENT_SIZE).min(width / 2)
+    }
+}
+
+/// Split a rendered line into styled spans: the node's name, punctuation, strings, numbers and booleans
+fn highlight(text: &str) -> Vec<StyledSpan> {
+    let mut spans = Vec::new();
+
+    // Indentation, diff marker and expand icon
+    let mut body_start = text.find(|c: char| !matches!(c, ' ' | '+' | '▼' | '▶')).unwrap_or(text.len());
+    if text[body_start..].starts_with("- ") {
+        // Marker of removed items, unlike negative numbers followed by a space
+        body_start += 2;
+    }
+    let (lead, mut body) = text.split_at(body_start);
+    if !lead.is_empty() {
+        spans.push(StyledSpan::default(lead));
+    }
+
+    // Node name, e.g. `name: ` or `[3]: ` (map keys and set items have none)
+    if let Some(colon) = body.find(": ") {
+        let name = &body[..colon];
+        let is_name = !name.is_empty() && !name.contains([' ', '"', '{', '(', ',']);
+        if is_name {
+            spans.push(StyledSpan::new(name, SpanStyle::Key));
+            spans.push(StyledSpan::new(": ", SpanStyle::Punctuation));
+            body = &body[colon + 2..];
+        }
+    }
+
+    highlight_value(body, &mut spans);
+    spans
+}
+
+/// Highlight the value part of a line
+/// Numbers followed by a word are counts like `3 entries` and stay unstyled
+fn highlight_value(text: &str, spans: &mut Vec<StyledSpan>) {
+    let bytes = text.as_bytes();
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let mut plain = 0; // Start of pending unstyled text
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let style = match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += 1;
+                }
+                i = (i + 1).min(bytes.len());
+                Some(SpanStyle::String)
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'>') => {
+                i += 2;
+                Some(SpanStyle::Punctuation)
+            }
+            b'{' | b'}' | b'(' | b')' | b'[' | b']' | b',' | b':' => {
+                i += 1;
+                Some(SpanStyle::Punctuation)
+            }
+            b if b.is_ascii_digit() || (b == b'-' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) => {
+                i += 1;
+                while i < bytes.len() && is_word(bytes[i]) {
+                    i += 1;
+                }
+                let rest = &bytes[i..];
+                let is_count = rest.len() >= 2 && rest[0] == b' ' && rest[1].is_ascii_alphabetic();
+                let is_number = text[start..i].trim_start_matches('-').bytes().all(|b| b.is_ascii_digit());
+                (is_number && !is_count).then_some(SpanStyle::Number)
+            }
+            b if is_word(b) => {
+                while i < bytes.len() && is_word(bytes[i]) {
+                    i += 1;
+                }
+                matches!(&text[start..i], "true" | "false").then_some(SpanStyle::Boolean)
+            }
+            _ => {
+                i += 1;
+                None
+            }
+        };
+
+        if let Some(style) = style {
+            if plain < start {
+                spans.push(StyledSpan::default(&text[plain..start]));
+            }
+            spans.push(StyledSpan::new(&text[start..i], style));
+            plain = i;
+        }
+    }
+
+    if plain < text.len() {
+        spans.push(StyledSpan::default(&text[plain..]));
+    }
+}
+
+/// How declared variable types are shown next to top-level variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TypeDisplay {
+    Hidden,
+    /// Appended to the variable's header line
+    #[default]
+    Inline,
+    /// On a separate line below the variable's header
+    Line,
+}
+
+impl TypeDisplay {
+    /// Cycle to the next display mode
+    pub fn next(self) -> Self {
         match self {
-            SpanStyle::Default => None,
-            SpanStyle::String => Some(Color::Cyan),
-            SpanStyle::Number => Some(Color::Magenta),
-            SpanStyle::Boolean => Some(Color::Blue),
+            TypeDisplay::Hidden => TypeDisplay::Inline,
+            TypeDisplay::Inline => TypeDisplay::Line,
+            TypeDisplay::Line => TypeDisplay::Hidden,
         }
     }
 }
 
-impl StyledSpan {
-    fn new(text: impl Into<String>, style: SpanStyle) -> Self {
-        Self { text: text.into(), style }
+/// How the module prefix of qualified variable names like `propeller::choreo::s` is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrefixDisplay {
+    #[default]
+    Full,
+    /// The modules shared by all variables elided, like `…::s`
+    Elided,
+    /// Only the last part of the name, like `s`
+    Short,
+}
+
+impl PrefixDisplay {
+    /// Cycle to the next display mode
+    pub fn next(self) -> Self {
+        match self {
+            PrefixDisplay::Full => PrefixDisplay::Elided,
+            PrefixDisplay::Elided => PrefixDisplay::Short,
+            PrefixDisplay::Short => PrefixDisplay::Full,
+        }
     }
 
-    fn default(text: impl Into<String>) -> Self {
-        Self::new(text, SpanStyle::Default)
+    /// Name a variable is shown with, `common` being the module shared by all qualified variables
+    fn label<'a>(self, name: &'a str, common: &str) -> Cow<'a, str> {
+        match self {
+            PrefixDisplay::Full => Cow::Borrowed(name),
+            PrefixDisplay::Elided if !common.is_empty() && in_module(name, common) => Cow::Owned(format!("…{}", &name[common.len()..])),
+            PrefixDisplay::Elided => Cow::Borrowed(name),
+            PrefixDisplay::Short => Cow::Borrowed(name.rsplit(MODULE_SEPARATOR).next().unwrap_or(name)),
+        }
     }
+}
 
-    #[allow(dead_code)]
-    fn string(text: impl Into<String>) -> Self {
-        Self::new(text, SpanStyle::String)
+/// Module shared by all qualified variables, like `propeller::choreo`, empty if they share none
+fn common_module(names: &[&str]) -> String {
+    let mut modules = names.iter().filter_map(|name| name.rsplit_once(MODULE_SEPARATOR)).map(|(module, _)| module.split(MODULE_SEPARATOR).collect::<Vec<_>>());
+    let Some(mut common) = modules.next() else {
+        return String::new();
+    };
+    for module in modules {
+        let shared = common.iter().zip(&module).take_while(|(a, b)| a == b).count();
+        common.truncate(shared);
     }
+    common.join(MODULE_SEPARATOR)
+}
+
+/// Attach a variable's type to its rendered lines
+pub fn annotate_type(lines: &mut Vec<TreeLine>, var_type: &str, display: TypeDisplay) {
+    let Some(header) = lines.first_mut() else {
+        return;
+    };
 
-    #[allow(dead_code)]
-    fn number(text: impl Into<String>) -> Self {
-        Self::new(text, SpanStyle::Number)
+    match display {
+        TypeDisplay::Hidden => {}
+        TypeDisplay::Inline => {
+            header.spans.push(StyledSpan::new(format!("  : {}", var_type), SpanStyle::Type));
+        }
+        TypeDisplay::Line => {
+            let spans = vec![StyledSpan::new(format!("{}type: {}", " ".repeat(INDENT_SIZE), var_type), SpanStyle::Type)];
+            let detail = TreeLine { path: header.path.clone(), expandable: false, diff: DiffKind::Unchanged, spans };
+            lines.insert(1, detail);
+        }
     }
+}
 
-    #[allow(dead_code)]
-    fn boolean(text: impl Into<String>) -> Self {
-        Self::new(text, SpanStyle::Boolean)
+/// Mark modified nodes with `~`, where added and removed ones have `+` and `-`, for when colors
+/// can't tell them apart; closing delimiters of collections are left unmarked
+pub fn add_modified_markers(lines: &mut [TreeLine]) {
+    for i in 0..lines.len() {
+        if lines[i].diff != DiffKind::Modified || (i > 0 && lines[i - 1].path == lines[i].path) {
+            continue;
+        }
+        if let Some(first) = lines[i].spans.first_mut() {
+            let indent = first.text.len() - first.text.trim_start_matches(' ').len();
+            first.text.insert_str(indent, "~ ");
+        }
     }
 }
 
-impl TreeLine {
-    /// Create a TreeLine with default (unstyled) spans from text
-    fn with_default_spans(path: NodePath, text: String, expandable: bool, diff: DiffKind) -> Self {
-        let spans = vec![StyledSpan::default(text)];
-        Self { path, expandable, diff, spans }
+/// Most expansion changes that can be undone
+const MAX_UNDO: usize = 100;
+
+/// Expansion states before (to undo) and after (to redo) changes made by the user
+#[derive(Default)]
+pub struct ExpansionHistory {
+    undo: Vec<ExpansionState>,
+    redo: Vec<ExpansionState>,
+}
+
+impl ExpansionHistory {
+    /// Record a change from `before` to `after`, which can't be redone anymore after a new change
+    pub fn record(&mut self, before: ExpansionState, after: &ExpansionState) {
+        if before == *after {
+            return;
+        }
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    /// Go back to the expansion before the last change, false if there is none
+    pub fn undo(&mut self, expansion: &mut ExpansionState) -> bool {
+        let Some(previous) = self.undo.pop() else {
+            return false;
+        };
+        self.redo.push(std::mem::replace(expansion, previous));
+        true
+    }
+
+    /// Make the last undone change again, false if there is none
+    pub fn redo(&mut self, expansion: &mut ExpansionState) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push(std::mem::replace(expansion, next));
+        true
     }
 }
 
+/// Draw indentation guides (`│`, `├`, `└`) in the leading whitespace of rendered lines
+/// A line's parent is the closest line above it with less indentation
+pub fn add_guides(lines: &mut [TreeLine]) {
+    let indents: Vec<usize> = lines
+        .iter()
+        .map(|line| line.spans.first().map_or(0, |span| span.text.len() - span.text.trim_start_matches(' ').len()))
+        .collect();
+
+    let mut parents: Vec<Option<usize>> = Vec::with_capacity(lines.len());
+    let mut stack: Vec<usize> = Vec::new();
+    let mut last_child = HashMap::new();
+    for (i, &indent) in indents.iter().enumerate() {
+        while stack.last().is_some_and(|&top| indents[top] >= indent) {
+            stack.pop();
+        }
+        let parent = stack.last().copied();
+        if let Some(p) = parent {
+            last_child.insert(p, i);
+        }
+        parents.push(parent);
+        stack.push(i);
+    }
+    let is_last = |i: usize| match parents[i] {
+        Some(p) => last_child[&p] == i,
+        None => true,
+    };
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        let Some(parent) = parents[i] else {
+            continue;
+        };
+        let mut guides = vec![' '; indents[i]];
+        guides[indents[parent]] = if is_last(i) { '└' } else { '├' };
+        // Guides of ancestors continue past this line unless their branch already ended
+        let mut child = parent;
+        while let Some(ancestor) = parents[child] {
+            if !is_last(child) {
+                guides[indents[ancestor]] = '│';
+            }
+            child = ancestor;
+        }
+
+        let first = &mut line.spans[0];
+        first.text = first.text[indents[i]..].to_string();
+        line.spans.insert(0, StyledSpan::new(guides.into_iter().collect::<String>(), SpanStyle::Guide));
+    }
+}
+
+/// Align the lines of the two diff panels by inserting blank filler lines, so that the same node sits
+/// on the same row on both sides. Lines match by path and rank among the lines sharing it (like a
+/// collection's header and closing delimiter); ghost lines of removed items never match
+pub fn align_lines(left: Vec<TreeLine>, right: Vec<TreeLine>) -> (Vec<TreeLine>, Vec<TreeLine>) {
+    fn keys(lines: &[TreeLine]) -> Vec<Option<(&[String], usize)>> {
+        let mut ranks: HashMap<&[String], usize> = HashMap::new();
+        lines
+            .iter()
+            .map(|line| {
+                if line.diff == DiffKind::Removed {
+                    return None;
+                }
+                let rank = ranks.entry(line.path.as_slice()).or_insert(0);
+                *rank += 1;
+                Some((line.path.as_slice(), *rank - 1))
+            })
+            .collect()
+    }
+    // Blank line indented like the one it stands in for, so that guides run through it
+    fn filler(line: &TreeLine) -> TreeLine {
+        let indent = line.spans.first().map_or(0, |span| span.text.len() - span.text.trim_start_matches(' ').len());
+        TreeLine { path: Vec::new(), expandable: false, diff: DiffKind::Unchanged, spans: vec![StyledSpan::default(" ".repeat(indent))] }
+    }
+
+    let (left_keys, right_keys) = (keys(&left), keys(&right));
+    let left_set: HashSet<_> = left_keys.iter().flatten().collect();
+    let right_set: HashSet<_> = right_keys.iter().flatten().collect();
+
+    let mut aligned = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() || j < right.len() {
+        let left_key = left_keys.get(i).map(Option::as_ref);
+        let right_key = right_keys.get(j).map(Option::as_ref);
+        let left_only = left_key.is_some_and(|l| l.is_none_or(|l| !right_set.contains(l)));
+        let right_only = right_key.is_some_and(|r| r.is_none_or(|r| !left_set.contains(r)));
+        match (left_key, right_key) {
+            (Some(Some(l)), Some(Some(r))) if l == r => {
+                aligned.0.push(left[i].clone());
+                aligned.1.push(right[j].clone());
+                i += 1;
+                j += 1;
+            }
+            // Lines matching out of order on both sides are shown apart, the left one first
+            (Some(_), _) if left_only || !right_only => {
+                aligned.0.push(left[i].clone());
+                aligned.1.push(filler(&left[i]));
+                i += 1;
+            }
+            _ => {
+                aligned.0.push(filler(&right[j]));
+                aligned.1.push(right[j].clone());
+                j += 1;
+            }
+        }
+    }
+    aligned
+}
+
+/// Turn the lines of a state into a unified diff: a modified value shown inline is preceded by
+/// a `-` line with its previous value, and marked itself with `+`
+pub fn add_previous_values(lines: Vec<TreeLine>, diff: &DiffResult, state: &State) -> Vec<TreeLine> {
+    let mut result = Vec::with_capacity(lines.len());
+    for mut line in lines {
+        let texts = diff.previous.get(&line.path)
+            .filter(|_| line.diff == DiffKind::Modified && !line.expandable)
+            .zip(value_at(state, &line.path))
+            .and_then(|(previous, value)| format_value_full(previous, usize::MAX).zip(format_value_full(value, usize::MAX)));
+        let Some((old, new)) = texts else {
+            result.push(line);
+            continue;
+        };
+        // The value comes before the type annotation, if any
+        let annotation = line.spans.iter().rev().take_while(|span| span.style == SpanStyle::Type).count();
+        let text: String = line.spans[..line.spans.len() - annotation].iter().map(|span| span.text.as_str()).collect();
+        let Some(prefix) = text.strip_suffix(&new) else {
+            result.push(line);
+            continue;
+        };
+
+        let body = prefix.trim_start_matches(' ');
+        let indent = &prefix[..prefix.len() - body.len()];
+        let old_text = format!("{}- {}{}", indent, body, old);
+        result.push(TreeLine::highlighted(line.path.clone(), old_text, false, DiffKind::Removed));
+
+        // The marker goes after the indentation, which the first span holds
+        let first = &mut line.spans[0];
+        if first.text.len() > indent.len() {
+            line.spans.insert(0, StyledSpan::default(format!("{}+ ", indent)));
+            line.spans[1].text = line.spans[1].text[indent.len()..].to_string();
+        } else {
+            first.text.push_str("+ ");
+        }
+        result.push(line);
+    }
+    result
+}
+
 /// Get diff marker for a DiffKind
 fn diff_marker(diff: DiffKind) -> &'static str {
     match diff {
         DiffKind::Added => "+ ",
+        DiffKind::Removed => "- ",
         _ => "",
     }
 }
@@ -274,13 +1161,102 @@ fn name_prefix_with_icon(icon: &str, name: &str, diff: DiffKind) -> String {
     }
 }
 
+/// Render the variables of a state (and its metadata) into tree lines
+#[allow(clippy::too_many_arguments)]
+pub fn render_state(
+    trace: &Trace,
+    state_idx: usize,
+    expansion: &ExpansionState,
+    diff: &DiffResult,
+    formatters: &Formatters,
+    var_order: VarOrder,
+    type_display: TypeDisplay,
+    group_modules: bool,
+    prefixes: PrefixDisplay,
+    terminal_width: usize,
+    collapse_threshold: usize,
+) -> Vec<TreeLine> {
+    let mut tree_lines = Vec::new();
+    if let Some(state) = trace.states.get(state_idx) {
+        // A variable shown under a label, its full name or, grouped by module, its name in the module, unless it has an alias
+        let render_var = |name: &str, label: &str, depth: usize| {
+            let path = vec![name.to_string()];
+            let label = trace.alias(name).unwrap_or(label);
+            let mut var_lines = render_value(label, &state.values[name], path, expansion, diff, formatters, depth, terminal_width, collapse_threshold);
+            if let Some(var_type) = trace.var_type(name) {
+                annotate_type(&mut var_lines, var_type, type_display);
+            }
+            var_lines
+        };
+        let names = state.var_names(&trace.vars, var_order);
+        if group_modules {
+            render_modules(&names, "", 0, expansion, diff, &render_var, &mut tree_lines);
+        } else {
+            let common = if prefixes == PrefixDisplay::Elided { common_module(&names) } else { String::new() };
+            for name in names {
+                tree_lines.extend(render_var(name, &prefixes.label(name, &common), 0));
+            }
+        }
+        for (name, value) in state_sections(state) {
+            let path = vec![name.to_string()];
+            tree_lines.extend(render_value(name, value, path, expansion, diff, formatters, 0, terminal_width, collapse_threshold));
+        }
+    }
+    tree_lines
+}
+
+/// Render the variables of `module` (all of them for the empty one) by their name in it, and each of its submodules as
+/// a collapsible group of their own, placed where its first variable would be
+fn render_modules(
+    names: &[&str],
+    module: &str,
+    depth: usize,
+    expansion: &ExpansionState,
+    diff: &DiffResult,
+    render_var: &dyn Fn(&str, &str, usize) -> Vec<TreeLine>,
+    lines: &mut Vec<TreeLine>,
+) {
+    let mut submodules = Vec::new();
+    for &name in names {
+        let rest = if module.is_empty() { name } else { &name[module.len() + MODULE_SEPARATOR.len()..] };
+        let Some((head, _)) = rest.split_once(MODULE_SEPARATOR) else {
+            lines.extend(render_var(name, rest, depth));
+            continue;
+        };
+        if submodules.contains(&head) {
+            continue;
+        }
+        submodules.push(head);
+
+        let submodule = if module.is_empty() { head.to_string() } else { format!("{}{}{}", module, MODULE_SEPARATOR, head) };
+        let members: Vec<&str> = names.iter().copied().filter(|name| in_module(name, &submodule)).collect();
+        let path = module_path(&submodule);
+        let expanded = expansion.is_expanded(&path);
+        let changed = members.iter().any(|name| diff.get(&vec![name.to_string()]) != DiffKind::Unchanged);
+        let icon = if expanded { "▼" } else { "▶" };
+        let mut line = TreeLine::with_default_spans(path, format!("{}{} ", "  ".repeat(depth), icon), true, if changed { DiffKind::Modified } else { DiffKind::Unchanged });
+        line.spans.push(StyledSpan::new(head, SpanStyle::Key));
+        line.spans.push(StyledSpan::new(MODULE_SEPARATOR, SpanStyle::Punctuation));
+        if !expanded {
+            let noun = if members.len() == 1 { "variable" } else { "variables" };
+            line.spans.push(StyledSpan::default(format!(" ({} {})", members.len(), noun)));
+        }
+        lines.push(line);
+        if expanded {
+            render_modules(&members, &submodule, depth + 1, expansion, diff, render_var, lines);
+        }
+    }
+}
+
 /// Render an itf::Value into tree lines
+#[allow(clippy::too_many_arguments)]
 pub fn render_value(
     name: &str,
     value: &itf::Value,
     path: NodePath,
     expansion: &ExpansionState,
     diff: &DiffResult,
+    formatters: &Formatters,
     depth: usize,
     terminal_width: usize,
     collapse_threshold: usize,
@@ -291,23 +1267,49 @@ pub fn render_value(
     let prefix = name_prefix(name, diff_kind);
     let thresholds = DisplayThresholds::new(terminal_width, depth);
 
+    // Custom formatters from the config replace the default rendering
+    if let Some(formatted) = format_custom(formatters, &path, value, thresholds.value) {
+        let mut line = TreeLine::highlighted(path, format!("{}{}", indent, prefix), false, diff_kind);
+        line.spans.push(StyledSpan::default(formatted));
+        return vec![line];
+    }
+    // Values with custom formatted descendants or removed items are not inlined, so that those show up
+    let inline_allowed = || !has_custom_format_below(formatters, &path, value) && !diff.has_removed_below(&path);
+
     match value {
         // Leaf values - not expandable
         itf::Value::Bool(b) => {
             let text = format!("{}{}{}", indent, prefix, b);
-            vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+            vec![TreeLine::highlighted(path, text, false, diff_kind)]
         }
         itf::Value::Number(n) => {
             let text = format!("{}{}{}", indent, prefix, n);
-            vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+            let mut line = TreeLine::highlighted(path, text, false, diff_kind);
+            line.mark_changed(diff, value);
+            vec![line]
         }
-        itf::Value::String(s) => {
+        itf::Value::String(s) if s.chars().count() + 2 <= thresholds.inline && !s.contains('\n') => {
             let text = format!("{}{}\"{}\"", indent, prefix, s);
-            vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+            let mut line = TreeLine::highlighted(path, text, false, diff_kind);
+            line.mark_changed(diff, value);
+            vec![line]
+        }
+        // Long string - expandable to show its full content
+        itf::Value::String(s) => {
+            let icon = if expanded { "▼" } else { "▶" };
+            let icon_prefix = name_prefix_with_icon(icon, name, diff_kind);
+            let text = format!("{}{}{}", indent, icon_prefix, format_string_preview(s, thresholds.value));
+            let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
+            if expanded {
+                lines.extend(render_string_lines(s, &path, diff_kind, depth + 1, terminal_width));
+            }
+            lines
         }
         itf::Value::BigInt(n) => {
             let text = format!("{}{}{}", indent, prefix, n);
-            vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+            let mut line = TreeLine::highlighted(path, text, false, diff_kind);
+            line.mark_changed(diff, value);
+            vec![line]
         }
 
         // Record - expandable
@@ -318,38 +1320,37 @@ pub fn render_value(
                     SumTypeVariant::Unit(tag) => {
                         // Just show the tag without parentheses
                         let text = format!("{}{}{}", indent, prefix, tag);
-                        vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                        vec![TreeLine::highlighted(path, text, false, diff_kind)]
                     }
                     SumTypeVariant::WithValue(tag, inner_value) => {
                         // Display as Tag(preview of value)
-                        let inner_preview = format_value_full(inner_value, thresholds.preview)
-                            .unwrap_or_else(|| format_value_short(inner_value));
+                        let inner_preview = format_value_preview(inner_value, thresholds.preview);
 
                         // Check if inner value can be fully inlined
-                        let can_inline = format_value_full(inner_value, thresholds.preview).is_some();
+                        let can_inline = format_value_full(inner_value, thresholds.preview).is_some() && inline_allowed();
 
                         if can_inline {
                             let text = format!("{}{}{}({})", indent, prefix, tag, inner_preview);
-                            vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                            vec![TreeLine::highlighted(path, text, false, diff_kind)]
                         } else {
                             let icon = if expanded { "▼" } else { "▶" };
                             let icon_prefix = name_prefix_with_icon(icon, name, diff_kind);
                             let text = format!("{}{}{}({})", indent, icon_prefix, tag, inner_preview);
-                            let mut lines = vec![TreeLine::with_default_spans(path.clone(), text, true, diff_kind)];
+                            let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
                             if expanded {
                                 // Only show the inner value's contents, skip tag
                                 let mut value_path = path.clone();
                                 value_path.push("value".to_string());
-                                lines.extend(render_value_children(inner_value, value_path, expansion, diff, depth + 1, terminal_width, collapse_threshold));
+                                lines.extend(render_value_children(inner_value, value_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold));
                             }
                             lines
                         }
                     }
                 }
-            } else if let Some(inline) = format_value_full(value, thresholds.inline) {
+            } else if let Some(inline) = format_value_full(value, thresholds.inline).filter(|_| inline_allowed()) {
                 // Small record, show inline without expand
                 let text = format!("{}{}{}", indent, prefix, inline);
-                vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                vec![TreeLine::highlighted(path, text, false, diff_kind)]
             } else {
                 // Large record
                 let icon = if expanded { "▼" } else { "▶" };
@@ -359,16 +1360,16 @@ pub fn render_value(
                 } else {
                     format!("{}{}{{{} fields}}", indent, icon_prefix, fields.len())
                 };
-                let mut lines = vec![TreeLine::with_default_spans(path.clone(), text, true, diff_kind)];
+                let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
                 if expanded {
                     for (field_name, field_value) in fields.iter() {
                         let mut child_path = path.clone();
                         child_path.push(field_name.clone());
-                        lines.extend(render_value(field_name, field_value, child_path, expansion, diff, depth + 1, terminal_width, collapse_threshold));
+                        lines.extend(render_value(field_name, field_value, child_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold));
                     }
                     // Add closing brace
                     let close_text = format!("{}}}", indent);
-                    lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, diff_kind));
+                    lines.push(TreeLine::highlighted(path.clone(), close_text, false, diff_kind));
                 }
                 lines
             }
@@ -376,10 +1377,10 @@ pub fn render_value(
 
         // Map - expandable
         itf::Value::Map(pairs) => {
-            if pairs.is_empty() {
+            if pairs.is_empty() && !diff.has_removed_below(&path) {
                 // Empty map, no expand needed
                 let text = format!("{}{}Map()", indent, prefix);
-                vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                vec![TreeLine::highlighted(path, text, false, diff_kind)]
             } else {
                 let icon = if expanded { "▼" } else { "▶" };
                 let icon_prefix = name_prefix_with_icon(icon, name, diff_kind);
@@ -388,7 +1389,7 @@ pub fn render_value(
                 } else {
                     format!("{}{}Map({} entries)", indent, icon_prefix, pairs.len())
                 };
-                let mut lines = vec![TreeLine::with_default_spans(path.clone(), text, true, diff_kind)];
+                let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
                 if expanded {
                     // Group entries by change status
                     let groups = group_by_change_status(pairs.len(), diff, &path);
@@ -401,17 +1402,16 @@ pub fn render_value(
                         if has_any_changed && !is_changed && group_count >= collapse_threshold && group_count >= 3 {
                             // Create unique path for this collapsed group
                             let mut group_path = path.clone();
-                            group_path.push(format!("__collapsed_{}_{}", start, start + group_count - 1));
+                            group_path.push(format!("{}{}_{}", COLLAPSED_PREFIX, start, start + group_count - 1));
 
                             let group_expanded = expansion.is_expanded(&group_path);
 
                             if group_expanded {
                                 // Show entries individually
-                                for i in start..(start + group_count) {
-                                    let (key, val) = pairs_vec[i];
+                                for (i, (key, val)) in pairs_vec.iter().enumerate().skip(start).take(group_count) {
                                     let mut entry_path = path.clone();
                                     entry_path.push(format!("{}", i));
-                                    lines.extend(render_map_entry(key, val, entry_path, expansion, diff, depth + 1, terminal_width, collapse_threshold));
+                                    lines.extend(render_map_entry(key, val, entry_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold));
                                 }
                             } else {
                                 // Show collapsed summary (expandable)
@@ -421,18 +1421,18 @@ pub fn render_value(
                             }
                         } else {
                             // Show entries individually
-                            for i in start..(start + group_count) {
-                                let (key, val) = pairs_vec[i];
+                            for (i, (key, val)) in pairs_vec.iter().enumerate().skip(start).take(group_count) {
                                 let mut entry_path = path.clone();
                                 entry_path.push(format!("{}", i));
-                                lines.extend(render_map_entry(key, val, entry_path, expansion, diff, depth + 1, terminal_width, collapse_threshold));
+                                lines.extend(render_map_entry(key, val, entry_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold));
                             }
                         }
                     }
+                    lines.extend(render_removed(diff, &path, depth + 1, terminal_width));
 
                     // Add closing paren
                     let close_text = format!("{})", indent);
-                    lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, diff_kind));
+                    lines.push(TreeLine::highlighted(path.clone(), close_text, false, diff_kind));
                 }
                 lines
             }
@@ -442,7 +1442,7 @@ pub fn render_value(
         itf::Value::Set(items) => {
             let count = items.iter().count();
             let all_simple = all_simple(items.iter());
-            let inline = if all_simple {
+            let inline = if all_simple && inline_allowed() {
                 format_collection_inline(items.iter(), "Set(", ")", thresholds.inline)
             } else {
                 None
@@ -451,7 +1451,7 @@ pub fn render_value(
             // If we can show inline, no need for expand/collapse
             if let Some(ref inline_str) = inline {
                 let text = format!("{}{}{}", indent, prefix, inline_str);
-                vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                vec![TreeLine::highlighted(path, text, false, diff_kind)]
             } else {
                 // Complex or too long - needs expand/collapse
                 let icon = if expanded { "▼" } else { "▶" };
@@ -461,7 +1461,7 @@ pub fn render_value(
                 } else {
                     format!("{}{}Set({} items)", indent, icon_prefix, count)
                 };
-                let mut lines = vec![TreeLine::with_default_spans(path.clone(), text, true, diff_kind)];
+                let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
 
                 if expanded {
                     let items_vec: Vec<_> = items.iter().collect();
@@ -477,24 +1477,38 @@ pub fn render_value(
                             let item = items_vec[i];
                             let mut child_path = path.clone();
                             child_path.push(format!("{}", i));
-                            render_value("", item, child_path, expansion, diff, depth + 1, terminal_width, collapse_threshold)
+                            render_value("", item, child_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold)
                         },
                         |_start, _end, count| format!("... ({} unchanged)", count),
                     );
                     lines.extend(item_lines);
+                    lines.extend(render_removed(diff, &path, depth + 1, terminal_width));
 
                     // Add closing paren
                     let close_text = format!("{})", indent);
-                    lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, diff_kind));
+                    lines.push(TreeLine::highlighted(path.clone(), close_text, false, diff_kind));
                 }
                 lines
             }
         }
 
+        // List of bytes shown as a hex dump
+        itf::Value::List(items) if expansion.is_hex_dump(&path) && as_bytes(value).is_some() => {
+            let icon = if expanded { "▼" } else { "▶" };
+            let icon_prefix = name_prefix_with_icon(icon, name, diff_kind);
+            let text = format!("{}{}Hex({} bytes)", indent, icon_prefix, items.len());
+            let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
+            if expanded {
+                let bytes = as_bytes(value).unwrap_or_default();
+                lines.extend(render_hex_dump(&bytes, &path, diff_kind, depth + 1, terminal_width));
+            }
+            lines
+        }
+
         // List - expandable only if content can't be shown inline
         itf::Value::List(items) => {
             let all_simple = all_simple(items.iter());
-            let inline = if all_simple {
+            let inline = if all_simple && inline_allowed() {
                 format_collection_inline(items.iter(), "[", "]", thresholds.inline)
             } else {
                 None
@@ -503,7 +1517,7 @@ pub fn render_value(
             // If we can show inline, no need for expand/collapse
             if let Some(ref inline_str) = inline {
                 let text = format!("{}{}{}", indent, prefix, inline_str);
-                vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                vec![TreeLine::highlighted(path, text, false, diff_kind)]
             } else {
                 // Complex or too long - needs expand/collapse
                 let icon = if expanded { "▼" } else { "▶" };
@@ -513,7 +1527,7 @@ pub fn render_value(
                 } else {
                     format!("{}{}List({} items)", indent, icon_prefix, items.len())
                 };
-                let mut lines = vec![TreeLine::with_default_spans(path.clone(), text, true, diff_kind)];
+                let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
 
                 if expanded {
                     let item_lines = render_items_with_collapsing(
@@ -527,7 +1541,7 @@ pub fn render_value(
                             let item = &items[i];
                             let mut child_path = path.clone();
                             child_path.push(format!("{}", i));
-                            render_value(&format!("[{}]", i), item, child_path, expansion, diff, depth + 1, terminal_width, collapse_threshold)
+                            render_value(&format!("[{}]", i), item, child_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold)
                         },
                         |start, end, count| format!("... ([{}..{}] {} unchanged)", start, end, count),
                     );
@@ -535,7 +1549,7 @@ pub fn render_value(
 
                     // Add closing bracket
                     let close_text = format!("{}]", indent);
-                    lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, diff_kind));
+                    lines.push(TreeLine::highlighted(path.clone(), close_text, false, diff_kind));
                 }
                 lines
             }
@@ -543,17 +1557,13 @@ pub fn render_value(
 
         // Tuple - expandable only if content can't be shown inline
         itf::Value::Tuple(items) => {
-            let all_simple = all_simple(items.iter());
-            let inline = if all_simple {
-                format_collection_inline(items.iter(), "(", ")", thresholds.inline)
-            } else {
-                None
-            };
+            // Like records, small tuples are inline whatever their items
+            let inline = format_value_full(value, thresholds.inline).filter(|_| inline_allowed());
 
             // If we can show inline, no need for expand/collapse
             if let Some(ref inline_str) = inline {
                 let text = format!("{}{}{}", indent, prefix, inline_str);
-                vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+                vec![TreeLine::highlighted(path, text, false, diff_kind)]
             } else {
                 // Complex or too long - needs expand/collapse
                 let icon = if expanded { "▼" } else { "▶" };
@@ -563,7 +1573,7 @@ pub fn render_value(
                 } else {
                     format!("{}{}Tuple({} items)", indent, icon_prefix, items.len())
                 };
-                let mut lines = vec![TreeLine::with_default_spans(path.clone(), text, true, diff_kind)];
+                let mut lines = vec![TreeLine::highlighted(path.clone(), text, true, diff_kind)];
 
                 if expanded {
                     let items_vec: Vec<_> = items.iter().collect();
@@ -579,7 +1589,7 @@ pub fn render_value(
                             let item = items_vec[i];
                             let mut child_path = path.clone();
                             child_path.push(format!("{}", i));
-                            render_value(&format!("[{}]", i), item, child_path, expansion, diff, depth + 1, terminal_width, collapse_threshold)
+                            render_value(&format!("[{}]", i), item, child_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold)
                         },
                         |start, end, count| format!("... ([{}..{}] {} unchanged)", start, end, count),
                     );
@@ -587,7 +1597,7 @@ pub fn render_value(
 
                     // Add closing paren
                     let close_text = format!("{})", indent);
-                    lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, diff_kind));
+                    lines.push(TreeLine::highlighted(path.clone(), close_text, false, diff_kind));
                 }
                 lines
             }
@@ -595,18 +1605,125 @@ pub fn render_value(
 
         itf::Value::Unserializable(u) => {
             let text = format!("{}{}{:?}", indent, prefix, u);
-            vec![TreeLine::with_default_spans(path, text, false, diff_kind)]
+            vec![TreeLine::highlighted(path, text, false, diff_kind)]
+        }
+    }
+}
+
+/// Text of a value under the first matching custom formatter, None if no rule applies or the value does not fit it.
+/// The values filling a template are cut to the `width` left on the line
+fn format_custom(formatters: &Formatters, path: &[String], value: &itf::Value, width: usize) -> Option<String> {
+    if formatters.is_empty() {
+        return None;
+    }
+    let variant = match value {
+        itf::Value::Record(fields) => classify_sum_type(fields),
+        _ => None,
+    };
+    let tag = match variant {
+        Some(SumTypeVariant::Unit(tag)) | Some(SumTypeVariant::WithValue(tag, _)) => Some(tag),
+        None => None,
+    };
+
+    match formatters.format_for(path, tag)? {
+        Format::Date => {
+            let seconds = match value {
+                itf::Value::Number(n) => *n,
+                itf::Value::BigInt(n) => n.to_string().parse().ok()?,
+                _ => return None,
+            };
+            Some(format_date(seconds))
+        }
+        Format::Hex => match value {
+            itf::Value::Number(n) if *n < 0 => Some(format!("-{:#x}", n.unsigned_abs())),
+            itf::Value::Number(n) => Some(format!("{:#x}", n)),
+            itf::Value::BigInt(n) => Some(format!("{:#x}", n.get())),
+            _ => None,
+        },
+        Format::Template(template) => {
+            // Templates of sum types refer to the variant's payload
+            let payload = match variant {
+                Some(SumTypeVariant::WithValue(_, inner_value)) => inner_value,
+                _ => value,
+            };
+            fill_template(template, payload, width)
         }
     }
 }
 
+/// Substitute `{field}` with record fields and `{}` with the value itself, each cut to `width`, None if a field is
+/// missing
+fn fill_template(template: &str, value: &itf::Value, width: usize) -> Option<String> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let field = &rest[start + 1..start + len];
+        let field_value = match (field, value) {
+            ("", _) => value,
+            (_, itf::Value::Record(fields)) => fields.get(field)?,
+            _ => return None,
+        };
+        result.push_str(&format_value_preview(field_value, width));
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+/// ISO 8601 date (UTC) of a number of seconds since the Unix epoch
+fn format_date(seconds: i64) -> String {
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = seconds.div_euclid(86400);
+    let time = seconds.rem_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Whether a custom formatter applies to a descendant of a value rendered at `path`
+fn has_custom_format_below(formatters: &Formatters, path: &NodePath, value: &itf::Value) -> bool {
+    if formatters.is_empty() {
+        return false;
+    }
+    let below = |segment: String, child: &itf::Value| {
+        let mut child_path = path.clone();
+        child_path.push(segment);
+        format_custom(formatters, &child_path, child, usize::MAX).is_some() || has_custom_format_below(formatters, &child_path, child)
+    };
+    match value {
+        itf::Value::Record(fields) => match classify_sum_type(fields) {
+            Some(SumTypeVariant::Unit(_)) => false,
+            Some(SumTypeVariant::WithValue(_, inner_value)) => below("value".to_string(), inner_value),
+            None => fields.iter().any(|(name, field)| below(name.clone(), field)),
+        },
+        itf::Value::Map(pairs) => pairs.iter().enumerate().any(|(i, (_, val))| below(i.to_string(), val)),
+        itf::Value::Set(items) => items.iter().enumerate().any(|(i, item)| below(i.to_string(), item)),
+        itf::Value::List(items) => items.iter().enumerate().any(|(i, item)| below(i.to_string(), item)),
+        itf::Value::Tuple(items) => items.iter().enumerate().any(|(i, item)| below(i.to_string(), item)),
+        _ => false,
+    }
+}
+
 /// Render just the children of a value (without the header line)
 /// Used when expanding map entries where the header is already shown
+#[allow(clippy::too_many_arguments)]
 fn render_value_children(
     value: &itf::Value,
     path: NodePath,
     expansion: &ExpansionState,
     diff: &DiffResult,
+    formatters: &Formatters,
     depth: usize,
     terminal_width: usize,
     collapse_threshold: usize,
@@ -618,17 +1735,17 @@ fn render_value_children(
 
             // Add opening delimiter
             let open_text = format!("{}{{", indent);
-            lines.push(TreeLine::with_default_spans(path.clone(), open_text, false, DiffKind::Unchanged));
+            lines.push(TreeLine::highlighted(path.clone(), open_text, false, DiffKind::Unchanged));
 
             for (field_name, field_value) in fields.iter() {
                 let mut field_path = path.clone();
                 field_path.push(field_name.clone());
-                lines.extend(render_value(field_name, field_value, field_path, expansion, diff, depth + 1, terminal_width, collapse_threshold));
+                lines.extend(render_value(field_name, field_value, field_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold));
             }
 
             // Add closing delimiter
             let close_text = format!("{}}}", indent);
-            lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, DiffKind::Unchanged));
+            lines.push(TreeLine::highlighted(path.clone(), close_text, false, DiffKind::Unchanged));
 
             lines
         }
@@ -639,6 +1756,7 @@ fn render_value_children(
                 &path,
                 expansion,
                 diff,
+                formatters,
                 depth,
                 terminal_width,
                 collapse_threshold,
@@ -648,6 +1766,9 @@ fn render_value_children(
                 false,
             )
         }
+        itf::Value::List(_) if expansion.is_hex_dump(&path) && as_bytes(value).is_some() => {
+            render_hex_dump(&as_bytes(value).unwrap_or_default(), &path, diff.get(&path), depth, terminal_width)
+        }
         itf::Value::List(items) => {
             let items_vec: Vec<_> = items.iter().collect();
             render_collection_children(
@@ -655,6 +1776,7 @@ fn render_value_children(
                 &path,
                 expansion,
                 diff,
+                formatters,
                 depth,
                 terminal_width,
                 collapse_threshold,
@@ -670,18 +1792,19 @@ fn render_value_children(
 
             // Add opening delimiter
             let open_text = format!("{}Map(", indent);
-            lines.push(TreeLine::with_default_spans(path.clone(), open_text, false, DiffKind::Unchanged));
+            lines.push(TreeLine::highlighted(path.clone(), open_text, false, DiffKind::Unchanged));
 
             // Use helper function for each map entry
             for (i, (k, v)) in pairs.iter().enumerate() {
                 let mut entry_path = path.clone();
                 entry_path.push(format!("{}", i));
-                lines.extend(render_map_entry(k, v, entry_path, expansion, diff, depth, terminal_width, collapse_threshold));
+                lines.extend(render_map_entry(k, v, entry_path, expansion, diff, formatters, depth, terminal_width, collapse_threshold));
             }
+            lines.extend(render_removed(diff, &path, depth, terminal_width));
 
             // Add closing delimiter
             let close_text = format!("{})", indent);
-            lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, DiffKind::Unchanged));
+            lines.push(TreeLine::highlighted(path.clone(), close_text, false, DiffKind::Unchanged));
 
             lines
         }
@@ -692,6 +1815,7 @@ fn render_value_children(
                 &path,
                 expansion,
                 diff,
+                formatters,
                 depth,
                 terminal_width,
                 collapse_threshold,
@@ -701,14 +1825,83 @@ fn render_value_children(
                 true,
             )
         }
+        itf::Value::String(s) => render_string_lines(s, &path, diff.get(&path), depth, terminal_width),
         // Simple values have no children
         _ => Vec::new(),
     }
 }
 
+/// Bytes of a list made only of integers from 0 to 255
+pub fn as_bytes(value: &itf::Value) -> Option<Vec<u8>> {
+    let itf::Value::List(items) = value else {
+        return None;
+    };
+    if items.is_empty() {
+        return None;
+    }
+    items
+        .iter()
+        .map(|item| match item {
+            itf::Value::Number(n) => u8::try_from(*n).ok(),
+            itf::Value::BigInt(n) => n.to_string().parse().ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Render bytes as hex dump rows: offset, hex bytes and printable ASCII characters
+fn render_hex_dump(bytes: &[u8], path: &NodePath, diff_kind: DiffKind, depth: usize, terminal_width: usize) -> Vec<TreeLine> {
+    let indent = "  ".repeat(depth);
+    // A row of 16 bytes takes 72 characters, narrower screens get 8 per row
+    let per_row = if DisplayThresholds::new(terminal_width, depth).inline >= 72 { 16 } else { 8 };
+
+    bytes
+        .chunks(per_row)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            let spans = vec![
+                StyledSpan::default(indent.clone()),
+                StyledSpan::new(format!("{:04x}", row * per_row), SpanStyle::Type),
+                StyledSpan::default("  "),
+                StyledSpan::new(format!("{:<width$}", hex.join(" "), width = per_row * 3 - 1), SpanStyle::Number),
+                StyledSpan::default("  "),
+                StyledSpan::new(ascii, SpanStyle::String),
+            ];
+            TreeLine { path: path.clone(), expandable: false, diff: diff_kind, spans }
+        })
+        .collect()
+}
+
+/// Render the full content of a long string, split at newlines and at the inline width
+fn render_string_lines(s: &str, path: &NodePath, diff_kind: DiffKind, depth: usize, terminal_width: usize) -> Vec<TreeLine> {
+    let indent = "  ".repeat(depth);
+    let width = DisplayThresholds::new(terminal_width, depth).inline.max(1);
+    let mut lines = Vec::new();
+
+    for text_line in s.split('\n') {
+        let chars: Vec<char> = text_line.chars().collect();
+        let chunks: Vec<String> = if chars.is_empty() {
+            vec![String::new()]
+        } else {
+            chars.chunks(width).map(|chunk| chunk.iter().collect()).collect()
+        };
+        for chunk in chunks {
+            // Content is styled as a whole so that it is not highlighted as syntax
+            let spans = vec![StyledSpan::default(indent.clone()), StyledSpan::new(chunk, SpanStyle::String)];
+            lines.push(TreeLine { path: path.clone(), expandable: false, diff: diff_kind, spans });
+        }
+    }
+    lines
+}
+
 /// Classify sum type variants
 #[derive(Debug, Clone, Copy)]
-enum SumTypeVariant<'a> {
+pub enum SumTypeVariant<'a> {
     /// Unit variant (no value): PreVoteStage
     Unit(&'a str),
     /// Variant with value: Some(42)
@@ -716,8 +1909,8 @@ enum SumTypeVariant<'a> {
 }
 
 /// Classify a sum type pattern: {tag: String, value: X}
-/// Returns Unit if value is empty tuple/record, WithValue otherwise
-fn classify_sum_type(fields: &itf::value::Record) -> Option<SumTypeVariant<'_>> {
+/// Returns Unit if value is empty tuple/record or Apalache's unit value, WithValue otherwise
+pub fn classify_sum_type(fields: &itf::value::Record) -> Option<SumTypeVariant<'_>> {
     // Must have exactly 2 fields: "tag" and "value"
     if fields.len() != 2 {
         return None;
@@ -733,13 +1926,16 @@ fn classify_sum_type(fields: &itf::value::Record) -> Option<SumTypeVariant<'_>>
         return None;
     };
 
-    // Check if inner value is unit (empty tuple or empty record)
+    // Check if inner value is unit (empty tuple, empty record or Apalache's "U_OF_UNIT")
     let is_unit = matches!(
         inner_value,
         itf::Value::Tuple(items) if items.is_empty()
     ) || matches!(
         inner_value,
         itf::Value::Record(fields) if fields.is_empty()
+    ) || matches!(
+        inner_value,
+        itf::Value::String(s) if s == APALACHE_UNIT
     );
 
     if is_unit {
@@ -881,6 +2077,24 @@ fn format_value(value: &itf::Value, mode: FormatMode) -> Option<String> {
     Some(result)
 }
 
+/// Truncated preview of a string too long to show in full: `"abcdef…" (142 chars)`
+fn format_string_preview(s: &str, max_len: usize) -> String {
+    let count = s.chars().count();
+    let suffix = format!(" ({} chars)", count);
+    let keep = max_len.saturating_sub(suffix.len() + 3).max(1);
+    // Newlines would break the line, show them escaped
+    let preview: String = s.chars().take(keep).collect::<String>().replace('\n', "\\n");
+    format!("\"{}…\"{}", preview, suffix)
+}
+
+/// Preview of a value too long to show inline: strings are truncated, other values summarized
+fn format_value_collapsed(value: &itf::Value, max_len: usize) -> String {
+    match value {
+        itf::Value::String(s) => format_string_preview(s, max_len),
+        _ => format_value_short(value),
+    }
+}
+
 /// Short format for map keys (wrapper for backward compatibility)
 fn format_value_short(value: &itf::Value) -> String {
     format_value(value, FormatMode::Short).unwrap()
@@ -921,12 +2135,13 @@ fn format_collection_inline<'a>(
 }
 
 /// Check if all items in a collection are simple
-fn all_simple<'a>(items: impl Iterator<Item = &'a itf::Value>) -> bool {
-    items.fold(true, |acc, v| acc && is_simple(v))
+fn all_simple<'a>(mut items: impl Iterator<Item = &'a itf::Value>) -> bool {
+    items.all(is_simple)
 }
 
 /// Render items with automatic collapsing of unchanged groups
 /// Generic function that handles the grouping/collapsing pattern used by all collections
+#[allow(clippy::too_many_arguments)]
 fn render_items_with_collapsing<F, G>(
     total_count: usize,
     path: &NodePath,
@@ -957,7 +2172,7 @@ where
         if has_any_changed && !is_changed && group_count >= collapse_threshold && group_count >= 3 {
             // Create unique path for this collapsed group
             let mut group_path = path.clone();
-            group_path.push(format!("__collapsed_{}_{}", start, start + group_count - 1));
+            group_path.push(format!("{}{}_{}", COLLAPSED_PREFIX, start, start + group_count - 1));
 
             let group_expanded = expansion.is_expanded(&group_path);
 
@@ -1030,12 +2245,14 @@ fn group_by_change_status(total_count: usize, diff: &DiffResult, base_path: &Nod
 
 /// Render a single map entry (key-value pair)
 /// Returns TreeLines for the entry and its children (if expanded)
+#[allow(clippy::too_many_arguments)]
 fn render_map_entry(
     key: &itf::Value,
     val: &itf::Value,
     entry_path: NodePath,
     expansion: &ExpansionState,
     diff: &DiffResult,
+    formatters: &Formatters,
     depth: usize,
     terminal_width: usize,
     collapse_threshold: usize,
@@ -1048,8 +2265,17 @@ fn render_map_entry(
     let key_str = format_value_full(key, thresholds.key)
         .unwrap_or_else(|| format_value_short(key));
 
-    // Try to format value fully inline
-    let val_full = format_value_full(val, thresholds.value);
+    // Try to format value fully inline, with a custom formatter or by default (hex dumps are never inline)
+    let custom = format_custom(formatters, &entry_path, val, thresholds.value);
+    let is_custom = custom.is_some();
+    let val_full = custom.or_else(|| {
+        format_value_full(val, thresholds.value)
+            .filter(|_| {
+                !has_custom_format_below(formatters, &entry_path, val)
+                    && !diff.has_removed_below(&entry_path)
+                    && !expansion.is_hex_dump(&entry_path)
+            })
+    });
     let can_inline = val_full.is_some();
 
     // Get diff status for this entry
@@ -1068,29 +2294,58 @@ fn render_map_entry(
             format!("{}  {}{} {} ->", indent, marker, entry_icon, key_str)
         } else {
             // Collapsed: show preview
-            let val_preview = format_value_short(val);
+            let val_preview = format_value_collapsed(val, thresholds.value);
             format!("{}  {}{} {} -> {}", indent, marker, entry_icon, key_str, val_preview)
         }
     };
 
-    lines.push(TreeLine::with_default_spans(entry_path.clone(), entry_text, !can_inline, entry_diff));
+    let mut entry_line = TreeLine::highlighted(entry_path.clone(), entry_text, !can_inline, entry_diff);
+    if can_inline && !is_custom {
+        entry_line.mark_changed(diff, val);
+    }
+    lines.push(entry_line);
 
     // If value can't be inlined and this entry is expanded, show children
     if !can_inline && expansion.is_expanded(&entry_path) {
-        let child_lines = render_value_children(val, entry_path, expansion, diff, depth + 1, terminal_width, collapse_threshold);
+        let child_lines = render_value_children(val, entry_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold);
         lines.extend(child_lines);
     }
 
     lines
 }
 
+/// Render ghost lines for the entries or elements removed from the collection at `path`
+/// They share the collection's path, like its closing delimiter
+fn render_removed(diff: &DiffResult, path: &NodePath, depth: usize, terminal_width: usize) -> Vec<TreeLine> {
+    let indent = "  ".repeat(depth);
+    let thresholds = DisplayThresholds::new(terminal_width, depth);
+
+    diff.removed_at(path)
+        .iter()
+        .map(|item| {
+            let text = match item {
+                RemovedItem::Entry(key, val) => format!(
+                    "{}  - {} -> {}",
+                    indent,
+                    format_value_preview(key, thresholds.key),
+                    format_value_preview(val, thresholds.value)
+                ),
+                RemovedItem::Element(item) => format!("{}- {}", indent, format_value_preview(item, thresholds.inline)),
+            };
+            TreeLine::highlighted(path.clone(), text, false, DiffKind::Removed)
+        })
+        .collect()
+}
+
 /// Unified collection rendering for Sets, Lists, and Tuples
 /// Handles the common pattern of opening delimiter, collapsing items, closing delimiter
+#[allow(clippy::too_many_arguments)]
 fn render_collection_children(
     items_vec: Vec<&itf::Value>,
     path: &NodePath,
     expansion: &ExpansionState,
     diff: &DiffResult,
+    formatters: &Formatters,
     depth: usize,
     terminal_width: usize,
     collapse_threshold: usize,
@@ -1104,7 +2359,7 @@ fn render_collection_children(
 
     // Add opening delimiter
     let open_text = format!("{}{}", indent, open_delimiter);
-    lines.push(TreeLine::with_default_spans(path.clone(), open_text, false, DiffKind::Unchanged));
+    lines.push(TreeLine::highlighted(path.clone(), open_text, false, DiffKind::Unchanged));
 
     // Use generic collapsing logic
     let item_count = items_vec.len();
@@ -1124,7 +2379,7 @@ fn render_collection_children(
             } else {
                 item_label_format.replace("{}", &i.to_string())
             };
-            render_value(&label, item, child_path, expansion, diff, depth + 1, terminal_width, collapse_threshold)
+            render_value(&label, item, child_path, expansion, diff, formatters, depth + 1, terminal_width, collapse_threshold)
         },
         |start, end, count| {
             if use_range_in_summary {
@@ -1135,10 +2390,664 @@ fn render_collection_children(
         },
     );
     lines.extend(item_lines);
+    lines.extend(render_removed(diff, path, depth + 1, terminal_width));
 
     // Add closing delimiter
     let close_text = format!("{}{}", indent, close_delimiter);
-    lines.push(TreeLine::with_default_spans(path.clone(), close_text, false, DiffKind::Unchanged));
+    lines.push(TreeLine::highlighted(path.clone(), close_text, false, DiffKind::Unchanged));
 
     lines
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(segments: &[&str]) -> NodePath {
+        segments.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let value: itf::Value = serde_json::from_str(
+            r##"{ "a": { "#map": [["k1", [10, 20]], ["k2", { "#tup": [true, "x"] }]] } }"##,
+        )
+        .unwrap();
+
+        assert_eq!(resolve_path(&value, &[]), Some(&value));
+        assert_eq!(resolve_path(&value, &path(&["a", "0", "1"])), Some(&itf::Value::Number(20)));
+        assert_eq!(
+            resolve_path(&value, &path(&["a", "1", "1"])),
+            Some(&itf::Value::String("x".to_string()))
+        );
+        assert_eq!(resolve_path(&value, &path(&["a", "2"])), None);
+        assert_eq!(resolve_path(&value, &path(&["b"])), None);
+
+        // Collapsed groups resolve to the collection holding them
+        let list = resolve_path(&value, &path(&["a", "0"]));
+        assert_eq!(resolve_path(&value, &path(&["a", "0", "__collapsed_0_1"])), list);
+    }
+
+    #[test]
+    fn test_expand_subtree() {
+        let trace = crate::loader::load_trace(
+            &std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json"),
+        )
+        .unwrap();
+        let name = "clock_sync4::clock_sync::states";
+        let value = trace.states[7].values[name].as_ref();
+        let root = path(&[name]);
+        let diff = DiffResult::default();
+
+        let mut expansion = ExpansionState::new();
+        expansion.expand_subtree(value, &root);
+
+        // Narrow enough that nothing fits inline
+        let lines = render_value(name, value, root.clone(), &expansion, &diff, &Formatters::default(), 0, 20, usize::MAX);
+        assert!(lines.iter().any(|line| line.path.len() > 3));
+        assert!(lines.iter().filter(|line| line.expandable).all(|line| expansion.is_expanded(&line.path)));
+
+        // A collapsed group only expands its own items
+        let mut expansion = ExpansionState::new();
+        let group = path(&[name, "__collapsed_1_2"]);
+        expansion.expand_subtree(value, &group);
+        assert!(expansion.is_expanded(&group));
+        assert!(expansion.is_expanded(&path(&[name, "1"])));
+        assert!(expansion.is_expanded(&path(&[name, "2"])));
+        assert!(!expansion.is_expanded(&path(&[name, "0"])));
+        assert!(!expansion.is_expanded(&root));
+    }
+
+    #[test]
+    fn test_collapse_subtree_and_focus() {
+        let mut expansion = ExpansionState::new();
+        let paths = [path(&["a"]), path(&["a", "0"]), path(&["a", "0", "x"]), path(&["a", "1"]), path(&["b"])];
+        expansion.expand_all(&paths);
+
+        expansion.collapse_subtree(&path(&["a", "0"]));
+        assert!(expansion.is_expanded(&path(&["a"])));
+        assert!(!expansion.is_expanded(&path(&["a", "0"])));
+        assert!(!expansion.is_expanded(&path(&["a", "0", "x"])));
+        assert!(expansion.is_expanded(&path(&["a", "1"])));
+
+        expansion.expand_all(&paths);
+        expansion.focus(&path(&["a", "0"]), &[path(&["c"])]);
+        assert!(expansion.is_expanded(&path(&["a"])));
+        assert!(expansion.is_expanded(&path(&["a", "0"])));
+        assert!(expansion.is_expanded(&path(&["a", "0", "x"])));
+        assert!(!expansion.is_expanded(&path(&["a", "1"])));
+        assert!(!expansion.is_expanded(&path(&["b"])));
+
+        // Collapsed nodes are protected from auto-expansion
+        expansion.expand_to_changes(&[path(&["b"]), path(&["c"])]);
+        assert!(!expansion.is_expanded(&path(&["b"])));
+        assert!(!expansion.is_expanded(&path(&["c"])));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let styled = |text: &str| -> Vec<(String, SpanStyle)> {
+            highlight(text).into_iter().filter(|span| span.style != SpanStyle::Default).map(|span| (span.text, span.style)).collect()
+        };
+        let span = |text: &str, style: SpanStyle| (text.to_string(), style);
+
+        assert_eq!(
+            styled("  ▶ [2]: { ok: true, n: -3 }"),
+            vec![
+                span("[2]", SpanStyle::Key),
+                span(": ", SpanStyle::Punctuation),
+                span("{", SpanStyle::Punctuation),
+                span(":", SpanStyle::Punctuation),
+                span("true", SpanStyle::Boolean),
+                span(",", SpanStyle::Punctuation),
+                span(":", SpanStyle::Punctuation),
+                span("-3", SpanStyle::Number),
+                span("}", SpanStyle::Punctuation),
+            ]
+        );
+
+        // Map entries have no name, strings may contain anything
+        assert_eq!(
+            styled("    \"a: b\" -> Some(7)"),
+            vec![
+                span("\"a: b\"", SpanStyle::String),
+                span("->", SpanStyle::Punctuation),
+                span("(", SpanStyle::Punctuation),
+                span("7", SpanStyle::Number),
+                span(")", SpanStyle::Punctuation),
+            ]
+        );
+
+        // Counts and digits inside words are not numbers
+        let spans = styled("▶ msgs: Set(4 items) c1_OF_PERSON");
+        assert!(spans.iter().all(|(_, style)| *style != SpanStyle::Number));
+
+        // Highlighting never changes the text
+        let text = "  + ▼ 世界: Map(\"ü\" -> 1)";
+        assert_eq!(highlight(text).into_iter().map(|span| span.text).collect::<String>(), text);
+    }
+
+    #[test]
+    fn test_add_guides() {
+        let text = ["▼ a: Map", "  ▼ 1 -> Set", "    2", "    3", "  ▶ 4 -> Set", "▶ b: 1"];
+        let mut lines: Vec<TreeLine> = text
+            .iter()
+            .map(|text| TreeLine::highlighted(Vec::new(), text.to_string(), false, DiffKind::Unchanged))
+            .collect();
+        add_guides(&mut lines);
+
+        let rendered: Vec<String> = lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect();
+        assert_eq!(rendered, ["▼ a: Map", "├ ▼ 1 -> Set", "│ ├ 2", "│ └ 3", "└ ▶ 4 -> Set", "▶ b: 1"]);
+        assert!(lines[2].spans[0].style == SpanStyle::Guide && lines[2].spans[0].text == "│ ├ ");
+        assert!(lines[0].spans.iter().all(|span| span.style != SpanStyle::Guide));
+    }
+
+    #[test]
+    fn test_expansion_history() {
+        let path = |name: &str| vec![name.to_string()];
+        let mut history = ExpansionHistory::default();
+        let mut expansion = ExpansionState::new();
+
+        let before = expansion.clone();
+        expansion.toggle(&path("a"));
+        history.record(before, &expansion);
+        let before = expansion.clone();
+        expansion.clear();
+        history.record(before, &expansion);
+        // Changes to nothing are not recorded
+        history.record(expansion.clone(), &expansion);
+
+        assert!(history.undo(&mut expansion));
+        assert!(expansion.is_expanded(&path("a")));
+        assert!(history.undo(&mut expansion));
+        assert!(!expansion.is_expanded(&path("a")));
+        assert!(!history.undo(&mut expansion));
+
+        assert!(history.redo(&mut expansion));
+        assert!(expansion.is_expanded(&path("a")));
+
+        // A new change drops what could be redone
+        let before = expansion.clone();
+        expansion.toggle(&path("b"));
+        history.record(before, &expansion);
+        assert!(!history.redo(&mut expansion));
+    }
+
+    #[test]
+    fn test_add_modified_markers() {
+        let path = |name: &str| vec![name.to_string()];
+        let mut lines = vec![
+            TreeLine::highlighted(path("a"), "▼ a: Map(".to_string(), true, DiffKind::Modified),
+            TreeLine::highlighted(path("a"), ")".to_string(), false, DiffKind::Modified),
+            TreeLine::highlighted(path("b"), "+ b: 1".to_string(), false, DiffKind::Added),
+            TreeLine::highlighted(path("c"), "  c: 2".to_string(), false, DiffKind::Modified),
+            TreeLine::highlighted(path("d"), "d: 3".to_string(), false, DiffKind::Unchanged),
+        ];
+        add_modified_markers(&mut lines);
+
+        let rendered: Vec<String> = lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect();
+        assert_eq!(rendered, ["~ ▼ a: Map(", ")", "+ b: 1", "  ~ c: 2", "d: 3"]);
+        assert_eq!(lines[3].plain_text(), "  ~ c: 2");
+    }
+
+    #[test]
+    fn test_wrap() {
+        let line = TreeLine::highlighted(Vec::new(), "  name: \"abcdefghij\"".to_string(), false, DiffKind::Unchanged);
+        let rows: Vec<String> = line.wrap(12).iter().map(|row| row.iter().map(|span| span.text.as_str()).collect()).collect();
+        // Continuation rows are indented two levels deeper than the line
+        assert_eq!(rows, ["  name: \"abc", "      defghi", "      j\""]);
+        assert_eq!(line.row_count(12), rows.len());
+        assert!(line.wrap(12)[1].iter().any(|span| span.style == SpanStyle::String));
+
+        assert_eq!(line.wrap(40).len(), 1);
+        assert_eq!(line.row_count(40), 1);
+        assert_eq!(line.row_count(0), line.wrap(0).len());
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let text = ["▼ a: Map(", "  + ▶ 1 -> Set(2 items)", "  - 2", "  -3", "s: \"▼ \""];
+        let plain: Vec<String> = text
+            .iter()
+            .map(|text| TreeLine::highlighted(Vec::new(), text.to_string(), false, DiffKind::Unchanged).plain_text())
+            .collect();
+        assert_eq!(plain, ["a: Map(", "  + 1 -> Set(2 items)", "  - 2", "  -3", "s: \"▼ \""]);
+    }
+
+    #[test]
+    fn test_long_strings() {
+        let text = |lines: &[TreeLine]| -> Vec<String> {
+            lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect()
+        };
+        let diff = DiffResult::default();
+        let root = path(&["s"]);
+        let value = itf::Value::String(format!("{}\nend", "x".repeat(50)));
+
+        // Collapsed: a truncated preview with the length
+        let mut expansion = ExpansionState::new();
+        let lines = render_value("s", &value, root.clone(), &expansion, &diff, &Formatters::default(), 0, 40, usize::MAX);
+        assert_eq!(text(&lines), [format!("▶ s: \"{}…\" (54 chars)", "x".repeat(4))]);
+        assert!(lines[0].expandable);
+
+        // Expanded: the full content split at newlines and at the inline width
+        expansion.expand_subtree(&value, &root);
+        let lines = render_value("s", &value, root.clone(), &expansion, &diff, &Formatters::default(), 0, 40, usize::MAX);
+        let width = DisplayThresholds::new(40, 1).inline;
+        assert_eq!(
+            text(&lines[1..]),
+            [format!("  {}", "x".repeat(width)), format!("  {}", "x".repeat(50 - width)), "  end".to_string()]
+        );
+        assert!(lines.iter().all(|line| line.path == root));
+
+        // Short strings stay inline
+        let value = itf::Value::String("short".to_string());
+        let lines = render_value("s", &value, root.clone(), &expansion, &diff, &Formatters::default(), 0, 40, usize::MAX);
+        assert_eq!(text(&lines), ["s: \"short\""]);
+        assert!(!lines[0].expandable);
+    }
+
+    #[test]
+    fn test_custom_formatters() {
+        let formatters: Formatters = serde_json::from_value(serde_json::json!([
+            { "path": "*.time", "format": "date" },
+            { "path": "addrs.*", "format": "hex" },
+            { "tag": "Send", "format": { "template": "{src} -> {dst}" } }
+        ]))
+        .unwrap();
+        let value = |json| serde_json::from_value::<itf::Value>(json).unwrap();
+        let render = |name: &str, value: &itf::Value| -> Vec<String> {
+            let diff = DiffResult::default();
+            render_value(name, value, path(&[name]), &ExpansionState::new(), &diff, &formatters, 0, 80, usize::MAX)
+                .iter()
+                .map(|line| line.spans.iter().map(|span| span.text.as_str()).collect())
+                .collect()
+        };
+
+        assert_eq!(render("time", &value(serde_json::json!({ "#bigint": "1700000000" }))), ["time: 2023-11-14T22:13:20Z"]);
+        assert_eq!(format_date(-1), "1969-12-31T23:59:59Z");
+        assert_eq!(render("addrs", &value(serde_json::json!([255, -16]))), ["▶ addrs: List(2 items)"]);
+        assert_eq!(format_custom(&formatters, &path(&["addrs", "1"]), &value(serde_json::json!(-16)), 80).unwrap(), "-0x10");
+        assert_eq!(
+            render("msg", &value(serde_json::json!({ "tag": "Send", "value": { "src": "a", "dst": "b" } }))),
+            ["msg: \"a\" -> \"b\""]
+        );
+        // Template values are cut to the width left on the line rather than a fixed one
+        let long = value(serde_json::json!({ "tag": "Send", "value": { "src": "a".repeat(60), "dst": "b" } }));
+        assert!(format_custom(&formatters, &path(&["msg"]), &long, 80).unwrap().contains(&"a".repeat(60)));
+        assert!(!format_custom(&formatters, &path(&["msg"]), &long, 20).unwrap().contains(&"a".repeat(60)));
+
+        // Values that don't fit the format fall back to the default rendering
+        assert_eq!(render("time", &value(serde_json::json!("now"))), ["time: \"now\""]);
+        assert_eq!(render("msg", &value(serde_json::json!({ "tag": "Send", "value": { "src": "a" } }))), ["msg: Send({ src: \"a\" })"]);
+    }
+
+    #[test]
+    fn test_unit_variants() {
+        let value = |json| serde_json::from_value::<itf::Value>(json).unwrap();
+        let render = |value: &itf::Value| -> Vec<String> {
+            let diff = DiffResult::default();
+            render_value("v", value, path(&["v"]), &ExpansionState::new(), &diff, &Formatters::default(), 0, 80, usize::MAX)
+                .iter()
+                .map(|line| line.spans.iter().map(|span| span.text.as_str()).collect())
+                .collect()
+        };
+
+        // Quint's empty record, an empty tuple and Apalache's unit value all render as the bare tag
+        for payload in [serde_json::json!({}), serde_json::json!({ "#tup": [] }), serde_json::json!("U_OF_UNIT")] {
+            let none = value(serde_json::json!({ "tag": "None", "value": payload }));
+            assert_eq!(render(&none), ["v: None"]);
+            assert_eq!(format_value_short(&none), "None");
+        }
+
+        let record = value(serde_json::json!({ "a": { "tag": "None", "value": {} }, "b": { "tag": "Some", "value": { "tag": "None", "value": {} } } }));
+        assert_eq!(render(&record), ["v: { a: None, b: Some(None) }"]);
+    }
+
+    #[test]
+    fn test_map_entries_sorted_by_key() {
+        // Entries appear sorted by key whatever their order in the file, integer keys by value
+        let map: itf::Value = serde_json::from_value(serde_json::json!({ "#map": [
+            [{ "#bigint": "10" }, "ten"],
+            [{ "#bigint": "-1" }, "minus one"],
+            [{ "#bigint": "2" }, "two"]
+        ] }))
+        .unwrap();
+        let root = path(&["m"]);
+        let mut expansion = ExpansionState::new();
+        expansion.toggle(&root);
+        let diff = DiffResult::default();
+        let lines = render_value("m", &map, root, &expansion, &diff, &Formatters::default(), 0, 80, usize::MAX);
+        let text: Vec<String> = lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect();
+        assert_eq!(text, ["▼ m: Map(", "    -1 -> \"minus one\"", "    2 -> \"two\"", "    10 -> \"ten\"", ")"]);
+    }
+
+    #[test]
+    fn test_set_elements_in_canonical_order() {
+        // The same set written in two orders renders and resolves its item paths identically
+        let set = |items: serde_json::Value| serde_json::from_value::<itf::Value>(serde_json::json!({ "#set": items })).unwrap();
+        let first = set(serde_json::json!(["b", { "#bigint": "3" }, "a", { "#bigint": "-7" }]));
+        let second = set(serde_json::json!(["a", { "#bigint": "-7" }, "b", { "#bigint": "3" }]));
+        assert_eq!(format_value_short(&first), format_value_short(&second));
+        assert_eq!(format_value_preview(&first, 80), "Set(\"a\", \"b\", -7, 3)");
+        for i in 0..4 {
+            let item = path(&[&i.to_string()]);
+            assert_eq!(resolve_path(&first, &item), resolve_path(&second, &item));
+        }
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let bytes: Vec<serde_json::Value> = b"Hi\x00 there, bytes!".iter().map(|b| serde_json::json!({ "#bigint": b.to_string() })).collect();
+        let value: itf::Value = serde_json::from_value(serde_json::Value::Array(bytes)).unwrap();
+        assert_eq!(as_bytes(&value).unwrap().len(), 17);
+        assert!(as_bytes(&serde_json::from_value(serde_json::json!([1, 256])).unwrap()).is_none());
+        assert!(as_bytes(&serde_json::from_value(serde_json::json!([])).unwrap()).is_none());
+
+        let root = path(&["data"]);
+        let mut expansion = ExpansionState::new();
+        expansion.toggle_hex_dump(&root);
+        expansion.toggle(&root);
+        let diff = DiffResult::default();
+        let lines = render_value("data", &value, root.clone(), &expansion, &diff, &Formatters::default(), 0, 120, usize::MAX);
+        let text: Vec<String> = lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect();
+        assert_eq!(
+            text,
+            [
+                "▼ data: Hex(17 bytes)".to_string(),
+                "  0000  48 69 00 20 74 68 65 72 65 2c 20 62 79 74 65 73  Hi. there, bytes".to_string(),
+                format!("  0010  21{}  !", " ".repeat(45)),
+            ]
+        );
+
+        // Toggling again restores the list rendering
+        expansion.toggle_hex_dump(&root);
+        let lines = render_value("data", &value, root, &expansion, &diff, &Formatters::default(), 0, 120, usize::MAX);
+        assert!(lines[0].spans.iter().all(|span| !span.text.contains("Hex")));
+    }
+
+    #[test]
+    fn test_inline_tuples() {
+        let render = |json: serde_json::Value, width: usize| -> Vec<String> {
+            let value: itf::Value = serde_json::from_value(json).unwrap();
+            let diff = DiffResult::default();
+            render_value("t", &value, path(&["t"]), &ExpansionState::new(), &diff, &Formatters::default(), 0, width, usize::MAX)
+                .iter()
+                .map(|line| line.spans.iter().map(|span| span.text.as_str()).collect())
+                .collect()
+        };
+        let tuple = serde_json::json!({ "#tup": [
+            { "tag": "Some", "value": { "#bigint": "1" } },
+            { "a": { "#bigint": "2" }, "b": "x" },
+            [1, 2]
+        ] });
+
+        assert_eq!(render(serde_json::json!({ "#tup": [{ "#bigint": "1" }, "a"] }), 80), ["t: (1, \"a\")"]);
+        assert_eq!(render(tuple.clone(), 80), ["t: (Some(1), { a: 2, b: \"x\" }, [1, 2])"]);
+        // Too long for the width: expandable as before
+        assert_eq!(render(tuple, 20), ["▶ t: Tuple(3 items)"]);
+    }
+
+    #[test]
+    fn test_removed_items() {
+        use std::rc::Rc;
+
+        let state = |json: serde_json::Value| -> HashMap<Rc<str>, Rc<itf::Value>> {
+            let value: itf::Value = serde_json::from_value(json).unwrap();
+            HashMap::from([(Rc::from("s"), Rc::new(value))])
+        };
+        let prev = state(serde_json::json!({
+            "balances": { "#map": [["alice", 1], ["bob", 2]] },
+            "owners": { "#set": ["alice", "bob"] }
+        }));
+        let curr = state(serde_json::json!({
+            "balances": { "#map": [["bob", 2]] },
+            "owners": { "#set": ["bob", "carol"] }
+        }));
+        let diff = crate::diff::compute_diff(&prev, &curr);
+        let mut expansion = ExpansionState::new();
+        expansion.expand_to_changes(&diff.changes.keys().cloned().collect::<Vec<_>>());
+
+        let lines = render_value("s", &curr["s"], path(&["s"]), &expansion, &diff, &Formatters::default(), 0, 80, usize::MAX);
+        let texts: Vec<String> = lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect();
+        assert_eq!(
+            texts,
+            [
+                "▼ s: {",
+                "  ▼ balances: Map(",
+                "      \"bob\" -> 2",
+                "      - \"alice\" -> 1",
+                "  )",
+                "  ▼ owners: Set(",
+                "    \"bob\"",
+                "    + \"carol\"",
+                "    - \"alice\"",
+                "  )",
+                "}",
+            ]
+        );
+
+        // Ghost lines share the collection's path and keep the marker out of the value
+        let ghost = &lines[3];
+        assert_eq!(ghost.diff, DiffKind::Removed);
+        assert_eq!(ghost.path, path(&["s", "balances"]));
+        assert_eq!(ghost.spans[0].text, "      - ");
+        assert_eq!(ghost.spans[1].text, "\"alice\"");
+        assert_eq!(ghost.spans[1].style, SpanStyle::String);
+    }
+
+    #[test]
+    fn test_changed_chars() {
+        use std::rc::Rc;
+
+        let state = |json: serde_json::Value| -> HashMap<Rc<str>, Rc<itf::Value>> {
+            let value: itf::Value = serde_json::from_value(json).unwrap();
+            HashMap::from([(Rc::from("s"), Rc::new(value))])
+        };
+        let prev = state(serde_json::json!({
+            "height": { "#bigint": "129" },
+            "round": "round-3",
+            "votes": { "#map": [["p1", "prevote"]] },
+            "word": "abc"
+        }));
+        let curr = state(serde_json::json!({
+            "height": { "#bigint": "139" },
+            "round": "round-4",
+            "votes": { "#map": [["p1", "precommit"]] },
+            "word": "ab"
+        }));
+        let diff = crate::diff::compute_diff(&prev, &curr);
+        let mut expansion = ExpansionState::new();
+        expansion.expand_to_changes(&diff.changes.keys().cloned().collect::<Vec<_>>());
+
+        let lines = render_value("s", &curr["s"], path(&["s"]), &expansion, &diff, &Formatters::default(), 0, 80, usize::MAX);
+        let changed = |path: NodePath| -> Vec<String> {
+            let line = lines.iter().find(|line| line.path == path).unwrap();
+            line.spans.iter().filter(|span| span.changed).map(|span| span.text.clone()).collect()
+        };
+
+        assert_eq!(changed(path(&["s", "height"])), ["3"]);
+        assert_eq!(changed(path(&["s", "round"])), ["4"]);
+        assert_eq!(changed(path(&["s", "votes", "0"])), ["commit"]);
+        // Only deleted characters: the whole line stays highlighted
+        assert!(changed(path(&["s", "word"])).is_empty());
+
+        // Splitting keeps the text and syntax styles of the line
+        let round = lines.iter().find(|line| line.path == path(&["s", "round"])).unwrap();
+        assert_eq!(round.spans.iter().map(|span| span.text.as_str()).collect::<String>(), "  round: \"round-4\"");
+        assert!(round.spans.iter().filter(|span| span.text.contains('"')).all(|span| span.style == SpanStyle::String));
+    }
+
+    #[test]
+    fn test_previous_values() {
+        use std::rc::Rc;
+
+        let state = |json: serde_json::Value| -> HashMap<Rc<str>, Rc<itf::Value>> {
+            let value: itf::Value = serde_json::from_value(json).unwrap();
+            HashMap::from([(Rc::from("s"), Rc::new(value))])
+        };
+        let prev = state(serde_json::json!({ "pair": { "#tup": [1, "a"] }, "round": 3, "votes": { "#map": [["p1", 1]] } }));
+        let curr = state(serde_json::json!({ "pair": { "#tup": [2, "a"] }, "round": 4, "votes": { "#map": [["p1", 2]] } }));
+        let diff = crate::diff::compute_diff(&prev, &curr);
+        let mut expansion = ExpansionState::new();
+        expansion.expand_to_changes(&diff.changes.keys().cloned().collect::<Vec<_>>());
+
+        let mut lines = render_value("s", &curr["s"], path(&["s"]), &expansion, &diff, &Formatters::default(), 0, 80, usize::MAX);
+        annotate_type(&mut lines, "{ round: int }", TypeDisplay::Inline);
+        let state = State { index: 1, values: curr, meta: None, action: None, choices: None };
+        let lines = add_previous_values(lines, &diff, &state);
+
+        let texts: Vec<String> = lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect();
+        assert_eq!(
+            texts,
+            [
+                "▼ s: {  : { round: int }",
+                "  - pair: (1, \"a\")",
+                "  + pair: (2, \"a\")",
+                "  - round: 3",
+                "  + round: 4",
+                "  ▼ votes: Map(",
+                "      - \"p1\" -> 1",
+                "      + \"p1\" -> 2",
+                "  )",
+                "}",
+            ]
+        );
+        let kinds: Vec<DiffKind> = lines[3..5].iter().map(|line| line.diff).collect();
+        assert_eq!(kinds, [DiffKind::Removed, DiffKind::Modified]);
+        // The new value keeps its changed characters
+        assert!(lines[4].spans.iter().any(|span| span.changed && span.text == "4"));
+    }
+
+    #[test]
+    fn test_align_lines() {
+        let line = |segments: &[&str], text: &str, diff: DiffKind| TreeLine::highlighted(path(segments), text.to_string(), false, diff);
+        let left = vec![
+            line(&["a"], "a: 1", DiffKind::Unchanged),
+            line(&["b"], "b: Map(", DiffKind::Unchanged),
+            line(&["b", "0"], "  1 -> 2", DiffKind::Unchanged),
+            line(&["b", "1"], "  2 -> 3", DiffKind::Unchanged),
+            line(&["b"], ")", DiffKind::Unchanged),
+        ];
+        let right = vec![
+            line(&["a"], "a: 2", DiffKind::Modified),
+            line(&["b"], "b: Map(", DiffKind::Modified),
+            line(&["b", "0"], "  1 -> 2", DiffKind::Unchanged),
+            line(&["b"], "  - 2 -> 3", DiffKind::Removed),
+            line(&["b"], ")", DiffKind::Modified),
+            line(&["c"], "c: true", DiffKind::Added),
+        ];
+
+        let (left, right) = align_lines(left, right);
+        let texts = |lines: &[TreeLine]| -> Vec<String> {
+            lines.iter().map(|line| line.spans.iter().map(|span| span.text.as_str()).collect()).collect()
+        };
+        assert_eq!(texts(&left), ["a: 1", "b: Map(", "  1 -> 2", "  2 -> 3", "  ", ")", ""]);
+        assert_eq!(texts(&right), ["a: 2", "b: Map(", "  1 -> 2", "  ", "  - 2 -> 3", ")", "c: true"]);
+        // Fillers stand for no node
+        assert!(left[6].path.is_empty() && right[3].path.is_empty());
+    }
+
+    #[test]
+    fn test_display_path() {
+        let trace = crate::loader::load_trace(
+            &std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("MissionariesAndCannibals.itf.json"),
+        )
+        .unwrap();
+        let state = &trace.states[0];
+
+        assert_eq!(display_path(state, &path(&["bank_of_boat"])), "bank_of_boat");
+        assert_eq!(display_path(state, &path(&["who_is_on_bank", "1"])), "who_is_on_bank[\"W\"]");
+        assert_eq!(display_path(state, &path(&["who_is_on_bank", "0", "2"])), "who_is_on_bank[\"E\"][2]");
+        assert_eq!(display_path(state, &path(&["who_is_on_bank", "0", "__collapsed_0_1"])), "who_is_on_bank[\"E\"]");
+    }
+
+    #[test]
+    fn test_source_at() {
+        let file = std::env::temp_dir().join(format!("quint-trace-explorer-source-{}.itf.json", std::process::id()));
+        std::fs::write(
+            &file,
+            r##"{ "#meta": { "format": "ITF" }, "vars": ["m"], "states": [
+                { "#meta": { "index": 0 }, "m": { "#map": [["b", { "#bigint": "2" }], ["a", { "#set": [3, 1] }]] } }
+            ] }"##,
+        )
+        .unwrap();
+        let trace = crate::loader::load_trace(&file).unwrap();
+        let state = &trace.states[0];
+        let source = trace.state_source(state).unwrap();
+
+        // Entries are found by value although the parsed map and set are sorted, literals are kept
+        assert_eq!(source_at(&source, state, &path(&["m", "1"])), Some(r##"{ "#bigint": "2" }"##));
+        assert_eq!(source_at(&source, state, &path(&["m", "0", "0"])), Some("1"));
+        assert_eq!(source_at(&source, state, &path(&["#meta", "index"])), Some("0"));
+        assert_eq!(source_at(&source, state, &path(&["m", "2"])), None);
+
+        // The file is read again, and not trusted once it changed
+        std::fs::write(&file, "{}").unwrap();
+        assert!(trace.state_source(state).is_err());
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(indent_json(r##"{"a":[1, {}],"b":"x, [y]"}"##), "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": \"x, [y]\"\n}");
+    }
+
+    #[test]
+    fn test_group_modules() {
+        let trace = crate::loader::load_trace(
+            &std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json"),
+        )
+        .unwrap();
+        let render = |expansion: &ExpansionState| {
+            render_state(&trace, 0, expansion, &DiffResult::default(), &Formatters::default(), VarOrder::Declaration, TypeDisplay::Hidden, true, PrefixDisplay::Full, 80, usize::MAX)
+                .iter()
+                .map(|line| line.plain_text())
+                .collect::<Vec<_>>()
+        };
+
+        let mut expansion = ExpansionState::new();
+        let lines = render(&expansion);
+        assert_eq!(&lines[..4], ["clock_sync4::", "  clock_sync::", "    time: 0", "    messages: Set()"]);
+
+        let module = module_path("clock_sync4::clock_sync");
+        assert_eq!(module_of(&module), Some("clock_sync4::clock_sync"));
+        expansion.toggle(&module);
+        assert_eq!(render(&expansion), ["clock_sync4::", "  clock_sync:: (3 variables)"]);
+
+        // Revealing a variable expands its module groups again
+        expansion.reveal(&path(&["clock_sync4::clock_sync::time"]));
+        assert!(expansion.is_expanded(&module));
+        assert!(in_module("clock_sync4::clock_sync::time", "clock_sync4"));
+        assert!(!in_module("clock_sync4x::time", "clock_sync4"));
+    }
+
+    #[test]
+    fn test_prefix_display() {
+        let names = ["propeller::choreo::s", "propeller::choreo::ctx::step", "round"];
+        let common = common_module(&names);
+        assert_eq!(common, "propeller::choreo");
+        assert_eq!(common_module(&["a::x::v", "a::y::w"]), "a");
+        assert_eq!(common_module(&["a::v", "b::w"]), "");
+        assert_eq!(common_module(&["round"]), "");
+
+        let labels = |display: PrefixDisplay| names.map(|name| display.label(name, &common).into_owned());
+        assert_eq!(labels(PrefixDisplay::Full), names);
+        assert_eq!(labels(PrefixDisplay::Elided), ["…::s", "…::ctx::step", "round"]);
+        assert_eq!(labels(PrefixDisplay::Short), ["s", "step", "round"]);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut trace = crate::loader::load_trace(
+            &std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("clock.itf.json"),
+        )
+        .unwrap();
+        trace.aliases.insert("clock_sync4::clock_sync::time".to_string(), "now".to_string());
+        let render = |group_modules: bool| {
+            render_state(&trace, 0, &ExpansionState::new(), &DiffResult::default(), &Formatters::default(), VarOrder::Declaration, TypeDisplay::Hidden, group_modules, PrefixDisplay::Short, 80, usize::MAX)
+                .iter()
+                .map(|line| line.plain_text())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(&render(false)[..2], ["now: 0", "messages: Set()"]);
+        assert_eq!(&render(true)[2..4], ["    now: 0", "    messages: Set()"]);
+    }
+}
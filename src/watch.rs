@@ -0,0 +1,129 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+
+use crate::loader::load_trace;
+
+/// Suffix of the trace files looked for in a watched directory
+const TRACE_SUFFIX: &str = ".itf.json";
+
+/// A trace that appeared in the watched directory
+pub struct QueuedTrace {
+    pub path: PathBuf,
+    /// Number of states and action of the last one, to tell the traces apart in the queue
+    pub states: usize,
+    pub last_action: Option<String>,
+    /// Whether it was opened in the explorer
+    pub reviewed: bool,
+}
+
+/// Directory watched for the traces a long run writes, like `quint run --n-traces`, queued as they appear
+pub struct Watcher {
+    pub dir: PathBuf,
+    pub queue: Vec<QueuedTrace>,
+    /// Files already there when watching started, or queued since
+    seen: HashSet<PathBuf>,
+    /// Files that failed to load, with their modification time and size: they may have been read while being
+    /// written, and are tried again once they change
+    failed: HashMap<PathBuf, (Option<SystemTime>, u64)>,
+}
+
+impl Watcher {
+    /// Watch a directory, the traces already in it not counting as new
+    pub fn new(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("{} is not a directory", dir.display());
+        }
+        let seen = trace_files(dir)?.into_iter().collect();
+        Ok(Self { dir: dir.to_path_buf(), queue: Vec::new(), seen, failed: HashMap::new() })
+    }
+
+    /// Queue the traces that appeared since the last scan, in the order of their names, returning how many
+    pub fn scan(&mut self) -> usize {
+        // The directory may be gone for a moment, like when a run starts over
+        let Ok(files) = trace_files(&self.dir) else {
+            return 0;
+        };
+        let before = self.queue.len();
+        let new: Vec<PathBuf> = files.into_iter().filter(|path| !self.seen.contains(path)).collect();
+        for path in new {
+            let version = fs::metadata(&path).map_or((None, 0), |metadata| (metadata.modified().ok(), metadata.len()));
+            if self.failed.get(&path) == Some(&version) {
+                continue;
+            }
+            match load_trace(&path) {
+                Ok(trace) => {
+                    self.failed.remove(&path);
+                    self.seen.insert(path.clone());
+                    let last_action = trace.states.last().and_then(|state| state.action_taken()).map(str::to_string);
+                    self.queue.push(QueuedTrace { path, states: trace.states.len(), last_action, reviewed: false });
+                }
+                Err(_) => {
+                    self.failed.insert(path, version);
+                }
+            }
+        }
+        self.queue.len() - before
+    }
+
+    /// Number of queued traces not opened yet
+    pub fn unreviewed(&self) -> usize {
+        self.queue.iter().filter(|queued| !queued.reviewed).count()
+    }
+}
+
+/// Most recently modified trace of a directory, to start with when no trace is given
+pub fn newest_trace(dir: &Path) -> Result<PathBuf> {
+    let files = trace_files(dir)?;
+    let newest = files.into_iter().max_by_key(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok());
+    newest.with_context(|| format!("No {} file in {} yet", TRACE_SUFFIX, dir.display()))
+}
+
+/// Trace files of a directory, sorted by name
+fn trace_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).context(format!("Failed to read {}", dir.display()))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.file_name().is_some_and(|name| name.to_string_lossy().ends_with(TRACE_SUFFIX)))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join(format!("quint-trace-explorer-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let example = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("dining.itf.json");
+        fs::copy(&example, dir.join("old.itf.json")).unwrap();
+
+        // Traces already there are not new
+        let mut watcher = Watcher::new(&dir).unwrap();
+        assert_eq!(watcher.scan(), 0);
+
+        // A trace still being written is queued once it is complete, other files never
+        fs::write(dir.join("b.itf.json"), "{ \"states\": [").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::copy(&example, dir.join("a.itf.json")).unwrap();
+        assert_eq!(watcher.scan(), 1);
+        fs::copy(&example, dir.join("b.itf.json")).unwrap();
+        assert_eq!(watcher.scan(), 1);
+        assert_eq!(watcher.scan(), 0);
+
+        let names: Vec<_> = watcher.queue.iter().map(|queued| queued.path.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(names, vec!["a.itf.json", "b.itf.json"]);
+        assert_eq!((watcher.queue[0].states, watcher.queue[0].last_action.as_deref()), (21, Some("releaseForks")));
+        watcher.queue[0].reviewed = true;
+        assert_eq!(watcher.unreviewed(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}